@@ -0,0 +1,45 @@
+use criterion::{BatchSize, Criterion, black_box, criterion_group, criterion_main};
+use simplz_blockchain::{Block, Blockchain, Target, Transaction};
+
+fn sample_block() -> Block {
+    let transactions = (0..100)
+        .map(|i| Transaction::with_fee("alice".to_owned(), "bob".to_owned(), i, 1))
+        .collect();
+    Block::with_transactions(1, "Benchmark data".to_owned(), transactions, "0".repeat(64))
+}
+
+fn bench_calculate_hash(c: &mut Criterion) {
+    let block = sample_block();
+    c.bench_function("calculate_hash", |b| b.iter(|| black_box(&block).calculate_hash()));
+}
+
+fn bench_mine_block(c: &mut Criterion) {
+    let block = sample_block();
+    let mut group = c.benchmark_group("mine_block");
+    for difficulty in [2, 3, 4] {
+        group.bench_function(format!("difficulty_{}", difficulty), |b| {
+            b.iter_batched(
+                || {
+                    let mut block = block.clone();
+                    block.header.nonce = 0;
+                    block
+                },
+                |mut block| block.mine_block(Target::from_leading_zeros(difficulty)),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_is_valid_chain(c: &mut Criterion) {
+    let mut blockchain = Blockchain::new();
+    for i in 1..=20 {
+        blockchain.add_block(format!("Block {} data", i));
+    }
+    c.bench_function("is_valid_chain_20_blocks", |b| b.iter(|| blockchain.is_valid_chain()));
+    c.bench_function("validate_suffix_20_blocks", |b| b.iter(|| blockchain.validate_suffix(0)));
+}
+
+criterion_group!(benches, bench_calculate_hash, bench_mine_block, bench_is_valid_chain);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use ed25519_dalek::SigningKey;
+use rayon::ThreadPoolBuilder;
+use simplz_blockchain::utxo::address;
+use simplz_blockchain::{Authority, Block, Blockchain, ChainParams, Network, Transaction, validate};
+
+const BLOCKS: usize = 100_000;
+
+/// A synthetic chain of `BLOCKS` blocks with a few payments each, mined at
+/// the testing target or, with `authority`, sealed by its validators.
+fn synthetic_chain(authority: Option<Authority>) -> (Vec<Block>, ChainParams) {
+    let params = ChainParams {
+        authority,
+        ..ChainParams::testing()
+    };
+    let mut blockchain = Blockchain::with_params(params.clone());
+    let senders: Vec<SigningKey> = (7..10).map(|seed| SigningKey::from_bytes(&[seed; 32])).collect();
+    for i in 0..BLOCKS as u64 {
+        for sender in &senders {
+            let mut transaction = Transaction {
+                nonce: i,
+                ..Transaction::with_fee(address(&sender.verifying_key(), Network::Mainnet), "bob".to_owned(), i, 1)
+            };
+            transaction.sign(sender);
+            blockchain.add_transaction(transaction);
+        }
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+    }
+    (blockchain.iter().cloned().collect(), params)
+}
+
+fn bench_validate(c: &mut Criterion) {
+    let keys: Vec<SigningKey> = (1..=4).map(|seed| SigningKey::from_bytes(&[seed; 32])).collect();
+    let mut authority = Authority::new(keys.iter().map(SigningKey::verifying_key).collect()).unwrap();
+    for key in keys {
+        authority.add_key(key);
+    }
+    let sequential = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+    let mut group = c.benchmark_group("validate_100k_blocks");
+    group.sample_size(10);
+    for (name, authority) in [("proof_of_work", None), ("authority", Some(authority))] {
+        let (blocks, params) = synthetic_chain(authority);
+        group.bench_function(format!("{}/sequential", name), |b| {
+            b.iter(|| sequential.install(|| assert!(validate(&blocks, &params))))
+        });
+        group.bench_function(format!("{}/parallel", name), |b| b.iter(|| assert!(validate(&blocks, &params))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_validate);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // Generates the gRPC service and messages from the schema. protox
+    // compiles it, so protoc need not be installed.
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/simplz.proto");
+        let descriptors = protox::compile(["proto/simplz.proto"], ["proto"]).expect("proto/simplz.proto compiles");
+        tonic_build::configure().compile_fds(descriptors).expect("gRPC code generates");
+    }
+}
@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simplz_blockchain::codec::{decode_block, encode_block};
+use simplz_blockchain::{Block, ChainParams, validate};
+
+// Blocks arrive from peers as JSON and from exports in the binary codec;
+// neither decoder may panic, and whatever they accept must survive a round
+// trip and validation.
+fuzz_target!(|data: &[u8]| {
+    let mut blocks = Vec::new();
+    if let Ok(block) = decode_block(&mut &data[..]) {
+        let mut encoded = Vec::new();
+        encode_block(&mut encoded, &block).unwrap();
+        assert_eq!(decode_block(&mut encoded.as_slice()).unwrap(), block);
+        blocks.push(block);
+    }
+    if let Ok(block) = serde_json::from_slice::<Block>(data) {
+        let encoded = serde_json::to_vec(&block).unwrap();
+        assert_eq!(serde_json::from_slice::<Block>(&encoded).unwrap(), block);
+        blocks.push(block);
+    }
+    let _ = validate(&blocks, &ChainParams::testing());
+});
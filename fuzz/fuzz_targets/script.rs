@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simplz_blockchain::script::{self, Context, Script};
+
+// The first byte splits the rest into an unlocking and a locking script.
+fuzz_target!(|data: &[u8]| {
+    let Some((&split, rest)) = data.split_first() else {
+        return;
+    };
+    let (unlock, lock) = rest.split_at((split as usize).min(rest.len()));
+    let (Ok(unlock), Ok(lock)) = (Script::from_bytes(unlock), Script::from_bytes(lock)) else {
+        return;
+    };
+    assert_eq!(Script::from_bytes(&lock.to_bytes()).as_ref(), Ok(&lock));
+    let context = Context {
+        message: b"fuzz",
+        height: split as u32,
+    };
+    let _ = script::verify(&unlock, &lock, &context);
+});
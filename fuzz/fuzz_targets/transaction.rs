@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simplz_blockchain::Transaction;
+use simplz_blockchain::codec::{decode_transaction, decode_utxo_transaction, encode_transaction, encode_utxo_transaction};
+use simplz_blockchain::utxo::UtxoTransaction;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(transaction) = decode_transaction(&mut &data[..]) {
+        let mut encoded = Vec::new();
+        encode_transaction(&mut encoded, &transaction).unwrap();
+        assert_eq!(decode_transaction(&mut encoded.as_slice()).unwrap(), transaction);
+        let _ = transaction.id();
+    }
+    if let Ok(transaction) = decode_utxo_transaction(&mut &data[..]) {
+        let mut encoded = Vec::new();
+        encode_utxo_transaction(&mut encoded, &transaction).unwrap();
+        assert_eq!(decode_utxo_transaction(&mut encoded.as_slice()).unwrap(), transaction);
+        let _ = transaction.id();
+    }
+    if let Ok(transaction) = serde_json::from_slice::<Transaction>(data) {
+        let _ = transaction.id();
+    }
+    let _ = serde_json::from_slice::<UtxoTransaction>(data);
+});
@@ -0,0 +1,325 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::script::Script;
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Bytes of the double SHA-256 appended to every address.
+const CHECKSUM_LEN: usize = 4;
+
+/// Version byte, payload and checksum.
+const DECODED_LEN: usize = 1 + 32 + CHECKSUM_LEN;
+
+const MAINNET_KEY: u8 = 0x3f;
+const MAINNET_SCRIPT: u8 = 0x40;
+const TESTNET_KEY: u8 = 0x7f;
+const TESTNET_SCRIPT: u8 = 0x80;
+
+/// A chain run from this crate. Block hashes, the peer handshake and
+/// addresses all name their network, so chains never mix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+}
+
+impl Network {
+    /// Id committed to by block headers and sent in the peer handshake.
+    pub fn id(self) -> u32 {
+        match self {
+            Network::Mainnet => 1,
+            Network::Testnet => 2,
+        }
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            1 => Some(Network::Mainnet),
+            2 => Some(Network::Testnet),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            other => Err(format!("unknown network {:?}, expected mainnet or testnet", other)),
+        }
+    }
+}
+
+/// What an address pays to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressKind {
+    /// An ed25519 public key, carried whole so signatures can be checked
+    /// against it.
+    Key,
+    /// The SHA-256 of a lock script.
+    Script,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidCharacter(char),
+    InvalidLength(usize),
+    BadChecksum,
+    UnknownVersion(u8),
+    InvalidKey,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::InvalidCharacter(c) => write!(f, "invalid base58 character {:?}", c),
+            AddressError::InvalidLength(len) => write!(f, "address decodes to {} bytes, not {}", len, DECODED_LEN),
+            AddressError::BadChecksum => write!(f, "address checksum does not match"),
+            AddressError::UnknownVersion(version) => write!(f, "unknown address version {:#04x}", version),
+            AddressError::InvalidKey => write!(f, "address does not hold a valid public key"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// A Base58Check address: a version byte naming the network and kind, a
+/// 32-byte payload and a checksum that catches typos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+    network: Network,
+    kind: AddressKind,
+    payload: [u8; 32],
+}
+
+impl Address {
+    pub fn from_key(key: &VerifyingKey, network: Network) -> Self {
+        Address {
+            network,
+            kind: AddressKind::Key,
+            payload: key.to_bytes(),
+        }
+    }
+
+    pub fn from_script(script: &Script, network: Network) -> Self {
+        Address {
+            network,
+            kind: AddressKind::Script,
+            payload: Sha256::digest(script.to_bytes()).into(),
+        }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn kind(&self) -> AddressKind {
+        self.kind
+    }
+
+    /// The key signatures must verify against, for key addresses.
+    pub fn verifying_key(&self) -> Option<VerifyingKey> {
+        match self.kind {
+            AddressKind::Key => VerifyingKey::from_bytes(&self.payload).ok(),
+            AddressKind::Script => None,
+        }
+    }
+
+    fn version(&self) -> u8 {
+        match (self.network, self.kind) {
+            (Network::Mainnet, AddressKind::Key) => MAINNET_KEY,
+            (Network::Mainnet, AddressKind::Script) => MAINNET_SCRIPT,
+            (Network::Testnet, AddressKind::Key) => TESTNET_KEY,
+            (Network::Testnet, AddressKind::Script) => TESTNET_SCRIPT,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = Vec::with_capacity(DECODED_LEN);
+        bytes.push(self.version());
+        bytes.extend_from_slice(&self.payload);
+        bytes.extend_from_slice(&checksum(&bytes));
+        f.write_str(&encode_base58(&bytes))
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = decode_base58(s)?;
+        if bytes.len() != DECODED_LEN {
+            return Err(AddressError::InvalidLength(bytes.len()));
+        }
+        let (body, sum) = bytes.split_at(DECODED_LEN - CHECKSUM_LEN);
+        if checksum(body) != sum {
+            return Err(AddressError::BadChecksum);
+        }
+        let (network, kind) = match body[0] {
+            MAINNET_KEY => (Network::Mainnet, AddressKind::Key),
+            MAINNET_SCRIPT => (Network::Mainnet, AddressKind::Script),
+            TESTNET_KEY => (Network::Testnet, AddressKind::Key),
+            TESTNET_SCRIPT => (Network::Testnet, AddressKind::Script),
+            other => return Err(AddressError::UnknownVersion(other)),
+        };
+        let address = Address {
+            network,
+            kind,
+            payload: body[1..].try_into().expect("length checked above"),
+        };
+        if kind == AddressKind::Key && address.verifying_key().is_none() {
+            return Err(AddressError::InvalidKey);
+        }
+        Ok(address)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        encoded.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let digest = Sha256::digest(Sha256::digest(bytes));
+    digest[..CHECKSUM_LEN].try_into().expect("digest is 32 bytes")
+}
+
+fn encode_base58(bytes: &[u8]) -> String {
+    // Little-endian base-58 digits of the big-endian number in `bytes`.
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in bytes {
+        let mut carry = byte as u32;
+        for digit in &mut digits {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    let mut encoded = "1".repeat(zeros);
+    encoded.extend(digits.iter().rev().map(|&digit| ALPHABET[digit as usize] as char));
+    encoded
+}
+
+fn decode_base58(s: &str) -> Result<Vec<u8>, AddressError> {
+    // Little-endian bytes of the number, built up one digit at a time.
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in s.chars() {
+        let mut carry = ALPHABET
+            .iter()
+            .position(|&letter| letter as char == c)
+            .ok_or(AddressError::InvalidCharacter(c))? as u32;
+        for byte in &mut bytes {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = s.chars().take_while(|&c| c == '1').count();
+    bytes.extend(std::iter::repeat_n(0, zeros));
+    bytes.reverse();
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+
+    #[test]
+    fn test_base58_round_trip() {
+        assert_eq!(encode_base58(b"hello world"), "StV1DL6CwTryKyV");
+        assert_eq!(encode_base58(&[0, 0, 1]), "112");
+        assert_eq!(decode_base58("112").unwrap(), vec![0, 0, 1]);
+        assert_eq!(decode_base58("StV1DL6CwTryKyV").unwrap(), b"hello world");
+        assert_eq!(decode_base58("0OIl"), Err(AddressError::InvalidCharacter('0')));
+    }
+
+    #[test]
+    fn test_addresses_round_trip() {
+        let key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        let mainnet = Address::from_key(&key, Network::Mainnet);
+        let testnet = Address::from_key(&key, Network::Testnet);
+        assert_ne!(mainnet.to_string(), testnet.to_string());
+
+        let parsed: Address = mainnet.to_string().parse().unwrap();
+        assert_eq!(parsed, mainnet);
+        assert_eq!(parsed.verifying_key(), Some(key));
+        assert_eq!(testnet.to_string().parse::<Address>().unwrap().network(), Network::Testnet);
+
+        let script = Address::from_script(&Script::pay_to_key(&key), Network::Mainnet);
+        let parsed: Address = script.to_string().parse().unwrap();
+        assert_eq!(parsed.kind(), AddressKind::Script);
+        assert_eq!(parsed.verifying_key(), None);
+
+        let json = serde_json::to_string(&mainnet).unwrap();
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), mainnet);
+    }
+
+    #[test]
+    fn test_typos_are_rejected() {
+        let key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        let encoded = Address::from_key(&key, Network::Mainnet).to_string();
+
+        for i in 0..encoded.len() {
+            let original = encoded.as_bytes()[i];
+            let replacement = if original == b'2' { b'3' } else { b'2' };
+            let mut typo = encoded.clone().into_bytes();
+            typo[i] = replacement;
+            assert!(String::from_utf8(typo).unwrap().parse::<Address>().is_err());
+        }
+
+        let mut swapped = encoded.clone().into_bytes();
+        swapped.swap(5, 6);
+        if swapped != encoded.as_bytes() {
+            assert!(String::from_utf8(swapped).unwrap().parse::<Address>().is_err());
+        }
+        assert!(matches!(
+            encoded[1..].parse::<Address>(),
+            Err(AddressError::InvalidLength(_) | AddressError::BadChecksum)
+        ));
+        assert!(hex::encode(key.as_bytes()).parse::<Address>().is_err());
+    }
+}
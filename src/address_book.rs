@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Ban score at which an address is no longer dialled or gossiped.
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// What we know about one peer address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerAddress {
+    /// Unix time of the last successful connection, if there was one.
+    pub last_seen: Option<i64>,
+    pub ban_score: u32,
+}
+
+impl PeerAddress {
+    pub fn is_banned(&self) -> bool {
+        self.ban_score >= BAN_THRESHOLD
+    }
+}
+
+/// Peer addresses learned from configuration and from other peers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressBook {
+    addresses: HashMap<String, PeerAddress>,
+}
+
+impl AddressBook {
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn get(&self, address: &str) -> Option<&PeerAddress> {
+        self.addresses.get(address)
+    }
+
+    /// Records `address` if it is new. Returns whether it was.
+    pub fn add(&mut self, address: String) -> bool {
+        if self.addresses.contains_key(&address) {
+            return false;
+        }
+        self.addresses.insert(address, PeerAddress::default());
+        true
+    }
+
+    /// Notes a successful connection to `address` at `now`.
+    pub fn mark_seen(&mut self, address: &str, now: i64) {
+        self.addresses.entry(address.to_owned()).or_default().last_seen = Some(now);
+    }
+
+    /// Adds `points` to the address's ban score.
+    pub fn penalize(&mut self, address: &str, points: u32) {
+        let entry = self.addresses.entry(address.to_owned()).or_default();
+        entry.ban_score = entry.ban_score.saturating_add(points);
+    }
+
+    /// Up to `count` addresses that are not banned, most recently seen first
+    /// and never-seen ones last.
+    pub fn best(&self, count: usize) -> Vec<&str> {
+        let mut candidates: Vec<(&String, &PeerAddress)> =
+            self.addresses.iter().filter(|(_, peer)| !peer.is_banned()).collect();
+        candidates.sort_by(|(a, a_peer), (b, b_peer)| b_peer.last_seen.cmp(&a_peer.last_seen).then(a.cmp(b)));
+        candidates.into_iter().take(count).map(|(address, _)| address.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_best_prefers_recent_unbanned_addresses() {
+        let mut book = AddressBook::default();
+        assert!(book.add("10.0.0.1:7000".to_owned()));
+        assert!(!book.add("10.0.0.1:7000".to_owned()));
+        book.add("10.0.0.2:7000".to_owned());
+        book.mark_seen("10.0.0.3:7000", 200);
+        book.mark_seen("10.0.0.2:7000", 100);
+        book.penalize("10.0.0.4:7000", BAN_THRESHOLD);
+
+        assert_eq!(book.len(), 4);
+        assert!(book.get("10.0.0.4:7000").unwrap().is_banned());
+        assert_eq!(book.best(10), vec!["10.0.0.3:7000", "10.0.0.2:7000", "10.0.0.1:7000"]);
+        assert_eq!(book.best(1), vec!["10.0.0.3:7000"]);
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+#[cfg(feature = "net")]
+use std::io;
+#[cfg(feature = "net")]
+use std::net::TcpStream;
+#[cfg(feature = "net")]
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::utxo::{OutPoint, TxOutput};
+#[cfg(feature = "net")]
+use crate::network::{Envelope, Inbox, Message, spawn_tcp_peer};
+#[cfg(feature = "net")]
+use crate::transport::{NodeIdentity, Role};
+#[cfg(feature = "net")]
+use crate::Network;
+use crate::{Block, ChainParams, genesis_utxo};
+
+/// Where two copies of a chain part ways and what that changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditReport {
+    /// Last block both chains hold, or `None` if even their genesis blocks
+    /// differ.
+    pub fork_point: Option<BlockSummary>,
+    /// Blocks after the fork point on the left chain.
+    pub left_blocks: Vec<BlockSummary>,
+    /// Blocks after the fork point on the right chain.
+    pub right_blocks: Vec<BlockSummary>,
+    /// Ids of transactions in the left chain's diverging blocks that the
+    /// right chain's lack.
+    pub left_only_transactions: Vec<String>,
+    pub right_only_transactions: Vec<String>,
+    /// Addresses whose balance moves differently after the fork point.
+    pub balances: Vec<BalanceDifference>,
+}
+
+impl AuditReport {
+    pub fn is_identical(&self) -> bool {
+        self.left_blocks.is_empty() && self.right_blocks.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSummary {
+    pub height: u32,
+    pub hash: String,
+    /// Account and UTXO transactions, counting the reward or coinbase.
+    pub transactions: usize,
+}
+
+impl BlockSummary {
+    fn of(block: &Block) -> Self {
+        BlockSummary {
+            height: block.header.index,
+            hash: block.header.hash.clone(),
+            transactions: block.body.transactions.len() + block.body.utxo_transactions.len(),
+        }
+    }
+}
+
+/// How much an address gains, or loses if negative, over each chain's
+/// diverging blocks, across both account and UTXO transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceDifference {
+    pub address: String,
+    pub left: i128,
+    pub right: i128,
+}
+
+/// Compares two chains under `params`, each given from its genesis block.
+/// Neither is validated, so a chain one node version accepts and another
+/// rejects can still be compared.
+pub fn audit(left: &[Block], right: &[Block], params: &ChainParams) -> AuditReport {
+    let shared = left.iter().zip(right).take_while(|(left, right)| left == right).count();
+    let (left_diverging, right_diverging) = (&left[shared..], &right[shared..]);
+    let (left_ids, right_ids) = (transaction_ids(left_diverging), transaction_ids(right_diverging));
+    let (left_changes, right_changes) = (balance_changes(left, shared, params), balance_changes(right, shared, params));
+
+    let addresses: BTreeSet<&String> = left_changes.keys().chain(right_changes.keys()).collect();
+    let balances = addresses
+        .into_iter()
+        .map(|address| BalanceDifference {
+            address: address.clone(),
+            left: left_changes.get(address).copied().unwrap_or(0),
+            right: right_changes.get(address).copied().unwrap_or(0),
+        })
+        .filter(|difference| difference.left != difference.right)
+        .collect();
+
+    AuditReport {
+        fork_point: shared.checked_sub(1).map(|last| BlockSummary::of(&left[last])),
+        left_blocks: left_diverging.iter().map(BlockSummary::of).collect(),
+        right_blocks: right_diverging.iter().map(BlockSummary::of).collect(),
+        left_only_transactions: left_ids.difference(&right_ids).cloned().collect(),
+        right_only_transactions: right_ids.difference(&left_ids).cloned().collect(),
+        balances,
+    }
+}
+
+fn transaction_ids(blocks: &[Block]) -> BTreeSet<String> {
+    blocks
+        .iter()
+        .flat_map(|block| {
+            let account = block.body.transactions.iter().map(|transaction| transaction.id());
+            account.chain(block.body.utxo_transactions.iter().map(|transaction| transaction.id()))
+        })
+        .collect()
+}
+
+/// Net balance change per address over `blocks[from..]`. The earlier blocks
+/// are replayed only to find the outputs the later ones spend.
+fn balance_changes(blocks: &[Block], from: usize, params: &ChainParams) -> BTreeMap<String, i128> {
+    let premine = genesis_utxo(params);
+    let mut outputs: HashMap<OutPoint, TxOutput> =
+        premine.iter().map(|(outpoint, output)| (outpoint.clone(), output.clone())).collect();
+    let mut changes = BTreeMap::new();
+    for (position, block) in blocks.iter().enumerate() {
+        let counted = position >= from;
+        let mut credit = |address: &str, amount: i128| {
+            if counted {
+                *changes.entry(address.to_owned()).or_insert(0) += amount;
+            }
+        };
+        for transaction in &block.body.transactions {
+            if !transaction.is_reward() {
+                credit(&transaction.sender, -(transaction.amount as i128 + transaction.fee as i128));
+            }
+            credit(&transaction.recipient, transaction.amount as i128);
+        }
+        for transaction in &block.body.utxo_transactions {
+            for input in &transaction.inputs {
+                if let Some(spent) = outputs.remove(&input.outpoint) {
+                    credit(&spent.owner, -(spent.value as i128));
+                }
+            }
+            let txid = transaction.id();
+            for (vout, output) in transaction.outputs.iter().enumerate() {
+                credit(&output.owner, output.value as i128);
+                let outpoint = OutPoint {
+                    txid: txid.clone(),
+                    vout: vout as u32,
+                };
+                outputs.insert(outpoint, output.clone());
+            }
+        }
+    }
+    changes.retain(|_, change| *change != 0);
+    changes
+}
+
+/// Downloads every block of the full node listening at `address`, as a
+/// throwaway peer. Fails if the node has pruned any of them.
+#[cfg(feature = "net")]
+pub fn fetch_chain(address: &str, network: Network, timeout: Duration) -> io::Result<Vec<Block>> {
+    let identity = NodeIdentity::generate()?;
+    let inbox = Inbox::new();
+    let stream = TcpStream::connect(address)?;
+    let (_, outbox) = spawn_tcp_peer(stream, &identity, network, Role::Initiator, inbox.sender())?;
+    let send = |message| {
+        let envelope = Envelope {
+            from: identity.id(),
+            message,
+        };
+        outbox.send(envelope).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    };
+    send(Message::GetHeaders)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut headers = None;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Some(envelope) = inbox.recv_timeout(remaining) else {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "full node did not answer"));
+        };
+        match envelope.message {
+            Message::Headers(received) if headers.is_none() => {
+                send(Message::GetBodies(received.iter().map(|header| header.hash.clone()).collect()))?;
+                headers = Some(received);
+            }
+            Message::Bodies(bodies) => {
+                let Some(headers) = headers else { continue };
+                if bodies.len() != headers.len() {
+                    return Err(io::Error::other("full node has pruned some of its blocks"));
+                }
+                return Ok(headers.into_iter().zip(bodies).map(|(header, body)| Block { header, body }).collect());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Blockchain;
+    use crate::transaction::accounts::{account, payment};
+    use crate::utxo::UtxoTransaction;
+
+    #[test]
+    fn test_audit_finds_the_fork_and_its_effects() {
+        let params = ChainParams::testing();
+        let mut left = Blockchain::with_params(params.clone());
+        assert!(left.add_block("shared".to_owned()));
+        let mut right = Blockchain::with_params(params.clone());
+        assert!(right.accept_block(left.latest_block().clone()));
+
+        assert!(left.add_transaction(payment("alice", "bob", 5, 1)));
+        assert!(left.mine_pending_transactions("miner".to_owned()));
+        assert!(right.add_utxo_block(vec![UtxoTransaction::coinbase("carol".to_owned(), 50)]));
+        assert!(right.add_block("longer".to_owned()));
+
+        let (left, right): (Vec<Block>, Vec<Block>) = (left.iter().cloned().collect(), right.iter().cloned().collect());
+        let report = audit(&left, &right, &params);
+        assert_eq!(report.fork_point, Some(BlockSummary::of(&left[1])));
+        assert_eq!(report.left_blocks, [BlockSummary::of(&left[2])]);
+        assert_eq!(report.right_blocks.iter().map(|block| block.height).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(report.left_only_transactions.len(), 2);
+        assert_eq!(report.right_only_transactions.len(), 1);
+
+        let change = |address: &str| {
+            let difference = report.balances.iter().find(|difference| difference.address == address).unwrap();
+            (difference.left, difference.right)
+        };
+        assert_eq!(change(&account("alice")), (-6, 0));
+        assert_eq!(change(&account("bob")), (5, 0));
+        assert_eq!(change("carol"), (0, 50));
+        assert!(!report.is_identical());
+        assert!(audit(&left, &left, &params).is_identical());
+    }
+
+    #[test]
+    fn test_audit_charges_spent_outputs_to_their_owner() {
+        use ed25519_dalek::SigningKey;
+
+        use crate::utxo::address;
+
+        let params = ChainParams::testing();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let owner = address(&key.verifying_key(), params.network);
+        let coinbase = UtxoTransaction::coinbase(owner.clone(), 50);
+        let mut blockchain = Blockchain::with_params(params.clone());
+        assert!(blockchain.add_utxo_block(vec![coinbase.clone()]));
+        let base: Vec<Block> = blockchain.iter().cloned().collect();
+
+        let outpoint = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        let mut payment = UtxoTransaction::new(vec![outpoint], vec![TxOutput::new(50, "bob".to_owned())]);
+        payment.sign(&key);
+        assert!(blockchain.add_utxo_block(vec![payment]));
+        let spent: Vec<Block> = blockchain.iter().cloned().collect();
+
+        let report = audit(&base, &spent, &params);
+        let moved: Vec<(&str, i128, i128)> =
+            report.balances.iter().map(|difference| (difference.address.as_str(), difference.left, difference.right)).collect();
+        assert_eq!(moved, [(owner.as_str(), 0, -50), ("bob", 0, 50)]);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_fetch_chain_from_full_node() {
+        use std::net::TcpListener;
+        use std::thread;
+
+        use crate::Node;
+
+        let mut blockchain = Blockchain::new();
+        assert!(blockchain.add_transaction(payment("alice", "bob", 5, 0)));
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        let expected: Vec<Block> = blockchain.iter().cloned().collect();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let identity = NodeIdentity::generate().unwrap();
+            let mut node = Node::with_blockchain(identity.id(), blockchain);
+            let (stream, _) = listener.accept().unwrap();
+            let (peer, sender) = spawn_tcp_peer(stream, &identity, Network::Mainnet, Role::Responder, node.inbox_sender()).unwrap();
+            node.add_peer(peer, sender);
+            loop {
+                node.process_messages();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let fetched = fetch_chain(&address, Network::Mainnet, Duration::from_secs(10)).unwrap();
+        assert_eq!(fetched, expected);
+        assert!(audit(&fetched, &expected, &ChainParams::default()).is_identical());
+    }
+}
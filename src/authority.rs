@@ -0,0 +1,98 @@
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+
+use crate::BlockHeader;
+
+/// Proof-of-authority settings. Instead of mining, validators take turns
+/// sealing blocks: block `n` must carry a seal by `validators[n % len]`
+/// over its hash. The genesis block is mined as usual.
+#[derive(Debug, Clone)]
+pub struct Authority {
+    validators: Vec<VerifyingKey>,
+    /// Keys this node seals its validators' turns with. Local to each node.
+    keys: Vec<SigningKey>,
+}
+
+impl Authority {
+    /// Validators sealing in the given order, with no keys to seal with.
+    /// `None` if there are no validators.
+    pub fn new(validators: Vec<VerifyingKey>) -> Option<Self> {
+        if validators.is_empty() {
+            return None;
+        }
+        Some(Authority {
+            validators,
+            keys: Vec::new(),
+        })
+    }
+
+    /// Seals the turns of `key`'s validator from now on. Returns false, and
+    /// keeps nothing, if `key` is not a validator's.
+    pub fn add_key(&mut self, key: SigningKey) -> bool {
+        if !self.validators.contains(&key.verifying_key()) {
+            return false;
+        }
+        self.keys.push(key);
+        true
+    }
+
+    pub fn validators(&self) -> &[VerifyingKey] {
+        &self.validators
+    }
+
+    /// The validator whose turn it is to seal the block at `height`.
+    pub fn sealer(&self, height: u32) -> &VerifyingKey {
+        &self.validators[height as usize % self.validators.len()]
+    }
+
+    /// This node's key for the block at `height`, if it is one of its turns.
+    pub fn key_for(&self, height: u32) -> Option<&SigningKey> {
+        let sealer = self.sealer(height);
+        self.keys.iter().find(|key| key.verifying_key() == *sealer)
+    }
+
+    /// Whether `header` carries the scheduled validator's seal over its
+    /// hash. Does not recompute the hash.
+    pub fn verify(&self, header: &BlockHeader) -> bool {
+        let Ok(signature) = Signature::from_slice(&header.seal) else {
+            return false;
+        };
+        self.sealer(header.index).verify(header.hash.as_bytes(), &signature).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Block, BlockBody, Target};
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    #[test]
+    fn test_validators_take_turns() {
+        assert!(Authority::new(Vec::new()).is_none());
+        let mut authority = Authority::new(vec![key(1).verifying_key(), key(2).verifying_key()]).unwrap();
+        assert!(!authority.add_key(key(3)));
+        assert!(authority.add_key(key(2)));
+
+        assert_eq!(*authority.sealer(4), key(1).verifying_key());
+        assert_eq!(*authority.sealer(5), key(2).verifying_key());
+        assert!(authority.key_for(4).is_none());
+        assert_eq!(authority.key_for(5).map(SigningKey::verifying_key), Some(key(2).verifying_key()));
+    }
+
+    #[test]
+    fn test_only_the_scheduled_seal_verifies() {
+        let authority = Authority::new(vec![key(1).verifying_key(), key(2).verifying_key()]).unwrap();
+        let mut block = Block::unmined(1, 0, "PreviousHash".to_owned(), BlockBody::default());
+        block.seal_block(Target::from_leading_zeros(1), &key(2));
+        assert_eq!(block.header.hash, block.calculate_hash());
+        assert!(authority.verify(&block.header));
+
+        block.seal_block(Target::from_leading_zeros(1), &key(1));
+        assert!(!authority.verify(&block.header));
+        block.header.seal.clear();
+        assert!(!authority.verify(&block.header));
+    }
+}
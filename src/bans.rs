@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Default time a banned peer stays banned, in seconds.
+pub const DEFAULT_BAN_DURATION: i64 = 24 * 60 * 60;
+
+/// Peer behaviour that earns penalty points. A peer is banned once its
+/// points reach `BAN_THRESHOLD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    InvalidBlock,
+    InvalidTransaction,
+    MalformedMessage,
+    /// More transactions than the per-minute allowance.
+    TransactionFlood,
+}
+
+impl Misbehavior {
+    pub fn penalty(self) -> u32 {
+        match self {
+            Misbehavior::InvalidBlock => 50,
+            Misbehavior::InvalidTransaction => 10,
+            Misbehavior::MalformedMessage => 10,
+            Misbehavior::TransactionFlood => 5,
+        }
+    }
+}
+
+/// Banned peer ids and the Unix time each ban ends.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanList {
+    bans: HashMap<String, i64>,
+}
+
+impl BanList {
+    pub fn ban(&mut self, peer: String, until: i64) {
+        self.bans.insert(peer, until);
+    }
+
+    pub fn unban(&mut self, peer: &str) -> bool {
+        self.bans.remove(peer).is_some()
+    }
+
+    pub fn is_banned(&self, peer: &str, now: i64) -> bool {
+        self.bans.get(peer).is_some_and(|&until| until > now)
+    }
+
+    /// Forgets bans that have run out by `now`.
+    pub fn expire(&mut self, now: i64) {
+        self.bans.retain(|_, until| *until > now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bans.is_empty()
+    }
+
+    /// Banned peers and when their bans end.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, i64)> {
+        self.bans.iter().map(|(peer, &until)| (peer.as_str(), until))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bans_run_out() {
+        let mut bans = BanList::default();
+        bans.ban("mallory".to_owned(), 100);
+        assert!(bans.is_banned("mallory", 99));
+        assert!(!bans.is_banned("mallory", 100));
+        assert!(!bans.is_banned("alice", 0));
+
+        bans.expire(50);
+        assert_eq!(bans.len(), 1);
+        bans.expire(100);
+        assert!(bans.is_empty());
+    }
+}
@@ -0,0 +1,428 @@
+#[cfg(feature = "system-clock")]
+use std::time::Instant;
+
+use ed25519_dalek::{SIGNATURE_LENGTH, Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, info_span, warn};
+
+use crate::merkle::{self, Hash, MerkleProof};
+use crate::utxo::UtxoTransaction;
+use crate::{Network, Target, Transaction, Work, codec};
+#[cfg(feature = "system-clock")]
+use crate::{Clock, DIFFICULTY, SystemClock, metrics};
+
+const HASH_HEX_LEN: usize = 64;
+
+/// Number of nonces tried between calls to a mining progress callback.
+const PROGRESS_INTERVAL: u64 = 10_000;
+
+/// The part of a block that is hashed and mined. It commits to the body
+/// through `merkle_root`, so a header chain can be checked without bodies,
+/// and to the chain state after the block through `state_root`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub index: u32,
+    pub timestamp: i64,
+    pub prev_hash: String,
+    pub merkle_root: String,
+    /// Commitment to the UTXO set and account nonces once the block is
+    /// applied; see `state_root`.
+    #[serde(default)]
+    pub state_root: String,
+    pub nonce: u64,
+    /// Widens the search space past the `u64` nonces: bumped whenever the
+    /// nonce wraps, and set apart per miner so parallel miners never try
+    /// the same header.
+    #[serde(default)]
+    pub extra_nonce: u64,
+    /// Proof-of-work target in compact form; see `Target::to_compact`.
+    pub bits: u32,
+    /// Network the block was mined for.
+    #[serde(default)]
+    pub network: Network,
+    pub hash: String,
+    /// In authority mode, the scheduled validator's signature over `hash`,
+    /// which does not cover it. Empty under proof of work.
+    #[serde(default, with = "hex::serde", skip_serializing_if = "Vec::is_empty")]
+    pub seal: Vec<u8>,
+}
+
+impl BlockHeader {
+    pub fn calculate_hash(&self) -> String {
+        let mut hasher = self.hasher_without_nonce();
+        hasher.update(self.nonce.to_le_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    /// Hasher primed with every field except the nonce, which always comes
+    /// last. Mining clones it per attempt instead of rehashing the header.
+    pub(crate) fn hasher_without_nonce(&self) -> Sha256 {
+        let mut hasher = Sha256::new();
+        // Writing to a hasher cannot fail; see `codec::digest`.
+        let _ = codec::encode_header_without_nonce(&mut hasher, self);
+        hasher
+    }
+
+    /// The proof-of-work target encoded in `bits`.
+    pub fn target(&self) -> Target {
+        Target::from_compact(self.bits)
+    }
+
+    /// The work meeting `target` takes, which forks are weighed by.
+    pub fn work(&self) -> Work {
+        self.target().work()
+    }
+
+    /// Searches nonces from the current one until the hash meets the
+    /// header's target. If every nonce is used up, moves on to the next
+    /// extra nonce and starts again from zero.
+    pub fn mine(&mut self) {
+        self.mine_with_progress(&mut |_| {});
+    }
+
+    /// Like `mine`, calling `progress` with the number of nonces tried so
+    /// far every 10 000 attempts.
+    pub fn mine_with_progress(&mut self, progress: &mut dyn FnMut(u64)) {
+        let target = self.target();
+        let _span = info_span!("mine_block", index = self.index, difficulty = target.difficulty()).entered();
+        let mut prefix = self.hasher_without_nonce();
+        #[cfg(feature = "system-clock")]
+        let started = Instant::now();
+        let mut attempts: u64 = 0;
+
+        loop {
+            let mut hasher = prefix.clone();
+            hasher.update(self.nonce.to_le_bytes());
+            let digest = hasher.finalize();
+            if target.is_met_by(&digest) {
+                self.hash = hex::encode(digest);
+                break;
+            }
+            match self.nonce.checked_add(1) {
+                Some(nonce) => self.nonce = nonce,
+                None => {
+                    self.extra_nonce = self.extra_nonce.wrapping_add(1);
+                    self.nonce = 0;
+                    prefix = self.hasher_without_nonce();
+                }
+            }
+            attempts = attempts.saturating_add(1);
+            if attempts.is_multiple_of(PROGRESS_INTERVAL) {
+                progress(attempts);
+            }
+        }
+        #[cfg(feature = "system-clock")]
+        {
+            let hashes = attempts.saturating_add(1) as f64;
+            let seconds = started.elapsed().as_secs_f64().max(f64::EPSILON);
+            metrics::registry().hash_rate.set((hashes / seconds) as u64);
+        }
+        info!(hash = %self.hash, nonce = self.nonce, extra_nonce = self.extra_nonce, "block mined");
+    }
+
+    /// Whether the recorded hash, read as a number, is within the header's
+    /// target. Does not recompute the hash.
+    pub fn meets_target(&self) -> bool {
+        let mut digest = [0; HASH_HEX_LEN / 2];
+        hex::decode_to_slice(&self.hash, &mut digest).is_ok() && self.target().is_met_by(&digest)
+    }
+
+    /// Checks this header as the successor of `previous`: its hash, proof
+    /// of work at `target`, the link between the two, that both belong
+    /// to the same network, and that its timestamp does not go backwards.
+    pub fn follows(&self, previous: &BlockHeader, target: Target) -> bool {
+        if self.hash != self.calculate_hash() {
+            warn!(index = self.index, "validation failed: block hash mismatch");
+            return false;
+        }
+        if self.bits != target.to_compact() || !self.meets_target() {
+            warn!(index = self.index, "validation failed: insufficient proof of work");
+            return false;
+        }
+        self.links_to(previous)
+    }
+
+    /// The part of `follows` that needs `previous`: the same network, the
+    /// link and a timestamp that does not go backwards.
+    pub(crate) fn links_to(&self, previous: &BlockHeader) -> bool {
+        if self.network != previous.network {
+            warn!(index = self.index, network = %self.network, "validation failed: block is from another network");
+            return false;
+        }
+
+        if self.index != previous.index + 1 || self.prev_hash != previous.hash {
+            warn!(index = self.index, "validation failed: broken link to previous block");
+            return false;
+        }
+
+        if self.timestamp < previous.timestamp {
+            warn!(index = self.index, "validation failed: timestamp precedes previous block");
+            return false;
+        }
+        true
+    }
+
+    /// Exact size of the header in the binary encoding.
+    pub fn size_bytes(&self) -> usize {
+        codec::encoded_len(|writer| codec::encode_header(writer, self))
+    }
+
+    /// Size of a header once mined, with every hash filled in, and sealed
+    /// too if `sealed`. Lets a block's size be budgeted before it is.
+    pub fn finished_size(sealed: bool) -> usize {
+        let hash = "0".repeat(HASH_HEX_LEN);
+        BlockHeader {
+            prev_hash: hash.clone(),
+            merkle_root: hash.clone(),
+            state_root: hash.clone(),
+            hash,
+            seal: if sealed { vec![0; SIGNATURE_LENGTH] } else { Vec::new() },
+            ..Block::unmined(0, 0, String::new(), BlockBody::default()).header
+        }
+        .size_bytes()
+    }
+}
+
+/// The transactions and free-form data a header commits to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockBody {
+    pub data: String,
+    pub transactions: Vec<Transaction>,
+    pub utxo_transactions: Vec<UtxoTransaction>,
+}
+
+impl BlockBody {
+    pub fn transaction_count(&self) -> usize {
+        self.transactions.len() + self.utxo_transactions.len()
+    }
+
+    /// Merkle leaves: the data, then each account transaction, then each
+    /// UTXO transaction in full, signatures and unlocking scripts included,
+    /// so a relayer cannot alter what spends an output without changing the
+    /// root.
+    pub fn leaves(&self) -> Vec<Hash> {
+        let mut leaves = vec![merkle::sha256(self.data.as_bytes())];
+        leaves.extend(self.transactions.iter().map(|transaction| transaction_leaf(&transaction.id())));
+        leaves.extend(
+            self.utxo_transactions
+                .iter()
+                .map(|transaction| codec::digest(|writer| codec::encode_utxo_transaction(writer, transaction))),
+        );
+        leaves
+    }
+
+    pub fn merkle_root(&self) -> String {
+        hex::encode(merkle::merkle_root(&self.leaves()))
+    }
+
+    /// Proof that the account transaction with `id` is in this body.
+    pub fn prove_transaction(&self, id: &str) -> Option<MerkleProof> {
+        let position = self.transactions.iter().position(|transaction| transaction.id() == id)?;
+        // Leaf 0 is the block data.
+        merkle::prove(&self.leaves(), position + 1)
+    }
+
+    /// Exact size of the body in the binary encoding.
+    pub fn size_bytes(&self) -> usize {
+        codec::encoded_len(|writer| codec::encode_body(writer, self))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub body: BlockBody,
+}
+
+impl Block {
+    #[cfg(feature = "system-clock")]
+    pub fn new(index: u32, data: String, prev_hash: String) -> Self {
+        Self::with_transactions(index, data, Vec::new(), prev_hash)
+    }
+
+    #[cfg(feature = "system-clock")]
+    pub fn with_transactions(
+        index: u32,
+        data: String,
+        transactions: Vec<Transaction>,
+        prev_hash: String,
+    ) -> Self {
+        let body = BlockBody {
+            data,
+            transactions,
+            utxo_transactions: Vec::new(),
+        };
+        let mut block = Self::unmined(index, SystemClock.now(), prev_hash, body);
+        block.mine_block(Target::from_leading_zeros(DIFFICULTY));
+        block
+    }
+
+    /// A block whose header has not been mined yet.
+    pub(crate) fn unmined(index: u32, timestamp: i64, prev_hash: String, body: BlockBody) -> Self {
+        Block {
+            header: BlockHeader {
+                index,
+                timestamp,
+                prev_hash,
+                merkle_root: String::new(),
+                state_root: String::new(),
+                nonce: 0,
+                extra_nonce: 0,
+                bits: 0,
+                network: Network::Mainnet,
+                hash: String::new(),
+                seal: Vec::new(),
+            },
+            body,
+        }
+    }
+
+    pub fn calculate_hash(&self) -> String {
+        self.header.calculate_hash()
+    }
+
+    /// Commits the header to the current body and mines it at `target`.
+    pub fn mine_block(&mut self, target: Target) {
+        self.mine_block_with_progress(target, &mut |_| {});
+    }
+
+    /// Commits the header to the current body and seals it with `key`
+    /// instead of mining it.
+    pub fn seal_block(&mut self, target: Target, key: &SigningKey) {
+        self.header.merkle_root = self.body.merkle_root();
+        self.header.bits = target.to_compact();
+        self.header.hash = self.header.calculate_hash();
+        self.header.seal = key.sign(self.header.hash.as_bytes()).to_bytes().to_vec();
+    }
+
+    /// Like `mine_block`, reporting progress as `BlockHeader::mine_with_progress`
+    /// does.
+    pub fn mine_block_with_progress(&mut self, target: Target, progress: &mut dyn FnMut(u64)) {
+        self.header.merkle_root = self.body.merkle_root();
+        self.header.bits = target.to_compact();
+        self.header.mine_with_progress(progress);
+    }
+
+    /// Exact size of the block in the binary encoding, which is what block
+    /// limits, fees and the mempool measure.
+    pub fn size_bytes(&self) -> usize {
+        self.header.size_bytes() + self.body.size_bytes()
+    }
+
+    /// The size with witness data discounted, as in Bitcoin's BIP 141: the
+    /// seal and the inputs' signatures and unlocking scripts count once,
+    /// every other byte four times.
+    pub fn weight(&self) -> usize {
+        let witness: usize = self.header.seal.len()
+            + self
+                .body
+                .utxo_transactions
+                .iter()
+                .map(UtxoTransaction::witness_bytes)
+                .sum::<usize>();
+        self.size_bytes().saturating_mul(4).saturating_sub(3 * witness)
+    }
+}
+
+/// Merkle leaf of the account transaction with `id`.
+pub(crate) fn transaction_leaf(id: &str) -> Hash {
+    merkle::sha256(id.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mining_reports_progress() {
+        let body = BlockBody {
+            data: "Progress".to_owned(),
+            ..BlockBody::default()
+        };
+        let mut block = Block::unmined(1, 0, "PreviousHash".to_owned(), body);
+        let mut reports = Vec::new();
+        block.mine_block_with_progress(Target::from_leading_zeros(4).scaled(1, 4), &mut |attempts| reports.push(attempts));
+
+        let expected: Vec<u64> = (1..=block.header.nonce / PROGRESS_INTERVAL)
+            .map(|i| i * PROGRESS_INTERVAL)
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(reports, expected);
+    }
+
+    #[test]
+    fn test_mining_moves_to_the_next_extra_nonce() {
+        let mut block = Block::unmined(1, 0, "PreviousHash".to_owned(), BlockBody::default());
+        block.header.nonce = u64::MAX - 1;
+        block.mine_block(Target::from_leading_zeros(3));
+
+        assert_eq!(block.header.extra_nonce, 1);
+        assert!(block.header.nonce < u64::MAX - 1);
+        assert_eq!(block.header.hash, block.calculate_hash());
+        assert!(block.header.meets_target());
+    }
+
+    #[test]
+    fn test_meets_target_compares_numerically() {
+        let mut header = Block::unmined(1, 0, "PreviousHash".to_owned(), BlockBody::default()).header;
+        header.bits = Target::from_leading_zeros(2).scaled(3, 2).to_compact();
+
+        header.hash = format!("017f{}", "f".repeat(60));
+        assert!(header.meets_target());
+        header.hash = format!("0181{}", "0".repeat(60));
+        assert!(!header.meets_target());
+        header.hash = "00".to_owned();
+        assert!(!header.meets_target());
+        header.hash = "z".repeat(64);
+        assert!(!header.meets_target());
+    }
+
+    #[cfg(feature = "system-clock")]
+    #[test]
+    fn test_header_commits_to_body() {
+        let mut block = Block::new(1, "Test Data".to_owned(), "PreviousHash".to_owned());
+        assert_eq!(block.header.merkle_root, block.body.merkle_root());
+        assert!(block.header.meets_target());
+
+        block.body.data = "Tampered Data".to_owned();
+        assert_ne!(block.header.merkle_root, block.body.merkle_root());
+        assert_eq!(block.header.hash, block.calculate_hash());
+    }
+
+    #[test]
+    fn test_merkle_root_commits_to_unlocking_scripts() {
+        use crate::script::Script;
+        use crate::utxo::OutPoint;
+
+        let outpoint = OutPoint {
+            txid: "0".repeat(HASH_HEX_LEN),
+            vout: 0,
+        };
+        let mut body = BlockBody {
+            utxo_transactions: vec![UtxoTransaction::new(vec![outpoint], Vec::new())],
+            ..BlockBody::default()
+        };
+        body.utxo_transactions[0].inputs[0].unlock = Script::unlock(&[vec![1; 64]]);
+        let root = body.merkle_root();
+
+        body.utxo_transactions[0].inputs[0].unlock = Script::unlock(&[vec![1; 64], vec![0; 4000]]);
+        assert_ne!(body.merkle_root(), root);
+        body.utxo_transactions[0].inputs[0].unlock = Script::unlock(&[vec![2; 64]]);
+        assert_ne!(body.merkle_root(), root);
+    }
+
+    #[test]
+    fn test_finished_size_and_weight() {
+        let prev_hash = "0".repeat(HASH_HEX_LEN);
+        let mut block = Block::unmined(1, 0, prev_hash.clone(), BlockBody::default());
+        block.header.state_root = prev_hash;
+        block.mine_block(Target::MAX);
+        assert_eq!(block.header.size_bytes(), BlockHeader::finished_size(false));
+        assert_eq!(block.weight(), 4 * block.size_bytes());
+
+        block.seal_block(Target::MAX, &SigningKey::from_bytes(&[1; 32]));
+        assert_eq!(block.header.size_bytes(), BlockHeader::finished_size(true));
+        assert_eq!(block.weight(), 4 * block.size_bytes() - 3 * SIGNATURE_LENGTH);
+    }
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+/// Known-good block hashes compiled into the node, as (height, hash) pairs.
+pub const DEFAULT_CHECKPOINTS: &[(u32, &str)] = &[(
+    0,
+    "000030449c08ce60ba1f43f3176cd46922016be71353abd4568b49f5a1816a19",
+)];
+
+/// A block hash the chain must contain at the given height. Blocks at or
+/// below the highest checkpoint can never be rolled back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub height: u32,
+    pub hash: String,
+}
+
+impl Checkpoint {
+    pub fn new(height: u32, hash: String) -> Self {
+        Checkpoint { height, hash }
+    }
+
+    pub fn defaults() -> Vec<Checkpoint> {
+        DEFAULT_CHECKPOINTS
+            .iter()
+            .map(|&(height, hash)| Checkpoint::new(height, hash.to_owned()))
+            .collect()
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+#[cfg(feature = "system-clock")]
+use chrono::Utc;
+
+/// Source of block timestamps, in seconds since the Unix epoch.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// Wall-clock time.
+#[cfg(feature = "system-clock")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "system-clock")]
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp()
+    }
+}
+
+/// A clock that only moves when told to, so tests produce the same blocks
+/// on every run.
+#[derive(Debug, Default)]
+pub struct ManualClock(AtomicI64);
+
+impl ManualClock {
+    pub fn new(now: i64) -> Self {
+        ManualClock(AtomicI64::new(now))
+    }
+
+    pub fn set(&self, now: i64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        self.0.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_moves_only_when_told() {
+        let clock = ManualClock::new(100);
+        assert_eq!(clock.now(), 100);
+        clock.advance(30);
+        assert_eq!(clock.now(), 130);
+        clock.set(5);
+        assert_eq!(clock.now(), 5);
+    }
+
+    #[cfg(feature = "system-clock")]
+    #[test]
+    fn test_system_clock_reads_wall_time() {
+        assert!(SystemClock.now() > 1_600_000_000);
+    }
+}
@@ -0,0 +1,377 @@
+use std::io::{self, Read, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::merkle::Hash;
+use crate::script::Script;
+use crate::utxo::{OutPoint, TxInput, TxOutput, UtxoTransaction};
+use crate::{Block, BlockBody, BlockHeader, Network, Transaction};
+
+/// Longest string, byte field or list the decoder will allocate for, so a
+/// corrupt length cannot exhaust memory.
+const MAX_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Writes `block` in the binary format: little-endian integers, and
+/// strings, byte fields and lists prefixed with their `u32` length.
+pub fn encode_block<W: Write>(writer: &mut W, block: &Block) -> io::Result<()> {
+    encode_header(writer, &block.header)?;
+    encode_body(writer, &block.body)
+}
+
+/// Writes a block header in the format `encode_block` uses.
+pub fn encode_header<W: Write>(writer: &mut W, header: &BlockHeader) -> io::Result<()> {
+    writer.write_all(&header.index.to_le_bytes())?;
+    writer.write_all(&header.timestamp.to_le_bytes())?;
+    write_str(writer, &header.prev_hash)?;
+    write_str(writer, &header.merkle_root)?;
+    write_str(writer, &header.state_root)?;
+    writer.write_all(&header.nonce.to_le_bytes())?;
+    writer.write_all(&header.extra_nonce.to_le_bytes())?;
+    writer.write_all(&header.bits.to_le_bytes())?;
+    writer.write_all(&header.network.id().to_le_bytes())?;
+    write_str(writer, &header.hash)?;
+    write_bytes(writer, &header.seal)
+}
+
+/// Writes a block body in the format `encode_block` uses.
+pub fn encode_body<W: Write>(writer: &mut W, body: &BlockBody) -> io::Result<()> {
+    write_str(writer, &body.data)?;
+    write_len(writer, body.transactions.len())?;
+    for transaction in &body.transactions {
+        encode_transaction(writer, transaction)?;
+    }
+    write_len(writer, body.utxo_transactions.len())?;
+    for transaction in &body.utxo_transactions {
+        encode_utxo_transaction(writer, transaction)?;
+    }
+    Ok(())
+}
+
+/// Writes an account transaction in the format `encode_block` uses.
+pub fn encode_transaction<W: Write>(writer: &mut W, transaction: &Transaction) -> io::Result<()> {
+    encode_unsigned_transaction(writer, transaction)?;
+    write_str(writer, &transaction.signature)
+}
+
+/// Writes what the sender of an account transaction signs: every field
+/// but the signature.
+pub fn encode_unsigned_transaction<W: Write>(writer: &mut W, transaction: &Transaction) -> io::Result<()> {
+    write_str(writer, &transaction.sender)?;
+    write_str(writer, &transaction.recipient)?;
+    writer.write_all(&transaction.amount.to_le_bytes())?;
+    writer.write_all(&transaction.fee.to_le_bytes())?;
+    writer.write_all(&transaction.nonce.to_le_bytes())?;
+    writer.write_all(&transaction.lock_until.to_le_bytes())?;
+    write_bytes(writer, &transaction.payload)
+}
+
+/// Writes a UTXO transaction in the format `encode_block` uses.
+pub fn encode_utxo_transaction<W: Write>(writer: &mut W, transaction: &UtxoTransaction) -> io::Result<()> {
+    write_len(writer, transaction.inputs.len())?;
+    for input in &transaction.inputs {
+        write_outpoint(writer, &input.outpoint)?;
+        write_str(writer, &input.signature)?;
+        write_bytes(writer, &input.unlock.to_bytes())?;
+    }
+    write_len(writer, transaction.outputs.len())?;
+    for output in &transaction.outputs {
+        write_output(writer, output)?;
+    }
+    Ok(())
+}
+
+/// Writes what a UTXO transaction's id commits to and its inputs sign:
+/// the spent outpoints and the outputs, without signatures or unlocking
+/// scripts.
+pub fn encode_unsigned_utxo_transaction<W: Write>(writer: &mut W, transaction: &UtxoTransaction) -> io::Result<()> {
+    write_len(writer, transaction.inputs.len())?;
+    for input in &transaction.inputs {
+        write_outpoint(writer, &input.outpoint)?;
+    }
+    write_len(writer, transaction.outputs.len())?;
+    for output in &transaction.outputs {
+        write_output(writer, output)?;
+    }
+    Ok(())
+}
+
+/// Writes what a header's hash commits to but the nonce, which mining
+/// appends last: every field but the hash and seal.
+pub(crate) fn encode_header_without_nonce<W: Write>(writer: &mut W, header: &BlockHeader) -> io::Result<()> {
+    writer.write_all(&header.index.to_le_bytes())?;
+    writer.write_all(&header.timestamp.to_le_bytes())?;
+    write_str(writer, &header.prev_hash)?;
+    write_str(writer, &header.merkle_root)?;
+    write_str(writer, &header.state_root)?;
+    writer.write_all(&header.bits.to_le_bytes())?;
+    writer.write_all(&header.extra_nonce.to_le_bytes())?;
+    writer.write_all(&header.network.id().to_le_bytes())
+}
+
+/// Reads a block written by `encode_block`.
+pub fn decode_block<R: Read>(reader: &mut R) -> io::Result<Block> {
+    let header = BlockHeader {
+        index: read_u32(reader)?,
+        timestamp: i64::from_le_bytes(read_array(reader)?),
+        prev_hash: read_str(reader)?,
+        merkle_root: read_str(reader)?,
+        state_root: read_str(reader)?,
+        nonce: read_u64(reader)?,
+        extra_nonce: read_u64(reader)?,
+        bits: read_u32(reader)?,
+        network: read_network(reader)?,
+        hash: read_str(reader)?,
+        seal: read_bytes(reader)?,
+    };
+
+    let data = read_str(reader)?;
+    let mut transactions = Vec::new();
+    for _ in 0..read_len(reader)? {
+        transactions.push(decode_transaction(reader)?);
+    }
+    let mut utxo_transactions = Vec::new();
+    for _ in 0..read_len(reader)? {
+        utxo_transactions.push(decode_utxo_transaction(reader)?);
+    }
+
+    Ok(Block {
+        header,
+        body: BlockBody {
+            data,
+            transactions,
+            utxo_transactions,
+        },
+    })
+}
+
+/// Reads an account transaction written by `encode_transaction`.
+pub fn decode_transaction<R: Read>(reader: &mut R) -> io::Result<Transaction> {
+    Ok(Transaction {
+        sender: read_str(reader)?,
+        recipient: read_str(reader)?,
+        amount: read_u64(reader)?,
+        fee: read_u64(reader)?,
+        nonce: read_u64(reader)?,
+        lock_until: read_u64(reader)?,
+        payload: read_bytes(reader)?,
+        signature: read_str(reader)?,
+    })
+}
+
+/// Reads a UTXO transaction written by `encode_utxo_transaction`.
+pub fn decode_utxo_transaction<R: Read>(reader: &mut R) -> io::Result<UtxoTransaction> {
+    let mut inputs = Vec::new();
+    for _ in 0..read_len(reader)? {
+        let outpoint = OutPoint {
+            txid: read_str(reader)?,
+            vout: read_u32(reader)?,
+        };
+        inputs.push(TxInput {
+            outpoint,
+            signature: read_str(reader)?,
+            unlock: read_script(reader)?,
+        });
+    }
+    let mut outputs = Vec::new();
+    for _ in 0..read_len(reader)? {
+        outputs.push(TxOutput {
+            value: read_u64(reader)?,
+            owner: read_str(reader)?,
+            lock: read_script(reader)?,
+        });
+    }
+    Ok(UtxoTransaction { inputs, outputs })
+}
+
+/// SHA-256 of what `encode` writes. Ids, roots and header hashes are all
+/// taken over this format, where every field is fixed width or length
+/// prefixed, so no two different values hash the same bytes. Only a field
+/// too long to encode stops it short, and no block can hold one.
+pub(crate) fn digest(encode: impl FnOnce(&mut Sha256) -> io::Result<()>) -> Hash {
+    let mut hasher = Sha256::new();
+    let _ = encode(&mut hasher);
+    hasher.finalize().into()
+}
+
+/// `digest`, hex-encoded.
+pub(crate) fn hash_hex(encode: impl FnOnce(&mut Sha256) -> io::Result<()>) -> String {
+    hex::encode(digest(encode))
+}
+
+/// Bytes `encode` writes. Sizes everywhere come from here, so they always
+/// match the encoding. Something too long to encode has no size to fit in
+/// any limit: `usize::MAX`.
+pub(crate) fn encoded_len(encode: impl FnOnce(&mut ByteCounter) -> io::Result<()>) -> usize {
+    let mut counter = ByteCounter(0);
+    match encode(&mut counter) {
+        Ok(()) => counter.0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// A writer that only counts what passes through it.
+pub(crate) struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn write_outpoint<W: Write>(writer: &mut W, outpoint: &OutPoint) -> io::Result<()> {
+    write_str(writer, &outpoint.txid)?;
+    writer.write_all(&outpoint.vout.to_le_bytes())
+}
+
+pub(crate) fn write_output<W: Write>(writer: &mut W, output: &TxOutput) -> io::Result<()> {
+    writer.write_all(&output.value.to_le_bytes())?;
+    write_str(writer, &output.owner)?;
+    write_bytes(writer, &output.lock.to_bytes())
+}
+
+pub(crate) fn write_len<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "field too long"))?;
+    writer.write_all(&len.to_le_bytes())
+}
+
+pub(crate) fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_len(writer, bytes.len())?;
+    writer.write_all(bytes)
+}
+
+pub(crate) fn write_str<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    write_bytes(writer, value.as_bytes())
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(reader)?))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+fn read_len<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let len = read_u32(reader)? as usize;
+    if len > MAX_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "length exceeds limit"));
+    }
+    Ok(len)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_len(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_str<R: Read>(reader: &mut R) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn read_network<R: Read>(reader: &mut R) -> io::Result<Network> {
+    Network::from_id(read_u32(reader)?).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown network id"))
+}
+
+fn read_script<R: Read>(reader: &mut R) -> io::Result<Script> {
+    Script::from_bytes(&read_bytes(reader)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Blockchain;
+    use crate::transaction::accounts::signed;
+
+    #[test]
+    fn test_blocks_round_trip() {
+        let mut blockchain = Blockchain::new();
+        let payment = Transaction::with_payload(String::new(), "bob".to_owned(), 5, 3, vec![1, 2, 3]);
+        assert!(blockchain.add_transaction(signed("alice", payment)));
+        blockchain.mine_pending_transactions("miner".to_owned());
+        assert!(blockchain.add_utxo_block(vec![UtxoTransaction::coinbase("miner".to_owned(), 50)]));
+
+        for block in &blockchain {
+            let mut encoded = Vec::new();
+            encode_block(&mut encoded, block).unwrap();
+            assert_eq!(decode_block(&mut encoded.as_slice()).unwrap(), *block);
+            assert_eq!(block.size_bytes(), encoded.len());
+
+            let truncated = &encoded[..encoded.len() - 1];
+            assert!(decode_block(&mut &truncated[..]).is_err());
+        }
+
+        let mut huge = vec![0; 4 + 8];
+        huge.extend(u32::MAX.to_le_bytes());
+        assert_eq!(
+            decode_block(&mut huge.as_slice()).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    /// Fields that used to be concatenated as text, where moving bytes from
+    /// one to the next kept the hash.
+    #[test]
+    fn test_hashes_separate_fields() {
+        let split = |sender: &str, recipient: &str| Transaction::new(sender.to_owned(), recipient.to_owned(), 12).id();
+        assert_ne!(split("ab", "c"), split("a", "bc"));
+        let amount = |recipient: &str, amount| Transaction::new("alice".to_owned(), recipient.to_owned(), amount).id();
+        assert_ne!(amount("bob1", 2), amount("bob", 12));
+
+        let output = |owner: &str, value| UtxoTransaction::new(Vec::new(), vec![TxOutput::new(value, owner.to_owned())]).id();
+        assert_ne!(output("a:1;b", 2), output("a", 1) + &output("b", 2));
+
+        let mut header = Block::unmined(1, 0, "ab".to_owned(), BlockBody::default()).header;
+        header.merkle_root = "c".to_owned();
+        let mut shifted = header.clone();
+        shifted.prev_hash = "a".to_owned();
+        shifted.merkle_root = "bc".to_owned();
+        assert_ne!(header.calculate_hash(), shifted.calculate_hash());
+    }
+
+    /// Hashes must not change between versions: blocks and signatures made
+    /// by one are checked by the next.
+    #[test]
+    fn test_hashes_match_reference_vectors() {
+        let transaction = Transaction {
+            lock_until: 7,
+            ..Transaction::with_payload("alice".to_owned(), "bob".to_owned(), 5, 3, vec![1, 2, 3])
+        };
+        assert_eq!(transaction.signing_hash(), "5d76fc7cc69c738a5487f0305ed2ee84df8bca5628ed79857eb16e81f6f24779");
+        assert_eq!(transaction.id(), "2241d1ff53a5a4429dbcbe74a614e725f94547a5962570f77ae2bda3953c065a");
+
+        let spend = UtxoTransaction::new(
+            vec![OutPoint {
+                txid: "00".repeat(32),
+                vout: 1,
+            }],
+            vec![TxOutput::new(50, "carol".to_owned())],
+        );
+        assert_eq!(spend.id(), "cb8c2ce3245c234e15593b2682bacd0292df65f700637fd26a0c20e98154dfbf");
+
+        let mut block = Block::unmined(1, 1_700_000_000, "11".repeat(32), BlockBody::default());
+        block.header.merkle_root = block.body.merkle_root();
+        block.header.state_root = "22".repeat(32);
+        block.header.bits = 0x1f00ffff;
+        block.header.nonce = 42;
+        assert_eq!(
+            block.calculate_hash(),
+            "040b97ce047cc4e01d9d59516632c8ad8c2289b05d2618cc69ac333fd8bbeada"
+        );
+    }
+}
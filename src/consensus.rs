@@ -0,0 +1,338 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use tracing::warn;
+
+use crate::nonces::AccountNonces;
+use crate::utxo::{BlockUndo, UtxoSet};
+use crate::{BLOCK_REWARD, Block, BlockHeader, ChainParams, Checkpoint, DIFFICULTY, Network, Target, genesis_utxo, metrics, state_root};
+
+/// How many of the latest blocks the median time past is taken over.
+pub(crate) const MEDIAN_TIME_BLOCKS: usize = 11;
+
+/// How far ahead of the clock a block may be stamped, in seconds.
+pub const MAX_FUTURE_DRIFT: i64 = 2 * 60 * 60;
+
+/// The median of the last `MEDIAN_TIME_BLOCKS` of `timestamps`, oldest
+/// first. The next block must be stamped after it, and time-locks are
+/// measured against it rather than a timestamp its miner picked.
+pub(crate) fn median_time_past(timestamps: &[i64]) -> i64 {
+    let mut latest = timestamps[timestamps.len().saturating_sub(MEDIAN_TIME_BLOCKS)..].to_vec();
+    latest.sort_unstable();
+    latest.get(latest.len() / 2).copied().unwrap_or(i64::MIN)
+}
+
+/// Checks that `blocks` form a valid chain under `params`. `blocks[0]` is
+/// trusted, the UTXO set starts out as the premine of `params` leaves it,
+/// and account nonces start out empty. Nothing but the arguments is
+/// consulted, so untrusted input can be checked without a `Blockchain`.
+pub fn validate(blocks: &[Block], params: &ChainParams) -> bool {
+    let checkpoints = default_checkpoints(params);
+    let rules = Rules {
+        params,
+        checkpoints: &checkpoints,
+    };
+    rules
+        .validate_blocks(blocks, &[], genesis_utxo(params), AccountNonces::default())
+        .is_some()
+}
+
+/// The compiled-in checkpoints, which only pin the main network's chain.
+pub(crate) fn default_checkpoints(params: &ChainParams) -> Vec<Checkpoint> {
+    if params.network == Network::Mainnet
+        && params.target == Target::from_leading_zeros(DIFFICULTY)
+        && params.retarget.is_none()
+        && params.nonce_seed == 0
+        && params.premine.is_empty()
+    {
+        Checkpoint::defaults()
+    } else {
+        Vec::new()
+    }
+}
+
+/// The consensus rules blocks are checked against.
+pub(crate) struct Rules<'a> {
+    pub(crate) params: &'a ChainParams,
+    pub(crate) checkpoints: &'a [Checkpoint],
+}
+
+impl Rules<'_> {
+    /// The target a block stamped `timestamp` on top of `parent` must meet.
+    pub(crate) fn next_target(&self, parent: &BlockHeader, timestamp: i64) -> Target {
+        match self.params.retarget {
+            Some(retarget) => retarget.next(parent.target(), timestamp - parent.timestamp, self.params.target),
+            None => self.params.target,
+        }
+    }
+
+    pub(crate) fn matches_checkpoints(&self, header: &BlockHeader) -> bool {
+        self.checkpoints
+            .iter()
+            .filter(|checkpoint| checkpoint.height == header.index)
+            .all(|checkpoint| checkpoint.hash == header.hash)
+    }
+
+    /// Replays `blocks` on top of `utxo` and `nonces`, returning the
+    /// resulting state and the undo data for each block if all of them are
+    /// valid. `earlier` holds the timestamps of the blocks before
+    /// `blocks[0]`, oldest first, as far back as the median time past
+    /// needs. What each block can be checked for on its own, hashes and
+    /// seals included, is checked across threads first when built with
+    /// `parallel`; only the links and state changes are replayed in order.
+    pub(crate) fn validate_blocks(
+        &self,
+        blocks: &[Block],
+        earlier: &[i64],
+        mut utxo: UtxoSet,
+        mut nonces: AccountNonces,
+    ) -> Option<(UtxoSet, AccountNonces, Vec<BlockUndo>)> {
+        let first = blocks.first()?;
+        if !self.matches_checkpoints(&first.header) {
+            warn!(index = first.header.index, "validation failed: checkpoint mismatch");
+            return None;
+        }
+
+        #[cfg(feature = "parallel")]
+        let contents_valid = blocks[1..].par_iter().all(|block| self.check_contents(block));
+        #[cfg(not(feature = "parallel"))]
+        let contents_valid = blocks[1..].iter().all(|block| self.check_contents(block));
+        if !contents_valid {
+            metrics::registry().validation_failures.inc();
+            return None;
+        }
+
+        let mut undo = vec![BlockUndo::new()];
+        let mut timestamps = earlier.to_vec();
+        timestamps.push(first.header.timestamp);
+        for pair in blocks.windows(2) {
+            let median = median_time_past(&timestamps);
+            let Some(block_undo) = self.check_state(&pair[1], &pair[0], median, &mut utxo, &mut nonces) else {
+                metrics::registry().validation_failures.inc();
+                return None;
+            };
+            undo.push(block_undo);
+            timestamps.push(pair[1].header.timestamp);
+        }
+        Some((utxo, nonces, undo))
+    }
+
+    /// Checks `current` as the successor of `previous`, with `median` the
+    /// median time past as of `previous`, and applies its transactions to
+    /// `utxo` and `nonces`, which are left untouched on failure.
+    pub(crate) fn validate_block(
+        &self,
+        current: &Block,
+        previous: &Block,
+        median: i64,
+        utxo: &mut UtxoSet,
+        nonces: &mut AccountNonces,
+    ) -> Option<BlockUndo> {
+        let undo = if self.check_contents(current) {
+            self.check_state(current, previous, median, utxo, nonces)
+        } else {
+            None
+        };
+        if undo.is_none() {
+            metrics::registry().validation_failures.inc();
+        }
+        undo
+    }
+
+    /// Checks what needs no other block: the header on its own, that it
+    /// commits to the body, the limits, the reward and the fees.
+    fn check_contents(&self, block: &Block) -> bool {
+        if !self.check_header_alone(&block.header) {
+            return false;
+        }
+
+        if block.header.merkle_root != block.body.merkle_root() {
+            warn!(index = block.header.index, "validation failed: body does not match header");
+            return false;
+        }
+
+        if !self.params.limits.allows(block) {
+            warn!(index = block.header.index, "validation failed: block exceeds block limits");
+            return false;
+        }
+
+        if !has_valid_reward(block) {
+            warn!(index = block.header.index, "validation failed: invalid miner reward");
+            return false;
+        }
+
+        if block.body.transactions.iter().any(|tx| tx.fee < tx.minimum_fee()) {
+            warn!(index = block.header.index, "validation failed: fee does not cover payload");
+            return false;
+        }
+
+        if block.body.transactions.iter().any(|tx| !tx.is_reward() && !tx.is_signed_by_sender()) {
+            warn!(index = block.header.index, "validation failed: transaction not signed by its sender");
+            return false;
+        }
+        true
+    }
+
+    /// Checks that `current` links to `previous` and that its time-locked
+    /// transactions are mature as of `median`, the median time past, and
+    /// applies its transactions, in order, to `utxo` and `nonces`, which
+    /// must then match the state root it commits to.
+    fn check_state(
+        &self,
+        current: &Block,
+        previous: &Block,
+        median: i64,
+        utxo: &mut UtxoSet,
+        nonces: &mut AccountNonces,
+    ) -> Option<BlockUndo> {
+        if !self.check_header_links(&current.header, &previous.header, median) {
+            return None;
+        }
+
+        let index = current.header.index;
+        if current.body.transactions.iter().any(|tx| !tx.is_reward() && !tx.is_mature(index, median)) {
+            warn!(index, "validation failed: transaction is time-locked");
+            return None;
+        }
+
+        if let Err(err) = nonces.apply_block(&current.body.transactions) {
+            warn!(index = current.header.index, error = %err, "validation failed: transaction out of sequence");
+            return None;
+        }
+
+        let undo = match utxo.apply_block(&current.body.utxo_transactions, current.header.index, self.params.network) {
+            Ok(undo) => undo,
+            Err(err) => {
+                nonces.rollback_block(&current.body.transactions);
+                warn!(index = current.header.index, error = %err, "validation failed: invalid UTXO transaction");
+                return None;
+            }
+        };
+
+        if state_root(utxo, nonces) != current.header.state_root {
+            utxo.rollback_block(&current.body.utxo_transactions, undo);
+            nonces.rollback_block(&current.body.transactions);
+            warn!(index = current.header.index, "validation failed: state root mismatch");
+            return None;
+        }
+        Some(undo)
+    }
+
+    /// Checks what can be checked of a block whose parent is unknown, so
+    /// junk stays out of the orphan pool.
+    pub(crate) fn check_detached(&self, block: &Block) -> bool {
+        self.check_contents(block)
+    }
+
+    /// Checks `current` as the successor of `previous` from the headers
+    /// alone: network, checkpoints, hash, proof of work or seal, the link
+    /// between them and the timestamp, given `median`, the median time past
+    /// as of `previous`.
+    pub(crate) fn check_header(&self, current: &BlockHeader, previous: &BlockHeader, median: i64) -> bool {
+        self.check_header_alone(current) && self.check_header_links(current, previous, median)
+    }
+
+    /// Checks each header of `headers` after the first as the successor of
+    /// the one before it. The first is trusted. Unless they start from the
+    /// genesis block, the first few lack the headers their median time past
+    /// is taken over and are not checked against it.
+    pub(crate) fn check_headers<'a>(&self, headers: impl IntoIterator<Item = &'a BlockHeader>) -> bool {
+        let headers: Vec<&BlockHeader> = headers.into_iter().collect();
+        let timestamps: Vec<i64> = headers.iter().map(|header| header.timestamp).collect();
+        let from_genesis = headers.first().is_some_and(|header| header.index == 0);
+        (1..headers.len()).all(|i| {
+            let median = if from_genesis || i >= MEDIAN_TIME_BLOCKS {
+                median_time_past(&timestamps[..i])
+            } else {
+                i64::MIN
+            };
+            self.check_header(headers[i], headers[i - 1], median)
+        })
+    }
+
+    /// The link to `previous`, the target that follows from it and a
+    /// timestamp after `median` but not too far ahead of the clock.
+    fn check_header_links(&self, current: &BlockHeader, previous: &BlockHeader, median: i64) -> bool {
+        if !current.links_to(previous) || !self.has_next_target(current, previous) {
+            return false;
+        }
+        if current.timestamp <= median {
+            warn!(index = current.index, "validation failed: timestamp is not after the median time past");
+            return false;
+        }
+        if current.timestamp > self.params.clock.now().saturating_add(MAX_FUTURE_DRIFT) {
+            warn!(index = current.index, "validation failed: timestamp is too far in the future");
+            return false;
+        }
+        true
+    }
+
+    fn has_next_target(&self, current: &BlockHeader, previous: &BlockHeader) -> bool {
+        let expected = self.next_target(previous, current.timestamp).to_compact() == current.bits;
+        if !expected {
+            warn!(index = current.index, "validation failed: unexpected target");
+        }
+        expected
+    }
+
+    /// Checks a header's network, checkpoints, hash and proof of work or
+    /// seal.
+    fn check_header_alone(&self, header: &BlockHeader) -> bool {
+        if header.network != self.params.network {
+            warn!(index = header.index, network = %header.network, "validation failed: block is from another network");
+            return false;
+        }
+        if !self.matches_checkpoints(header) {
+            warn!(index = header.index, "validation failed: checkpoint mismatch");
+            return false;
+        }
+        if header.hash != header.calculate_hash() {
+            warn!(index = header.index, "validation failed: block hash mismatch");
+            return false;
+        }
+        self.check_proof(header)
+    }
+
+    /// In authority mode, checks that the scheduled validator sealed the
+    /// header; otherwise, that it meets the target and carries no seal.
+    /// Under `retarget`, only that the target is no easier than the
+    /// chain's: which one it must be depends on the parent.
+    fn check_proof(&self, header: &BlockHeader) -> bool {
+        let allowed = match self.params.retarget {
+            Some(_) => header.target() <= self.params.target,
+            None => header.bits == self.params.target.to_compact(),
+        };
+        if !allowed {
+            warn!(index = header.index, "validation failed: unexpected target");
+            return false;
+        }
+        match &self.params.authority {
+            Some(authority) if !authority.verify(header) => {
+                warn!(index = header.index, "validation failed: not sealed by the scheduled validator");
+                false
+            }
+            Some(_) => true,
+            None if !header.meets_target() || !header.seal.is_empty() => {
+                warn!(index = header.index, "validation failed: insufficient proof of work");
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+/// A block may open with a single reward transaction paying at most the
+/// block reward plus the fees of the transactions it includes. Fees that
+/// overflow a `u64` make the block invalid.
+pub(crate) fn has_valid_reward(block: &Block) -> bool {
+    let rewards = block.body.transactions.iter().filter(|tx| tx.is_reward()).count();
+    match block.body.transactions.first() {
+        Some(reward) if reward.is_reward() => {
+            let fees = block.body.transactions[1..].iter().try_fold(0u64, |total, tx| total.checked_add(tx.fee));
+            match fees.and_then(|fees| fees.checked_add(BLOCK_REWARD)) {
+                Some(limit) => rewards == 1 && reward.amount <= limit,
+                None => false,
+            }
+        }
+        _ => rewards == 0,
+    }
+}
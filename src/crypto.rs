@@ -0,0 +1,56 @@
+use std::io;
+
+#[cfg(feature = "wallet")]
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+#[cfg(feature = "wallet")]
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// ChaCha20-Poly1305 as RFC 8439 defines it: encrypts `plaintext` and
+/// returns the ciphertext followed by a tag over it and `aad`. A `nonce`
+/// must never be used twice with the same key.
+#[cfg(feature = "wallet")]
+pub(crate) fn seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: plaintext, aad })
+        .expect("plaintext is within ChaCha20's limit")
+}
+
+/// Reverses `seal`, or returns `None` if the tag does not match.
+#[cfg(feature = "wallet")]
+pub(crate) fn open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+        .ok()
+}
+
+/// Bytes from the operating system's random number generator.
+pub(crate) fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).map_err(io::Error::from)?;
+    Ok(bytes)
+}
+
+#[cfg(all(test, feature = "wallet"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chacha20_poly1305_matches_rfc_8439() {
+        // Section 2.8.2.
+        let key: [u8; 32] = std::array::from_fn(|i| 0x80 + i as u8);
+        let nonce = [7, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47];
+        let aad = hex::decode("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, \
+            sunscreen would be it.";
+        let sealed = seal(&key, &nonce, &aad, plaintext);
+        assert_eq!(hex::encode(&sealed[..16]), "d31a8d34648e60db7b86afbc53ef7ec2");
+        assert_eq!(hex::encode(&sealed[plaintext.len()..]), "1ae10b594f09e26a7e902ecbd0600691");
+        assert_eq!(open(&key, &nonce, &aad, &sealed).unwrap(), plaintext);
+
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert_eq!(open(&key, &nonce, &aad, &tampered), None);
+        assert_eq!(open(&key, &nonce, b"other", &sealed), None);
+        assert_eq!(open(&key, &nonce, &aad, &sealed[..15]), None);
+    }
+}
@@ -0,0 +1,622 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use crate::address::Address;
+use crate::faucet::Faucet;
+use crate::network::{Envelope, spawn_tcp_peer};
+use crate::transport::{NodeIdentity, Role};
+use crate::utxo::TxOutput;
+use crate::pool::{self, Coordinator, PoolServer};
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::rpc::{self, RpcPolicy};
+use crate::storage::{
+    load_address_book, load_ban_list, load_chain, load_or_create_identity, network_dir, save_address_book, save_ban_list,
+    save_chain,
+};
+use crate::{
+    Authority, BAN_THRESHOLD, Blockchain, ChainParams, DEFAULT_BAN_DURATION, MempoolPolicy, Network, Node, Retarget, Target,
+    metrics,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Inbound handshakes that may run at once; connections beyond it are
+/// dropped until one finishes.
+const MAX_PENDING_HANDSHAKES: usize = 32;
+
+/// Settings for a long-lived node, read from a TOML file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct NodeConfig {
+    /// Network to join. Each keeps its state in its own directory under
+    /// `data_dir`.
+    #[serde(default)]
+    pub network: Network,
+    pub data_dir: PathBuf,
+    #[serde(default)]
+    pub listen: Vec<String>,
+    /// Seed peers. Others are discovered from them and kept in the
+    /// address book.
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Outbound connections to keep open, dialling from the address book.
+    #[serde(default = "default_target_outbound")]
+    pub target_outbound: usize,
+    /// How long misbehaving peers stay banned.
+    #[serde(default = "default_ban_duration_secs")]
+    pub ban_duration_secs: i64,
+    pub miner_address: Option<String>,
+    /// Extra nonce to mine from. Give each miner run by one operator its
+    /// own value so they never repeat each other's work.
+    #[serde(default)]
+    pub extra_nonce: u64,
+    /// Mine a block whenever transactions are pending. Needs `miner_address`.
+    #[serde(default)]
+    pub auto_mine: bool,
+    /// Address to serve Prometheus metrics on, at `/metrics`.
+    pub metrics_address: Option<String>,
+    /// Address to serve the HTTP RPC API on.
+    pub rpc_address: Option<String>,
+    /// Address to serve the gRPC API on. It shares the HTTP API's policy.
+    #[cfg(feature = "grpc")]
+    pub grpc_address: Option<String>,
+    /// Refuse RPC requests that change the chain, for an endpoint open to
+    /// the public.
+    #[serde(default)]
+    pub rpc_read_only: bool,
+    /// Keys that RPC clients must send to submit transactions or use the
+    /// faucet. Empty lets anyone.
+    #[serde(default)]
+    pub rpc_api_keys: Vec<String>,
+    /// RPC requests a minute each client address may make without a key.
+    /// Zero for no limit.
+    #[serde(default)]
+    pub rpc_requests_per_minute: u32,
+    /// RPC requests a minute each API key may make. Zero for no limit.
+    #[serde(default)]
+    pub rpc_key_requests_per_minute: u32,
+    /// Address to coordinate pool workers on. Needs `miner_address`, which
+    /// the blocks they find pay.
+    pub pool_address: Option<String>,
+    /// Leading zero hex digits a pool worker's share needs.
+    #[serde(default = "default_pool_share_difficulty")]
+    pub pool_share_difficulty: u32,
+    /// Total bytes of pending transactions to hold before evicting the
+    /// lowest fee rates.
+    #[serde(default = "default_mempool_max_bytes")]
+    pub mempool_max_bytes: usize,
+    /// How long a transaction may stay pending before it is dropped.
+    #[serde(default = "default_mempool_ttl_secs")]
+    pub mempool_ttl_secs: i64,
+    /// Seconds a block should take. When set, the target adjusts from
+    /// block to block toward it; every node on the network must agree.
+    /// Unset keeps the target fixed.
+    pub block_time_secs: Option<i64>,
+    /// Prune the chain, keeping the bodies of only this many of the latest
+    /// blocks. Unset keeps every block.
+    pub prune_keep_bodies: Option<usize>,
+    /// Hex Ed25519 public keys of validators that take turns sealing blocks
+    /// in authority mode. Empty runs proof of work.
+    #[serde(default)]
+    pub validators: Vec<String>,
+    /// Hex Ed25519 secret keys of the validators this node seals for.
+    #[serde(default)]
+    pub validator_keys: Vec<String>,
+    /// Balances the genesis block creates. Every node on the network must
+    /// list the same ones.
+    #[serde(default)]
+    pub premine: Vec<Allocation>,
+    /// Development mode: the genesis block also funds the dev faucet, which
+    /// pays out test funds on `POST /faucet` at `rpc_address`.
+    #[serde(default)]
+    pub dev: bool,
+}
+
+/// A premined balance.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Allocation {
+    pub address: String,
+    pub amount: u64,
+}
+
+fn default_pool_share_difficulty() -> u32 {
+    2
+}
+
+fn default_mempool_max_bytes() -> usize {
+    MempoolPolicy::default().max_bytes
+}
+
+fn default_mempool_ttl_secs() -> i64 {
+    MempoolPolicy::default().ttl_seconds
+}
+
+fn default_target_outbound() -> usize {
+    8
+}
+
+fn default_ban_duration_secs() -> i64 {
+    DEFAULT_BAN_DURATION
+}
+
+impl NodeConfig {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let config: NodeConfig = toml::from_str(contents).map_err(ConfigError::Parse)?;
+        if (config.auto_mine || config.pool_address.is_some()) && config.miner_address.is_none() {
+            return Err(ConfigError::MissingMinerAddress);
+        }
+        config.authority()?;
+        for allocation in &config.premine {
+            match allocation.address.parse::<Address>() {
+                Ok(address) if address.network() == config.network => {}
+                _ => return Err(ConfigError::InvalidPremineAddress(allocation.address.clone())),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The outputs the genesis block creates: the allocations, then the dev
+    /// faucet's supply in dev mode.
+    pub fn premine(&self) -> Vec<TxOutput> {
+        let mut outputs: Vec<TxOutput> = self
+            .premine
+            .iter()
+            .map(|allocation| TxOutput::new(allocation.amount, allocation.address.clone()))
+            .collect();
+        if self.dev {
+            outputs.push(Faucet::dev(self.network).premine());
+        }
+        outputs
+    }
+
+    /// The authority mode `validators` and `validator_keys` describe, or
+    /// `None` for proof of work.
+    pub fn authority(&self) -> Result<Option<Authority>, ConfigError> {
+        let decode = |key: &str| {
+            let mut bytes = [0; 32];
+            hex::decode_to_slice(key, &mut bytes).map_err(|_| ConfigError::InvalidValidatorKey)?;
+            Ok(bytes)
+        };
+        let mut validators = Vec::new();
+        for key in &self.validators {
+            let validator = VerifyingKey::from_bytes(&decode(key)?).map_err(|_| ConfigError::InvalidValidatorKey)?;
+            validators.push(validator);
+        }
+        let Some(mut authority) = Authority::new(validators) else {
+            return if self.validator_keys.is_empty() { Ok(None) } else { Err(ConfigError::InvalidValidatorKey) };
+        };
+        for key in &self.validator_keys {
+            if !authority.add_key(SigningKey::from_bytes(&decode(key)?)) {
+                return Err(ConfigError::InvalidValidatorKey);
+            }
+        }
+        Ok(Some(authority))
+    }
+
+    /// Where the chain and peer state of the configured network are kept.
+    pub fn network_dir(&self) -> PathBuf {
+        network_dir(&self.data_dir, self.network)
+    }
+
+    pub fn rpc_policy(&self) -> RpcPolicy {
+        RpcPolicy {
+            read_only: self.rpc_read_only,
+            api_keys: self.rpc_api_keys.clone(),
+            requests_per_minute: self.rpc_requests_per_minute,
+            key_requests_per_minute: self.rpc_key_requests_per_minute,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    MissingMinerAddress,
+    /// A validator key that is malformed or, for a secret key, not one of
+    /// the validators'. The key is left out so secrets are not logged.
+    InvalidValidatorKey,
+    /// A premine address that does not parse or is for another network.
+    InvalidPremineAddress(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "cannot read config: {}", err),
+            ConfigError::Parse(err) => write!(f, "invalid config: {}", err),
+            ConfigError::MissingMinerAddress => write!(f, "auto_mine and pool_address require a miner_address"),
+            ConfigError::InvalidValidatorKey => write!(f, "validator keys must be hex Ed25519 keys, and secret keys a listed validator's"),
+            ConfigError::InvalidPremineAddress(address) => write!(f, "premine address {:?} is not an address on this network", address),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Runs a node until `shutdown` is set, then saves the chain to the
+/// network's data directory and returns. The node is known to its peers by
+/// the identity key kept there, created on first run.
+pub fn run(config: &NodeConfig, shutdown: Arc<AtomicBool>) -> io::Result<()> {
+    let dir = config.network_dir();
+    fs::create_dir_all(&dir)?;
+    let params = ChainParams {
+        network: config.network,
+        extra_nonce: config.extra_nonce,
+        mempool: MempoolPolicy {
+            max_bytes: config.mempool_max_bytes,
+            ttl_seconds: config.mempool_ttl_secs,
+        },
+        retarget: config.block_time_secs.map(|block_time| Retarget { block_time }),
+        keep_bodies: config.prune_keep_bodies,
+        authority: config.authority().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?,
+        premine: config.premine(),
+        ..ChainParams::default()
+    };
+    let blockchain = match load_chain(&dir, params.clone())? {
+        Some(blockchain) => blockchain,
+        None => Blockchain::with_params(params),
+    };
+    let faucet = config.dev.then(|| Faucet::dev(config.network));
+    if let Some(faucet) = &faucet {
+        info!(address = %faucet.address(), "dev mode: faucet funded");
+    }
+    let identity = load_or_create_identity(&dir)?;
+    let mut node = Node::with_blockchain(identity.id(), blockchain);
+    info!(
+        node = %identity.id(),
+        network = %config.network,
+        height = node.blockchain().latest_block().header.index,
+        "node started"
+    );
+
+    if let Some(address) = &config.metrics_address {
+        let listener = TcpListener::bind(address)?;
+        info!(%address, "serving metrics");
+        thread::spawn(move || metrics::serve(listener));
+    }
+
+    let (rpc_calls, rpc_requests) = mpsc::channel();
+    if let Some(address) = &config.rpc_address {
+        let listener = TcpListener::bind(address)?;
+        info!(%address, "serving rpc");
+        let (calls, policy) = (rpc_calls.clone(), config.rpc_policy());
+        thread::spawn(move || rpc::serve(listener, calls, policy));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(address) = &config.grpc_address {
+        let listener = TcpListener::bind(address)?;
+        info!(%address, "serving grpc");
+        let (calls, policy) = (rpc_calls.clone(), config.rpc_policy());
+        thread::spawn(move || {
+            if let Err(err) = grpc::serve(listener, calls, policy) {
+                warn!(error = %err, "grpc server failed");
+            }
+        });
+    }
+
+    let (pool_events, worker_events) = mpsc::channel();
+    let mut pool = None;
+    if let (Some(address), Some(miner)) = (&config.pool_address, &config.miner_address) {
+        let listener = TcpListener::bind(address)?;
+        info!(%address, "coordinating pool workers");
+        thread::spawn(move || pool::serve(listener, pool_events));
+        pool = Some(PoolServer::new(Coordinator::new(miner.clone(), Target::from_leading_zeros(config.pool_share_difficulty as usize))));
+    }
+
+    let (new_peers, connected) = mpsc::channel();
+    for address in &config.listen {
+        let listener = TcpListener::bind(address)?;
+        info!(%address, "listening for peers");
+        spawn_acceptor(listener, identity.clone(), config.network, node.inbox_sender(), new_peers.clone());
+    }
+    let mut address_book = load_address_book(&dir)?;
+    for address in &config.peers {
+        address_book.add(address.clone());
+    }
+    *node.address_book_mut() = address_book;
+    *node.bans_mut() = load_ban_list(&dir)?;
+    node.set_ban_duration(config.ban_duration_secs);
+    for address in &config.listen {
+        node.advertise(address.clone());
+    }
+    let mut outbound = HashMap::new();
+    maintain_outbound(&mut node, &mut outbound, &identity, config);
+    let mut last_discovery = Instant::now();
+
+    let mut saved_tip = node.blockchain().latest_block().header.hash.clone();
+    while !shutdown.load(Ordering::SeqCst) {
+        for (id, sender) in connected.try_iter() {
+            node.add_peer(id, sender);
+        }
+        node.process_messages();
+        for call in rpc_requests.try_iter() {
+            rpc::answer(&mut node, faucet.as_ref(), call);
+        }
+        if let Some(pool) = &mut pool {
+            for event in worker_events.try_iter() {
+                pool.handle(event, &mut node);
+            }
+            pool.tick(&node);
+        }
+
+        if let Some(miner) = config.miner_address.as_ref().filter(|_| config.auto_mine)
+            && !node.blockchain().mempool().is_empty()
+            && node.blockchain().can_seal_next()
+        {
+            node.mine_pending_transactions(miner.clone());
+        }
+
+        if last_discovery.elapsed() >= DISCOVERY_INTERVAL {
+            maintain_outbound(&mut node, &mut outbound, &identity, config);
+            save_address_book(&dir, node.address_book())?;
+            let now = node.blockchain().params().clock.now();
+            node.bans_mut().expire(now);
+            save_ban_list(&dir, node.bans())?;
+            last_discovery = Instant::now();
+        }
+
+        if node.blockchain().latest_block().header.hash != saved_tip {
+            save_chain(&dir, node.blockchain())?;
+            saved_tip = node.blockchain().latest_block().header.hash.clone();
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    save_chain(&dir, node.blockchain())?;
+    save_address_book(&dir, node.address_book())?;
+    save_ban_list(&dir, node.bans())?;
+    info!(height = node.blockchain().latest_block().header.index, "node stopped");
+    Ok(())
+}
+
+/// Dials the best addresses in the node's address book until
+/// `target_outbound` of the connections we opened are still up. `outbound`
+/// maps each dialled address to the peer id that answered.
+fn maintain_outbound(node: &mut Node, outbound: &mut HashMap<String, String>, identity: &NodeIdentity, config: &NodeConfig) {
+    outbound.retain(|_, id| node.is_connected(id));
+    if outbound.len() >= config.target_outbound {
+        return;
+    }
+    let candidates: Vec<String> = node
+        .address_book()
+        .best(usize::MAX)
+        .into_iter()
+        .filter(|address| !outbound.contains_key(*address) && !config.listen.iter().any(|own| own == address))
+        .map(str::to_owned)
+        .collect();
+
+    for address in candidates {
+        if outbound.len() >= config.target_outbound {
+            break;
+        }
+        match dial(&address, identity, config.network, node.inbox_sender()) {
+            Ok((id, _)) if node.is_banned(&id) => {
+                debug!(%address, peer = %id, "address belongs to a banned peer");
+                node.address_book_mut().penalize(&address, BAN_THRESHOLD);
+            }
+            Ok((id, sender)) if id != node.id() && !node.is_connected(&id) => {
+                let now = node.blockchain().params().clock.now();
+                node.address_book_mut().mark_seen(&address, now);
+                node.add_peer(id.clone(), sender);
+                outbound.insert(address, id);
+            }
+            Ok((id, _)) => debug!(%address, peer = %id, "already connected"),
+            Err(err) => debug!(%address, error = %err, "cannot reach peer"),
+        }
+    }
+}
+
+fn dial(
+    address: &str,
+    identity: &NodeIdentity,
+    network: Network,
+    inbox: Sender<Envelope>,
+) -> io::Result<(String, Sender<Envelope>)> {
+    let socket = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "address did not resolve"))?;
+    let stream = TcpStream::connect_timeout(&socket, CONNECT_TIMEOUT)?;
+    spawn_tcp_peer(stream, identity, network, Role::Initiator, inbox)
+}
+
+/// Accepts peers on `listener`. Each handshake runs on its own thread, so
+/// a peer that stalls cannot hold up the ones behind it.
+fn spawn_acceptor(
+    listener: TcpListener,
+    identity: NodeIdentity,
+    network: Network,
+    inbox: Sender<Envelope>,
+    new_peers: Sender<(String, Sender<Envelope>)>,
+) {
+    let pending = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!(error = %err, "rejected incoming peer");
+                    continue;
+                }
+            };
+            if pending.fetch_add(1, Ordering::SeqCst) >= MAX_PENDING_HANDSHAKES {
+                pending.fetch_sub(1, Ordering::SeqCst);
+                debug!("too many pending handshakes, dropping incoming peer");
+                continue;
+            }
+            let (identity, inbox, new_peers, pending) = (identity.clone(), inbox.clone(), new_peers.clone(), pending.clone());
+            thread::spawn(move || {
+                match spawn_tcp_peer(stream, &identity, network, Role::Responder, inbox) {
+                    Ok(peer) => {
+                        let _ = new_peers.send(peer);
+                    }
+                    Err(err) => warn!(error = %err, "rejected incoming peer"),
+                }
+                pending.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = NodeConfig::parse(
+            r#"
+            data_dir = "data"
+            listen = ["127.0.0.1:7000"]
+            peers = ["127.0.0.1:7001"]
+            miner_address = "miner"
+            auto_mine = true
+            extra_nonce = 3
+            metrics_address = "127.0.0.1:9100"
+            rpc_address = "127.0.0.1:8080"
+            rpc_api_keys = ["secret"]
+            rpc_requests_per_minute = 60
+            pool_address = "127.0.0.1:3333"
+            mempool_ttl_secs = 600
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.target_outbound, 8);
+        assert_eq!(config.ban_duration_secs, DEFAULT_BAN_DURATION);
+        assert_eq!(config.data_dir, PathBuf::from("data"));
+        assert_eq!(config.listen, vec!["127.0.0.1:7000"]);
+        assert_eq!(config.peers, vec!["127.0.0.1:7001"]);
+        assert!(config.auto_mine);
+        assert_eq!(config.extra_nonce, 3);
+        assert_eq!(config.metrics_address.as_deref(), Some("127.0.0.1:9100"));
+        assert_eq!(config.rpc_address.as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(
+            config.rpc_policy(),
+            RpcPolicy {
+                read_only: false,
+                api_keys: vec!["secret".to_owned()],
+                requests_per_minute: 60,
+                key_requests_per_minute: 0,
+            }
+        );
+        assert_eq!(config.pool_address.as_deref(), Some("127.0.0.1:3333"));
+        assert_eq!(config.pool_share_difficulty, 2);
+        assert_eq!(config.mempool_max_bytes, MempoolPolicy::default().max_bytes);
+        assert_eq!(config.mempool_ttl_secs, 600);
+
+        assert!(matches!(
+            NodeConfig::parse("data_dir = \"data\"\nauto_mine = true"),
+            Err(ConfigError::MissingMinerAddress)
+        ));
+        assert!(matches!(
+            NodeConfig::parse("data_dir = \"data\"\npool_address = \"127.0.0.1:3333\""),
+            Err(ConfigError::MissingMinerAddress)
+        ));
+    }
+
+    #[test]
+    fn test_parse_premine_config() {
+        let alice = Address::from_key(&SigningKey::from_bytes(&[1; 32]).verifying_key(), Network::Mainnet);
+        let config = NodeConfig::parse(&format!(
+            "data_dir = \"data\"\ndev = true\n[[premine]]\naddress = \"{}\"\namount = 500",
+            alice
+        ))
+        .unwrap();
+        assert_eq!(
+            config.premine(),
+            vec![TxOutput::new(500, alice.to_string()), Faucet::dev(Network::Mainnet).premine()]
+        );
+
+        let testnet = format!("data_dir = \"data\"\nnetwork = \"testnet\"\n[[premine]]\naddress = \"{}\"\namount = 1", alice);
+        assert!(matches!(NodeConfig::parse(&testnet), Err(ConfigError::InvalidPremineAddress(_))));
+        assert!(NodeConfig::parse("data_dir = \"data\"").unwrap().premine().is_empty());
+    }
+
+    #[test]
+    fn test_parse_authority_config() {
+        let [first, second] = [1, 2].map(|seed| SigningKey::from_bytes(&[seed; 32]));
+        let public = |key: &SigningKey| hex::encode(key.verifying_key().as_bytes());
+        let config = NodeConfig::parse(&format!(
+            "data_dir = \"data\"\nvalidators = [{:?}, {:?}]\nvalidator_keys = [{:?}]",
+            public(&first),
+            public(&second),
+            hex::encode(second.to_bytes())
+        ))
+        .unwrap();
+        let authority = config.authority().unwrap().unwrap();
+        assert_eq!(authority.validators(), [first.verifying_key(), second.verifying_key()]);
+        assert!(authority.key_for(1).is_some());
+        assert!(authority.key_for(2).is_none());
+
+        assert!(NodeConfig::parse("data_dir = \"data\"").unwrap().authority().unwrap().is_none());
+        let unlisted = format!(
+            "data_dir = \"data\"\nvalidators = [{:?}]\nvalidator_keys = [{:?}]",
+            public(&first),
+            hex::encode(second.to_bytes())
+        );
+        assert!(matches!(NodeConfig::parse(&unlisted), Err(ConfigError::InvalidValidatorKey)));
+        assert!(matches!(
+            NodeConfig::parse("data_dir = \"data\"\nvalidators = [\"zz\"]"),
+            Err(ConfigError::InvalidValidatorKey)
+        ));
+    }
+
+    #[test]
+    fn test_maintain_outbound_dials_the_address_book() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let [remote_identity, identity] = [1, 2].map(|seed| NodeIdentity::from_key(SigningKey::from_bytes(&[seed; 32])));
+        let remote_id = remote_identity.id();
+        let remote = Node::new(remote_id.clone());
+        let (new_peers, _connected) = mpsc::channel();
+        spawn_acceptor(listener, remote_identity, Network::Mainnet, remote.inbox_sender(), new_peers);
+
+        let config = NodeConfig::parse("data_dir = \"data\"\ntarget_outbound = 1").unwrap();
+        let mut node = Node::new(identity.id());
+        node.address_book_mut().add(address.clone());
+        let mut outbound = HashMap::new();
+        maintain_outbound(&mut node, &mut outbound, &identity, &config);
+
+        assert_eq!(outbound.get(&address), Some(&remote_id));
+        assert!(node.is_connected(&remote_id));
+        assert!(node.address_book().get(&address).unwrap().last_seen.is_some());
+    }
+
+    #[test]
+    fn test_run_saves_chain_on_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::parse(&format!("data_dir = {:?}", dir.path())).unwrap();
+
+        let shutdown = Arc::new(AtomicBool::new(true));
+        run(&config, shutdown).unwrap();
+
+        assert!(load_chain(dir.path(), ChainParams::default()).unwrap().is_some());
+
+        let config = NodeConfig::parse(&format!("network = \"testnet\"\ndata_dir = {:?}", dir.path())).unwrap();
+        run(&config, Arc::new(AtomicBool::new(true))).unwrap();
+        let testnet = ChainParams::for_network(Network::Testnet);
+        let saved = load_chain(&dir.path().join("testnet"), testnet).unwrap().unwrap();
+        assert_eq!(saved.latest_block().header.network, Network::Testnet);
+    }
+}
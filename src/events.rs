@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tx_index::entries;
+use crate::utxo::BlockUndo;
+use crate::{Block, Target, Transaction};
+
+/// Confirmations a watcher hears about unless its filter says otherwise.
+pub const DEFAULT_CONFIRMATION_DEPTH: u32 = 6;
+
+/// Notable changes to the chain, pushed to every subscriber as they happen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    NewBlock { index: u32, hash: String },
+    NewTransaction { id: String },
+    ChainReorg { disconnected: String, height: u32, hash: String },
+    /// The tip, at `height`, has a different target from the tip before
+    /// it. `bits` is the new target in compact form.
+    DifficultyChange { height: u32, bits: u32 },
+}
+
+impl ChainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChainEvent::NewBlock { .. } => "new_block",
+            ChainEvent::NewTransaction { .. } => "new_transaction",
+            ChainEvent::ChainReorg { .. } => "chain_reorg",
+            ChainEvent::DifficultyChange { .. } => "difficulty_change",
+        }
+    }
+
+    /// Encodes the event as a single JSON object tagged with its name.
+    pub fn to_json(&self) -> String {
+        match self {
+            ChainEvent::NewBlock { index, hash } => format!(
+                r#"{{"event":"{}","index":{},"hash":"{}"}}"#,
+                self.name(),
+                index,
+                hash
+            ),
+            ChainEvent::NewTransaction { id } => {
+                format!(r#"{{"event":"{}","id":"{}"}}"#, self.name(), id)
+            }
+            ChainEvent::ChainReorg {
+                disconnected,
+                height,
+                hash,
+            } => format!(
+                r#"{{"event":"{}","disconnected":"{}","height":{},"hash":"{}"}}"#,
+                self.name(),
+                disconnected,
+                height,
+                hash
+            ),
+            ChainEvent::DifficultyChange { height, bits } => {
+                let target = Target::from_compact(*bits);
+                format!(
+                    r#"{{"event":"{}","height":{},"target":"{}","difficulty":{}}}"#,
+                    self.name(),
+                    height,
+                    target,
+                    target.difficulty()
+                )
+            }
+        }
+    }
+}
+
+/// Why a pending transaction left the mempool without being mined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// A transaction with the same sender and nonce paid more.
+    Replaced,
+    /// The full mempool made room for a better paying one.
+    Evicted,
+    /// It outlived the mempool's time to live.
+    Expired,
+}
+
+/// A step in a transaction's life, as a wallet watching it sees it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TransactionEvent {
+    /// It entered the mempool.
+    Accepted { id: String },
+    /// It is in the block at `height`, and `confirmations` blocks deep
+    /// counting that one. Sent again for every block on top, up to the
+    /// watcher's depth.
+    Confirmed {
+        id: String,
+        block_hash: String,
+        height: u32,
+        confirmations: u32,
+    },
+    /// Its block left the chain.
+    Unconfirmed { id: String },
+    Dropped { id: String, reason: DropReason },
+}
+
+/// Which transactions a watcher hears about: those with one of the ids in
+/// `transactions`, and those paying or spending from one of `addresses`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionFilter {
+    pub addresses: BTreeSet<String>,
+    pub transactions: BTreeSet<String>,
+    /// Confirmations to report a mined transaction up to.
+    pub depth: u32,
+}
+
+impl Default for TransactionFilter {
+    fn default() -> Self {
+        TransactionFilter {
+            addresses: BTreeSet::new(),
+            transactions: BTreeSet::new(),
+            depth: DEFAULT_CONFIRMATION_DEPTH,
+        }
+    }
+}
+
+impl TransactionFilter {
+    pub fn matches(&self, id: &str, addresses: &[String]) -> bool {
+        self.transactions.contains(id) || addresses.iter().any(|address| self.addresses.contains(address))
+    }
+}
+
+/// A filtered subscriber and the mined transactions it is still counting
+/// confirmations for, by id, with the hash and height of their block.
+struct Watcher {
+    filter: TransactionFilter,
+    sender: Sender<TransactionEvent>,
+    confirming: BTreeMap<String, (String, u32)>,
+}
+
+/// Callbacks for embedders, run synchronously as the chain changes, so an
+/// application can react without polling. Every method does nothing by
+/// default.
+pub trait ChainObserver: Send {
+    /// A block was mined onto the tip or accepted from elsewhere.
+    fn on_block_added(&mut self, _block: &Block) {}
+
+    /// The `disconnected` blocks left the chain and the `connected` ones
+    /// took their place, both oldest first. `connected` is empty when the
+    /// tip was rolled back.
+    fn on_reorg(&mut self, _disconnected: &[Block], _connected: &[Block]) {}
+
+    /// A block from elsewhere failed validation. For a rejected chain,
+    /// this is its tip.
+    fn on_validation_failure(&mut self, _block: &Block) {}
+
+    /// A transaction entered the mempool.
+    fn on_transaction_accepted(&mut self, _transaction: &Transaction) {}
+}
+
+/// Fan-out of chain events to any number of channel subscribers, and of
+/// callbacks to registered observers. Dropped receivers are forgotten on
+/// the next publish.
+#[derive(Default)]
+pub(crate) struct EventBus {
+    subscribers: Vec<Sender<ChainEvent>>,
+    watchers: Vec<Watcher>,
+    observers: Vec<Box<dyn ChainObserver>>,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribers", &self.subscribers.len())
+            .field("watchers", &self.watchers.len())
+            .field("observers", &self.observers.len())
+            .finish()
+    }
+}
+
+impl EventBus {
+    pub(crate) fn register(&mut self, observer: Box<dyn ChainObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Runs `callback` on every observer, in the order they registered.
+    pub(crate) fn notify(&mut self, mut callback: impl FnMut(&mut dyn ChainObserver)) {
+        for observer in &mut self.observers {
+            callback(observer.as_mut());
+        }
+    }
+
+    pub(crate) fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub(crate) fn publish(&mut self, event: ChainEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    pub(crate) fn watch(&mut self, filter: TransactionFilter) -> Receiver<TransactionEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.watchers.push(Watcher {
+            filter,
+            sender,
+            confirming: BTreeMap::new(),
+        });
+        receiver
+    }
+
+    /// Sends `event` about the pending `transaction` to the watchers it
+    /// matches.
+    pub(crate) fn publish_pending(&mut self, transaction: &Transaction, event: TransactionEvent) {
+        let id = transaction.id();
+        let addresses = [transaction.sender.clone(), transaction.recipient.clone()];
+        self.watchers.retain(|watcher| {
+            !watcher.filter.matches(&id, &addresses) || watcher.sender.send(event.clone()).is_ok()
+        });
+    }
+
+    /// Reports `block`, just appended with `undo` holding the outputs it
+    /// spent: one more confirmation for what watchers are counting, and a
+    /// first for the matching transactions in it.
+    pub(crate) fn connect(&mut self, block: &Block, undo: &BlockUndo) {
+        if self.watchers.is_empty() {
+            return;
+        }
+        let height = block.header.index;
+        let entries = entries(block, undo);
+        self.watchers.retain_mut(|watcher| {
+            let mut alive = true;
+            let depth = watcher.filter.depth;
+            let sender = &watcher.sender;
+            watcher.confirming.retain(|id, (block_hash, mined)| {
+                let confirmations = height.saturating_sub(*mined) + 1;
+                alive &= sender
+                    .send(TransactionEvent::Confirmed {
+                        id: id.clone(),
+                        block_hash: block_hash.clone(),
+                        height: *mined,
+                        confirmations,
+                    })
+                    .is_ok();
+                confirmations < depth
+            });
+            for (id, receipt, addresses) in &entries {
+                if !watcher.filter.matches(id, addresses) {
+                    continue;
+                }
+                alive &= sender
+                    .send(TransactionEvent::Confirmed {
+                        id: id.clone(),
+                        block_hash: receipt.block_hash.clone(),
+                        height,
+                        confirmations: 1,
+                    })
+                    .is_ok();
+                if depth > 1 {
+                    watcher.confirming.insert(id.clone(), (receipt.block_hash.clone(), height));
+                }
+            }
+            alive
+        });
+    }
+
+    /// Reports `block`, just disconnected from the tip.
+    pub(crate) fn disconnect(&mut self, block: &Block, undo: &BlockUndo) {
+        if self.watchers.is_empty() {
+            return;
+        }
+        let entries = entries(block, undo);
+        self.watchers.retain_mut(|watcher| {
+            let mut alive = true;
+            for (id, _, addresses) in entries.iter().rev() {
+                if watcher.filter.matches(id, addresses) {
+                    watcher.confirming.remove(id);
+                    alive &= watcher.sender.send(TransactionEvent::Unconfirmed { id: id.clone() }).is_ok();
+                }
+            }
+            alive
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::accounts::{account, payment, signed};
+    use crate::{Blockchain, ChainParams};
+
+    #[test]
+    fn test_publish_reaches_live_subscribers() {
+        let mut bus = EventBus::default();
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        drop(second);
+
+        bus.publish(ChainEvent::NewTransaction { id: "abc".to_owned() });
+
+        assert_eq!(bus.subscribers.len(), 1);
+        assert_eq!(
+            first.try_recv().unwrap().to_json(),
+            r#"{"event":"new_transaction","id":"abc"}"#
+        );
+    }
+
+    #[test]
+    fn test_watchers_follow_matching_transactions() {
+        let mut blockchain = Blockchain::with_params(ChainParams::testing());
+        let events = blockchain.watch(TransactionFilter {
+            addresses: BTreeSet::from([account("bob")]),
+            depth: 2,
+            ..TransactionFilter::default()
+        });
+        let first = payment("alice", "bob", 5, 1);
+        let replacement = signed("alice", Transaction { fee: 2, ..first.clone() });
+        assert!(blockchain.add_transaction(first.clone()));
+        assert!(blockchain.add_transaction(payment("carol", "dave", 1, 0)));
+        assert!(blockchain.add_transaction(replacement.clone()));
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        let block_hash = blockchain.latest_block().header.hash.clone();
+        assert!(blockchain.add_block("Second".to_owned()));
+        assert!(blockchain.add_block("Third".to_owned()));
+        assert!(blockchain.rollback_block().is_some());
+        assert!(blockchain.rollback_block().is_some());
+        assert!(blockchain.rollback_block().is_some());
+
+        let id = replacement.id();
+        let confirmed = |confirmations| TransactionEvent::Confirmed {
+            id: id.clone(),
+            block_hash: block_hash.clone(),
+            height: 1,
+            confirmations,
+        };
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                TransactionEvent::Accepted { id: first.id() },
+                TransactionEvent::Dropped {
+                    id: first.id(),
+                    reason: DropReason::Replaced
+                },
+                TransactionEvent::Accepted { id: id.clone() },
+                confirmed(1),
+                confirmed(2),
+                TransactionEvent::Unconfirmed { id: id.clone() },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&TransactionEvent::Unconfirmed { id }).unwrap(),
+            format!(r#"{{"event":"unconfirmed","id":"{}"}}"#, replacement.id())
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{decode_block, encode_block};
+use crate::utxo::{TxInput, TxOutput, UtxoTransaction};
+use crate::{Block, BlockBody, BlockHeader, Blockchain, ChainParams, Network, Transaction};
+
+/// First bytes of a binary export.
+const BINARY_MAGIC: &[u8; 8] = b"SIMPLZ\x00\x01";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON-encoded block per line.
+    Json,
+    /// One row per block followed by one row per transaction in it.
+    Csv,
+    /// `codec` blocks, each prefixed with its `u32` length.
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "binary" => Ok(Format::Binary),
+            other => Err(format!("unknown format {:?}, expected json, csv or binary", other)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// Block number `position` in the file could not be read.
+    Decode { position: usize, source: io::Error },
+    /// The export does not start with our genesis block.
+    GenesisMismatch,
+    /// Block number `position` in the file failed validation.
+    InvalidBlock { position: usize, index: u32, hash: String },
+    Empty,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Decode { position, source } => write!(f, "cannot read block {} of the export: {}", position, source),
+            ImportError::GenesisMismatch => write!(f, "export does not start with the genesis block"),
+            ImportError::InvalidBlock { position, index, hash } => write!(
+                f,
+                "block {} of the export (index {}, hash {}) is invalid",
+                position, index, hash
+            ),
+            ImportError::Empty => write!(f, "export contains no blocks"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Writes every block of `blockchain` to `writer` in `format`, one block at
+/// a time.
+pub fn export<W: Write>(blockchain: &Blockchain, format: Format, writer: W) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    match format {
+        Format::Json => {
+            for block in blockchain {
+                serde_json::to_writer(&mut writer, block)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Format::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut writer);
+            for block in blockchain {
+                for row in CsvRow::from_block(block) {
+                    csv.serialize(row)?;
+                }
+            }
+            csv.flush()?;
+        }
+        Format::Binary => {
+            writer.write_all(BINARY_MAGIC)?;
+            let mut encoded = Vec::new();
+            for block in blockchain {
+                encoded.clear();
+                encode_block(&mut encoded, block)?;
+                writer.write_all(&(encoded.len() as u32).to_le_bytes())?;
+                writer.write_all(&encoded)?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+/// Rebuilds a chain under `params` from an export, validating each block as
+/// it is read so the whole export never has to be held in memory at once.
+pub fn import<R: Read>(reader: R, format: Format, params: ChainParams) -> Result<Blockchain, ImportError> {
+    let mut blockchain = Blockchain::with_params(params);
+    let mut position = 0;
+    read_blocks(reader, format, |block| {
+        if position == 0 {
+            if block != *blockchain.latest_block() {
+                return Err(ImportError::GenesisMismatch);
+            }
+        } else if !blockchain.accept_block(block.clone()) {
+            return Err(ImportError::InvalidBlock {
+                position,
+                index: block.header.index,
+                hash: block.header.hash,
+            });
+        }
+        position += 1;
+        Ok(())
+    })?;
+    if position == 0 {
+        return Err(ImportError::Empty);
+    }
+    Ok(blockchain)
+}
+
+/// Reads every block of an export without validating them, for inspecting
+/// a chain that this node might reject.
+pub fn read<R: Read>(reader: R, format: Format) -> Result<Vec<Block>, ImportError> {
+    let mut blocks = Vec::new();
+    read_blocks(reader, format, |block| {
+        blocks.push(block);
+        Ok(())
+    })?;
+    if blocks.is_empty() {
+        return Err(ImportError::Empty);
+    }
+    Ok(blocks)
+}
+
+/// Calls `accept` with each block of the export in order.
+fn read_blocks<R: Read>(
+    reader: R,
+    format: Format,
+    mut accept: impl FnMut(Block) -> Result<(), ImportError>,
+) -> Result<(), ImportError> {
+    let mut reader = BufReader::new(reader);
+    let mut position = 0;
+    let decode_error = |position, source| ImportError::Decode { position, source };
+    match format {
+        Format::Json => {
+            for line in reader.lines() {
+                let line = line.map_err(|err| decode_error(position, err))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let block = serde_json::from_str(&line).map_err(|err| decode_error(position, err.into()))?;
+                accept(block)?;
+                position += 1;
+            }
+        }
+        Format::Csv => {
+            let mut current: Option<Block> = None;
+            for row in csv::Reader::from_reader(reader).deserialize::<CsvRow>() {
+                let row = row.map_err(|err| decode_error(position, err.into()))?;
+                if row.kind == Some(RowKind::Block) {
+                    if let Some(block) = current.take() {
+                        accept(block)?;
+                        position += 1;
+                    }
+                    current = Some(row.into_block().map_err(|err| decode_error(position, err))?);
+                    continue;
+                }
+                let Some(block) = current.as_mut().filter(|block| block.header.index == row.block_index) else {
+                    return Err(decode_error(position, invalid_data("transaction row outside its block")));
+                };
+                row.add_to(&mut block.body).map_err(|err| decode_error(position, err))?;
+            }
+            if let Some(block) = current {
+                accept(block)?;
+            }
+        }
+        Format::Binary => {
+            let mut magic = [0; BINARY_MAGIC.len()];
+            reader.read_exact(&mut magic).map_err(|err| decode_error(position, err))?;
+            if &magic != BINARY_MAGIC {
+                return Err(decode_error(position, invalid_data("not a binary chain export")));
+            }
+            while !reader.fill_buf().map_err(|err| decode_error(position, err))?.is_empty() {
+                let mut len = [0; 4];
+                reader.read_exact(&mut len).map_err(|err| decode_error(position, err))?;
+                let mut record = reader.by_ref().take(u32::from_le_bytes(len) as u64);
+                let block = decode_block(&mut record).map_err(|err| decode_error(position, err))?;
+                if record.limit() != 0 {
+                    return Err(decode_error(position, invalid_data("trailing bytes in block record")));
+                }
+                accept(block)?;
+                position += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RowKind {
+    Block,
+    Transaction,
+    UtxoTransaction,
+}
+
+/// A CSV row. Block rows fill the header columns and `data`; transaction
+/// rows fill the transaction columns; UTXO transaction rows carry their
+/// inputs and outputs as JSON.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CsvRow {
+    kind: Option<RowKind>,
+    block_index: u32,
+    block_hash: String,
+    prev_hash: String,
+    timestamp: Option<i64>,
+    nonce: Option<u64>,
+    extra_nonce: Option<u64>,
+    bits: Option<u32>,
+    network: Option<Network>,
+    merkle_root: String,
+    #[serde(default)]
+    state_root: String,
+    #[serde(default)]
+    seal: String,
+    data: String,
+    sender: String,
+    recipient: String,
+    amount: Option<u64>,
+    fee: Option<u64>,
+    #[serde(default)]
+    lock_until: Option<u64>,
+    payload: String,
+    #[serde(default)]
+    signature: String,
+    inputs: String,
+    outputs: String,
+}
+
+impl CsvRow {
+    fn from_block(block: &Block) -> Vec<CsvRow> {
+        let header = &block.header;
+        let mut rows = vec![CsvRow {
+            kind: Some(RowKind::Block),
+            block_index: header.index,
+            block_hash: header.hash.clone(),
+            prev_hash: header.prev_hash.clone(),
+            timestamp: Some(header.timestamp),
+            nonce: Some(header.nonce),
+            extra_nonce: Some(header.extra_nonce),
+            bits: Some(header.bits),
+            network: Some(header.network),
+            merkle_root: header.merkle_root.clone(),
+            state_root: header.state_root.clone(),
+            seal: hex::encode(&header.seal),
+            data: block.body.data.clone(),
+            ..CsvRow::default()
+        }];
+        rows.extend(block.body.transactions.iter().map(|transaction| CsvRow {
+            kind: Some(RowKind::Transaction),
+            block_index: header.index,
+            block_hash: header.hash.clone(),
+            sender: transaction.sender.clone(),
+            recipient: transaction.recipient.clone(),
+            amount: Some(transaction.amount),
+            fee: Some(transaction.fee),
+            nonce: Some(transaction.nonce),
+            lock_until: Some(transaction.lock_until),
+            payload: hex::encode(&transaction.payload),
+            signature: transaction.signature.clone(),
+            ..CsvRow::default()
+        }));
+        rows.extend(block.body.utxo_transactions.iter().map(|transaction| CsvRow {
+            kind: Some(RowKind::UtxoTransaction),
+            block_index: header.index,
+            block_hash: header.hash.clone(),
+            inputs: serde_json::to_string(&transaction.inputs).unwrap_or_default(),
+            outputs: serde_json::to_string(&transaction.outputs).unwrap_or_default(),
+            ..CsvRow::default()
+        }));
+        rows
+    }
+
+    fn into_block(self) -> io::Result<Block> {
+        let missing = || invalid_data("block row is missing a header field");
+        Ok(Block {
+            header: BlockHeader {
+                index: self.block_index,
+                timestamp: self.timestamp.ok_or_else(missing)?,
+                prev_hash: self.prev_hash,
+                merkle_root: self.merkle_root,
+                state_root: self.state_root,
+                nonce: self.nonce.ok_or_else(missing)?,
+                extra_nonce: self.extra_nonce.ok_or_else(missing)?,
+                bits: self.bits.ok_or_else(missing)?,
+                network: self.network.ok_or_else(missing)?,
+                hash: self.block_hash,
+                seal: hex::decode(&self.seal).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            },
+            body: BlockBody {
+                data: self.data,
+                ..BlockBody::default()
+            },
+        })
+    }
+
+    fn add_to(self, body: &mut BlockBody) -> io::Result<()> {
+        match self.kind {
+            Some(RowKind::Transaction) => {
+                let missing = || invalid_data("transaction row is missing a field");
+                body.transactions.push(Transaction {
+                    sender: self.sender,
+                    recipient: self.recipient,
+                    amount: self.amount.ok_or_else(missing)?,
+                    fee: self.fee.ok_or_else(missing)?,
+                    nonce: self.nonce.ok_or_else(missing)?,
+                    lock_until: self.lock_until.unwrap_or(0),
+                    payload: hex::decode(&self.payload).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                    signature: self.signature,
+                });
+            }
+            Some(RowKind::UtxoTransaction) => {
+                let inputs: Vec<TxInput> = serde_json::from_str(&self.inputs)?;
+                let outputs: Vec<TxOutput> = serde_json::from_str(&self.outputs)?;
+                body.utxo_transactions.push(UtxoTransaction { inputs, outputs });
+            }
+            Some(RowKind::Block) | None => return Err(invalid_data("row has no kind")),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::accounts::signed;
+
+    fn sample_chain() -> Blockchain {
+        let mut blockchain = Blockchain::new();
+        blockchain.add_block("First, with \"quotes\"\nand a newline".to_owned());
+        let payment = Transaction::with_payload(String::new(), "bob".to_owned(), 5, 2, vec![0xde, 0xad]);
+        assert!(blockchain.add_transaction(signed("alice", payment)));
+        blockchain.mine_pending_transactions("miner".to_owned());
+        assert!(blockchain.add_utxo_block(vec![UtxoTransaction::coinbase("miner".to_owned(), 50)]));
+        blockchain
+    }
+
+    #[test]
+    fn test_every_format_round_trips() {
+        let blockchain = sample_chain();
+        for format in [Format::Json, Format::Csv, Format::Binary] {
+            let mut exported = Vec::new();
+            export(&blockchain, format, &mut exported).unwrap();
+            let imported = import(exported.as_slice(), format, ChainParams::default()).unwrap();
+            assert!(imported.iter().eq(blockchain.iter()), "{:?} export differs", format);
+            assert_eq!(imported.utxo_set().root(), blockchain.utxo_set().root());
+        }
+    }
+
+    #[test]
+    fn test_import_reports_the_offending_block() {
+        let blockchain = sample_chain();
+        let mut exported = Vec::new();
+        export(&blockchain, Format::Json, &mut exported).unwrap();
+
+        let tampered = String::from_utf8(exported)
+            .unwrap()
+            .replace("First, with", "Rewritten, with");
+        match import(tampered.as_bytes(), Format::Json, ChainParams::default()) {
+            Err(ImportError::InvalidBlock { position, index, .. }) => {
+                assert_eq!((position, index), (1, 1));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert_eq!(read(tampered.as_bytes(), Format::Json).unwrap().len(), 4);
+
+        let mut exported = Vec::new();
+        export(&blockchain, Format::Binary, &mut exported).unwrap();
+        exported.truncate(exported.len() - 3);
+        assert!(matches!(
+            import(exported.as_slice(), Format::Binary, ChainParams::default()),
+            Err(ImportError::Decode { position: 3, .. })
+        ));
+        assert!(matches!(
+            import(&b""[..], Format::Json, ChainParams::default()),
+            Err(ImportError::Empty)
+        ));
+    }
+
+    #[test]
+    fn test_import_rejects_other_networks() {
+        let mut exported = Vec::new();
+        export(&sample_chain(), Format::Json, &mut exported).unwrap();
+        let testnet = ChainParams::for_network(Network::Testnet);
+        assert!(matches!(
+            import(exported.as_slice(), Format::Json, testnet),
+            Err(ImportError::GenesisMismatch)
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+
+use crate::address::{Address, Network};
+use crate::utxo::{TxOutput, UtxoTransaction};
+use crate::Node;
+
+/// What the genesis block premines to the faucet in dev mode.
+pub const FAUCET_SUPPLY: u64 = 1_000_000_000;
+
+/// Hands out test funds on a development network. The genesis block
+/// premines `FAUCET_SUPPLY` to a well-known key, so every dev node agrees
+/// on it, and each payout spends from that key in a block of its own. The
+/// key is public: never run a faucet on a network with real value.
+#[derive(Debug, Clone)]
+pub struct Faucet {
+    key: SigningKey,
+    network: Network,
+}
+
+impl Faucet {
+    /// The faucet every dev node on `network` shares.
+    pub fn dev(network: Network) -> Self {
+        let seed: [u8; 32] = Sha256::digest(b"simplz dev faucet").into();
+        Faucet {
+            key: SigningKey::from_bytes(&seed),
+            network,
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from_key(&self.key.verifying_key(), self.network)
+    }
+
+    /// The genesis output funding the faucet.
+    pub fn premine(&self) -> TxOutput {
+        TxOutput::new(FAUCET_SUPPLY, self.address().to_string())
+    }
+
+    /// Pays `amount` to `to` from the faucet's outputs, returning change to
+    /// it, and mines the payment into a block on `node`'s chain. Returns the
+    /// transaction id.
+    pub fn pay(&self, node: &mut Node, to: &Address, amount: u64) -> Result<String, FaucetError> {
+        if amount == 0 {
+            return Err(FaucetError::ZeroAmount);
+        }
+        let own = self.address().to_string();
+        let mut outpoints = Vec::new();
+        let mut gathered: u64 = 0;
+        for (outpoint, output) in node.blockchain().utxo_set().outputs_owned_by(&own) {
+            if gathered >= amount {
+                break;
+            }
+            if output.lock.is_empty() {
+                outpoints.push(outpoint.clone());
+                gathered += output.value;
+            }
+        }
+        if gathered < amount {
+            return Err(FaucetError::Dry { available: gathered });
+        }
+
+        let mut outputs = vec![TxOutput::new(amount, to.to_string())];
+        if gathered > amount {
+            outputs.push(TxOutput::new(gathered - amount, own));
+        }
+        let mut transaction = UtxoTransaction::new(outpoints, outputs);
+        transaction.sign(&self.key);
+        let id = transaction.id();
+        if !node.mine_utxo_block(vec![transaction]) {
+            return Err(FaucetError::Rejected);
+        }
+        Ok(id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaucetError {
+    ZeroAmount,
+    /// The faucet holds less than was asked for.
+    Dry { available: u64 },
+    /// The chain refused the payout's block.
+    Rejected,
+}
+
+impl fmt::Display for FaucetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaucetError::ZeroAmount => write!(f, "amount must be positive"),
+            FaucetError::Dry { available } => write!(f, "the faucet only holds {}", available),
+            FaucetError::Rejected => write!(f, "the payout block was rejected"),
+        }
+    }
+}
+
+impl std::error::Error for FaucetError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Blockchain, ChainParams};
+
+    #[test]
+    fn test_faucet_pays_from_the_premine() {
+        let faucet = Faucet::dev(Network::Mainnet);
+        let params = ChainParams {
+            premine: vec![faucet.premine()],
+            ..ChainParams::testing()
+        };
+        let mut node = Node::with_blockchain("dev".to_owned(), Blockchain::with_params(params.clone()));
+        let to = Address::from_key(&SigningKey::from_bytes(&[7; 32]).verifying_key(), Network::Mainnet);
+
+        let id = faucet.pay(&mut node, &to, 250).unwrap();
+        let utxo = node.blockchain().utxo_set();
+        assert_eq!(utxo.balance(&to.to_string()), 250);
+        assert_eq!(utxo.balance(&faucet.address().to_string()), FAUCET_SUPPLY - 250);
+        assert!(node.blockchain().receipt(&id).is_some());
+        assert!(node.blockchain().is_valid_chain());
+
+        assert_eq!(faucet.pay(&mut node, &to, 0), Err(FaucetError::ZeroAmount));
+        assert_eq!(
+            faucet.pay(&mut node, &to, FAUCET_SUPPLY),
+            Err(FaucetError::Dry { available: FAUCET_SUPPLY - 250 })
+        );
+        let blocks: Vec<_> = node.blockchain().iter().cloned().collect();
+        assert!(crate::validate(&blocks, &params));
+    }
+}
@@ -0,0 +1,358 @@
+//! The gRPC interface described by proto/simplz.proto. Like the HTTP API,
+//! it hands each call to the thread that owns the node and lets through
+//! only what the node's `RpcPolicy` allows.
+
+// tonic's handlers return its `Status`, large as it is.
+#![allow(clippy::result_large_err)]
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::sync::mpsc::Sender;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Instant;
+
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::transport::Server;
+use tonic::{Status, async_trait};
+
+use crate::rpc::{self, Call, Gate, Request, RpcPolicy, Subscribers};
+use crate::script::Script;
+use crate::utxo::{OutPoint, TxInput, TxOutput, UtxoTransaction};
+use crate::{Block, ChainEvent, Network, Transaction};
+
+use proto::get_block_request::By;
+use proto::node_server::NodeServer;
+
+/// Messages, service and client generated from proto/simplz.proto.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("simplz.v1");
+}
+
+/// Blocks a `StreamBlocks` client may fall behind by before the stream
+/// waits for it.
+const MAX_PENDING_BLOCKS: usize = 16;
+
+/// A block asked for by `GetBlock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum BlockId {
+    Index(u32),
+    Hash(String),
+}
+
+/// Serves gRPC on `listener`, handing calls that `policy` lets through to
+/// `calls`, until the listener fails.
+pub(crate) fn serve(listener: TcpListener, calls: Sender<Call>, policy: RpcPolicy) -> io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_io().build()?;
+    runtime.block_on(async {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let service = NodeService {
+            calls,
+            gate: Mutex::new(Gate::new(policy)),
+            subscribers: Subscribers::default(),
+        };
+        Server::builder()
+            .add_service(NodeServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+            .map_err(io::Error::other)
+    })
+}
+
+struct NodeService {
+    calls: Sender<Call>,
+    gate: Mutex<Gate>,
+    subscribers: Subscribers,
+}
+
+impl NodeService {
+    /// Checks `request` against the policy as the HTTP request `method`
+    /// and `path` it stands for, with the API key from its `x-api-key`
+    /// metadata.
+    fn admit<T>(&self, request: &tonic::Request<T>, method: &str, path: &str) -> Result<(), Status> {
+        let client = request.remote_addr().map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |address| address.ip());
+        let api_key = request.metadata().get("x-api-key").and_then(|key| key.to_str().ok());
+        let mirrored = Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            body: Vec::new(),
+            api_key: api_key.map(str::to_owned),
+        };
+        let refusal = self.gate.lock().unwrap_or_else(PoisonError::into_inner).check(&mirrored, client, Instant::now());
+        match refusal.map(|response| response.status) {
+            None => Ok(()),
+            Some(401) => Err(Status::unauthenticated("missing or unknown API key")),
+            Some(403) => Err(Status::permission_denied("the node is read-only")),
+            Some(_) => Err(Status::resource_exhausted("too many requests")),
+        }
+    }
+
+    /// `rpc::ask`, waiting for the node thread off the async workers.
+    async fn ask<T: Send + 'static>(&self, make: impl FnOnce(Sender<T>) -> Call + Send + 'static) -> Result<T, Status> {
+        let calls = self.calls.clone();
+        tokio::task::spawn_blocking(move || rpc::ask(&calls, make))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?
+            .map_err(|_| Status::unavailable("the node is shutting down"))
+    }
+}
+
+#[async_trait]
+impl proto::node_server::Node for NodeService {
+    async fn get_block(
+        &self,
+        request: tonic::Request<proto::GetBlockRequest>,
+    ) -> Result<tonic::Response<proto::Block>, Status> {
+        self.admit(&request, "GET", "/blocks")?;
+        let id = match request.into_inner().by {
+            Some(By::Index(index)) => BlockId::Index(index),
+            Some(By::Hash(hash)) => BlockId::Hash(hash),
+            None => return Err(Status::invalid_argument("name a block by index or hash")),
+        };
+        match self.ask(|reply| Call::Block(id, reply)).await? {
+            Some(block) => Ok(tonic::Response::new(block.into())),
+            None => Err(Status::not_found("no such block")),
+        }
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: tonic::Request<proto::SubmitTransactionRequest>,
+    ) -> Result<tonic::Response<proto::Submitted>, Status> {
+        self.admit(&request, "POST", "/transactions")?;
+        let transaction = request
+            .into_inner()
+            .transaction
+            .ok_or_else(|| Status::invalid_argument("missing transaction"))?;
+        let transaction = UtxoTransaction::try_from(transaction)
+            .map_err(|err| Status::invalid_argument(format!("invalid transaction: {}", err)))?;
+        match self.ask(|reply| Call::Submit(transaction, reply)).await? {
+            Ok(submitted) => Ok(tonic::Response::new(proto::Submitted {
+                id: submitted.id,
+                block_hash: submitted.block_hash,
+            })),
+            Err(message) => Err(Status::failed_precondition(message)),
+        }
+    }
+
+    type StreamBlocksStream = ReceiverStream<Result<proto::Block, Status>>;
+
+    /// Looks up each block the chain's events name on a thread of its own,
+    /// which holds a subscriber slot until the client goes away. That is
+    /// noticed by the next block sent.
+    async fn stream_blocks(
+        &self,
+        request: tonic::Request<proto::StreamBlocksRequest>,
+    ) -> Result<tonic::Response<Self::StreamBlocksStream>, Status> {
+        self.admit(&request, "GET", "/ws")?;
+        let slot = self
+            .subscribers
+            .enter()
+            .ok_or_else(|| Status::resource_exhausted("too many subscribers"))?;
+        let events = self.ask(Call::Subscribe).await?;
+        let (sender, blocks) = tokio::sync::mpsc::channel(MAX_PENDING_BLOCKS);
+        let calls = self.calls.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            for event in events {
+                let hash = match event {
+                    ChainEvent::NewBlock { hash, .. } | ChainEvent::ChainReorg { hash, .. } => hash,
+                    _ => continue,
+                };
+                let block = match rpc::ask(&calls, |reply| Call::Block(BlockId::Hash(hash), reply)) {
+                    Ok(Some(block)) => block,
+                    Ok(None) => continue,
+                    Err(_) => break,
+                };
+                if sender.blocking_send(Ok(block.into())).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(blocks)))
+    }
+}
+
+impl From<Block> for proto::Block {
+    fn from(block: Block) -> Self {
+        let (header, body) = (block.header, block.body);
+        let network = match header.network {
+            Network::Mainnet => proto::Network::Mainnet,
+            Network::Testnet => proto::Network::Testnet,
+        };
+        proto::Block {
+            header: Some(proto::BlockHeader {
+                index: header.index,
+                timestamp: header.timestamp,
+                prev_hash: header.prev_hash,
+                merkle_root: header.merkle_root,
+                state_root: header.state_root,
+                nonce: header.nonce,
+                extra_nonce: header.extra_nonce,
+                bits: header.bits,
+                network: network.into(),
+                hash: header.hash,
+                seal: header.seal,
+            }),
+            body: Some(proto::BlockBody {
+                data: body.data,
+                transactions: body.transactions.into_iter().map(Into::into).collect(),
+                utxo_transactions: body.utxo_transactions.into_iter().map(Into::into).collect(),
+            }),
+        }
+    }
+}
+
+impl From<Transaction> for proto::Transaction {
+    fn from(transaction: Transaction) -> Self {
+        proto::Transaction {
+            sender: transaction.sender,
+            recipient: transaction.recipient,
+            amount: transaction.amount,
+            fee: transaction.fee,
+            nonce: transaction.nonce,
+            lock_until: transaction.lock_until,
+            payload: transaction.payload,
+            signature: transaction.signature,
+        }
+    }
+}
+
+impl From<UtxoTransaction> for proto::UtxoTransaction {
+    fn from(transaction: UtxoTransaction) -> Self {
+        let inputs = transaction.inputs.into_iter().map(|input| proto::TxInput {
+            outpoint: Some(proto::OutPoint {
+                txid: input.outpoint.txid,
+                vout: input.outpoint.vout,
+            }),
+            signature: input.signature,
+            unlock: input.unlock.to_bytes(),
+        });
+        let outputs = transaction.outputs.into_iter().map(|output| proto::TxOutput {
+            value: output.value,
+            owner: output.owner,
+            lock: output.lock.to_bytes(),
+        });
+        proto::UtxoTransaction {
+            inputs: inputs.collect(),
+            outputs: outputs.collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::UtxoTransaction> for UtxoTransaction {
+    type Error = String;
+
+    fn try_from(transaction: proto::UtxoTransaction) -> Result<Self, String> {
+        let inputs = transaction
+            .inputs
+            .into_iter()
+            .map(|input| {
+                let outpoint = input.outpoint.ok_or("input without an outpoint")?;
+                Ok(TxInput {
+                    outpoint: OutPoint {
+                        txid: outpoint.txid,
+                        vout: outpoint.vout,
+                    },
+                    signature: input.signature,
+                    unlock: Script::from_bytes(&input.unlock).map_err(|err| format!("unlocking script: {}", err))?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let outputs = transaction
+            .outputs
+            .into_iter()
+            .map(|output| {
+                Ok(TxOutput {
+                    value: output.value,
+                    owner: output.owner,
+                    lock: Script::from_bytes(&output.lock).map_err(|err| format!("locking script: {}", err))?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(UtxoTransaction { inputs, outputs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use ed25519_dalek::SigningKey;
+    use proto::node_client::NodeClient;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::utxo::address;
+    use crate::{BLOCK_REWARD, Blockchain, ChainParams, Node};
+
+    #[test]
+    fn test_grpc_serves_the_node() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let owner = address(&key.verifying_key(), Network::Mainnet);
+        let coinbase = UtxoTransaction::coinbase(owner.clone(), BLOCK_REWARD);
+        let mut node = Node::with_blockchain("grpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        assert!(node.blockchain_mut().add_utxo_block(vec![coinbase.clone()]));
+        let funded = node.blockchain().latest_block().clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (calls, incoming) = mpsc::channel();
+        let policy = RpcPolicy {
+            api_keys: vec!["secret".to_owned()],
+            ..RpcPolicy::default()
+        };
+        thread::spawn(move || serve(listener, calls, policy));
+        thread::spawn(move || {
+            for call in incoming {
+                rpc::answer(&mut node, None, call);
+            }
+        });
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let mut client = NodeClient::connect(format!("http://{}", address)).await.unwrap();
+            let by_index = proto::GetBlockRequest { by: Some(By::Index(1)) };
+            let block = client.get_block(by_index).await.unwrap().into_inner();
+            assert_eq!(block, proto::Block::from(funded.clone()));
+            let by_hash = proto::GetBlockRequest {
+                by: Some(By::Hash(funded.header.hash.clone())),
+            };
+            assert_eq!(client.get_block(by_hash).await.unwrap().into_inner(), block);
+            let missing = proto::GetBlockRequest { by: Some(By::Index(9)) };
+            assert_eq!(client.get_block(missing).await.unwrap_err().code(), tonic::Code::NotFound);
+
+            let mut blocks = client.stream_blocks(proto::StreamBlocksRequest {}).await.unwrap().into_inner();
+
+            let outpoint = OutPoint {
+                txid: coinbase.id(),
+                vout: 0,
+            };
+            let mut payment = UtxoTransaction::new(vec![outpoint], vec![TxOutput::new(BLOCK_REWARD, "bob".to_owned())]);
+            payment.sign(&key);
+            let submit = |transaction: &UtxoTransaction, key: Option<&str>| {
+                let mut request = tonic::Request::new(proto::SubmitTransactionRequest {
+                    transaction: Some(transaction.clone().into()),
+                });
+                if let Some(key) = key {
+                    request.metadata_mut().insert("x-api-key", key.parse().unwrap());
+                }
+                request
+            };
+            let refused = client.submit_transaction(submit(&payment, None)).await.unwrap_err();
+            assert_eq!(refused.code(), tonic::Code::Unauthenticated);
+            let submitted = client.submit_transaction(submit(&payment, Some("secret"))).await.unwrap().into_inner();
+            assert_eq!(submitted.id, payment.id());
+            let spent = client.submit_transaction(submit(&payment, Some("secret"))).await.unwrap_err();
+            assert_eq!(spent.code(), tonic::Code::FailedPrecondition);
+
+            let mined = blocks.next().await.unwrap().unwrap();
+            let header = mined.header.as_ref().unwrap();
+            assert_eq!((header.index, &header.hash), (2, &submitted.block_hash));
+            let body = mined.body.unwrap();
+            assert_eq!(UtxoTransaction::try_from(body.utxo_transactions[0].clone()).unwrap(), payment);
+        });
+    }
+}
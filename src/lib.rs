@@ -0,0 +1,2087 @@
+pub mod address;
+mod address_book;
+pub mod audit;
+mod authority;
+mod bans;
+mod block;
+mod checkpoint;
+mod clock;
+pub mod codec;
+mod consensus;
+#[cfg(any(feature = "net", feature = "wallet"))]
+mod crypto;
+#[cfg(feature = "net")]
+pub mod daemon;
+mod events;
+pub mod export;
+pub mod faucet;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod light;
+mod mempool;
+mod merkle;
+pub mod metrics;
+pub mod network;
+mod node;
+mod nonces;
+mod orphans;
+pub mod pool;
+pub mod repl;
+pub mod rpc;
+pub mod script;
+pub mod simulation;
+mod snapshot;
+mod stats;
+pub mod storage;
+mod target;
+mod template;
+mod transaction;
+#[cfg(feature = "net")]
+mod transport;
+mod tx_index;
+pub mod utxo;
+#[cfg(feature = "wallet")]
+pub mod wallet;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "net")]
+mod websocket;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+
+use tracing::{debug, info, warn};
+
+pub use address::Network;
+pub use address_book::{AddressBook, BAN_THRESHOLD, PeerAddress};
+pub use authority::Authority;
+pub use bans::{BanList, DEFAULT_BAN_DURATION, Misbehavior};
+pub use block::{Block, BlockBody, BlockHeader};
+pub use checkpoint::{Checkpoint, DEFAULT_CHECKPOINTS};
+#[cfg(feature = "system-clock")]
+pub use clock::SystemClock;
+pub use clock::{Clock, ManualClock};
+pub use consensus::validate;
+pub use events::{ChainEvent, ChainObserver, DEFAULT_CONFIRMATION_DEPTH, DropReason, TransactionEvent, TransactionFilter};
+pub use light::{InclusionProof, LightClient};
+pub use mempool::{Mempool, MempoolError, MempoolPolicy};
+pub use merkle::MerkleProof;
+pub use node::Node;
+pub use nonces::{AccountNonces, NonceError};
+pub use orphans::{MAX_ORPHANS, OrphanPool};
+pub use snapshot::{Snapshot, SnapshotError};
+pub use stats::{BlockSize, ChainStats, DifficultyPoint, FeePercentiles};
+pub use target::{Retarget, Target, Work};
+pub use template::BlockTemplateBuilder;
+pub use transaction::Transaction;
+#[cfg(feature = "net")]
+pub use transport::{NodeIdentity, node_id};
+pub use tx_index::{Receipt, TransactionKind};
+use consensus::Rules;
+use events::EventBus;
+use mempool::NextBlock;
+use tx_index::TxIndex;
+use utxo::{BlockUndo, OutPoint, TxOutput, UtxoSet, UtxoTransaction};
+
+/// Leading zero hex digits the main network's target asks of a block hash.
+pub const DIFFICULTY: usize = 4;
+
+/// Value minted for the miner of each block, on top of collected fees.
+pub const BLOCK_REWARD: u64 = 50;
+
+/// Fee every transaction must pay per byte of payload it carries.
+pub const PAYLOAD_FEE_PER_BYTE: u64 = 1;
+
+/// Largest block the default limits allow, in encoded bytes. The peer
+/// protocol sizes its frames from it.
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Upper bounds a block must respect to be mined or accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLimits {
+    pub max_transactions: usize,
+    pub max_block_size: usize,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        BlockLimits {
+            max_transactions: 1_000,
+            max_block_size: MAX_BLOCK_SIZE,
+        }
+    }
+}
+
+impl BlockLimits {
+    pub fn allows(&self, block: &Block) -> bool {
+        block.body.transaction_count() <= self.max_transactions && block.size_bytes() <= self.max_block_size
+    }
+
+    /// `allows` for a block with `body` whose header is not finished yet.
+    pub(crate) fn allows_body(&self, body: &BlockBody, sealed: bool) -> bool {
+        body.transaction_count() <= self.max_transactions
+            && BlockHeader::finished_size(sealed).saturating_add(body.size_bytes()) <= self.max_block_size
+    }
+}
+
+/// Settings a chain is created with. Every node on a network must agree on
+/// the limits and target.
+#[derive(Debug, Clone)]
+pub struct ChainParams {
+    pub limits: BlockLimits,
+    /// Proof-of-work target every block must meet, genesis included. With
+    /// `retarget`, only the genesis block's, and the easiest any may have.
+    pub target: Target,
+    /// Adjusts the target from block to block so blocks come at a steady
+    /// rate. `None` keeps every block at `target`.
+    pub retarget: Option<Retarget>,
+    /// Timestamps newly mined blocks.
+    pub clock: Arc<dyn Clock>,
+    /// Nonce the proof-of-work search starts from.
+    pub nonce_seed: u64,
+    /// Extra nonce newly mined blocks start from. Miners sharing a network
+    /// take different values so they search disjoint spaces. The genesis
+    /// block always uses zero.
+    pub extra_nonce: u64,
+    /// Network the chain belongs to. Blocks from any other are rejected.
+    pub network: Network,
+    /// Size and lifetime limits for pending transactions. Local to each
+    /// node; peers need not agree.
+    pub mempool: MempoolPolicy,
+    /// Pruning mode: how many of the latest blocks keep their bodies. Older
+    /// blocks are cut down to their headers. `None` keeps every block.
+    /// Local to each node.
+    pub keep_bodies: Option<usize>,
+    /// Proof-of-authority mode: validators seal blocks in turn instead of
+    /// mining them. `None` is proof of work.
+    pub authority: Option<Authority>,
+    /// Outputs the genesis block creates out of nothing, funding accounts
+    /// from the start on a development network. Not capped by the block
+    /// reward. The compiled-in checkpoints only pin chains without one.
+    pub premine: Vec<TxOutput>,
+}
+
+impl Default for ChainParams {
+    /// The main network's parameters. Blocks are timestamped with the wall
+    /// clock, or with a `ManualClock` when built without `system-clock`.
+    fn default() -> Self {
+        #[cfg(feature = "system-clock")]
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        #[cfg(not(feature = "system-clock"))]
+        let clock: Arc<dyn Clock> = Arc::new(ManualClock::default());
+        ChainParams {
+            limits: BlockLimits::default(),
+            target: Target::from_leading_zeros(DIFFICULTY),
+            retarget: None,
+            clock,
+            nonce_seed: 0,
+            extra_nonce: 0,
+            network: Network::Mainnet,
+            mempool: MempoolPolicy::default(),
+            keep_bodies: None,
+            authority: None,
+            premine: Vec::new(),
+        }
+    }
+}
+
+impl ChainParams {
+    /// A one-hex-digit target and a clock frozen at the epoch: mining is near instant
+    /// and the same calls always produce the same blocks.
+    pub fn testing() -> Self {
+        ChainParams {
+            target: Target::from_leading_zeros(1),
+            clock: Arc::new(ManualClock::default()),
+            ..ChainParams::default()
+        }
+    }
+
+    /// The main network's parameters on `network` instead.
+    pub fn for_network(network: Network) -> Self {
+        ChainParams {
+            network,
+            ..ChainParams::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Blockchain {
+    chain: Vec<Block>,
+    mempool: Mempool,
+    params: ChainParams,
+    utxo: UtxoSet,
+    utxo_undo: Vec<BlockUndo>,
+    base_utxo: UtxoSet,
+    nonces: AccountNonces,
+    base_nonces: AccountNonces,
+    /// Headers of the blocks below the oldest one held, whose bodies have
+    /// been pruned. Empty unless pruning.
+    pruned_headers: Vec<BlockHeader>,
+    events: EventBus,
+    checkpoints: Vec<Checkpoint>,
+    tx_index: TxIndex,
+    /// Number of leading blocks known to be valid. Blocks are validated as
+    /// they are appended, so this only falls behind the chain when new
+    /// checkpoints call held blocks into question.
+    validated: Cell<usize>,
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blockchain {
+
+    pub fn new() -> Self {
+        Self::with_limits(BlockLimits::default())
+    }
+
+    pub fn with_limits(limits: BlockLimits) -> Self {
+        Self::with_params(ChainParams {
+            limits,
+            ..ChainParams::default()
+        })
+    }
+
+    pub fn with_params(params: ChainParams) -> Self {
+        let utxo_transactions = if params.premine.is_empty() {
+            Vec::new()
+        } else {
+            vec![UtxoTransaction::new(Vec::new(), params.premine.clone())]
+        };
+        let genesis_body = BlockBody {
+            data: "Genesis Block".to_owned(),
+            utxo_transactions,
+            ..BlockBody::default()
+        };
+        let utxo = genesis_utxo(&params);
+        let mut genesis_block = Block::unmined(0, 0, String::new(), genesis_body);
+        genesis_block.header.nonce = params.nonce_seed;
+        genesis_block.header.network = params.network;
+        genesis_block.header.state_root = state_root(&utxo, &AccountNonces::default());
+        genesis_block.mine_block(params.target);
+        Self::with_base(params, genesis_block, utxo, AccountNonces::default(), Vec::new())
+    }
+
+    /// Bootstraps a chain from a snapshot anchored at `trusted_hash`. Blocks
+    /// below the snapshot are not kept; only blocks appended afterwards are
+    /// validated.
+    pub fn from_snapshot(snapshot: Snapshot, trusted_hash: &str) -> Result<Self, SnapshotError> {
+        Self::bootstrapped(ChainParams::default(), snapshot, trusted_hash)
+    }
+
+    /// Replaces the chain with one bootstrapped from `snapshot`, as
+    /// `from_snapshot` does, keeping this chain's parameters and observers.
+    /// The chain is left alone if the snapshot is refused.
+    pub fn bootstrap(&mut self, snapshot: Snapshot, trusted_hash: &str) -> Result<(), SnapshotError> {
+        let mut chain = Self::bootstrapped(self.params.clone(), snapshot, trusted_hash)?;
+        chain.events = std::mem::take(&mut self.events);
+        *self = chain;
+        Ok(())
+    }
+
+    fn bootstrapped(params: ChainParams, snapshot: Snapshot, trusted_hash: &str) -> Result<Self, SnapshotError> {
+        if snapshot.block_hash() != trusted_hash {
+            return Err(SnapshotError::UntrustedBlock {
+                expected: trusted_hash.to_owned(),
+                found: snapshot.block_hash().to_owned(),
+            });
+        }
+        let utxo = snapshot.verify()?;
+        info!(height = snapshot.height(), hash = %snapshot.block_hash(), "bootstrapped from snapshot");
+        Ok(Self::with_base(params, snapshot.block, utxo, snapshot.nonces, snapshot.headers))
+    }
+
+    /// Restores a pruned chain from the headers of its pruned blocks and a
+    /// snapshot of the oldest block it held. Returns `None` if the headers
+    /// do not form a valid chain up to the snapshot.
+    pub(crate) fn from_pruned(params: ChainParams, headers: Vec<BlockHeader>, snapshot: Snapshot) -> Option<Self> {
+        let utxo = snapshot.verify().ok()?;
+        let checkpoints = consensus::default_checkpoints(&params);
+        let rules = Rules {
+            params: &params,
+            checkpoints: &checkpoints,
+        };
+        if !rules.check_headers(headers.iter().chain([&snapshot.block.header])) {
+            return None;
+        }
+        Some(Self::with_base(params, snapshot.block, utxo, snapshot.nonces, headers))
+    }
+
+    /// A chain holding only `base`, trusted along with the state as of it.
+    fn with_base(
+        params: ChainParams,
+        base: Block,
+        utxo: UtxoSet,
+        nonces: AccountNonces,
+        pruned_headers: Vec<BlockHeader>,
+    ) -> Self {
+        let mut tx_index = TxIndex::default();
+        tx_index.connect(&base, &BlockUndo::new());
+        Blockchain {
+            chain: vec![base],
+            mempool: Mempool::with_policy(params.mempool),
+            checkpoints: consensus::default_checkpoints(&params),
+            params,
+            base_utxo: utxo.clone(),
+            utxo,
+            utxo_undo: vec![BlockUndo::new()],
+            base_nonces: nonces.clone(),
+            nonces,
+            pruned_headers,
+            events: EventBus::default(),
+            tx_index,
+            validated: Cell::new(1),
+        }
+    }
+
+    /// Captures the latest block and the UTXO state and account nonces as
+    /// of it.
+    pub fn snapshot(&self) -> Snapshot {
+        let headers = self.headers_before(self.chain.len() - 1);
+        snapshot_of(self.latest_block(), headers, &self.utxo, &self.nonces)
+    }
+
+    /// Like `snapshot`, for the oldest block held.
+    pub fn base_snapshot(&self) -> Snapshot {
+        snapshot_of(&self.chain[0], self.headers_before(0), &self.base_utxo, &self.base_nonces)
+    }
+
+    /// Like `snapshot`, for the held block with `hash`, or `None` if there
+    /// is no such block.
+    pub fn snapshot_at(&self, hash: &str) -> Option<Snapshot> {
+        let block = self.get_block_by_hash(hash)?;
+        let offset = (block.header.index - self.base_height()) as usize;
+        let (utxo, nonces) = self.state_at(offset);
+        Some(snapshot_of(block, self.headers_before(offset), &utxo, &nonces))
+    }
+
+    /// The UTXO state and account nonces as of the held block at `offset`,
+    /// rolled back from the tip.
+    fn state_at(&self, offset: usize) -> (UtxoSet, AccountNonces) {
+        let mut utxo = self.utxo.clone();
+        let mut nonces = self.nonces.clone();
+        for (block, undo) in self.chain[offset + 1..].iter().zip(&self.utxo_undo[offset + 1..]).rev() {
+            utxo.rollback_block(&block.body.utxo_transactions, undo.clone());
+            nonces.rollback_block(&block.body.transactions);
+        }
+        (utxo, nonces)
+    }
+
+    /// Drops the bodies of all but the latest `keep` blocks, keeping their
+    /// headers. The oldest block left becomes the base that validation and
+    /// rollbacks stop at, as for a chain bootstrapped from a snapshot.
+    /// Returns how many bodies were dropped.
+    pub fn prune(&mut self, keep: usize) -> usize {
+        let pruned = self.chain.len().saturating_sub(keep.max(1));
+        if pruned == 0 {
+            return 0;
+        }
+        let (utxo, nonces) = self.state_at(pruned);
+        self.base_utxo = utxo;
+        self.base_nonces = nonces;
+        self.pruned_headers.extend(self.chain.drain(..pruned).map(|block| block.header));
+        self.utxo_undo.drain(..pruned);
+        self.utxo_undo[0] = BlockUndo::new();
+        self.validated.set(self.validated.get().saturating_sub(pruned).max(1));
+        debug!(base = self.base_height(), pruned, "block bodies pruned");
+        pruned
+    }
+
+    /// Headers of the blocks whose bodies were pruned, oldest first.
+    pub fn pruned_headers(&self) -> &[BlockHeader] {
+        &self.pruned_headers
+    }
+
+    /// Whether the block at `height` lies below the oldest block held, so
+    /// its body is not available.
+    pub fn is_pruned(&self, height: u32) -> bool {
+        height < self.base_height()
+    }
+
+    /// Prunes down to `ChainParams::keep_bodies` when pruning is on.
+    fn apply_pruning(&mut self) {
+        if let Some(keep) = self.params.keep_bodies {
+            self.prune(keep);
+        }
+    }
+
+    /// Height of the oldest block held: zero unless bootstrapped from a
+    /// snapshot.
+    pub fn base_height(&self) -> u32 {
+        self.chain[0].header.index
+    }
+
+    /// Pins `checkpoint.hash` at `checkpoint.height`, on top of the
+    /// compiled-in `DEFAULT_CHECKPOINTS`.
+    pub fn add_checkpoint(&mut self, checkpoint: Checkpoint) {
+        if let Some(offset) = checkpoint.height.checked_sub(self.base_height()) {
+            self.forget_validation(offset as usize);
+        }
+        self.checkpoints.push(checkpoint);
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    pub fn latest_block(&self) -> &Block {
+        self.chain.last().unwrap()
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    pub fn limits(&self) -> BlockLimits {
+        self.params.limits
+    }
+
+    /// Whether this node may produce the next block: always under proof of
+    /// work, and in authority mode when it holds the scheduled validator's
+    /// key.
+    pub fn can_seal_next(&self) -> bool {
+        let height = self.latest_block().header.index + 1;
+        self.params.authority.as_ref().is_none_or(|authority| authority.key_for(height).is_some())
+    }
+
+    /// The target a block stamped `timestamp` on top of the tip must meet.
+    pub fn next_target(&self, timestamp: i64) -> Target {
+        self.rules().next_target(&self.latest_block().header, timestamp)
+    }
+
+    /// Mines a block carrying `data` and appends it. Returns `false` without
+    /// mining if the block would exceed the configured size limit.
+    pub fn add_block(&mut self, data: String) -> bool {
+        self.push_new_block(data, Vec::new(), Vec::new(), &mut |_| {})
+    }
+
+    /// Like `add_block`, calling `progress` with the number of nonces tried
+    /// so far while the block is mined.
+    pub fn add_block_with_progress(&mut self, data: String, mut progress: impl FnMut(u64)) -> bool {
+        self.push_new_block(data, Vec::new(), Vec::new(), &mut progress)
+    }
+
+    /// Mines a block carrying UTXO-model transactions. Returns `false` if any
+    /// of them fails validation against the UTXO set or the block would
+    /// exceed the configured limits.
+    pub fn add_utxo_block(&mut self, transactions: Vec<UtxoTransaction>) -> bool {
+        self.push_new_block(String::new(), Vec::new(), transactions, &mut |_| {})
+    }
+
+    /// Removes the latest block and restores the UTXO set and account nonces
+    /// to their state before that block. The genesis block is never removed.
+    pub fn rollback_block(&mut self) -> Option<Block> {
+        if self.chain.len() == 1 {
+            return None;
+        }
+        let tip = self.latest_block().header.index;
+        if self.checkpoints.iter().any(|checkpoint| checkpoint.height >= tip) {
+            warn!(index = tip, "rollback refused: block is covered by a checkpoint");
+            return None;
+        }
+        let block = self.chain.pop()?;
+        let undo = self.utxo_undo.pop().unwrap_or_default();
+        self.forget_validation(self.chain.len());
+        self.tx_index.disconnect(&block, &undo);
+        self.events.disconnect(&block, &undo);
+        self.utxo.rollback_block(&block.body.utxo_transactions, undo);
+        self.nonces.rollback_block(&block.body.transactions);
+        debug!(index = block.header.index, hash = %block.header.hash, "block rolled back");
+        let tip = self.latest_block();
+        let event = ChainEvent::ChainReorg {
+            disconnected: block.header.hash.clone(),
+            height: tip.header.index,
+            hash: tip.header.hash.clone(),
+        };
+        self.events.publish(event);
+        self.publish_difficulty_change(block.header.bits);
+        self.events.notify(|observer| observer.on_reorg(std::slice::from_ref(&block), &[]));
+        Some(block)
+    }
+
+    pub fn utxo_set(&self) -> &UtxoSet {
+        &self.utxo
+    }
+
+    /// The nonce `account`'s next transaction must carry.
+    pub fn next_nonce(&self, account: &str) -> u64 {
+        self.nonces.next(account)
+    }
+
+    /// Queues a transaction for mining. Returns `false` if its sender did
+    /// not sign it, its fee does not cover its payload, its nonce has
+    /// already been used, or the mempool turns it away: a duplicate, an
+    /// underpaid replacement, or too low a fee rate for a full pool.
+    /// Transactions past their time to live are dropped first.
+    pub fn add_transaction(&mut self, transaction: Transaction) -> bool {
+        if !transaction.is_signed_by_sender() {
+            warn!(id = %transaction.id(), sender = %transaction.sender, "transaction rejected: not signed by its sender");
+            return false;
+        }
+        if transaction.fee < transaction.minimum_fee() {
+            warn!(
+                id = %transaction.id(),
+                fee = transaction.fee,
+                required = transaction.minimum_fee(),
+                "transaction rejected: fee does not cover payload"
+            );
+            return false;
+        }
+        let next_nonce = self.nonces.next(&transaction.sender);
+        if transaction.nonce < next_nonce {
+            warn!(
+                id = %transaction.id(),
+                nonce = transaction.nonce,
+                next = next_nonce,
+                "transaction rejected: nonce already used"
+            );
+            return false;
+        }
+        let id = transaction.id();
+        let now = self.params.clock.now();
+        self.expire_pending(now);
+        match self.mempool.add(transaction.clone(), now) {
+            Ok(removed) => {
+                for removed in removed {
+                    debug!(id = %removed.id(), "pending transaction replaced or evicted");
+                    let reason = if removed.sender == transaction.sender && removed.nonce == transaction.nonce {
+                        DropReason::Replaced
+                    } else {
+                        DropReason::Evicted
+                    };
+                    let event = TransactionEvent::Dropped { id: removed.id(), reason };
+                    self.events.publish_pending(&removed, event);
+                }
+            }
+            Err(error) => {
+                warn!(id = %id, %error, "transaction rejected");
+                return false;
+            }
+        }
+        self.events.publish_pending(&transaction, TransactionEvent::Accepted { id: id.clone() });
+        self.events.publish(ChainEvent::NewTransaction { id });
+        self.events.notify(|observer| observer.on_transaction_accepted(&transaction));
+        true
+    }
+
+    /// Drops pending transactions that have outlived the mempool's time to
+    /// live as of `now`.
+    fn expire_pending(&mut self, now: i64) {
+        for transaction in self.mempool.expire(now) {
+            debug!(id = %transaction.id(), "pending transaction expired");
+            let event = TransactionEvent::Dropped {
+                id: transaction.id(),
+                reason: DropReason::Expired,
+            };
+            self.events.publish_pending(&transaction, event);
+        }
+    }
+
+    /// Where the transaction with `id` was most recently mined.
+    pub fn receipt(&self, id: &str) -> Option<&Receipt> {
+        self.tx_index.receipt(id)
+    }
+
+    /// Ids of the mined transactions that paid or spent from `address`,
+    /// oldest first.
+    pub fn history(&self, address: &str) -> &[String] {
+        self.tx_index.history(address)
+    }
+
+    pub(crate) fn tx_index(&self) -> &TxIndex {
+        &self.tx_index
+    }
+
+    /// Replaces the transaction index with one saved for this chain at its
+    /// current tip, which also covers the blocks pruned before it was
+    /// loaded.
+    pub(crate) fn restore_tx_index(&mut self, index: TxIndex) {
+        self.tx_index = index;
+    }
+
+    /// The most recently mined account transaction with `id`.
+    pub fn get_transaction(&self, id: &str) -> Option<&Transaction> {
+        let receipt = self.receipt(id).filter(|receipt| receipt.kind == TransactionKind::Account)?;
+        self.get_block_by_index(receipt.height)?.body.transactions.get(receipt.position)
+    }
+
+    /// Mined account transactions that `address` sent or received, oldest
+    /// first. Looked up through the transaction index rather than by
+    /// scanning the chain; pruned blocks contribute nothing.
+    pub fn find_transactions_by_address(&self, address: &str) -> Vec<&Transaction> {
+        // The nth time an id shows up in an address's history is the nth
+        // place it was mined, as both are appended block by block.
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        self.history(address)
+            .iter()
+            .filter_map(|id| {
+                let occurrence = seen.entry(id).or_default();
+                let receipt = self.tx_index.receipts(id).get(*occurrence);
+                *occurrence += 1;
+                let receipt = receipt.filter(|receipt| receipt.kind == TransactionKind::Account)?;
+                self.get_block_by_index(receipt.height)?.body.transactions.get(receipt.position)
+            })
+            .collect()
+    }
+
+    /// The most recently mined UTXO-model transaction with `id`.
+    pub fn utxo_transaction(&self, id: &str) -> Option<&UtxoTransaction> {
+        let receipt = self.receipt(id).filter(|receipt| receipt.kind == TransactionKind::Utxo)?;
+        self.get_block_by_index(receipt.height)?.body.utxo_transactions.get(receipt.position)
+    }
+
+    /// Returns a channel that receives every chain event from now on.
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns a channel that receives what happens from now on to the
+    /// transactions `filter` selects: entering and leaving the mempool, and
+    /// confirmations up to its depth.
+    pub fn watch(&mut self, filter: TransactionFilter) -> Receiver<TransactionEvent> {
+        self.events.watch(filter)
+    }
+
+    /// Calls `observer` back on every later change to the chain.
+    pub fn register_observer(&mut self, observer: impl ChainObserver + 'static) {
+        self.events.register(Box::new(observer));
+    }
+
+    /// Tells observers that `block`, received from elsewhere, is invalid.
+    pub(crate) fn report_invalid(&mut self, block: &Block) {
+        self.events.notify(|observer| observer.on_validation_failure(block));
+    }
+
+    pub fn pending_transactions(&self) -> &[Transaction] {
+        self.mempool.transactions()
+    }
+
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Packs the highest fee-per-byte pending transactions into a new block
+    /// until the transaction count or block size limit is reached, and pays
+    /// `miner` the block reward plus their fees. Transactions that do not fit
+    /// stay pending for a later block. Returns `false` if nothing could be
+    /// mined.
+    pub fn mine_pending_transactions(&mut self, miner: String) -> bool {
+        self.expire_pending(self.params.clock.now());
+        let (max_transactions, max_size) = self.pending_budget(&miner);
+        let next = NextBlock {
+            nonces: &self.nonces,
+            height: self.latest_block().header.index + 1,
+            median_time_past: self.median_time_past(),
+        };
+        let included = self.mempool.take(max_transactions, max_size, &next);
+        if included.is_empty() {
+            return false;
+        }
+        let transactions = with_reward(miner, included);
+        self.push_new_block(String::new(), transactions, Vec::new(), &mut |_| {})
+    }
+
+    /// An unmined block on the tip holding what `mine_pending_transactions`
+    /// would pack, with every header field but the nonce filled in. The
+    /// mempool is left alone; the block joins the chain through
+    /// `accept_block` once someone mines it. `BlockTemplateBuilder` builds
+    /// one under a tighter size budget or a deadline.
+    pub fn block_template(&self, miner: String) -> Block {
+        BlockTemplateBuilder::new(self, miner).build()
+    }
+
+    /// Most pending transactions, and their total size, that fit in a block
+    /// next to the reward paying `miner`.
+    fn pending_budget(&self, miner: &str) -> (usize, usize) {
+        (
+            self.params.limits.max_transactions.saturating_sub(1),
+            self.params
+                .limits
+                .max_block_size
+                .saturating_sub(block_overhead(self.params.authority.is_some(), miner)),
+        )
+    }
+
+    /// The timestamp for a block on the tip. A clock running behind the tip
+    /// must not produce an invalid block.
+    fn next_timestamp(&self) -> i64 {
+        let earliest = self.latest_block().header.timestamp.max(self.median_time_past().saturating_add(1));
+        self.params.clock.now().max(earliest)
+    }
+
+    /// The median timestamp of the latest blocks, which the next block must
+    /// be stamped after and its time-locks are measured against.
+    pub fn median_time_past(&self) -> i64 {
+        consensus::median_time_past(&self.timestamps_before(self.chain.len()))
+    }
+
+    /// Timestamps of the blocks before the one at `offset`, pruned ones
+    /// included, oldest first, as far back as the median time past needs.
+    fn timestamps_before(&self, offset: usize) -> Vec<i64> {
+        self.headers_before(offset).iter().map(|header| header.timestamp).collect()
+    }
+
+    /// Headers of the blocks before the one at `offset`, as far back as the
+    /// median time past needs.
+    fn headers_before(&self, offset: usize) -> Vec<BlockHeader> {
+        let earlier = self.pruned_headers.len() + offset;
+        let skip = earlier.saturating_sub(consensus::MEDIAN_TIME_BLOCKS);
+        self.headers().take(earlier).skip(skip).cloned().collect()
+    }
+
+    fn push_new_block(
+        &mut self,
+        data: String,
+        transactions: Vec<Transaction>,
+        utxo_transactions: Vec<UtxoTransaction>,
+        progress: &mut dyn FnMut(u64),
+    ) -> bool {
+        let tip = &self.latest_block().header;
+        let body = BlockBody {
+            data,
+            transactions,
+            utxo_transactions,
+        };
+        let timestamp = self.next_timestamp();
+        let mut new_block = Block::unmined(tip.index + 1, timestamp, tip.hash.clone(), body);
+        if !self.params.limits.allows_body(&new_block.body, self.params.authority.is_some()) {
+            warn!(
+                size = new_block.body.size_bytes(),
+                transactions = new_block.body.transactions.len(),
+                "block rejected: exceeds block limits"
+            );
+            return false;
+        }
+        let sealer = match &self.params.authority {
+            Some(authority) => match authority.key_for(new_block.header.index) {
+                Some(key) => Some(key.clone()),
+                None => {
+                    warn!(index = new_block.header.index, "block rejected: not this node's turn to seal");
+                    return false;
+                }
+            },
+            None => None,
+        };
+        if let Err(err) = self.nonces.apply_block(&new_block.body.transactions) {
+            warn!(error = %err, "block rejected: transaction out of sequence");
+            return false;
+        }
+        let undo = match self.utxo.apply_block(&new_block.body.utxo_transactions, new_block.header.index, self.params.network) {
+            Ok(undo) => undo,
+            Err(err) => {
+                self.nonces.rollback_block(&new_block.body.transactions);
+                warn!(error = %err, "block rejected: invalid UTXO transaction");
+                return false;
+            }
+        };
+        new_block.header.state_root = state_root(&self.utxo, &self.nonces);
+        new_block.header.nonce = self.params.nonce_seed;
+        new_block.header.extra_nonce = self.params.extra_nonce;
+        new_block.header.network = self.params.network;
+        let target = self.next_target(new_block.header.timestamp);
+        match &sealer {
+            Some(key) => new_block.seal_block(target, key),
+            None => new_block.mine_block_with_progress(target, progress),
+        }
+        if !self.rules().matches_checkpoints(&new_block.header) {
+            warn!(index = new_block.header.index, hash = %new_block.header.hash, "block rejected: checkpoint mismatch");
+            self.utxo.rollback_block(&new_block.body.utxo_transactions, undo);
+            self.nonces.rollback_block(&new_block.body.transactions);
+            return false;
+        }
+        debug!(index = new_block.header.index, hash = %new_block.header.hash, "block appended");
+        self.record_append(&new_block);
+        self.events.publish(ChainEvent::NewBlock {
+            index: new_block.header.index,
+            hash: new_block.header.hash.clone(),
+        });
+        self.events.notify(|observer| observer.on_block_added(&new_block));
+        let bits = self.latest_block().header.bits;
+        self.push_validated(new_block, undo);
+        self.publish_difficulty_change(bits);
+        self.apply_pruning();
+        true
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Block> {
+        self.chain.iter()
+    }
+
+    pub fn get_block_by_index(&self, index: u32) -> Option<&Block> {
+        let offset = index.checked_sub(self.base_height())?;
+        self.chain.get(offset as usize)
+    }
+
+    /// Mutable access to a held block, for deliberately corrupting it.
+    /// Validation of it and every later block is checked again.
+    pub(crate) fn block_mut(&mut self, index: u32) -> Option<&mut Block> {
+        let offset = index.checked_sub(self.base_height())? as usize;
+        self.forget_validation(offset);
+        self.chain.get_mut(offset)
+    }
+
+    pub fn get_block_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.chain.iter().find(|block| block.header.hash == hash)
+    }
+
+    /// Returns the blocks whose indices fall within `range`, clamped to the
+    /// blocks currently held.
+    pub fn range<R: RangeBounds<u32>>(&self, range: R) -> &[Block] {
+        let base = self.base_height() as usize;
+        let len = base + self.chain.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start as usize,
+            Bound::Excluded(&start) => start as usize + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end as usize + 1,
+            Bound::Excluded(&end) => end as usize,
+            Bound::Unbounded => len,
+        };
+        let start = start.max(base);
+        let end = end.min(len);
+        if start >= end {
+            return &[];
+        }
+        &self.chain[start - base..end - base]
+    }
+
+    /// Encoded size of every block held, as `Block::size_bytes` counts it.
+    pub fn size_bytes(&self) -> usize {
+        self.chain.iter().map(Block::size_bytes).sum()
+    }
+
+    /// Block interval, difficulty, work, throughput, fee and size metrics
+    /// over the blocks `range` selects, as `range` clamps them.
+    pub fn stats<R: RangeBounds<u32>>(&self, range: R) -> ChainStats {
+        ChainStats::compute(self.range(range))
+    }
+
+    /// Whether every block held is valid. Only blocks whose earlier result
+    /// is no longer cached are checked again.
+    pub fn is_valid_chain(&self) -> bool {
+        let validated = self.validated.get();
+        if validated >= self.chain.len() {
+            return true;
+        }
+        let valid = self.validate_suffix(self.base_height() + validated as u32);
+        if valid {
+            self.validated.set(self.chain.len());
+        }
+        valid
+    }
+
+    /// Checks the blocks from `from_height` up to the tip again, ignoring
+    /// cached results, against the state they were applied to.
+    pub fn validate_suffix(&self, from_height: u32) -> bool {
+        let start = (from_height.saturating_sub(self.base_height()) as usize).max(1);
+        if start >= self.chain.len() {
+            return true;
+        }
+        let (utxo, nonces) = if start == 1 {
+            (self.base_utxo.clone(), self.base_nonces.clone())
+        } else {
+            let mut utxo = self.utxo.clone();
+            let mut nonces = self.nonces.clone();
+            for (block, undo) in self.chain[start..].iter().zip(&self.utxo_undo[start..]).rev() {
+                utxo.rollback_block(&block.body.utxo_transactions, undo.clone());
+                nonces.rollback_block(&block.body.transactions);
+            }
+            (utxo, nonces)
+        };
+        self.rules()
+            .validate_blocks(&self.chain[start - 1..], &self.timestamps_before(start - 1), utxo, nonces)
+            .is_some()
+    }
+
+    /// Validates a block received from elsewhere and appends it if it
+    /// extends the current tip. Transactions it includes leave the mempool.
+    pub fn accept_block(&mut self, block: Block) -> bool {
+        let mut utxo = std::mem::take(&mut self.utxo);
+        let mut nonces = std::mem::take(&mut self.nonces);
+        let median = self.median_time_past();
+        let undo = self.rules().validate_block(&block, self.latest_block(), median, &mut utxo, &mut nonces);
+        self.utxo = utxo;
+        self.nonces = nonces;
+        let Some(undo) = undo else {
+            self.report_invalid(&block);
+            return false;
+        };
+        debug!(index = block.header.index, hash = %block.header.hash, "block accepted");
+        self.record_append(&block);
+        self.mempool.remove_confirmed(&self.nonces);
+        self.expire_pending(self.params.clock.now());
+        self.events.publish(ChainEvent::NewBlock {
+            index: block.header.index,
+            hash: block.header.hash.clone(),
+        });
+        self.events.notify(|observer| observer.on_block_added(&block));
+        let bits = self.latest_block().header.bits;
+        self.push_validated(block, undo);
+        self.publish_difficulty_change(bits);
+        self.apply_pruning();
+        true
+    }
+
+    /// Swaps in `candidate` if it shares our oldest block, carries strictly
+    /// more work than the current chain and is valid throughout. Our blocks
+    /// past the fork point are undone through the undo log and the
+    /// candidate's are applied in their place; transactions only the old
+    /// branch held return to the mempool.
+    pub fn try_replace(&mut self, candidate: Vec<Block>) -> bool {
+        if candidate.first() != self.chain.first() {
+            return false;
+        }
+        let fork = self
+            .chain
+            .iter()
+            .zip(&candidate)
+            .take_while(|(ours, theirs)| ours == theirs)
+            .count();
+        let work = |blocks: &[Block]| blocks.iter().map(|block| block.header.work()).sum::<Work>();
+        if work(&candidate[fork..]) <= work(&self.chain[fork..]) {
+            return false;
+        }
+
+        let mut utxo = self.utxo.clone();
+        let mut nonces = self.nonces.clone();
+        for (block, undo) in self.chain[fork..].iter().zip(&self.utxo_undo[fork..]).rev() {
+            utxo.rollback_block(&block.body.utxo_transactions, undo.clone());
+            nonces.rollback_block(&block.body.transactions);
+        }
+        let earlier = self.timestamps_before(fork - 1);
+        let Some((utxo, nonces, utxo_undo)) = self.rules().validate_blocks(&candidate[fork - 1..], &earlier, utxo, nonces) else {
+            if let Some(tip) = candidate.last() {
+                self.report_invalid(tip);
+            }
+            return false;
+        };
+
+        let disconnected = self.latest_block().header.hash.clone();
+        let bits = self.latest_block().header.bits;
+        let orphaned: Vec<Block> = self.chain.drain(fork..).collect();
+        for (block, undo) in orphaned.iter().zip(self.utxo_undo.drain(fork..)).rev() {
+            self.tx_index.disconnect(block, &undo);
+            self.events.disconnect(block, &undo);
+        }
+        self.forget_validation(fork);
+        for (block, undo) in candidate.into_iter().skip(fork).zip(utxo_undo.into_iter().skip(1)) {
+            self.push_validated(block, undo);
+        }
+        self.utxo = utxo;
+        self.nonces = nonces;
+        let now = self.params.clock.now();
+        for transaction in orphaned.iter().flat_map(|block| &block.body.transactions) {
+            if !transaction.is_reward() {
+                // Duplicates and losing replacements are already covered.
+                let _ = self.mempool.add(transaction.clone(), now);
+            }
+        }
+        self.mempool.remove_confirmed(&self.nonces);
+
+        let tip = self.latest_block();
+        info!(
+            height = tip.header.index,
+            hash = %tip.header.hash,
+            depth = orphaned.len(),
+            "chain replaced"
+        );
+        metrics::registry().chain_height.set(tip.header.index as u64);
+        let event = ChainEvent::ChainReorg {
+            disconnected,
+            height: tip.header.index,
+            hash: tip.header.hash.clone(),
+        };
+        self.events.publish(event);
+        self.publish_difficulty_change(bits);
+        self.events.notify(|observer| observer.on_reorg(&orphaned, &self.chain[fork..]));
+        self.apply_pruning();
+        true
+    }
+
+    /// Proof that the account transaction with `id` was mined, in the
+    /// block it was most recently mined in.
+    pub fn prove_transaction(&self, id: &str) -> Option<InclusionProof> {
+        let receipt = self.receipt(id).filter(|receipt| receipt.kind == TransactionKind::Account)?;
+        let block = self.get_block_by_index(receipt.height)?;
+        Some(InclusionProof {
+            block_hash: block.header.hash.clone(),
+            merkle: block.body.prove_transaction(id)?,
+        })
+    }
+
+    /// Every header from the oldest known, pruned blocks included.
+    pub fn headers(&self) -> impl Iterator<Item = &BlockHeader> {
+        self.pruned_headers.iter().chain(self.chain.iter().map(|block| &block.header))
+    }
+
+    /// Total proof of work of every header from the oldest known.
+    pub fn chain_work(&self) -> Work {
+        self.headers().map(BlockHeader::work).sum()
+    }
+
+    /// Checks a peer's header chain, which must start at our oldest header.
+    /// If it is valid and carries strictly more work than our chain,
+    /// returns the headers past the last block we share: the bodies still
+    /// to fetch.
+    pub fn missing_headers<'a>(&self, headers: &'a [BlockHeader]) -> Option<&'a [BlockHeader]> {
+        if headers.first() != self.headers().next() {
+            return None;
+        }
+        let shared = headers
+            .iter()
+            .zip(self.headers())
+            .take_while(|(theirs, ours)| theirs == ours)
+            .count();
+        let theirs: Work = headers[shared..].iter().map(BlockHeader::work).sum();
+        if theirs <= self.headers().skip(shared).map(BlockHeader::work).sum() {
+            return None;
+        }
+        if !self.rules().check_headers(headers) {
+            metrics::registry().validation_failures.inc();
+            return None;
+        }
+        Some(&headers[shared..])
+    }
+
+    /// Swaps in `blocks` after the block they build on, which we must hold,
+    /// if the result is a valid chain with more work. See `try_replace`.
+    pub fn try_replace_suffix(&mut self, blocks: Vec<Block>) -> bool {
+        let Some(first) = blocks.first() else {
+            return false;
+        };
+        let Some(parent) = self.get_block_by_hash(&first.header.prev_hash) else {
+            return false;
+        };
+        let shared = (parent.header.index - self.base_height()) as usize + 1;
+        let mut candidate = self.chain[..shared].to_vec();
+        candidate.extend(blocks);
+        self.try_replace(candidate)
+    }
+
+    fn rules(&self) -> Rules<'_> {
+        Rules {
+            params: &self.params,
+            checkpoints: &self.checkpoints,
+        }
+    }
+
+    /// Publishes `difficulty_change` if the tip's target is no longer
+    /// `bits`, that of the tip before the chain changed.
+    fn publish_difficulty_change(&mut self, bits: u32) {
+        let tip = &self.latest_block().header;
+        if tip.bits != bits {
+            let event = ChainEvent::DifficultyChange {
+                height: tip.index,
+                bits: tip.bits,
+            };
+            self.events.publish(event);
+        }
+    }
+
+    /// Appends a block that has already passed validation, with the undo
+    /// data from applying it, so it is never checked again.
+    fn push_validated(&mut self, block: Block, undo: BlockUndo) {
+        if self.validated.get() == self.chain.len() {
+            self.validated.set(self.chain.len() + 1);
+        }
+        self.tx_index.connect(&block, &undo);
+        self.events.connect(&block, &undo);
+        self.chain.push(block);
+        self.utxo_undo.push(undo);
+    }
+
+    /// Drops cached validation for blocks from `offset` on.
+    fn forget_validation(&self, offset: usize) {
+        self.validated.set(self.validated.get().min(offset));
+    }
+
+    fn record_append(&self, block: &Block) {
+        let metrics = metrics::registry();
+        metrics.chain_height.set(block.header.index as u64);
+        let interval = block.header.timestamp - self.latest_block().header.timestamp;
+        metrics.block_interval_seconds.observe(interval.max(0) as u64);
+    }
+}
+
+/// Hex-encoded SHA-256 commitment to the whole chain state: the UTXO set
+/// and every account's next nonce.
+pub fn state_root(utxo: &UtxoSet, nonces: &AccountNonces) -> String {
+    codec::hash_hex(|writer| {
+        codec::write_str(writer, &utxo.root())?;
+        codec::write_str(writer, &nonces.root())
+    })
+}
+
+fn snapshot_of(block: &Block, headers: Vec<BlockHeader>, utxo: &UtxoSet, nonces: &AccountNonces) -> Snapshot {
+    Snapshot {
+        block: block.clone(),
+        headers,
+        state_root: state_root(utxo, nonces),
+        utxos: utxo
+            .iter()
+            .map(|(outpoint, output)| (outpoint.clone(), output.clone()))
+            .collect(),
+        nonces: nonces.clone(),
+    }
+}
+
+/// The UTXO set the genesis block under `params` leaves: its premine.
+/// Applied directly, since no block reward caps it.
+pub(crate) fn genesis_utxo(params: &ChainParams) -> UtxoSet {
+    let premine = UtxoTransaction::new(Vec::new(), params.premine.clone());
+    let txid = premine.id();
+    premine
+        .outputs
+        .into_iter()
+        .enumerate()
+        .map(|(vout, output)| {
+            let outpoint = OutPoint {
+                txid: txid.clone(),
+                vout: vout as u32,
+            };
+            (outpoint, output)
+        })
+        .collect()
+}
+
+/// Bytes of a block besides its pending transactions: the finished header,
+/// sealed or not, and a body holding just the reward paying `miner`.
+pub(crate) fn block_overhead(sealed: bool, miner: &str) -> usize {
+    let body = BlockBody {
+        transactions: vec![Transaction::reward(miner.to_owned(), 0)],
+        ..BlockBody::default()
+    };
+    BlockHeader::finished_size(sealed) + body.size_bytes()
+}
+
+/// `included` behind a reward paying `miner` the block reward plus their
+/// fees.
+fn with_reward(miner: String, included: Vec<Transaction>) -> Vec<Transaction> {
+    let fees = included.iter().fold(0u64, |total, tx| total.saturating_add(tx.fee));
+    let mut transactions = vec![Transaction::reward(miner, BLOCK_REWARD.saturating_add(fees))];
+    transactions.extend(included);
+    transactions
+}
+
+impl<'a> IntoIterator for &'a Blockchain {
+    type Item = &'a Block;
+    type IntoIter = std::slice::Iter<'a, Block>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::accounts::{account, payment, signed};
+
+    /// The block at `offset` for a test to tamper with. Like every change to
+    /// a held block, it drops the cached validation from there on.
+    fn tamper(blockchain: &mut Blockchain, offset: usize) -> &mut Block {
+        blockchain.forget_validation(offset);
+        &mut blockchain.chain[offset]
+    }
+
+    #[cfg(feature = "system-clock")]
+    #[test]
+    fn test_block_creation() {
+        let block = Block::new(1, "Test Data".to_owned(), "PreviousHash".to_owned());
+
+        assert_eq!(block.header.index, 1);
+        assert_eq!(block.body.data, "Test Data");
+        assert_eq!(block.header.prev_hash, "PreviousHash");
+        assert!(block.header.hash.starts_with("0000"));
+    }
+
+    #[test]
+    fn test_blockchain_initialization() {
+        let blockchain = Blockchain::new();
+        let genesis_block = &blockchain.chain[0];
+
+        assert_eq!(blockchain.chain.len(), 1);
+        assert_eq!(genesis_block.header.index, 0);
+        assert_eq!(genesis_block.body.data, "Genesis Block");
+        assert_eq!(genesis_block.header.prev_hash, "");
+        assert!(genesis_block.header.hash.starts_with("0000"));
+    }
+
+    #[test]
+    fn test_add_block() {
+        let mut blockchain = Blockchain::new();
+
+        blockchain.add_block("First block data".to_owned());
+
+        assert_eq!(blockchain.chain.len(), 2);
+
+        let latest_block = &blockchain.chain[1];
+        let previous_block = &blockchain.chain[0];
+
+        assert_eq!(latest_block.header.index, 1);
+        assert_eq!(latest_block.body.data, "First block data");
+        assert_eq!(latest_block.header.prev_hash, previous_block.header.hash);
+        assert!(latest_block.header.hash.starts_with("0000"));
+    }
+
+    #[test]
+    fn test_multiple_blocks() {
+        let mut blockchain = Blockchain::new();
+
+        blockchain.add_block("Block 1 data".to_owned());
+        blockchain.add_block("Block 2 data".to_owned());
+        blockchain.add_block("Block 3 data".to_owned());
+
+        assert_eq!(blockchain.chain.len(), 4);
+        assert!(blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_genesis_block_consistency() {
+        let blockchain_1 = Blockchain::new();
+        let blockchain_2 = Blockchain::new();
+
+        assert_eq!(blockchain_1.chain[0].header.hash, blockchain_2.chain[0].header.hash);
+        assert_eq!(blockchain_1.chain[0].body.data, "Genesis Block");
+        assert_eq!(blockchain_1.chain[0].header.prev_hash, "");
+    }
+
+    #[test]
+    fn test_premine_funds_genesis() {
+        let params = ChainParams {
+            premine: vec![TxOutput::new(1_000, "alice".to_owned()), TxOutput::new(5, "bob".to_owned())],
+            ..ChainParams::default()
+        };
+        let mut blockchain = Blockchain::with_params(params.clone());
+        assert_eq!(blockchain.utxo_set().balance("alice"), 1_000);
+        assert_eq!(blockchain.utxo_set().balance("bob"), 5);
+        assert_ne!(blockchain.latest_block().header.hash, Blockchain::new().latest_block().header.hash);
+        assert!(blockchain.checkpoints().is_empty());
+
+        assert!(blockchain.add_block("after the premine".to_owned()));
+        let blocks: Vec<Block> = blockchain.iter().cloned().collect();
+        assert!(validate(&blocks, &params));
+        assert!(!validate(&blocks, &ChainParams::default()));
+        assert!(blockchain.base_snapshot().verify().is_ok());
+    }
+
+    #[test]
+    fn test_is_valid_chain() {
+        let mut blockchain = Blockchain::new();
+
+        blockchain.add_block("First block data".to_owned());
+        blockchain.add_block("Second block data".to_owned());
+
+        assert!(blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_tampered_block_validation() {
+        let mut blockchain = Blockchain::new();
+
+        blockchain.add_block("First block data".to_owned());
+
+        tamper(&mut blockchain, 1).body.data = "Tampered Data".to_owned();
+
+        assert!(!blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_validate_suffix_finds_tampering() {
+        let mut blockchain = Blockchain::with_params(ChainParams::testing());
+        for i in 1..=3 {
+            blockchain.add_block(format!("Block {} data", i));
+        }
+
+        blockchain.chain[1].body.data = "Tampered Data".to_owned();
+        assert!(blockchain.validate_suffix(2));
+        assert!(!blockchain.validate_suffix(1));
+        assert!(!blockchain.validate_suffix(0));
+    }
+
+    #[test]
+    fn test_blocks_are_validated_once() {
+        let mut blockchain = Blockchain::with_params(ChainParams::testing());
+        for i in 1..=3 {
+            blockchain.add_block(format!("Block {} data", i));
+        }
+        assert_eq!(blockchain.validated.get(), 4);
+
+        // Held blocks are not checked again while their result is cached.
+        blockchain.chain[2].body.data = "Tampered Data".to_owned();
+        assert!(blockchain.is_valid_chain());
+        assert_eq!(blockchain.validated.get(), 4);
+
+        let hash = blockchain.chain[2].header.hash.clone();
+        blockchain.add_checkpoint(Checkpoint::new(2, hash));
+        assert_eq!(blockchain.validated.get(), 2);
+        assert!(!blockchain.is_valid_chain());
+        assert_eq!(blockchain.validated.get(), 2);
+    }
+
+    #[test]
+    fn test_large_blockchain_performance() {
+        let mut blockchain = Blockchain::new();
+
+        for i in 1..=10 {
+            blockchain.add_block(format!("Block {} data", i));
+        }
+
+        assert_eq!(blockchain.chain.len(), 11);
+        assert!(blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_block_lookup() {
+        let mut blockchain = Blockchain::new();
+
+        blockchain.add_block("First block data".to_owned());
+        blockchain.add_block("Second block data".to_owned());
+
+        let second = blockchain.get_block_by_index(2).unwrap();
+        assert_eq!(second.body.data, "Second block data");
+        assert_eq!(blockchain.get_block_by_hash(&second.header.hash).unwrap().header.index, 2);
+        assert!(blockchain.get_block_by_index(3).is_none());
+        assert!(blockchain.get_block_by_hash("missing").is_none());
+    }
+
+    #[test]
+    fn test_iteration_and_range() {
+        let mut blockchain = Blockchain::new();
+
+        blockchain.add_block("Block 1 data".to_owned());
+        blockchain.add_block("Block 2 data".to_owned());
+        blockchain.add_block("Block 3 data".to_owned());
+
+        let indices: Vec<u32> = blockchain.iter().map(|block| block.header.index).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3]);
+
+        let range: Vec<u32> = blockchain.range(1..3).iter().map(|block| block.header.index).collect();
+        assert_eq!(range, vec![1, 2]);
+        assert_eq!(blockchain.range(2..).len(), 2);
+        assert_eq!(blockchain.range(..=10).len(), 4);
+        assert!(blockchain.range(5..).is_empty());
+    }
+
+    #[test]
+    fn test_find_transactions_by_address() {
+        let mut blockchain = Blockchain::new();
+        let sent = payment("alice", "bob", 5, 1);
+        let received = payment("carol", "alice", 3, 0);
+        assert!(blockchain.add_transaction(sent.clone()));
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        assert!(blockchain.add_transaction(received.clone()));
+        assert!(blockchain.add_utxo_block(vec![UtxoTransaction::coinbase(account("alice"), 50)]));
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+
+        assert_eq!(blockchain.find_transactions_by_address(&account("alice")), [&sent, &received]);
+        assert_eq!(blockchain.find_transactions_by_address(&account("bob")), [&sent]);
+        assert_eq!(blockchain.find_transactions_by_address(&account("carol")), [&received]);
+        let rewards = blockchain.find_transactions_by_address("miner");
+        assert_eq!(rewards.len(), 2);
+        assert!(rewards.iter().all(|transaction| transaction.is_reward()));
+        assert!(blockchain.find_transactions_by_address(&account("dave")).is_empty());
+
+        assert!(blockchain.rollback_block().is_some());
+        assert_eq!(blockchain.find_transactions_by_address(&account("alice")), [&sent]);
+        assert!(blockchain.find_transactions_by_address(&account("carol")).is_empty());
+    }
+
+    #[test]
+    fn test_mine_pending_transactions_respects_transaction_limit() {
+        let mut blockchain = Blockchain::with_limits(BlockLimits {
+            max_transactions: 3,
+            ..BlockLimits::default()
+        });
+
+        for amount in 1..=3 {
+            let transaction = Transaction {
+                nonce: amount - 1,
+                ..Transaction::new(String::new(), account("bob"), amount)
+            };
+            assert!(blockchain.add_transaction(signed("alice", transaction)));
+        }
+
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        assert_eq!(blockchain.latest_block().body.transactions.len(), 3);
+        assert_eq!(blockchain.pending_transactions().len(), 1);
+        assert_eq!(blockchain.pending_transactions()[0].amount, 3);
+        assert!(blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_oversized_blocks_are_rejected() {
+        let mut blockchain = Blockchain::with_limits(BlockLimits {
+            max_block_size: 512,
+            ..BlockLimits::default()
+        });
+
+        assert!(!blockchain.add_block("x".repeat(1024)));
+        assert_eq!(blockchain.chain.len(), 1);
+
+        assert!(blockchain.add_block("small".to_owned()));
+        blockchain.params.limits.max_block_size = 16;
+        assert!(!blockchain.validate_suffix(0));
+    }
+
+    #[test]
+    fn test_utxo_blocks_update_and_roll_back_the_utxo_set() {
+        use ed25519_dalek::SigningKey;
+        use utxo::{OutPoint, TxOutput, address};
+
+        let miner = SigningKey::from_bytes(&[7; 32]);
+        let miner_address = address(&miner.verifying_key(), Network::Mainnet);
+        let recipient = address(&SigningKey::from_bytes(&[8; 32]).verifying_key(), Network::Mainnet);
+        let mut blockchain = Blockchain::new();
+
+        let coinbase = UtxoTransaction::coinbase(miner_address.clone(), BLOCK_REWARD);
+        let funding = OutPoint { txid: coinbase.id(), vout: 0 };
+        assert!(blockchain.add_utxo_block(vec![coinbase]));
+
+        let mut payment = UtxoTransaction::new(
+            vec![funding.clone()],
+            vec![TxOutput::new(50, recipient.clone())],
+        );
+        payment.sign(&miner);
+        assert!(blockchain.add_utxo_block(vec![payment.clone()]));
+        assert_eq!(blockchain.utxo_set().balance(&recipient), 50);
+        assert!(blockchain.is_valid_chain());
+
+        assert!(!blockchain.add_utxo_block(vec![payment]));
+        assert_eq!(blockchain.chain.len(), 3);
+
+        assert_eq!(blockchain.rollback_block().unwrap().header.index, 2);
+        assert_eq!(blockchain.utxo_set().balance(&recipient), 0);
+        assert_eq!(blockchain.utxo_set().balance(&miner_address), BLOCK_REWARD);
+    }
+
+    #[test]
+    fn test_bootstrap_from_snapshot() {
+        use ed25519_dalek::SigningKey;
+        use utxo::address;
+
+        let miner = address(&SigningKey::from_bytes(&[7; 32]).verifying_key(), Network::Mainnet);
+        let mut blockchain = Blockchain::new();
+        blockchain.add_block("First block data".to_owned());
+        assert!(blockchain.add_utxo_block(vec![UtxoTransaction::coinbase(miner.clone(), 50)]));
+
+        let snapshot = blockchain.snapshot();
+        assert_eq!(snapshot.height(), 2);
+        assert_eq!(
+            Blockchain::from_snapshot(snapshot.clone(), "other").unwrap_err(),
+            SnapshotError::UntrustedBlock {
+                expected: "other".to_owned(),
+                found: snapshot.block_hash().to_owned(),
+            }
+        );
+
+        let mut tampered = snapshot.clone();
+        tampered.utxos[0].1.value = 1_000;
+        assert_eq!(
+            Blockchain::from_snapshot(tampered.clone(), snapshot.block_hash()).unwrap_err(),
+            SnapshotError::StateRootMismatch
+        );
+        tampered.state_root = state_root(&tampered.utxos.iter().cloned().collect(), &tampered.nonces);
+        assert_eq!(
+            Blockchain::from_snapshot(tampered, snapshot.block_hash()).unwrap_err(),
+            SnapshotError::StateRootMismatch
+        );
+        let mut unlinked = snapshot.clone();
+        assert_eq!(unlinked.headers.len(), 2);
+        unlinked.headers.pop();
+        assert_eq!(
+            Blockchain::from_snapshot(unlinked, snapshot.block_hash()).unwrap_err(),
+            SnapshotError::UnlinkedHeaders
+        );
+
+        let trusted = snapshot.block_hash().to_owned();
+        let mut synced = Blockchain::from_snapshot(snapshot, &trusted).unwrap();
+        assert_eq!(synced.utxo_set().balance(&miner), 50);
+        assert!(synced.add_block("Third block data".to_owned()));
+        assert_eq!(synced.latest_block().header.index, 3);
+        assert_eq!(synced.get_block_by_index(3).unwrap().body.data, "Third block data");
+        assert!(synced.get_block_by_index(1).is_none());
+        assert_eq!(synced.range(..).len(), 2);
+        assert!(synced.range(..2).is_empty());
+        assert!(synced.is_valid_chain());
+        assert!(synced.rollback_block().is_some());
+        assert!(synced.rollback_block().is_none());
+    }
+
+    #[test]
+    fn test_mining_prioritises_fees_and_pays_the_miner() {
+        let mut blockchain = Blockchain::with_limits(BlockLimits {
+            max_transactions: 2,
+            ..BlockLimits::default()
+        });
+
+        blockchain.add_transaction(payment("alice", "bob", 5, 1));
+        blockchain.add_transaction(payment("carol", "bob", 5, 7));
+
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        let block = blockchain.latest_block();
+        assert_eq!(block.body.transactions[0], Transaction::reward("miner".to_owned(), BLOCK_REWARD + 7));
+        assert_eq!(block.body.transactions[1].sender, account("carol"));
+        assert_eq!(blockchain.pending_transactions()[0].sender, account("alice"));
+        assert!(blockchain.is_valid_chain());
+
+        let last = blockchain.chain.len() - 1;
+        let block = tamper(&mut blockchain, last);
+        block.body.transactions[0].amount += 1;
+        block.mine_block(Target::from_leading_zeros(DIFFICULTY));
+        assert!(!blockchain.is_valid_chain());
+
+        let block = tamper(&mut blockchain, last);
+        block.body.transactions[0].amount = BLOCK_REWARD;
+        block.body.transactions[1] = payment("carol", "bob", 5, u64::MAX);
+        block.body.transactions.push(payment("dave", "bob", 5, u64::MAX));
+        block.mine_block(Target::from_leading_zeros(DIFFICULTY));
+        assert!(!consensus::has_valid_reward(block));
+        assert!(!blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_blocks_commit_to_the_state() {
+        let params = ChainParams::testing();
+        let mut blockchain = Blockchain::with_params(params.clone());
+        let genesis_root = blockchain.latest_block().header.state_root.clone();
+        assert!(blockchain.add_transaction(payment("alice", "bob", 5, 1)));
+        let template = blockchain.block_template("miner".to_owned());
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+
+        let block = blockchain.rollback_block().unwrap();
+        assert_eq!(block.header.state_root, template.header.state_root);
+        assert_ne!(block.header.state_root, genesis_root);
+
+        let mut diverged = block.clone();
+        diverged.header.state_root = genesis_root;
+        diverged.mine_block(params.target);
+        assert!(!blockchain.accept_block(diverged));
+        assert!(blockchain.accept_block(block));
+        assert_eq!(blockchain.latest_block().header.state_root, blockchain.snapshot().state_root);
+    }
+
+    #[test]
+    fn test_time_locked_transactions_wait_for_their_height() {
+        let params = ChainParams::testing();
+        let mut blockchain = Blockchain::with_params(params.clone());
+        let locked = signed(
+            "alice",
+            Transaction {
+                lock_until: 2,
+                ..payment("alice", "bob", 5, 1)
+            },
+        );
+        assert!(blockchain.add_transaction(locked.clone()));
+        assert!(!blockchain.mine_pending_transactions("miner".to_owned()));
+        assert_eq!(blockchain.pending_transactions(), std::slice::from_ref(&locked));
+
+        let mut early = blockchain.block_template("miner".to_owned());
+        early.body.transactions.push(locked.clone());
+        early.header.merkle_root = early.body.merkle_root();
+        early.mine_block(params.target);
+        assert!(!blockchain.accept_block(early));
+
+        assert!(blockchain.add_block("filler".to_owned()));
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        assert_eq!(blockchain.latest_block().body.transactions[1], locked);
+        assert!(blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_timestamps_follow_the_median_time_past() {
+        use consensus::MAX_FUTURE_DRIFT;
+
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let params = ChainParams {
+            clock: clock.clone(),
+            ..ChainParams::testing()
+        };
+        let mut blockchain = Blockchain::with_params(params.clone());
+        assert!(blockchain.add_block("First".to_owned()));
+        let stamped = |blockchain: &Blockchain, timestamp: i64, transactions: &[Transaction]| {
+            let mut block = blockchain.block_template("miner".to_owned());
+            block.header.timestamp = timestamp;
+            block.body.transactions.extend_from_slice(transactions);
+            let mut nonces = blockchain.nonces.clone();
+            nonces.apply_block(&block.body.transactions).unwrap();
+            block.header.state_root = state_root(&blockchain.utxo, &nonces);
+            block.mine_block(params.target);
+            block
+        };
+
+        // Of the genesis block and the first, the median is the later.
+        let now = clock.now();
+        assert_eq!(blockchain.median_time_past(), now);
+        assert!(!blockchain.accept_block(stamped(&blockchain, now, &[])));
+        assert!(!blockchain.accept_block(stamped(&blockchain, now + MAX_FUTURE_DRIFT + 1, &[])));
+        assert!(blockchain.accept_block(stamped(&blockchain, now + MAX_FUTURE_DRIFT, &[])));
+        assert_eq!(blockchain.median_time_past(), now);
+
+        // A block stamped past a time-lock cannot include it until the
+        // median catches up.
+        let locked = signed(
+            "alice",
+            Transaction {
+                lock_until: (now + 60) as u64,
+                ..payment("alice", "bob", 5, 1)
+            },
+        );
+        let tip = blockchain.latest_block().header.timestamp;
+        assert!(!blockchain.accept_block(stamped(&blockchain, tip, std::slice::from_ref(&locked))));
+        clock.set(tip);
+        assert!(blockchain.add_block("Catching up".to_owned()));
+        assert_eq!(blockchain.median_time_past(), tip);
+        assert!(blockchain.accept_block(stamped(&blockchain, tip + 1, &[locked])));
+        assert!(blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_replayed_transactions_are_rejected() {
+        let params = ChainParams::testing();
+        let mut blockchain = Blockchain::with_params(params.clone());
+        let payment = payment("alice", "bob", 5, 1);
+        assert!(blockchain.add_transaction(payment.clone()));
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        assert_eq!(blockchain.next_nonce(&account("alice")), 1);
+        assert!(!blockchain.add_transaction(payment.clone()));
+
+        let tip = blockchain.latest_block().header.clone();
+        let body = BlockBody {
+            transactions: vec![payment.clone()],
+            ..BlockBody::default()
+        };
+        let mut replay = Block::unmined(tip.index + 1, tip.timestamp, tip.hash, body);
+        replay.header.state_root = tip.state_root;
+        replay.mine_block(params.target);
+        let mut blocks: Vec<Block> = blockchain.iter().cloned().collect();
+        blocks.push(replay.clone());
+        assert!(!validate(&blocks, &params));
+        assert!(!blockchain.accept_block(replay));
+
+        assert!(blockchain.rollback_block().is_some());
+        assert_eq!(blockchain.next_nonce(&account("alice")), 0);
+        assert!(blockchain.add_transaction(payment));
+    }
+
+    #[test]
+    fn test_transactions_must_be_signed_by_their_sender() {
+        let params = ChainParams::testing();
+        let mut blockchain = Blockchain::with_params(params.clone());
+        let forged = Transaction {
+            sender: account("carol"),
+            ..payment("alice", "bob", 5, 1)
+        };
+        assert!(!blockchain.add_transaction(forged.clone()));
+        assert!(!blockchain.add_transaction(Transaction::with_fee("alice".to_owned(), account("bob"), 5, 1)));
+        assert!(blockchain.pending_transactions().is_empty());
+
+        let carrying = |blockchain: &Blockchain, transaction: Transaction| {
+            let mut block = blockchain.block_template("miner".to_owned());
+            block.body.transactions.push(transaction);
+            block.header.merkle_root = block.body.merkle_root();
+            let mut nonces = blockchain.nonces.clone();
+            nonces.apply_block(&block.body.transactions).unwrap();
+            block.header.state_root = state_root(&blockchain.utxo, &nonces);
+            block.mine_block(params.target);
+            block
+        };
+        assert!(!blockchain.accept_block(carrying(&blockchain, forged)));
+        assert!(blockchain.accept_block(carrying(&blockchain, payment("carol", "bob", 5, 1))));
+        assert!(blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_pruning_keeps_headers_and_state() {
+        let mut archive = Blockchain::with_params(ChainParams::testing());
+        let payment = payment("alice", "bob", 5, 1);
+        assert!(archive.add_transaction(payment.clone()));
+        assert!(archive.mine_pending_transactions("miner".to_owned()));
+        for i in 2..=4 {
+            assert!(archive.add_block(format!("Block {}", i)));
+        }
+
+        let mut pruned = Blockchain::with_params(ChainParams {
+            keep_bodies: Some(2),
+            ..ChainParams::testing()
+        });
+        assert!(pruned.try_replace(archive.iter().cloned().collect()));
+        assert_eq!(pruned.base_height(), 3);
+        assert_eq!(pruned.iter().count(), 2);
+        assert!(pruned.headers().eq(archive.headers()));
+        assert!(pruned.is_pruned(1) && !pruned.is_pruned(3));
+        assert!(pruned.get_block_by_index(1).is_none());
+        assert_eq!(pruned.receipt(&payment.id()).unwrap().height, 1);
+        assert!(pruned.get_transaction(&payment.id()).is_none());
+        assert_eq!(pruned.base_snapshot().nonces.next(&account("alice")), 1);
+        assert!(pruned.validate_suffix(0));
+
+        // New blocks are still checked against the state kept.
+        assert!(!pruned.add_transaction(payment.clone()));
+        let next = signed("alice", Transaction { nonce: 1, ..payment });
+        assert!(archive.add_transaction(next));
+        assert!(archive.mine_pending_transactions("miner".to_owned()));
+        let headers: Vec<BlockHeader> = archive.headers().cloned().collect();
+        assert_eq!(pruned.missing_headers(&headers).unwrap(), &headers[5..]);
+        assert!(pruned.accept_block(archive.latest_block().clone()));
+        assert_eq!((pruned.base_height(), pruned.next_nonce(&account("alice"))), (4, 2));
+
+        assert!(pruned.rollback_block().is_some());
+        assert!(pruned.rollback_block().is_none());
+    }
+
+    #[test]
+    fn test_subscribers_receive_chain_events() {
+        let mut blockchain = Blockchain::new();
+        let events = blockchain.subscribe();
+
+        let transaction = payment("alice", "bob", 5, 0);
+        blockchain.add_transaction(transaction.clone());
+        blockchain.add_block("First block data".to_owned());
+        let mined = blockchain.latest_block().header.hash.clone();
+        blockchain.rollback_block();
+
+        let received: Vec<ChainEvent> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            vec![
+                ChainEvent::NewTransaction { id: transaction.id() },
+                ChainEvent::NewBlock { index: 1, hash: mined.clone() },
+                ChainEvent::ChainReorg {
+                    disconnected: mined,
+                    height: 0,
+                    hash: blockchain.latest_block().header.hash.clone(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_observers_are_called_back() {
+        use std::sync::Mutex;
+
+        #[derive(Clone, Default)]
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl ChainObserver for Recorder {
+            fn on_block_added(&mut self, block: &Block) {
+                self.0.lock().unwrap().push(format!("added {}", block.header.index));
+            }
+
+            fn on_reorg(&mut self, disconnected: &[Block], connected: &[Block]) {
+                self.0.lock().unwrap().push(format!("reorg -{} +{}", disconnected.len(), connected.len()));
+            }
+
+            fn on_validation_failure(&mut self, block: &Block) {
+                self.0.lock().unwrap().push(format!("invalid {}", block.header.index));
+            }
+
+            fn on_transaction_accepted(&mut self, transaction: &Transaction) {
+                self.0.lock().unwrap().push(format!("accepted {}", transaction.recipient));
+            }
+        }
+
+        let mut blockchain = Blockchain::with_params(ChainParams::testing());
+        let recorder = Recorder::default();
+        blockchain.register_observer(recorder.clone());
+
+        assert!(blockchain.add_transaction(signed("alice", Transaction::new(String::new(), "bob".to_owned(), 5))));
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        let mut fork = Blockchain::with_params(ChainParams::testing());
+        assert!(fork.add_block("Fork 1".to_owned()));
+        assert!(fork.add_block("Fork 2".to_owned()));
+
+        let mut tampered = fork.latest_block().clone();
+        tampered.body.data = "Tampered".to_owned();
+        assert!(!blockchain.try_replace(vec![fork.chain[0].clone(), fork.chain[1].clone(), tampered]));
+        assert!(blockchain.try_replace(fork.chain.clone()));
+        assert!(blockchain.rollback_block().is_some());
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["accepted bob", "added 1", "invalid 2", "reorg -1 +2", "reorg -1 +0"]
+        );
+    }
+
+    #[test]
+    fn test_checkpoints_pin_history() {
+        let mut blockchain = Blockchain::new();
+        assert!(blockchain.is_valid_chain());
+
+        blockchain.add_block("First block data".to_owned());
+        blockchain.add_block("Second block data".to_owned());
+        let pinned = blockchain.get_block_by_index(1).unwrap().header.hash.clone();
+        blockchain.add_checkpoint(Checkpoint::new(1, pinned));
+
+        assert!(blockchain.rollback_block().is_some());
+        assert!(blockchain.rollback_block().is_none());
+        assert_eq!(blockchain.latest_block().header.index, 1);
+
+        blockchain.add_checkpoint(Checkpoint::new(2, "0".repeat(64)));
+        assert!(!blockchain.add_block("Replacement data".to_owned()));
+        assert_eq!(blockchain.latest_block().header.index, 1);
+
+        blockchain.chain[1].body.data = "Rewritten history".to_owned();
+        blockchain.chain[1].mine_block(Target::from_leading_zeros(DIFFICULTY));
+        assert!(!blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_headers_first_sync() {
+        let mut local = Blockchain::new();
+        let mut remote = Blockchain::new();
+        local.add_block("Shared block".to_owned());
+        remote.try_replace(local.iter().cloned().collect());
+        local.add_block("Local block".to_owned());
+        remote.add_block("Remote block 1".to_owned());
+        remote.add_block("Remote block 2".to_owned());
+
+        let headers: Vec<BlockHeader> = remote.headers().cloned().collect();
+        assert!(remote.missing_headers(&headers).is_none());
+        let missing = local.missing_headers(&headers).unwrap();
+        assert_eq!(missing.len(), 2);
+        assert_eq!(missing[0].index, 2);
+
+        let mut forged = headers.clone();
+        forged[2].nonce += 1;
+        assert!(local.missing_headers(&forged).is_none());
+
+        let mut blocks = remote.range(2..4).to_vec();
+        blocks[1].body.data = "Swapped body".to_owned();
+        assert!(!local.try_replace_suffix(blocks.clone()));
+
+        blocks[1].body = remote.latest_block().body.clone();
+        assert!(local.try_replace_suffix(blocks));
+        assert_eq!(local.latest_block(), remote.latest_block());
+    }
+
+    #[test]
+    fn test_three_block_reorg_rolls_state_back_to_the_fork() {
+        use ed25519_dalek::SigningKey;
+        use utxo::{OutPoint, TxOutput, address};
+
+        let alice = SigningKey::from_bytes(&[7; 32]);
+        let alice_address = address(&alice.verifying_key(), Network::Mainnet);
+        let bob = address(&SigningKey::from_bytes(&[8; 32]).verifying_key(), Network::Mainnet);
+        let carol = address(&SigningKey::from_bytes(&[9; 32]).verifying_key(), Network::Mainnet);
+        let coinbase = UtxoTransaction::coinbase(alice_address.clone(), BLOCK_REWARD);
+        let coinbase_id = coinbase.id();
+        let funding = OutPoint { txid: coinbase.id(), vout: 0 };
+        let pay = |owner: &str| {
+            let mut payment = UtxoTransaction::new(
+                vec![funding.clone()],
+                vec![TxOutput::new(BLOCK_REWARD, owner.to_owned())],
+            );
+            payment.sign(&alice);
+            payment
+        };
+
+        let mut local = Blockchain::new();
+        assert!(local.add_utxo_block(vec![coinbase]));
+        let mut remote = Blockchain::new();
+        assert!(remote.try_replace(local.iter().cloned().collect()));
+
+        assert!(local.add_utxo_block(vec![pay(&bob)]));
+        local.add_transaction(payment("dave", "erin", 3, 0));
+        assert!(local.mine_pending_transactions("local miner".to_owned()));
+        assert!(local.add_block("Local block 3".to_owned()));
+        assert_eq!(local.utxo_set().balance(&bob), BLOCK_REWARD);
+        assert!(local.pending_transactions().is_empty());
+
+        assert!(remote.add_utxo_block(vec![pay(&carol)]));
+        for i in 3..=5 {
+            assert!(remote.add_block(format!("Remote block {}", i)));
+        }
+
+        assert!(local.try_replace(remote.iter().cloned().collect()));
+        assert_eq!(local.latest_block(), remote.latest_block());
+        assert_eq!(local.utxo_set().balance(&bob), 0);
+        assert_eq!(local.utxo_set().balance(&carol), BLOCK_REWARD);
+        assert_eq!(local.utxo_set().root(), remote.utxo_set().root());
+        assert_eq!(local.pending_transactions().len(), 1);
+        assert_eq!(local.pending_transactions()[0].sender, account("dave"));
+        assert_eq!(local.next_nonce(&account("dave")), 0);
+        assert!(local.receipt(&pay(&bob).id()).is_none());
+        assert!(local.history(&bob).is_empty() && local.history(&account("erin")).is_empty());
+        assert_eq!(local.history(&carol), [pay(&carol).id()]);
+        assert_eq!(local.history(&alice_address), [coinbase_id.clone(), pay(&carol).id()]);
+        assert_eq!(local.utxo_transaction(&pay(&carol).id()), Some(&pay(&carol)));
+
+        for _ in 0..4 {
+            assert!(local.rollback_block().is_some());
+        }
+        assert_eq!(local.utxo_set().balance(&alice_address), BLOCK_REWARD);
+        assert_eq!(local.utxo_set().balance(&carol), 0);
+        assert!(local.receipt(&pay(&carol).id()).is_none());
+        assert_eq!(local.history(&alice_address), [coinbase_id]);
+        assert!(local.add_utxo_block(vec![pay(&bob)]));
+        assert_eq!(local.receipt(&pay(&bob).id()).unwrap().height, 2);
+    }
+
+    #[test]
+    fn test_testing_params_mine_reproducible_blocks() {
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let params = ChainParams {
+            clock: clock.clone(),
+            ..ChainParams::testing()
+        };
+        let mut first = Blockchain::with_params(params.clone());
+        let mut second = Blockchain::with_params(params);
+        assert!(first.checkpoints().is_empty());
+
+        clock.advance(60);
+        assert!(first.add_block("First block data".to_owned()));
+        assert!(second.add_block("First block data".to_owned()));
+        assert_eq!(first.latest_block(), second.latest_block());
+        assert_eq!(first.latest_block().header.timestamp, 1_700_000_060);
+        assert_eq!(first.latest_block().header.target(), Target::from_leading_zeros(1));
+        assert!(first.is_valid_chain());
+
+        let seeded = Blockchain::with_params(ChainParams {
+            nonce_seed: 1_000,
+            ..ChainParams::testing()
+        });
+        assert!(seeded.latest_block().header.nonce >= 1_000);
+        assert!(!Blockchain::new().try_replace(first.iter().cloned().collect()));
+
+        let mut parallel = Blockchain::with_params(ChainParams {
+            extra_nonce: 1,
+            clock,
+            ..ChainParams::testing()
+        });
+        assert_eq!(parallel.latest_block(), first.get_block_by_index(0).unwrap());
+        assert!(parallel.add_block("First block data".to_owned()));
+        assert_eq!(parallel.latest_block().header.extra_nonce, 1);
+        assert_ne!(parallel.latest_block().header.hash, first.latest_block().header.hash);
+        assert!(parallel.is_valid_chain());
+    }
+
+    #[test]
+    fn test_retarget_follows_block_times() {
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let params = ChainParams {
+            retarget: Some(Retarget { block_time: 10 }),
+            clock: clock.clone(),
+            ..ChainParams::testing()
+        };
+        let mut blockchain = Blockchain::with_params(params.clone());
+        let mut follower = Blockchain::with_params(params.clone());
+        let events = follower.subscribe();
+        for elapsed in [1, 1, 1, 60] {
+            clock.advance(elapsed);
+            assert!(blockchain.add_block(format!("{} seconds later", elapsed)));
+            assert!(follower.accept_block(blockchain.latest_block().clone()));
+        }
+        let targets: Vec<Target> = blockchain.iter().map(|block| block.header.target()).collect();
+        // Long after the genesis block, the first stays at the easiest
+        // target; quick blocks then get harder, a slow one easier.
+        assert_eq!(targets[1], targets[0]);
+        assert!(targets[2] < targets[1] && targets[3] < targets[2]);
+        assert!(targets[4] > targets[3]);
+        assert_eq!(blockchain.next_target(clock.now() + 10), targets[4]);
+        let changes: Vec<u32> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                ChainEvent::DifficultyChange { height, bits } => {
+                    assert_eq!(Target::from_compact(bits), targets[height as usize]);
+                    Some(height)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(changes, [2, 3, 4]);
+        assert!(consensus::validate(&blockchain.chain, &params));
+
+        // Mined at the genesis target instead of the adjusted one.
+        clock.advance(1);
+        let mut stale = blockchain.latest_block().clone();
+        stale.header.index += 1;
+        stale.header.prev_hash = stale.header.hash.clone();
+        stale.header.timestamp = clock.now();
+        stale.mine_block(params.target);
+        assert!(!blockchain.accept_block(stale));
+        assert!(blockchain.is_valid_chain());
+    }
+
+    #[test]
+    fn test_forks_are_weighed_by_work() {
+        let clock = Arc::new(ManualClock::new(1_700_000_000));
+        let params = ChainParams {
+            target: Target::from_leading_zeros(2),
+            retarget: Some(Retarget { block_time: 10 }),
+            clock: clock.clone(),
+            ..ChainParams::testing()
+        };
+        // Quick blocks make the shared history about a tenth harder.
+        let mut heavy = Blockchain::with_params(params.clone());
+        for i in 0..200 {
+            clock.advance(1);
+            assert!(heavy.add_block(format!("Shared {}", i)));
+        }
+        let mut light = Blockchain::with_params(params.clone());
+        assert!(light.try_replace(heavy.iter().cloned().collect()));
+
+        for i in 0..20 {
+            clock.advance(1);
+            assert!(heavy.add_block(format!("Heavy {}", i)));
+        }
+        for i in 0..21 {
+            clock.advance(1_000);
+            assert!(light.add_block(format!("Light {}", i)));
+        }
+        assert!(light.latest_block().header.index > heavy.latest_block().header.index);
+        assert!(light.chain_work() < heavy.chain_work());
+
+        let heavy_headers: Vec<BlockHeader> = heavy.headers().cloned().collect();
+        let light_headers: Vec<BlockHeader> = light.headers().cloned().collect();
+        assert!(heavy.missing_headers(&light_headers).is_none());
+        assert_eq!(light.missing_headers(&heavy_headers).unwrap().len(), 20);
+        assert!(!heavy.try_replace(light.iter().cloned().collect()));
+        assert!(light.try_replace(heavy.iter().cloned().collect()));
+        assert_eq!(light.latest_block(), heavy.latest_block());
+        assert!(!light.try_replace(heavy.iter().cloned().collect()));
+    }
+
+    #[test]
+    fn test_validators_seal_in_turn() {
+        use ed25519_dalek::SigningKey;
+
+        let keys = [SigningKey::from_bytes(&[1; 32]), SigningKey::from_bytes(&[2; 32])];
+        let authority = Authority::new(keys.iter().map(SigningKey::verifying_key).collect()).unwrap();
+        let node = |key: &SigningKey| {
+            let mut authority = authority.clone();
+            assert!(authority.add_key(key.clone()));
+            Blockchain::with_params(ChainParams {
+                authority: Some(authority),
+                ..ChainParams::testing()
+            })
+        };
+        let mut first = node(&keys[0]);
+        let mut second = node(&keys[1]);
+
+        assert!(!first.can_seal_next());
+        assert!(!first.add_block("Out of turn".to_owned()));
+        assert!(second.add_block("Block 1".to_owned()));
+        assert!(first.accept_block(second.latest_block().clone()));
+        assert!(first.add_block("Block 2".to_owned()));
+        assert!(second.accept_block(first.latest_block().clone()));
+        assert!(first.is_valid_chain());
+        assert!(consensus::validate(&second.chain, &second.params));
+
+        let mut forged = first.latest_block().clone();
+        forged.header.seal = Vec::new();
+        assert!(!second.rules().check_detached(&forged));
+        forged.seal_block(second.params.target, &keys[1]);
+        assert!(!second.rules().check_detached(&forged));
+        assert!(!Blockchain::with_params(ChainParams::testing()).try_replace(first.chain.clone()));
+    }
+}
@@ -0,0 +1,229 @@
+#[cfg(feature = "net")]
+use std::io;
+#[cfg(feature = "net")]
+use std::net::TcpStream;
+#[cfg(feature = "net")]
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::block::transaction_leaf;
+#[cfg(feature = "net")]
+use crate::network::{Envelope, Inbox, Message, spawn_tcp_peer};
+use crate::consensus::default_checkpoints;
+#[cfg(feature = "net")]
+use crate::transport::{NodeIdentity, Role};
+use crate::{BlockHeader, Blockchain, ChainParams, Checkpoint, DIFFICULTY, MerkleProof, Network, Target};
+
+/// Evidence from a full node that a transaction was mined in the block with
+/// `block_hash`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub block_hash: String,
+    pub merkle: MerkleProof,
+}
+
+/// Follows the chain through headers alone. It checks their proof of work
+/// and checkpoints, and trusts a full node only for Merkle proofs it can
+/// verify against those headers.
+#[derive(Debug, Clone)]
+pub struct LightClient {
+    headers: Vec<BlockHeader>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Default for LightClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LightClient {
+    /// A client that knows only the main network's genesis header.
+    pub fn new() -> Self {
+        Self::for_network(Network::Mainnet)
+    }
+
+    /// A client that knows only the genesis header of `network`.
+    pub fn for_network(network: Network) -> Self {
+        let params = ChainParams::for_network(network);
+        LightClient {
+            checkpoints: default_checkpoints(&params),
+            headers: vec![Blockchain::with_params(params).latest_block().header.clone()],
+        }
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers.last().expect("the genesis header is never removed")
+    }
+
+    pub fn headers(&self) -> &[BlockHeader] {
+        &self.headers
+    }
+
+    pub fn header_by_hash(&self, hash: &str) -> Option<&BlockHeader> {
+        self.headers.iter().find(|header| header.hash == hash)
+    }
+
+    /// Switches to `headers` if they start at our genesis, form a valid
+    /// chain and are longer than the one we follow.
+    pub fn add_headers(&mut self, headers: &[BlockHeader]) -> bool {
+        if headers.len() <= self.headers.len() || headers.first() != self.headers.first() {
+            return false;
+        }
+        for pair in headers.windows(2) {
+            if !self.matches_checkpoints(&pair[1]) || !pair[1].follows(&pair[0], Target::from_leading_zeros(DIFFICULTY)) {
+                warn!(index = pair[1].index, "rejecting header chain");
+                return false;
+            }
+        }
+        self.headers = headers.to_vec();
+        debug!(height = self.tip().index, "light client synced headers");
+        true
+    }
+
+    /// Whether `proof` shows the account transaction with `transaction_id`
+    /// is in one of the blocks we hold the header for.
+    pub fn verify_inclusion(&self, transaction_id: &str, proof: &InclusionProof) -> bool {
+        self.header_by_hash(&proof.block_hash).is_some_and(|header| {
+            proof
+                .merkle
+                .verify(&transaction_leaf(transaction_id), &header.merkle_root)
+        })
+    }
+
+    /// Syncs headers from the full node at `address` and asks it to prove
+    /// the transaction with `transaction_id` was mined. The returned proof,
+    /// if any, has not been verified yet. The client connects under a
+    /// throwaway identity.
+    #[cfg(feature = "net")]
+    pub fn fetch_proof(
+        &mut self,
+        address: &str,
+        transaction_id: &str,
+        timeout: Duration,
+    ) -> io::Result<Option<InclusionProof>> {
+        let identity = NodeIdentity::generate()?;
+        let inbox = Inbox::new();
+        let stream = TcpStream::connect(address)?;
+        let (peer, outbox) = spawn_tcp_peer(stream, &identity, self.tip().network, Role::Initiator, inbox.sender())?;
+        for message in [Message::GetHeaders, Message::GetProof(transaction_id.to_owned())] {
+            let envelope = Envelope {
+                from: identity.id(),
+                message,
+            };
+            outbox.send(envelope).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut synced = false;
+        let mut proof = None;
+        while !synced || proof.is_none() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Some(envelope) = inbox.recv_timeout(remaining) else {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "full node did not answer"));
+            };
+            match envelope.message {
+                Message::Headers(headers) => {
+                    self.add_headers(&headers);
+                    synced = true;
+                }
+                Message::Proof(answer) => proof = Some(answer),
+                _ => {}
+            }
+        }
+        debug!(peer = %peer, "light client query finished");
+        Ok(proof.flatten())
+    }
+
+    fn matches_checkpoints(&self, header: &BlockHeader) -> bool {
+        self.checkpoints
+            .iter()
+            .filter(|checkpoint| checkpoint.height == header.index)
+            .all(|checkpoint| checkpoint.hash == header.hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "net")]
+    use std::net::TcpListener;
+    #[cfg(feature = "net")]
+    use std::thread;
+
+    use super::*;
+    #[cfg(feature = "net")]
+    use crate::Node;
+    use crate::transaction::accounts::payment;
+
+    fn chain_with_payment() -> (Blockchain, String) {
+        let mut blockchain = Blockchain::new();
+        let paid = payment("alice", "bob", 10, 0);
+        let id = paid.id();
+        blockchain.add_transaction(paid);
+        blockchain.add_transaction(payment("carol", "dave", 5, 0));
+        blockchain.mine_pending_transactions("miner".to_owned());
+        blockchain.add_block("Later block".to_owned());
+        (blockchain, id)
+    }
+
+    #[test]
+    fn test_light_client_verifies_inclusion() {
+        let (blockchain, id) = chain_with_payment();
+        let headers: Vec<BlockHeader> = blockchain.headers().cloned().collect();
+
+        let mut client = LightClient::new();
+        assert!(client.add_headers(&headers));
+        assert_eq!(client.tip(), &blockchain.latest_block().header);
+        assert!(!client.add_headers(&headers));
+
+        let proof = blockchain.prove_transaction(&id).unwrap();
+        assert!(client.verify_inclusion(&id, &proof));
+        assert!(!client.verify_inclusion(&"0".repeat(64), &proof));
+        assert!(blockchain.prove_transaction(&"0".repeat(64)).is_none());
+
+        let mut forged = proof.clone();
+        forged.block_hash = blockchain.latest_block().header.hash.clone();
+        assert!(!client.verify_inclusion(&id, &forged));
+    }
+
+    #[test]
+    fn test_light_client_rejects_invalid_headers() {
+        let (blockchain, _) = chain_with_payment();
+        let mut headers: Vec<BlockHeader> = blockchain.headers().cloned().collect();
+        headers[1].merkle_root = "0".repeat(64);
+
+        let mut client = LightClient::new();
+        assert!(!client.add_headers(&headers));
+        assert_eq!(client.headers().len(), 1);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_fetch_proof_from_full_node() {
+        let (blockchain, id) = chain_with_payment();
+        let tip = blockchain.latest_block().header.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let identity = NodeIdentity::generate().unwrap();
+            let mut node = Node::with_blockchain(identity.id(), blockchain);
+            let (stream, _) = listener.accept().unwrap();
+            let (peer, sender) = spawn_tcp_peer(stream, &identity, Network::Mainnet, Role::Responder, node.inbox_sender()).unwrap();
+            node.add_peer(peer, sender);
+            loop {
+                node.process_messages();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let mut client = LightClient::new();
+        let proof = client
+            .fetch_proof(&address, &id, Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.tip(), &tip);
+        assert!(client.verify_inclusion(&id, &proof));
+    }
+}
@@ -1,217 +1,603 @@
-use chrono::Utc;
-use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command as Process;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use simplz_blockchain::address::Address;
+use simplz_blockchain::audit;
+use simplz_blockchain::{Block, Blockchain, ChainParams, LightClient, Network};
+use simplz_blockchain::daemon::{self, NodeConfig};
+use simplz_blockchain::export::{self, Format};
+use simplz_blockchain::pool;
+use simplz_blockchain::repl::Repl;
+use simplz_blockchain::rpc::{self, Balance, Request, Submitted, Unspent};
+use simplz_blockchain::storage::{load_chain, network_dir, save_chain};
+use simplz_blockchain::wallet::{self, Wallet, WalletError};
+use zeroize::Zeroizing;
+
+/// Read instead of prompting for the wallet password when set.
+const PASSWORD_VAR: &str = "SIMPLZ_WALLET_PASSWORD";
+
+/// Sent as the API key on RPC requests when set.
+const API_KEY_VAR: &str = "SIMPLZ_RPC_API_KEY";
+
+#[derive(Parser)]
+#[command(name = "simplz", about = "A simple proof-of-work blockchain")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-#[derive(Debug, Clone)]
-struct Block {
-    index: u32,
-    timestamp: i64,
-    data: String,
-    prev_hash: String,
-    hash: String,
-    nonce: u64,
+#[derive(Subcommand)]
+enum Command {
+    /// Run a long-lived node until interrupted.
+    Node {
+        #[arg(long, default_value = "simplz.toml")]
+        config: PathBuf,
+        /// Fund the dev faucet at genesis and serve POST /faucet over RPC.
+        #[arg(long)]
+        dev: bool,
+    },
+    /// Sync headers from a full node and verify that a transaction was mined.
+    Light {
+        /// Address of a full node's peer listener.
+        #[arg(long)]
+        peer: String,
+        /// Id of the account transaction to look up.
+        #[arg(long)]
+        transaction: String,
+        #[arg(long, default_value_t = 10)]
+        timeout_secs: u64,
+        /// mainnet or testnet.
+        #[arg(long, default_value = "mainnet")]
+        network: Network,
+    },
+    /// Mine for a node's pool coordinator until interrupted.
+    Worker {
+        /// Address of the node's pool listener.
+        #[arg(long)]
+        pool: String,
+        /// Name the node credits shares to.
+        #[arg(long, default_value = "worker")]
+        name: String,
+    },
+    /// Write the chain stored in a data directory to a file.
+    Export {
+        #[arg(long)]
+        data_dir: PathBuf,
+        /// mainnet or testnet.
+        #[arg(long, default_value = "mainnet")]
+        network: Network,
+        /// json, csv or binary.
+        #[arg(long, default_value = "json")]
+        format: Format,
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Validate an exported chain and store it in an empty data directory.
+    Import {
+        #[arg(long)]
+        data_dir: PathBuf,
+        /// mainnet or testnet.
+        #[arg(long, default_value = "mainnet")]
+        network: Network,
+        /// json, csv or binary.
+        #[arg(long, default_value = "json")]
+        format: Format,
+        #[arg(long)]
+        input: PathBuf,
+    },
+    /// Compare two chains and print where and how they diverge as JSON.
+    Audit {
+        /// Export to compare. Defaults to the chain stored in `--data-dir`.
+        #[arg(long, required_unless_present = "data_dir", conflicts_with = "data_dir")]
+        left: Option<PathBuf>,
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+        /// Export to compare against.
+        #[arg(long, required_unless_present = "peer", conflicts_with = "peer")]
+        right: Option<PathBuf>,
+        /// Address of a full node's peer listener to compare against instead.
+        #[arg(long)]
+        peer: Option<String>,
+        /// mainnet or testnet.
+        #[arg(long, default_value = "mainnet")]
+        network: Network,
+        /// Format of the exports: json, csv or binary.
+        #[arg(long, default_value = "json")]
+        format: Format,
+        #[arg(long, default_value_t = 30)]
+        timeout_secs: u64,
+    },
+    /// Manage keys in an encrypted wallet file and spend from them.
+    Wallet {
+        #[arg(long, default_value = "wallet.json")]
+        file: PathBuf,
+        #[command(subcommand)]
+        command: WalletCommand,
+    },
+    /// Mine, tamper with and fork a throwaway chain interactively.
+    Repl,
 }
 
-impl Block {
-    fn new(index: u32, data: String, prev_hash: String) -> Self {
-        let mut block = Block {
-            index,
-            timestamp: Utc::now().timestamp(),
-            data,
-            prev_hash,
-            hash: String::new(),
-            nonce: 0,
-        };
-        block.mine_block(4);
-        block
-    }
+#[derive(Subcommand)]
+enum WalletCommand {
+    /// Create the wallet file, or add a key to an existing one.
+    Create {
+        #[arg(long, default_value = "default")]
+        label: String,
+        /// mainnet or testnet. Ignored when adding to an existing wallet.
+        #[arg(long, default_value = "mainnet")]
+        network: Network,
+        /// Print a backup phrase for the new key.
+        #[arg(long)]
+        mnemonic: bool,
+        /// Restore the key from a backup phrase instead of generating one.
+        #[arg(long, conflicts_with = "mnemonic")]
+        restore: bool,
+    },
+    /// List the wallet's keys and their addresses.
+    List,
+    /// Ask a node for the balance of every key.
+    Balance {
+        /// Address of the node's RPC server.
+        #[arg(long)]
+        rpc: String,
+    },
+    /// Pay from one of the wallet's keys through a node.
+    Send {
+        /// Address of the node's RPC server.
+        #[arg(long)]
+        rpc: String,
+        /// Label of the key to pay from.
+        #[arg(long, default_value = "default")]
+        from: String,
+        #[arg(long)]
+        to: Address,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+    },
+}
 
-    fn calculate_hash(&self) -> String {
-        let content = format!(
-            "{}{}{}{}{}",
-            self.index, self.timestamp, self.data, self.prev_hash, self.nonce
-        );
-        let mut hasher = Sha256::new();
-        hasher.update(content);
-        format!("{:x}", hasher.finalize())
+fn main() -> ExitCode {
+    tracing_subscriber::fmt::init();
+
+    match Cli::parse().command {
+        Some(Command::Node { config, dev }) => run_node(&config, dev),
+        Some(Command::Light {
+            peer,
+            transaction,
+            timeout_secs,
+            network,
+        }) => run_light(&peer, &transaction, Duration::from_secs(timeout_secs), network),
+        Some(Command::Worker { pool, name }) => run_worker(&pool, &name),
+        Some(Command::Export {
+            data_dir,
+            network,
+            format,
+            output,
+        }) => run_export(&network_dir(&data_dir, network), network, format, &output),
+        Some(Command::Import {
+            data_dir,
+            network,
+            format,
+            input,
+        }) => run_import(&network_dir(&data_dir, network), network, format, &input),
+        Some(Command::Audit {
+            left,
+            data_dir,
+            right,
+            peer,
+            network,
+            format,
+            timeout_secs,
+        }) => {
+            let left = match (left, data_dir) {
+                (Some(path), _) => ChainSource::Export(path),
+                (None, Some(data_dir)) => ChainSource::Local(network_dir(&data_dir, network)),
+                (None, None) => unreachable!("clap requires --left or --data-dir"),
+            };
+            let right = match (right, peer) {
+                (Some(path), _) => ChainSource::Export(path),
+                (None, Some(peer)) => ChainSource::Peer(peer, Duration::from_secs(timeout_secs)),
+                (None, None) => unreachable!("clap requires --right or --peer"),
+            };
+            run_audit(&left, &right, network, format)
+        }
+        Some(Command::Wallet { file, command }) => {
+            let result = match command {
+                WalletCommand::Create {
+                    label,
+                    network,
+                    mnemonic,
+                    restore,
+                } => wallet_create(&file, label, network, mnemonic, restore),
+                WalletCommand::List => wallet_list(&file),
+                WalletCommand::Balance { rpc } => wallet_balance(&file, &rpc),
+                WalletCommand::Send {
+                    rpc,
+                    from,
+                    to,
+                    amount,
+                    fee,
+                } => wallet_send(&file, &rpc, &from, to, amount, fee),
+            };
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Some(Command::Repl) => run_repl(),
+        None => {
+            demo();
+            ExitCode::SUCCESS
+        }
     }
+}
 
-    fn mine_block(&mut self, difficulty: usize) {
-        let target = "0".repeat(difficulty);
+fn run_node(config: &Path, dev: bool) -> ExitCode {
+    let config = match NodeConfig::load(config) {
+        Ok(config) => NodeConfig {
+            dev: config.dev || dev,
+            ..config
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
 
-        self.hash = self.calculate_hash();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let handler = Arc::clone(&shutdown);
+    if let Err(err) = ctrlc::set_handler(move || handler.store(true, Ordering::SeqCst)) {
+        eprintln!("cannot install signal handler: {}", err);
+        return ExitCode::FAILURE;
+    }
 
-        while &self.hash[..difficulty] != target {
-            self.nonce += 1;
-            self.hash = self.calculate_hash();
+    match daemon::run(&config, shutdown) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("node failed: {}", err);
+            ExitCode::FAILURE
         }
-        println!("Block mined: {}", self.hash);
     }
 }
 
-#[derive(Debug)]
-struct Blockchain {
-    chain: Vec<Block>,
-}
-
-impl Blockchain {
+fn run_worker(pool: &str, name: &str) -> ExitCode {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let handler = Arc::clone(&shutdown);
+    if let Err(err) = ctrlc::set_handler(move || handler.store(true, Ordering::SeqCst)) {
+        eprintln!("cannot install signal handler: {}", err);
+        return ExitCode::FAILURE;
+    }
 
-    fn new() -> Self {
-        let genesis_block = Block {
-            index: 0,
-            timestamp: 0,
-            data: "Genesis Block".to_owned(),
-            prev_hash: String::new(),
-            hash: String::new(),
-            nonce: 0,
-        };
-        let mut genesis_block = genesis_block;
-        genesis_block.mine_block(4);
-        Blockchain {
-            chain: vec![genesis_block],
+    match pool::run_worker(pool, name, &shutdown) {
+        Ok(shares) => {
+            println!("Submitted {} shares.", shares);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("worker stopped: {}", err);
+            ExitCode::FAILURE
         }
+    }
+}
 
+fn run_light(peer: &str, transaction: &str, timeout: Duration, network: Network) -> ExitCode {
+    let mut client = LightClient::for_network(network);
+    let proof = match client.fetch_proof(peer, transaction, timeout) {
+        Ok(proof) => proof,
+        Err(err) => {
+            eprintln!("cannot query {}: {}", peer, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("Synced headers up to block {}.", client.tip().index);
+
+    match proof {
+        Some(proof) if client.verify_inclusion(transaction, &proof) => {
+            let header = client.header_by_hash(&proof.block_hash).expect("verified proofs name a known header");
+            let confirmations = client.tip().index - header.index + 1;
+            println!(
+                "Transaction {} is in block {} ({} confirmations).",
+                transaction, header.index, confirmations
+            );
+            ExitCode::SUCCESS
+        }
+        Some(_) => {
+            eprintln!("The proof for transaction {} does not verify.", transaction);
+            ExitCode::FAILURE
+        }
+        None => {
+            eprintln!("Transaction {} has not been mined.", transaction);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    fn latest_block(&self) -> &Block {
-        self.chain.last().unwrap()
+fn run_export(data_dir: &Path, network: Network, format: Format, output: &Path) -> ExitCode {
+    let blockchain = match load_chain(data_dir, ChainParams::for_network(network)) {
+        Ok(Some(blockchain)) => blockchain,
+        Ok(None) => {
+            eprintln!("no chain stored in {}", data_dir.display());
+            return ExitCode::FAILURE;
+        }
+        Err(err) => {
+            eprintln!("cannot load chain: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = File::create(output).and_then(|file| export::export(&blockchain, format, file));
+    match result {
+        Ok(()) => {
+            println!("Exported {} blocks to {}.", blockchain.iter().count(), output.display());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("cannot export chain: {}", err);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    fn add_block(&mut self, data: String) {
-        let prev_hash = self.latest_block().hash.clone();
-        let new_block = Block::new(self.chain.len() as u32, data, prev_hash);
-        self.chain.push(new_block);
+fn run_import(data_dir: &Path, network: Network, format: Format, input: &Path) -> ExitCode {
+    match load_chain(data_dir, ChainParams::for_network(network)) {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            eprintln!("{} already holds a chain", data_dir.display());
+            return ExitCode::FAILURE;
+        }
+        Err(err) => {
+            eprintln!("cannot read data directory: {}", err);
+            return ExitCode::FAILURE;
+        }
+    }
+    let file = match File::open(input) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("cannot open {}: {}", input.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let blockchain = match export::import(file, format, ChainParams::for_network(network)) {
+        Ok(blockchain) => blockchain,
+        Err(err) => {
+            eprintln!("import failed: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let saved = std::fs::create_dir_all(data_dir).and_then(|()| save_chain(data_dir, &blockchain));
+    match saved {
+        Ok(()) => {
+            println!("Imported {} blocks.", blockchain.iter().count());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("cannot save chain: {}", err);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    fn is_valid_chain(&self) -> bool {
-        for i in 1..self.chain.len() {
-            let current = &self.chain[i];
-            let previous = &self.chain[i - 1];
+/// A chain to audit.
+enum ChainSource {
+    Export(PathBuf),
+    /// The chain stored in this network directory.
+    Local(PathBuf),
+    /// The chain of the full node at this peer address.
+    Peer(String, Duration),
+}
 
-            if current.hash != current.calculate_hash() {
-                return false;
+impl ChainSource {
+    fn read(&self, network: Network, format: Format) -> Result<Vec<Block>, String> {
+        match self {
+            ChainSource::Export(path) => {
+                let file = File::open(path).map_err(|err| format!("cannot open {}: {}", path.display(), err))?;
+                export::read(file, format).map_err(|err| format!("cannot read {}: {}", path.display(), err))
             }
-
-            if current.prev_hash != previous.hash {
-                return false;
+            ChainSource::Local(data_dir) => match load_chain(data_dir, ChainParams::for_network(network)) {
+                Ok(Some(blockchain)) => Ok(blockchain.iter().cloned().collect()),
+                Ok(None) => Err(format!("no chain stored in {}", data_dir.display())),
+                Err(err) => Err(format!("cannot load chain: {}", err)),
+            },
+            ChainSource::Peer(peer, timeout) => {
+                audit::fetch_chain(peer, network, *timeout).map_err(|err| format!("cannot fetch chain from {}: {}", peer, err))
             }
         }
-        true
     }
 }
 
-fn main() {
-    let mut blockchain = Blockchain::new();
-
-    println!("Mining block 1...");
-    blockchain.add_block("First block data".to_owned());
-
-    println!("Mining block 2...");
-    blockchain.add_block("Second block data".to_owned());
+fn run_audit(left: &ChainSource, right: &ChainSource, network: Network, format: Format) -> ExitCode {
+    let chains = left.read(network, format).and_then(|left| Ok((left, right.read(network, format)?)));
+    let (left, right) = match chains {
+        Ok(chains) => chains,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = audit::audit(&left, &right, &ChainParams::for_network(network));
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => {
+            println!("{}", json);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("cannot encode report: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    if blockchain.is_valid_chain() {
-        println!("The blockchain is valid.");
+fn wallet_create(file: &Path, label: String, network: Network, mnemonic: bool, restore: bool) -> Result<(), WalletError> {
+    let mut wallet = match Wallet::load(file) {
+        Ok(wallet) => wallet,
+        Err(WalletError::Io(err)) if err.kind() == io::ErrorKind::NotFound => Wallet::new(network)?,
+        Err(err) => return Err(err),
+    };
+    let key = if restore {
+        let phrase = prompt("Backup phrase: ", false)?;
+        wallet::from_mnemonic(&phrase)?
     } else {
-        println!("The blockchain is INVALID!");
+        wallet::generate_key()?
+    };
+    let password = if wallet.keys().is_empty() {
+        new_password()?
+    } else {
+        password("Wallet password: ")?
+    };
+    let address = wallet.add_key(label.clone(), &key, &password)?;
+    wallet.save(file)?;
+    println!("Added key {:?} with address {} to {}.", label, address, file.display());
+    if mnemonic {
+        println!("Write down this backup phrase and keep it offline:\n{}", *Zeroizing::new(wallet::to_mnemonic(&key)));
     }
-
-    println!("{:#?}", blockchain);
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_block_creation() {
-        let block = Block::new(1, "Test Data".to_owned(), "PreviousHash".to_owned());
-
-        assert_eq!(block.index, 1);
-        assert_eq!(block.data, "Test Data");
-        assert_eq!(block.prev_hash, "PreviousHash");
-        assert!(block.hash.starts_with("0000"));
+fn wallet_list(file: &Path) -> Result<(), WalletError> {
+    let wallet = Wallet::load(file)?;
+    println!("{} wallet:", wallet.network());
+    for key in wallet.keys() {
+        println!("{}\t{}", key.label, key.address);
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_blockchain_initialization() {
-        let blockchain = Blockchain::new();
-        let genesis_block = &blockchain.chain[0];
-
-        assert_eq!(blockchain.chain.len(), 1);
-        assert_eq!(genesis_block.index, 0);
-        assert_eq!(genesis_block.data, "Genesis Block");
-        assert_eq!(genesis_block.prev_hash, "");
-        assert!(genesis_block.hash.starts_with("0000"));
+fn wallet_balance(file: &Path, rpc: &str) -> Result<(), WalletError> {
+    let wallet = Wallet::load(file)?;
+    let mut total: u64 = 0;
+    for key in wallet.keys() {
+        let balance: Balance = rpc_call(rpc, "GET", &format!("/addresses/{}/balance", key.address), Vec::new())?;
+        total = total.saturating_add(balance.balance);
+        println!("{}\t{}\t{}", key.label, key.address, balance.balance);
     }
+    println!("Total: {}", total);
+    Ok(())
+}
 
-    #[test]
-    fn test_add_block() {
-        let mut blockchain = Blockchain::new();
-
-        blockchain.add_block("First block data".to_owned());
-
-        assert_eq!(blockchain.chain.len(), 2);
-
-        let latest_block = &blockchain.chain[1];
-        let previous_block = &blockchain.chain[0];
+fn wallet_send(file: &Path, rpc: &str, from: &str, to: Address, amount: u64, fee: u64) -> Result<(), WalletError> {
+    let wallet = Wallet::load(file)?;
+    let address = wallet
+        .address(from)
+        .ok_or_else(|| WalletError::UnknownKey(from.to_owned()))?;
+    let unspent: Vec<Unspent> = rpc_call(rpc, "GET", &format!("/addresses/{}/outputs", address), Vec::new())?;
+    let key = wallet.unlock(from, &password("Wallet password: ")?)?;
+    let payment = wallet::build_payment(&key, wallet.network(), &unspent, to, amount, fee)?;
+    let body = serde_json::to_vec(&payment).map_err(io::Error::other)?;
+    let submitted: Submitted = rpc_call(rpc, "POST", "/transactions", body)?;
+    println!("Sent {} to {} in transaction {} (block {}).", amount, to, submitted.id, submitted.block_hash);
+    Ok(())
+}
 
-        assert_eq!(latest_block.index, 1);
-        assert_eq!(latest_block.data, "First block data");
-        assert_eq!(latest_block.prev_hash, previous_block.hash);
-        assert!(latest_block.hash.starts_with("0000"));
+/// Makes an RPC request and decodes the JSON reply, turning error replies
+/// into errors carrying their message.
+fn rpc_call<T: serde::de::DeserializeOwned>(address: &str, method: &str, path: &str, body: Vec<u8>) -> io::Result<T> {
+    let request = Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        body,
+        api_key: std::env::var(API_KEY_VAR).ok(),
+    };
+    let (status, body) = rpc::call(address, &request)?;
+    if status != 200 {
+        let message = format!("node replied {}: {}", status, String::from_utf8_lossy(&body));
+        return Err(io::Error::other(message));
     }
+    serde_json::from_slice(&body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
 
-    #[test]
-    fn test_multiple_blocks() {
-        let mut blockchain = Blockchain::new();
-
-        blockchain.add_block("Block 1 data".to_owned());
-        blockchain.add_block("Block 2 data".to_owned());
-        blockchain.add_block("Block 3 data".to_owned());
-
-        assert_eq!(blockchain.chain.len(), 4);
-        assert!(blockchain.is_valid_chain());
+/// The wallet password, from the environment or the terminal.
+fn password(message: &str) -> io::Result<Zeroizing<String>> {
+    match std::env::var(PASSWORD_VAR) {
+        Ok(password) => Ok(Zeroizing::new(password)),
+        Err(_) => prompt(message, true),
     }
+}
 
-    #[test]
-    fn test_genesis_block_consistency() {
-        let blockchain_1 = Blockchain::new();
-        let blockchain_2 = Blockchain::new();
-
-        assert_eq!(blockchain_1.chain[0].hash, blockchain_2.chain[0].hash);
-        assert_eq!(blockchain_1.chain[0].data, "Genesis Block");
-        assert_eq!(blockchain_1.chain[0].prev_hash, "");
+/// A password for a new wallet, asked for twice unless it comes from the
+/// environment.
+fn new_password() -> io::Result<Zeroizing<String>> {
+    if let Ok(password) = std::env::var(PASSWORD_VAR) {
+        return Ok(Zeroizing::new(password));
     }
+    loop {
+        let password = prompt("New wallet password: ", true)?;
+        if *password == *prompt("Repeat password: ", true)? {
+            return Ok(password);
+        }
+        eprintln!("Passwords do not match.");
+    }
+}
 
-    #[test]
-    fn test_is_valid_chain() {
-        let mut blockchain = Blockchain::new();
-
-        blockchain.add_block("First block data".to_owned());
-        blockchain.add_block("Second block data".to_owned());
+/// Reads a line from standard input after printing `message`, without
+/// echoing it if `hidden` and standard input is a terminal.
+fn prompt(message: &str, hidden: bool) -> io::Result<Zeroizing<String>> {
+    eprint!("{}", message);
+    io::stderr().flush()?;
+    let hide = hidden && io::stdin().is_terminal();
+    if hide {
+        Process::new("stty").arg("-echo").status()?;
+    }
+    let mut line = Zeroizing::new(String::new());
+    let read = io::stdin().lock().read_line(&mut line);
+    if hide {
+        Process::new("stty").arg("echo").status()?;
+        eprintln!();
+    }
+    read?;
+    Ok(Zeroizing::new(line.trim_end_matches(['\r', '\n']).to_owned()))
+}
 
-        assert!(blockchain.is_valid_chain());
+fn run_repl() -> ExitCode {
+    let mut repl = Repl::new(ChainParams::default());
+    println!("Type help for a list of commands.");
+    loop {
+        print!("{}", repl.prompt());
+        if io::stdout().flush().is_err() {
+            return ExitCode::FAILURE;
+        }
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => return ExitCode::SUCCESS,
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+        match repl.execute(&line) {
+            Some(output) if output.is_empty() => {}
+            Some(output) => println!("{}", output),
+            None => return ExitCode::SUCCESS,
+        }
     }
+}
 
-    #[test]
-    fn test_tampered_block_validation() {
-        let mut blockchain = Blockchain::new();
+fn demo() {
+    let mut blockchain = Blockchain::new();
 
-        blockchain.add_block("First block data".to_owned());
+    println!("Mining block 1...");
+    blockchain.add_block("First block data".to_owned());
 
-        blockchain.chain[1].data = "Tampered Data".to_owned();
+    println!("Mining block 2...");
+    blockchain.add_block("Second block data".to_owned());
 
-        assert!(!blockchain.is_valid_chain());
+    if blockchain.is_valid_chain() {
+        println!("The blockchain is valid.");
+    } else {
+        println!("The blockchain is INVALID!");
     }
 
-    #[test]
-    fn test_large_blockchain_performance() {
-        let mut blockchain = Blockchain::new();
-
-        for i in 1..=10 {
-            blockchain.add_block(format!("Block {} data", i));
-        }
-
-        assert_eq!(blockchain.chain.len(), 11);
-        assert!(blockchain.is_valid_chain());
-    }
+    println!("{:#?}", blockchain);
 }
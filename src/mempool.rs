@@ -0,0 +1,519 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+
+use crate::{AccountNonces, BlockLimits, Transaction, block_overhead, metrics};
+
+/// Space reserved for the header and the reward when estimating fees: a
+/// sealed header, and a reward to a hex-encoded public key address.
+fn reserved_size() -> usize {
+    block_overhead(true, &"0".repeat(64))
+}
+
+/// What the transactions of the next block must respect: each sender's next
+/// nonce, and the block's height and the median time past for time locks.
+pub(crate) struct NextBlock<'a> {
+    pub(crate) nonces: &'a AccountNonces,
+    pub(crate) height: u32,
+    pub(crate) median_time_past: i64,
+}
+
+/// How much the mempool holds and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolPolicy {
+    /// Total size of the pending transactions. Once reached, the lowest fee
+    /// rates are evicted to make room for higher ones.
+    pub max_bytes: usize,
+    /// Seconds a transaction may stay pending before it is dropped.
+    pub ttl_seconds: i64,
+}
+
+impl Default for MempoolPolicy {
+    fn default() -> Self {
+        MempoolPolicy {
+            max_bytes: 16 * 1024 * 1024,
+            ttl_seconds: 72 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    Duplicate,
+    /// A replacement must pay more than the transaction it replaces.
+    ReplacementUnderpaid { pending_fee: u64 },
+    /// The mempool is full of transactions paying at least as much per byte.
+    Full,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Duplicate => write!(f, "transaction is already pending"),
+            MempoolError::ReplacementUnderpaid { pending_fee } => {
+                write!(f, "replacement must pay a fee above the pending {}", pending_fee)
+            }
+            MempoolError::Full => write!(f, "mempool is full of higher fee transactions"),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// What the mempool keeps alongside a pending transaction, worked out once
+/// when it arrives.
+#[derive(Debug, Clone)]
+struct Entry {
+    id: String,
+    size: usize,
+    arrival: i64,
+}
+
+/// Pending transactions waiting to be mined, prioritised by fee per byte.
+/// A transaction carrying the same sender and nonce as a pending one is a
+/// replacement: it takes the pending one's place if it pays a higher fee.
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    policy: MempoolPolicy,
+    transactions: Vec<Transaction>,
+    /// Id, size and arrival time of each transaction, in step with
+    /// `transactions`.
+    entries: Vec<Entry>,
+    /// Ids of the pending transactions.
+    ids: HashSet<String>,
+    /// Position of each pending transaction by sender and nonce.
+    slots: HashMap<(String, u64), usize>,
+    bytes: usize,
+}
+
+impl Mempool {
+    pub fn with_policy(policy: MempoolPolicy) -> Self {
+        Mempool {
+            policy,
+            ..Mempool::default()
+        }
+    }
+
+    pub fn policy(&self) -> MempoolPolicy {
+        self.policy
+    }
+
+    /// Queues `transaction`, received at `now`. Returns the transactions it
+    /// pushed out: the one it replaces, and any evicted to make room.
+    pub fn add(&mut self, transaction: Transaction, now: i64) -> Result<Vec<Transaction>, MempoolError> {
+        let id = transaction.id();
+        if self.ids.contains(&id) {
+            return Err(MempoolError::Duplicate);
+        }
+        let replaced = self.slots.get(&(transaction.sender.clone(), transaction.nonce)).copied();
+        if let Some(i) = replaced
+            && self.transactions[i].fee >= transaction.fee
+        {
+            return Err(MempoolError::ReplacementUnderpaid {
+                pending_fee: self.transactions[i].fee,
+            });
+        }
+
+        let size = transaction.size_bytes();
+        let rate = FeeRate::new(transaction.fee, size);
+        let mut out: HashSet<usize> = replaced.into_iter().collect();
+        let mut bytes = self.bytes - out.iter().map(|&i| self.entries[i].size).sum::<usize>();
+        if bytes + size > self.policy.max_bytes {
+            let mut cheapest_first: Vec<usize> = (0..self.transactions.len()).filter(|i| !out.contains(i)).collect();
+            cheapest_first.sort_by_key(|&i| self.fee_rate(i));
+            let mut cheapest = cheapest_first.into_iter();
+            while bytes + size > self.policy.max_bytes {
+                match cheapest.next() {
+                    Some(i) if self.fee_rate(i) < rate => {
+                        bytes -= self.entries[i].size;
+                        out.insert(i);
+                    }
+                    _ => return Err(MempoolError::Full),
+                }
+            }
+        }
+
+        let removed = self.remove_where(|i, _| out.contains(&i));
+        self.slots.insert((transaction.sender.clone(), transaction.nonce), self.transactions.len());
+        self.ids.insert(id.clone());
+        self.transactions.push(transaction);
+        self.entries.push(Entry { id, size, arrival: now });
+        self.bytes += size;
+        self.record_size();
+        Ok(removed)
+    }
+
+    /// Drops the transactions that have been pending longer than the
+    /// policy allows as of `now`, and returns them.
+    pub fn expire(&mut self, now: i64) -> Vec<Transaction> {
+        let ttl = self.policy.ttl_seconds;
+        let arrivals: Vec<i64> = self.entries.iter().map(|entry| entry.arrival).collect();
+        let expired = self.remove_where(|i, _| now.saturating_sub(arrivals[i]) > ttl);
+        self.record_size();
+        expired
+    }
+
+    /// Total size of the pending transactions.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Pending transactions in arrival order.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    pub fn contains(&self, transaction: &Transaction) -> bool {
+        self.ids.contains(&transaction.id())
+    }
+
+    /// Drops the pending transactions whose nonce their sender has already
+    /// used: those a block included, and any they replaced.
+    pub(crate) fn remove_confirmed(&mut self, nonces: &AccountNonces) {
+        self.remove_where(|_, pending| pending.nonce < nonces.next(&pending.sender));
+        self.record_size();
+    }
+
+    /// Fee a transaction of `transaction_size` bytes should pay to make it
+    /// into the next block under `limits`: zero if the next block has room to
+    /// spare, otherwise just above the fee rate of the cheapest transaction
+    /// that would currently be included.
+    pub fn estimate_fee(&self, transaction_size: usize, limits: &BlockLimits) -> u64 {
+        let max_transactions = limits.max_transactions.saturating_sub(1);
+        let max_size = limits.max_block_size.saturating_sub(reserved_size());
+        let plan = self.plan(max_transactions, max_size, None, &mut || false);
+
+        let used: usize = plan.iter().map(|&i| self.entries[i].size).sum();
+        if plan.len() < max_transactions && used + transaction_size <= max_size {
+            return 0;
+        }
+        match plan.last() {
+            Some(&cheapest) => {
+                let fee = self.transactions[cheapest].fee as u128 * transaction_size as u128
+                    / self.entries[cheapest].size as u128;
+                fee as u64 + 1
+            }
+            None => 0,
+        }
+    }
+
+    /// Removes and returns the highest fee-per-byte transactions that fit in
+    /// `max_transactions` and `max_size` bytes, each sender's in nonce order
+    /// from its next nonce, leaving out those still time-locked at `next`.
+    /// Everything else stays pending.
+    pub(crate) fn take(&mut self, max_transactions: usize, max_size: usize, next: &NextBlock) -> Vec<Transaction> {
+        let plan = self.plan(max_transactions, max_size, Some(next), &mut || false);
+        let selected = plan.iter().map(|&i| self.transactions[i].clone()).collect();
+        let planned: HashSet<usize> = plan.into_iter().collect();
+        self.remove_where(|i, _| planned.contains(&i));
+        self.record_size();
+        selected
+    }
+
+    /// The transactions `take` would remove, left in place. Stops early,
+    /// with what it has picked so far, once `out_of_time` says so.
+    pub(crate) fn select(
+        &self,
+        max_transactions: usize,
+        max_size: usize,
+        next: &NextBlock,
+        out_of_time: &mut dyn FnMut() -> bool,
+    ) -> Vec<Transaction> {
+        let plan = self.plan(max_transactions, max_size, Some(next), out_of_time);
+        plan.iter().map(|&i| self.transactions[i].clone()).collect()
+    }
+
+    /// Removes the transactions `remove` picks by index, keeping entries,
+    /// indexes and the byte count in step, and returns them in order.
+    fn remove_where(&mut self, mut remove: impl FnMut(usize, &Transaction) -> bool) -> Vec<Transaction> {
+        let mut removed = Vec::new();
+        let mut kept = Vec::new();
+        let mut entries = Vec::new();
+        let pending = std::mem::take(&mut self.transactions);
+        for (i, (transaction, entry)) in pending.into_iter().zip(self.entries.drain(..)).enumerate() {
+            if remove(i, &transaction) {
+                self.bytes -= entry.size;
+                self.ids.remove(&entry.id);
+                removed.push(transaction);
+            } else {
+                kept.push(transaction);
+                entries.push(entry);
+            }
+        }
+        if !removed.is_empty() {
+            let slot = |(i, transaction): (usize, &Transaction)| ((transaction.sender.clone(), transaction.nonce), i);
+            self.slots = kept.iter().enumerate().map(slot).collect();
+        }
+        self.transactions = kept;
+        self.entries = entries;
+        removed
+    }
+
+    fn fee_rate(&self, i: usize) -> FeeRate {
+        FeeRate::new(self.transactions[i].fee, self.entries[i].size)
+    }
+
+    fn record_size(&self) {
+        metrics::registry().mempool_size.set(self.transactions.len() as u64);
+    }
+
+    /// Indices of the transactions to include, highest fee rate first. Ties
+    /// keep arrival order. Given `next`, a transaction only becomes a
+    /// candidate once its sender's previous one is in, so one whose
+    /// predecessor is missing, or that is still time-locked, is left out
+    /// along with everything after it. Planning ends early once
+    /// `out_of_time` says so.
+    fn plan(
+        &self,
+        max_transactions: usize,
+        max_size: usize,
+        next_block: Option<&NextBlock>,
+        out_of_time: &mut dyn FnMut() -> bool,
+    ) -> Vec<usize> {
+        let candidate = |i: usize| (self.fee_rate(i), Reverse(i));
+        let mut candidates: BinaryHeap<(FeeRate, Reverse<usize>)> = (0..self.transactions.len())
+            .filter(|&i| {
+                let transaction = &self.transactions[i];
+                next_block.is_none_or(|next_block| transaction.nonce == next_block.nonces.next(&transaction.sender))
+            })
+            .map(candidate)
+            .collect();
+
+        let mut size = 0;
+        let mut plan = Vec::new();
+        while plan.len() < max_transactions
+            && let Some((_, Reverse(i))) = candidates.pop()
+        {
+            if out_of_time() {
+                break;
+            }
+            let transaction = &self.transactions[i];
+            if next_block.is_some_and(|next_block| !transaction.is_mature(next_block.height, next_block.median_time_past)) {
+                continue;
+            }
+            if size + self.entries[i].size > max_size {
+                continue;
+            }
+            size += self.entries[i].size;
+            plan.push(i);
+            if next_block.is_some()
+                && let Some(nonce) = transaction.nonce.checked_add(1)
+                && let Some(&after) = self.slots.get(&(transaction.sender.clone(), nonce))
+            {
+                candidates.push(candidate(after));
+            }
+        }
+        plan
+    }
+}
+
+/// A fee per byte, compared exactly by cross-multiplying.
+#[derive(Debug, Clone, Copy)]
+struct FeeRate {
+    fee: u64,
+    size: usize,
+}
+
+impl FeeRate {
+    fn new(fee: u64, size: usize) -> Self {
+        FeeRate { fee, size }
+    }
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.fee as u128 * other.size as u128).cmp(&(other.fee as u128 * self.size as u128))
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(sender: &str, fee: u64) -> Transaction {
+        Transaction::with_fee(sender.to_owned(), "bob".to_owned(), 1, fee)
+    }
+
+    fn at(height: u32, nonces: &AccountNonces) -> NextBlock<'_> {
+        NextBlock {
+            nonces,
+            height,
+            median_time_past: 0,
+        }
+    }
+
+    #[test]
+    fn test_take_orders_by_fee_rate() {
+        let mut mempool = Mempool::default();
+        mempool.add(tx("alice", 1), 0).unwrap();
+        mempool.add(tx("carol", 9), 0).unwrap();
+        mempool.add(tx("dave", 5), 0).unwrap();
+        mempool.add(tx("alice-with-a-considerably-longer-name-than-the-rest", 9), 0).unwrap();
+
+        let taken = mempool.take(2, usize::MAX, &at(1, &AccountNonces::default()));
+        let senders: Vec<&str> = taken.iter().map(|tx| tx.sender.as_str()).collect();
+        assert_eq!(senders, vec!["carol", "dave"]);
+
+        let remaining: Vec<&str> = mempool.transactions().iter().map(|tx| tx.sender.as_str()).collect();
+        assert_eq!(remaining, vec!["alice", "alice-with-a-considerably-longer-name-than-the-rest"]);
+    }
+
+    #[test]
+    fn test_estimate_fee() {
+        let limits = BlockLimits {
+            max_transactions: 3,
+            ..BlockLimits::default()
+        };
+        let mut mempool = Mempool::default();
+        let size = tx("alice", 0).size_bytes();
+        assert_eq!(mempool.estimate_fee(size, &limits), 0);
+
+        mempool.add(tx("alice", 4), 0).unwrap();
+        assert_eq!(mempool.estimate_fee(size, &limits), 0);
+
+        mempool.add(tx("carol", 10), 0).unwrap();
+        assert_eq!(mempool.estimate_fee(size, &limits), 5);
+    }
+
+    #[test]
+    fn test_full_mempool_evicts_lowest_fee_rate() {
+        let size = tx("alice", 0).size_bytes();
+        let mut mempool = Mempool::with_policy(MempoolPolicy {
+            max_bytes: 2 * size,
+            ..MempoolPolicy::default()
+        });
+        mempool.add(tx("alice", 3), 0).unwrap();
+        mempool.add(tx("carol", 1), 0).unwrap();
+        assert_eq!(mempool.add(tx("david", 1), 0), Err(MempoolError::Full));
+
+        assert_eq!(mempool.add(tx("frank", 2), 0), Ok(vec![tx("carol", 1)]));
+        let senders: Vec<&str> = mempool.transactions().iter().map(|tx| tx.sender.as_str()).collect();
+        assert_eq!(senders, vec!["alice", "frank"]);
+        assert_eq!(mempool.bytes(), 2 * size);
+    }
+
+    #[test]
+    fn test_expired_transactions_are_dropped() {
+        let mut mempool = Mempool::with_policy(MempoolPolicy {
+            ttl_seconds: 60,
+            ..MempoolPolicy::default()
+        });
+        mempool.add(tx("alice", 1), 0).unwrap();
+        mempool.add(tx("carol", 1), 30).unwrap();
+        assert!(mempool.expire(60).is_empty());
+
+        assert_eq!(mempool.expire(61), vec![tx("alice", 1)]);
+        assert_eq!(mempool.transactions(), [tx("carol", 1)]);
+        assert_eq!(mempool.bytes(), tx("carol", 1).size_bytes());
+    }
+
+    #[test]
+    fn test_higher_fee_replaces_pending_payment() {
+        let mut mempool = Mempool::default();
+        mempool.add(tx("alice", 2), 0).unwrap();
+        assert_eq!(mempool.add(tx("alice", 2), 0), Err(MempoolError::Duplicate));
+        assert_eq!(
+            mempool.add(tx("alice", 1), 0),
+            Err(MempoolError::ReplacementUnderpaid { pending_fee: 2 })
+        );
+
+        assert_eq!(mempool.add(tx("alice", 5), 0), Ok(vec![tx("alice", 2)]));
+        assert_eq!(mempool.transactions(), [tx("alice", 5)]);
+    }
+
+    #[test]
+    fn test_indexes_follow_removals() {
+        let mut mempool = Mempool::with_policy(MempoolPolicy {
+            ttl_seconds: 60,
+            ..MempoolPolicy::default()
+        });
+        mempool.add(tx("alice", 1), 0).unwrap();
+        mempool.add(tx("carol", 2), 30).unwrap();
+        mempool.expire(61);
+        assert!(!mempool.contains(&tx("alice", 1)));
+        assert!(mempool.contains(&tx("carol", 2)));
+
+        // carol's payment moved up a place; replacing it still finds it.
+        assert_eq!(mempool.add(tx("carol", 2), 61), Err(MempoolError::Duplicate));
+        assert_eq!(mempool.add(tx("carol", 3), 61), Ok(vec![tx("carol", 2)]));
+        mempool.add(tx("alice", 1), 61).unwrap();
+        assert_eq!(mempool.transactions(), [tx("carol", 3), tx("alice", 1)]);
+        assert_eq!(mempool.bytes(), tx("carol", 3).size_bytes() + tx("alice", 1).size_bytes());
+    }
+
+    #[test]
+    fn test_confirmed_nonce_removes_its_replacements() {
+        let mut mempool = Mempool::default();
+        mempool.add(tx("alice", 5), 0).unwrap();
+        mempool.add(tx("carol", 1), 0).unwrap();
+
+        // A block confirmed the version alice first sent.
+        let mut nonces = AccountNonces::default();
+        nonces.apply_block(&[tx("alice", 2)]).unwrap();
+        mempool.remove_confirmed(&nonces);
+        assert_eq!(mempool.transactions(), [tx("carol", 1)]);
+    }
+
+    #[test]
+    fn test_take_follows_nonces() {
+        let later = |nonce, fee| Transaction { nonce, ..tx("alice", fee) };
+        let mut mempool = Mempool::default();
+        mempool.add(later(1, 9), 0).unwrap();
+        mempool.add(tx("carol", 5), 0).unwrap();
+        mempool.add(later(0, 1), 0).unwrap();
+        mempool.add(later(3, 9), 0).unwrap();
+
+        let taken = mempool.take(usize::MAX, usize::MAX, &at(1, &AccountNonces::default()));
+        assert_eq!(taken, vec![tx("carol", 5), later(0, 1), later(1, 9)]);
+        assert_eq!(mempool.transactions(), [later(3, 9)]);
+    }
+
+    #[test]
+    fn test_take_unlocks_a_long_chain_in_one_pass() {
+        // Each payment pays more than the one before it, so it only becomes
+        // a candidate once its predecessor is in.
+        let mut mempool = Mempool::default();
+        for nonce in (0..500).rev() {
+            mempool.add(Transaction { nonce, ..tx("alice", nonce + 1) }, 0).unwrap();
+        }
+
+        let taken = mempool.take(usize::MAX, usize::MAX, &at(1, &AccountNonces::default()));
+        assert_eq!(taken.iter().map(|tx| tx.nonce).collect::<Vec<_>>(), (0..500).collect::<Vec<_>>());
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn test_time_locked_transactions_wait() {
+        let locked = Transaction { lock_until: 3, ..tx("alice", 9) };
+        let mut mempool = Mempool::default();
+        mempool.add(locked.clone(), 0).unwrap();
+        mempool.add(tx("carol", 1), 0).unwrap();
+
+        let nonces = AccountNonces::default();
+        assert_eq!(mempool.take(usize::MAX, usize::MAX, &at(2, &nonces)), [tx("carol", 1)]);
+        assert_eq!(mempool.transactions(), std::slice::from_ref(&locked));
+        assert_eq!(mempool.take(usize::MAX, usize::MAX, &at(3, &nonces)), [locked]);
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+pub fn sha256(bytes: &[u8]) -> Hash {
+    Sha256::digest(bytes).into()
+}
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root of a binary Merkle tree over `leaves`. A level with an odd number of
+/// nodes pairs its last node with itself. An empty tree has an all-zero root.
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return [0; 32];
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+    }
+    level[0]
+}
+
+/// The sibling hashes on the path from one leaf up to the root, lowest
+/// first, along with the leaf's position.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub siblings: Vec<String>,
+}
+
+impl MerkleProof {
+    /// Whether `leaf` sits at `index` in the tree whose hex root is `root`.
+    pub fn verify(&self, leaf: &Hash, root: &str) -> bool {
+        let mut hash = *leaf;
+        let mut index = self.index;
+        for sibling in &self.siblings {
+            let Some(sibling) = hex::decode(sibling).ok().and_then(|bytes| Hash::try_from(bytes).ok()) else {
+                return false;
+            };
+            hash = if index.is_multiple_of(2) {
+                hash_pair(&hash, &sibling)
+            } else {
+                hash_pair(&sibling, &hash)
+            };
+            index /= 2;
+        }
+        index == 0 && hex::encode(hash) == root
+    }
+}
+
+/// Builds the proof for the leaf at `index`, or `None` if there is no such
+/// leaf.
+pub fn prove(leaves: &[Hash], mut index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let proof_index = index;
+    let mut siblings = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let sibling = level.get(index ^ 1).unwrap_or(&level[index]);
+        siblings.push(hex::encode(sibling));
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+        index /= 2;
+    }
+    Some(MerkleProof {
+        index: proof_index,
+        siblings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merkle_root() {
+        let a = sha256(b"a");
+        let b = sha256(b"b");
+        let c = sha256(b"c");
+
+        assert_eq!(merkle_root(&[]), [0; 32]);
+        assert_eq!(merkle_root(&[a]), a);
+        assert_eq!(merkle_root(&[a, b]), hash_pair(&a, &b));
+        assert_eq!(
+            merkle_root(&[a, b, c]),
+            hash_pair(&hash_pair(&a, &b), &hash_pair(&c, &c))
+        );
+        assert_ne!(merkle_root(&[a, b]), merkle_root(&[b, a]));
+    }
+
+    #[test]
+    fn test_proofs_verify_against_the_root() {
+        let leaves: Vec<Hash> = (0u8..5).map(|i| sha256(&[i])).collect();
+        let root = hex::encode(merkle_root(&leaves));
+
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = prove(&leaves, index).unwrap();
+            assert!(proof.verify(leaf, &root));
+            assert!(!proof.verify(&sha256(b"other"), &root));
+        }
+
+        let mut proof = prove(&leaves, 2).unwrap();
+        proof.index = 3;
+        assert!(!proof.verify(&leaves[2], &root));
+        assert!(prove(&leaves, 5).is_none());
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt::Write as _;
+#[cfg(feature = "net")]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(feature = "net")]
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "net")]
+use tracing::debug;
+
+/// Upper bounds, in seconds, of the block interval histogram buckets.
+const INTERVAL_BUCKETS: [u64; 8] = [1, 5, 10, 30, 60, 120, 300, 600];
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Gauge(AtomicU64::new(0))
+    }
+
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; INTERVAL_BUCKETS.len()],
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; INTERVAL_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        for (bound, bucket) in INTERVAL_BUCKETS.iter().zip(&self.buckets) {
+            if value <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Process-wide metrics, updated by the chain, mempool and network code.
+#[derive(Debug)]
+pub struct Metrics {
+    pub chain_height: Gauge,
+    pub mempool_size: Gauge,
+    pub hash_rate: Gauge,
+    pub block_interval_seconds: Histogram,
+    pub peers: Gauge,
+    pub orphans: Gauge,
+    pub validation_failures: Counter,
+}
+
+static REGISTRY: Metrics = Metrics {
+    chain_height: Gauge::new(),
+    mempool_size: Gauge::new(),
+    hash_rate: Gauge::new(),
+    block_interval_seconds: Histogram::new(),
+    peers: Gauge::new(),
+    orphans: Gauge::new(),
+    validation_failures: Counter::new(),
+};
+
+pub fn registry() -> &'static Metrics {
+    &REGISTRY
+}
+
+impl Metrics {
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        gauge(&mut out, "simplz_chain_height", "Index of the latest block.", &self.chain_height);
+        gauge(&mut out, "simplz_mempool_size", "Transactions waiting to be mined.", &self.mempool_size);
+        gauge(&mut out, "simplz_hash_rate", "Hashes per second while mining the last block.", &self.hash_rate);
+        gauge(&mut out, "simplz_peers", "Connected peers.", &self.peers);
+        gauge(&mut out, "simplz_orphans", "Blocks held until their parent arrives.", &self.orphans);
+
+        let _ = writeln!(out, "# HELP simplz_validation_failures_total Blocks that failed validation.");
+        let _ = writeln!(out, "# TYPE simplz_validation_failures_total counter");
+        let _ = writeln!(out, "simplz_validation_failures_total {}", self.validation_failures.get());
+
+        let histogram = &self.block_interval_seconds;
+        let _ = writeln!(out, "# HELP simplz_block_interval_seconds Time between consecutive blocks.");
+        let _ = writeln!(out, "# TYPE simplz_block_interval_seconds histogram");
+        for (bound, bucket) in INTERVAL_BUCKETS.iter().zip(&histogram.buckets) {
+            let _ = writeln!(
+                out,
+                "simplz_block_interval_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(out, "simplz_block_interval_seconds_bucket{{le=\"+Inf\"}} {}", histogram.count());
+        let _ = writeln!(out, "simplz_block_interval_seconds_sum {}", histogram.sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "simplz_block_interval_seconds_count {}", histogram.count());
+        out
+    }
+}
+
+/// Answers `GET /metrics` with `registry().render()` on every connection
+/// accepted from `listener`, until the listener fails.
+#[cfg(feature = "net")]
+pub fn serve(listener: TcpListener) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(respond);
+        if let Err(err) = result {
+            debug!(error = %err, "metrics request failed");
+        }
+    }
+}
+
+#[cfg(feature = "net")]
+fn respond(stream: TcpStream) -> io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", registry().render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, gauge: &Gauge) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, gauge.get());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new();
+        histogram.observe(3);
+        histogram.observe(45);
+
+        let buckets: Vec<u64> = histogram.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        assert_eq!(buckets, vec![0, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(histogram.count(), 2);
+        assert_eq!(histogram.sum.load(Ordering::Relaxed), 48);
+    }
+
+    #[test]
+    fn test_render_exposes_every_metric() {
+        let rendered = registry().render();
+        for name in [
+            "simplz_chain_height ",
+            "simplz_mempool_size ",
+            "simplz_hash_rate ",
+            "simplz_peers ",
+            "simplz_validation_failures_total ",
+            "simplz_block_interval_seconds_bucket{le=\"+Inf\"} ",
+        ] {
+            assert!(rendered.contains(name), "missing {}", name);
+        }
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_serve_answers_metrics_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener));
+
+        let request = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            io::Read::read_to_string(&mut stream, &mut response).unwrap();
+            response
+        };
+
+        let response = request("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("simplz_chain_height"));
+        assert!(request("/other").starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
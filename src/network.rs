@@ -0,0 +1,237 @@
+#[cfg(feature = "net")]
+use std::io::{self, BufReader, BufWriter};
+#[cfg(feature = "net")]
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(feature = "net")]
+use std::thread;
+#[cfg(feature = "net")]
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "net")]
+use tracing::{debug, warn};
+
+#[cfg(feature = "net")]
+use crate::Network;
+#[cfg(feature = "net")]
+use crate::transport::{NodeIdentity, Role, handshake, node_id};
+use crate::{Block, BlockBody, BlockHeader, InclusionProof, Snapshot, Transaction};
+
+/// Most bodies asked for, or sent, in one `GetBodies` round trip. A sync
+/// fetches the bodies it lacks in batches of this many.
+pub(crate) const MAX_BODIES_PER_MESSAGE: usize = 16;
+
+/// How long a connecting peer has to finish the transport handshake.
+#[cfg(feature = "net")]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages nodes exchange with their peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Message {
+    /// A newly mined or accepted block.
+    NewBlock(Block),
+    /// A transaction to add to the mempool.
+    NewTransaction(Transaction),
+    /// Asks the peer for all of its headers, sent when an announced block
+    /// does not extend our tip.
+    GetHeaders,
+    /// Reply to `GetHeaders`.
+    Headers(Vec<BlockHeader>),
+    /// Asks for the block with this hash, answered with `NewBlock`. Sent
+    /// for the missing parent of an orphan.
+    GetBlock(String),
+    /// Asks for the bodies of the blocks with these hashes, in order, at
+    /// most `MAX_BODIES_PER_MESSAGE` of them.
+    GetBodies(Vec<String>),
+    /// Reply to `GetBodies`, in the order they were asked for.
+    Bodies(Vec<BlockBody>),
+    /// Asks for a proof that the transaction with this id is in the chain.
+    GetProof(String),
+    /// Reply to `GetProof`, or `None` if the transaction is not in a block.
+    Proof(Option<InclusionProof>),
+    /// Asks for a snapshot of the state as of the block with this hash, to
+    /// bootstrap from.
+    GetSnapshot(String),
+    /// Reply to `GetSnapshot`, or `None` if the block is not held.
+    Snapshot(Option<Box<Snapshot>>),
+    /// Asks for peer addresses worth connecting to.
+    GetAddr,
+    /// Reply to `GetAddr`: the sender's own listen addresses first, then
+    /// ones it has learned.
+    Addr(Vec<String>),
+    /// Queued by the transport when a peer sends a line that does not
+    /// parse. Never sent over the wire.
+    #[serde(skip)]
+    Malformed,
+}
+
+/// A message together with the id of the node that sent it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub from: String,
+    pub message: Message,
+}
+
+/// Every node owns an inbox, and peers hold a sender into it: directly for
+/// in-process peers, or through `spawn_tcp_peer` for remote ones.
+#[derive(Debug)]
+pub(crate) struct Inbox {
+    sender: Sender<Envelope>,
+    receiver: Receiver<Envelope>,
+}
+
+impl Inbox {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Inbox { sender, receiver }
+    }
+
+    pub(crate) fn sender(&self) -> Sender<Envelope> {
+        self.sender.clone()
+    }
+
+    pub(crate) fn try_recv(&self) -> Option<Envelope> {
+        self.receiver.try_recv().ok()
+    }
+
+    #[cfg(feature = "net")]
+    pub(crate) fn recv_timeout(&self, timeout: Duration) -> Option<Envelope> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+/// Bridges a TCP connection to a node's inbox. The two sides first run the
+/// transport handshake, which refuses peers on another network and proves
+/// each side holds its identity key, then exchange one JSON-encoded
+/// `Envelope` per encrypted frame. Envelopes are delivered as coming from
+/// the peer's authenticated node id, whatever they claim. Returns that id
+/// and a sender whose envelopes are written to the socket; both halves run
+/// on their own threads until the connection drops. The side that dialled
+/// is the `Role::Initiator`. A peer that stalls the handshake is dropped
+/// after `HANDSHAKE_TIMEOUT`.
+#[cfg(feature = "net")]
+pub(crate) fn spawn_tcp_peer(
+    stream: TcpStream,
+    identity: &NodeIdentity,
+    network: Network,
+    role: Role,
+    inbox: Sender<Envelope>,
+) -> io::Result<(String, Sender<Envelope>)> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let mut reader = BufReader::new(stream);
+    let (remote_key, mut receiving, mut sending) = handshake(&mut reader, &mut writer, identity, network, role)?;
+    reader.get_ref().set_read_timeout(None)?;
+    reader.get_ref().set_write_timeout(None)?;
+    let remote_id = node_id(&remote_key);
+
+    let peer = remote_id.clone();
+    thread::spawn(move || {
+        loop {
+            let frame = match receiving.read_frame(&mut reader) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    if err.kind() == io::ErrorKind::InvalidData {
+                        warn!(peer = %peer, error = %err, "dropping connection");
+                    }
+                    break;
+                }
+            };
+            match serde_json::from_slice::<Envelope>(&frame) {
+                Ok(envelope) => {
+                    let envelope = Envelope {
+                        from: peer.clone(),
+                        ..envelope
+                    };
+                    if inbox.send(envelope).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    warn!(peer = %peer, error = %err, "dropping malformed message");
+                    let envelope = Envelope {
+                        from: peer.clone(),
+                        message: Message::Malformed,
+                    };
+                    if inbox.send(envelope).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        debug!(peer = %peer, "connection closed");
+    });
+
+    let (outbox, outgoing) = mpsc::channel::<Envelope>();
+    thread::spawn(move || {
+        for envelope in outgoing {
+            let Ok(encoded) = serde_json::to_vec(&envelope) else { continue };
+            if sending.write_frame(&mut writer, &encoded).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok((remote_id, outbox))
+}
+
+#[cfg(all(test, feature = "net"))]
+mod tests {
+    use std::net::TcpListener;
+
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+
+    fn identity(seed: u8) -> NodeIdentity {
+        NodeIdentity::from_key(SigningKey::from_bytes(&[seed; 32]))
+    }
+
+    #[test]
+    fn test_tcp_peers_exchange_envelopes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let inbox = Inbox::new();
+            let (remote, _outbox) = spawn_tcp_peer(stream, &identity(1), Network::Mainnet, Role::Responder, inbox.sender()).unwrap();
+            let envelope = inbox.receiver.recv().unwrap();
+            (remote, envelope)
+        });
+
+        let client_inbox = Inbox::new();
+        let stream = TcpStream::connect(address).unwrap();
+        let (remote, outbox) = spawn_tcp_peer(stream, &identity(2), Network::Mainnet, Role::Initiator, client_inbox.sender()).unwrap();
+        assert_eq!(remote, identity(1).id());
+        outbox
+            .send(Envelope {
+                from: "someone-else".to_owned(),
+                message: Message::GetHeaders,
+            })
+            .unwrap();
+
+        let (remote, envelope) = server.join().unwrap();
+        assert_eq!(remote, identity(2).id());
+        assert_eq!(envelope.from, identity(2).id());
+        assert_eq!(envelope.message, Message::GetHeaders);
+    }
+
+    #[test]
+    fn test_peers_on_other_networks_are_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let inbox = Inbox::new();
+            spawn_tcp_peer(stream, &identity(1), Network::Mainnet, Role::Responder, inbox.sender()).map(|(remote, _)| remote)
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let client = spawn_tcp_peer(stream, &identity(2), Network::Testnet, Role::Initiator, Inbox::new().sender());
+        assert_eq!(client.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // The dialler notices first and hangs up before the handshake ends.
+        assert!(server.join().unwrap().is_err());
+    }
+}
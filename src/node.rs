@@ -0,0 +1,701 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+use tracing::{debug, info, warn};
+
+/// Most addresses sent in, or accepted from, one `Addr` message.
+const MAX_ADDR_ENTRIES: usize = 1_000;
+
+/// Transactions a peer may relay per minute before it is penalised.
+const MAX_TRANSACTIONS_PER_MINUTE: u32 = 100;
+
+use crate::network::{Envelope, Inbox, MAX_BODIES_PER_MESSAGE, Message};
+use crate::utxo::UtxoTransaction;
+use crate::{
+    AddressBook, BAN_THRESHOLD, BanList, Block, BlockBody, BlockHeader, Blockchain, ChainObserver, DEFAULT_BAN_DURATION, Misbehavior,
+    OrphanPool, Snapshot, Transaction, metrics,
+};
+
+/// A blockchain plus its mempool and peer connections. Nodes exchange
+/// messages over in-process channels and only act on them when driven with
+/// `process_messages`, which keeps multi-node scenarios deterministic.
+#[derive(Debug)]
+pub struct Node {
+    id: String,
+    blockchain: Blockchain,
+    inbox: Inbox,
+    peers: HashMap<String, Sender<Envelope>>,
+    /// Syncs waiting for their bodies, by peer.
+    pending_syncs: HashMap<String, PendingSync>,
+    /// Blocks that arrived before their parent.
+    orphans: OrphanPool,
+    address_book: AddressBook,
+    /// Addresses other nodes can reach us on, shared in `Addr` replies.
+    advertised: Vec<String>,
+    /// Penalty points per peer; reaching `BAN_THRESHOLD` bans the peer.
+    scores: HashMap<String, u32>,
+    bans: BanList,
+    ban_duration: i64,
+    /// Start of the current minute and transactions seen in it, per peer.
+    transaction_counts: HashMap<String, (i64, u32)>,
+    /// Hash of the block a requested snapshot must be anchored at.
+    trusted_snapshot: Option<String>,
+}
+
+/// A sync with one peer: headers whose bodies are still to come, and the
+/// blocks assembled from the batches received so far.
+#[derive(Debug)]
+struct PendingSync {
+    headers: Vec<BlockHeader>,
+    blocks: Vec<Block>,
+}
+
+impl Node {
+    pub fn new(id: String) -> Self {
+        Self::with_blockchain(id, Blockchain::new())
+    }
+
+    pub fn with_blockchain(id: String, blockchain: Blockchain) -> Self {
+        Node {
+            id,
+            blockchain,
+            inbox: Inbox::new(),
+            peers: HashMap::new(),
+            pending_syncs: HashMap::new(),
+            orphans: OrphanPool::default(),
+            address_book: AddressBook::default(),
+            advertised: Vec::new(),
+            scores: HashMap::new(),
+            bans: BanList::default(),
+            ban_duration: DEFAULT_BAN_DURATION,
+            transaction_counts: HashMap::new(),
+            trusted_snapshot: None,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn blockchain(&self) -> &Blockchain {
+        &self.blockchain
+    }
+
+    pub fn blockchain_mut(&mut self) -> &mut Blockchain {
+        &mut self.blockchain
+    }
+
+    /// Calls `observer` back on every later change to the node's chain,
+    /// including blocks from peers that fail validation.
+    pub fn register_observer(&mut self, observer: impl ChainObserver + 'static) {
+        self.blockchain.register_observer(observer);
+    }
+
+    pub fn orphans(&self) -> &OrphanPool {
+        &self.orphans
+    }
+
+    pub fn peer_ids(&self) -> impl Iterator<Item = &str> {
+        self.peers.keys().map(String::as_str)
+    }
+
+    pub fn is_connected(&self, peer: &str) -> bool {
+        self.peers.contains_key(peer)
+    }
+
+    pub fn address_book(&self) -> &AddressBook {
+        &self.address_book
+    }
+
+    pub fn address_book_mut(&mut self) -> &mut AddressBook {
+        &mut self.address_book
+    }
+
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    pub fn bans_mut(&mut self) -> &mut BanList {
+        &mut self.bans
+    }
+
+    /// How long, in seconds, peers stay banned after misbehaving.
+    pub fn set_ban_duration(&mut self, seconds: i64) {
+        self.ban_duration = seconds;
+    }
+
+    pub fn is_banned(&self, peer: &str) -> bool {
+        self.bans.is_banned(peer, self.now())
+    }
+
+    /// Shares `address` with peers as one they can connect to us on.
+    pub fn advertise(&mut self, address: String) {
+        self.advertised.push(address);
+    }
+
+    /// Links two nodes in both directions and has each announce its tip so
+    /// they can catch up with one another. Nodes on different networks are
+    /// left unconnected.
+    pub fn connect(a: &mut Node, b: &mut Node) {
+        if a.blockchain.params().network != b.blockchain.params().network {
+            debug!(node = %a.id, peer = %b.id, "refusing peer on another network");
+            return;
+        }
+        a.add_peer(b.id.clone(), b.inbox.sender());
+        b.add_peer(a.id.clone(), a.inbox.sender());
+    }
+
+    pub(crate) fn inbox_sender(&self) -> Sender<Envelope> {
+        self.inbox.sender()
+    }
+
+    /// Registers a peer reachable through `sender`, announces our tip to it
+    /// and asks it for addresses. Banned peers are turned away.
+    pub(crate) fn add_peer(&mut self, id: String, sender: Sender<Envelope>) {
+        if self.is_banned(&id) {
+            debug!(node = %self.id, peer = %id, "refusing banned peer");
+            return;
+        }
+        debug!(node = %self.id, peer = %id, "peer connected");
+        self.peers.insert(id.clone(), sender);
+        metrics::registry().peers.set(self.peers.len() as u64);
+        self.send(&id, Message::NewBlock(self.blockchain.latest_block().clone()));
+        self.send(&id, Message::GetAddr);
+    }
+
+    pub fn disconnect(a: &mut Node, b: &mut Node) {
+        a.remove_peer(&b.id);
+        b.remove_peer(&a.id);
+    }
+
+    fn remove_peer(&mut self, peer: &str) {
+        self.peers.remove(peer);
+        self.pending_syncs.remove(peer);
+        self.transaction_counts.remove(peer);
+        metrics::registry().peers.set(self.peers.len() as u64);
+    }
+
+    /// Adds the penalty for `misbehavior` to the peer's score, and bans and
+    /// disconnects it once the score reaches `BAN_THRESHOLD`.
+    fn penalize(&mut self, peer: &str, misbehavior: Misbehavior) {
+        let score = self.scores.entry(peer.to_owned()).or_default();
+        *score = score.saturating_add(misbehavior.penalty());
+        let score = *score;
+        warn!(node = %self.id, %peer, ?misbehavior, score, "peer misbehaved");
+        if score >= BAN_THRESHOLD {
+            let until = self.now() + self.ban_duration;
+            self.bans.ban(peer.to_owned(), until);
+            self.scores.remove(peer);
+            self.remove_peer(peer);
+            info!(node = %self.id, %peer, until, "peer banned");
+        }
+    }
+
+    /// Counts a transaction relayed by `peer` and reports whether it is over
+    /// its allowance for the current minute.
+    fn over_transaction_limit(&mut self, peer: &str) -> bool {
+        let minute = self.now() / 60 * 60;
+        let entry = self.transaction_counts.entry(peer.to_owned()).or_insert((minute, 0));
+        if entry.0 != minute {
+            *entry = (minute, 0);
+        }
+        entry.1 += 1;
+        entry.1 > MAX_TRANSACTIONS_PER_MINUTE
+    }
+
+    fn now(&self) -> i64 {
+        self.blockchain.params().clock.now()
+    }
+
+    /// Mines a block carrying `data` and announces it to every peer.
+    pub fn mine_block(&mut self, data: String) -> bool {
+        if !self.blockchain.add_block(data) {
+            return false;
+        }
+        self.broadcast(Message::NewBlock(self.blockchain.latest_block().clone()), None);
+        true
+    }
+
+    /// Mines a block carrying UTXO-model transactions and announces it to
+    /// every peer.
+    pub fn mine_utxo_block(&mut self, transactions: Vec<UtxoTransaction>) -> bool {
+        if !self.blockchain.add_utxo_block(transactions) {
+            return false;
+        }
+        self.broadcast(Message::NewBlock(self.blockchain.latest_block().clone()), None);
+        true
+    }
+
+    /// Mines the pending transactions, paying `miner`, and announces the
+    /// block to every peer.
+    pub fn mine_pending_transactions(&mut self, miner: String) -> bool {
+        if !self.blockchain.mine_pending_transactions(miner) {
+            return false;
+        }
+        self.broadcast(Message::NewBlock(self.blockchain.latest_block().clone()), None);
+        true
+    }
+
+    /// Appends a block mined elsewhere, such as by a pool worker, and
+    /// announces it to every peer.
+    pub fn submit_block(&mut self, block: Block) -> bool {
+        if !self.blockchain.accept_block(block.clone()) {
+            return false;
+        }
+        self.broadcast(Message::NewBlock(block), None);
+        true
+    }
+
+    /// Adds a transaction to the local mempool and relays it to every peer.
+    pub fn submit_transaction(&mut self, transaction: Transaction) {
+        self.handle_transaction(transaction, None);
+    }
+
+    /// Asks `peer` for a snapshot anchored at the block with `trusted_hash`.
+    /// If it arrives, checks out and is ahead of our tip, the chain is
+    /// bootstrapped from it and the blocks after it are fetched from `peer`.
+    pub fn request_snapshot(&mut self, peer: &str, trusted_hash: String) {
+        self.trusted_snapshot = Some(trusted_hash.clone());
+        self.send(peer, Message::GetSnapshot(trusted_hash));
+    }
+
+    /// Handles every message currently waiting in the inbox and returns how
+    /// many there were. Messages sent in response are queued for the peers.
+    pub fn process_messages(&mut self) -> usize {
+        let mut handled = 0;
+        while let Some(envelope) = self.inbox.try_recv() {
+            handled += 1;
+            self.handle(envelope);
+        }
+        handled
+    }
+
+    fn handle(&mut self, Envelope { from, message }: Envelope) {
+        if self.is_banned(&from) {
+            return;
+        }
+        match message {
+            Message::NewBlock(block) => self.handle_block(block, &from),
+            Message::NewTransaction(transaction) => self.handle_transaction(transaction, Some(&from)),
+            Message::GetHeaders => {
+                let headers = self.blockchain.headers().cloned().collect();
+                self.send(&from, Message::Headers(headers));
+            }
+            Message::Headers(headers) => self.handle_headers(headers, &from),
+            Message::GetBlock(hash) => {
+                if let Some(block) = self.blockchain.get_block_by_hash(&hash) {
+                    let block = block.clone();
+                    self.send(&from, Message::NewBlock(block));
+                }
+            }
+            Message::GetBodies(hashes) => {
+                let bodies = hashes
+                    .iter()
+                    .take(MAX_BODIES_PER_MESSAGE)
+                    .map_while(|hash| self.blockchain.get_block_by_hash(hash))
+                    .map(|block| block.body.clone())
+                    .collect();
+                self.send(&from, Message::Bodies(bodies));
+            }
+            Message::Bodies(bodies) => self.handle_bodies(bodies, &from),
+            Message::GetProof(id) => {
+                let proof = self.blockchain.prove_transaction(&id);
+                self.send(&from, Message::Proof(proof));
+            }
+            // Only light clients ask for proofs.
+            Message::Proof(_) => {}
+            Message::GetSnapshot(hash) => {
+                let snapshot = self.blockchain.snapshot_at(&hash).map(Box::new);
+                self.send(&from, Message::Snapshot(snapshot));
+            }
+            Message::Snapshot(snapshot) => self.handle_snapshot(snapshot, &from),
+            Message::GetAddr => {
+                let mut addresses = self.advertised.clone();
+                let known = self.address_book.best(MAX_ADDR_ENTRIES);
+                addresses.extend(known.into_iter().map(str::to_owned));
+                addresses.truncate(MAX_ADDR_ENTRIES);
+                self.send(&from, Message::Addr(addresses));
+            }
+            Message::Addr(addresses) => {
+                for address in addresses.into_iter().take(MAX_ADDR_ENTRIES) {
+                    if !self.advertised.contains(&address) && self.address_book.add(address.clone()) {
+                        debug!(node = %self.id, peer = %from, %address, "learned peer address");
+                    }
+                }
+            }
+            Message::Malformed => self.penalize(&from, Misbehavior::MalformedMessage),
+        }
+    }
+
+    /// Bootstraps from a snapshot we asked for, then announces the new tip so
+    /// the peer sends the blocks after it. Snapshots nobody asked for are
+    /// ignored, and ones that do not check out are penalised.
+    fn handle_snapshot(&mut self, snapshot: Option<Box<Snapshot>>, from: &str) {
+        let (Some(trusted), Some(snapshot)) = (self.trusted_snapshot.as_deref(), snapshot) else {
+            return;
+        };
+        if snapshot.height() <= self.blockchain.latest_block().header.index {
+            debug!(node = %self.id, peer = %from, "snapshot is not ahead of our tip");
+            self.trusted_snapshot = None;
+            return;
+        }
+        if let Err(err) = self.blockchain.bootstrap(*snapshot, trusted) {
+            warn!(node = %self.id, peer = %from, %err, "refusing snapshot");
+            self.penalize(from, Misbehavior::InvalidBlock);
+            return;
+        }
+        self.trusted_snapshot = None;
+        self.pending_syncs.clear();
+        info!(node = %self.id, peer = %from, height = self.blockchain.base_height(), "bootstrapped from peer snapshot");
+        self.send(from, Message::NewBlock(self.blockchain.latest_block().clone()));
+    }
+
+    /// Second step of a sync: if the peer's headers describe a valid chain
+    /// with more work than ours, remember the ones we lack and ask for
+    /// their bodies.
+    fn handle_headers(&mut self, headers: Vec<BlockHeader>, from: &str) {
+        let Some(missing) = self.blockchain.missing_headers(&headers) else {
+            debug!(node = %self.id, peer = %from, "ignoring headers that do not extend our chain");
+            return;
+        };
+        let sync = PendingSync {
+            headers: missing.to_vec(),
+            blocks: Vec::new(),
+        };
+        self.request_bodies(from, sync);
+    }
+
+    /// Asks `peer` for the next batch of bodies the sync lacks.
+    fn request_bodies(&mut self, peer: &str, sync: PendingSync) {
+        let hashes = sync.headers.iter().take(MAX_BODIES_PER_MESSAGE).map(|header| header.hash.clone()).collect();
+        self.pending_syncs.insert(peer.to_owned(), sync);
+        self.send(peer, Message::GetBodies(hashes));
+    }
+
+    /// Last step of a sync: pairs the bodies with the pending headers, asks
+    /// for the next batch, and once all have arrived switches to the
+    /// resulting chain if it validates.
+    fn handle_bodies(&mut self, bodies: Vec<BlockBody>, from: &str) {
+        let Some(mut sync) = self.pending_syncs.remove(from) else {
+            return;
+        };
+        if bodies.len() != sync.headers.len().min(MAX_BODIES_PER_MESSAGE) {
+            debug!(node = %self.id, peer = %from, "peer sent an incomplete set of bodies");
+            return;
+        }
+        let headers: Vec<BlockHeader> = sync.headers.drain(..bodies.len()).collect();
+        if headers.iter().zip(&bodies).any(|(header, body)| header.merkle_root != body.merkle_root()) {
+            self.penalize(from, Misbehavior::InvalidBlock);
+            return;
+        }
+        sync.blocks.extend(headers.into_iter().zip(bodies).map(|(header, body)| Block { header, body }));
+        if !sync.headers.is_empty() {
+            self.request_bodies(from, sync);
+            return;
+        }
+        let hashes: Vec<String> = sync.blocks.iter().map(|block| block.header.hash.clone()).collect();
+        if self.blockchain.try_replace_suffix(sync.blocks) {
+            for hash in &hashes {
+                self.orphans.remove(hash);
+            }
+            self.broadcast(Message::NewBlock(self.blockchain.latest_block().clone()), Some(from));
+            self.connect_orphans(from);
+        }
+    }
+
+    fn handle_block(&mut self, block: Block, from: &str) {
+        let tip = self.blockchain.latest_block();
+        let hash = &block.header.hash;
+        if *hash == tip.header.hash || self.blockchain.get_block_by_hash(hash).is_some() || self.orphans.contains(hash) {
+            return;
+        }
+        if block.header.index <= tip.header.index && self.orphans.has_children(hash) {
+            // The parent we asked for is on a fork: sync it through headers.
+            debug!(node = %self.id, peer = %from, index = block.header.index, "orphans lead to a fork");
+            self.send(from, Message::GetHeaders);
+            return;
+        }
+        let forks_our_chain = self.blockchain.get_block_by_hash(&block.header.prev_hash).is_some();
+        if block.header.index < tip.header.index {
+            // The peer is behind: tell it about our tip so it can catch up.
+            self.send(from, Message::NewBlock(tip.clone()));
+        }
+        let tip = self.blockchain.latest_block();
+        if block.header.index <= tip.header.index {
+            if forks_our_chain {
+                // A fork no longer than ours may still carry more work.
+                self.send(from, Message::GetHeaders);
+            }
+            return;
+        }
+        if block.header.index == tip.header.index + 1 && block.header.prev_hash == tip.header.hash {
+            if self.blockchain.accept_block(block.clone()) {
+                self.broadcast(Message::NewBlock(block), Some(from));
+                self.connect_orphans(from);
+            } else {
+                self.penalize(from, Misbehavior::InvalidBlock);
+            }
+            return;
+        }
+        if forks_our_chain || (block.header.index - tip.header.index) as usize > self.orphans.capacity() {
+            // A fork of our chain, or too far ahead to fetch block by block.
+            debug!(node = %self.id, peer = %from, index = block.header.index, "requesting headers from peer");
+            self.send(from, Message::GetHeaders);
+            return;
+        }
+        if !self.blockchain.rules().check_detached(&block) {
+            self.blockchain.report_invalid(&block);
+            self.penalize(from, Misbehavior::InvalidBlock);
+            return;
+        }
+        debug!(node = %self.id, peer = %from, index = block.header.index, "holding orphan block");
+        let parent = block.header.prev_hash.clone();
+        self.orphans.insert(block);
+        metrics::registry().orphans.set(self.orphans.len() as u64);
+        self.send(from, Message::GetBlock(parent));
+    }
+
+    /// Appends every held orphan that now extends the tip, one generation
+    /// at a time, and announces each to peers other than `from`.
+    fn connect_orphans(&mut self, from: &str) {
+        loop {
+            let tip = self.blockchain.latest_block().header.hash.clone();
+            let children = self.orphans.take_children(&tip);
+            let Some(child) = children.into_iter().find(|child| self.blockchain.accept_block(child.clone())) else {
+                break;
+            };
+            debug!(node = %self.id, index = child.header.index, "connected orphan block");
+            self.broadcast(Message::NewBlock(child), Some(from));
+        }
+        metrics::registry().orphans.set(self.orphans.len() as u64);
+    }
+
+    fn handle_transaction(&mut self, transaction: Transaction, from: Option<&str>) {
+        if let Some(peer) = from
+            && self.over_transaction_limit(peer)
+        {
+            self.penalize(peer, Misbehavior::TransactionFlood);
+            return;
+        }
+        if self.blockchain.mempool().contains(&transaction) {
+            return;
+        }
+        if !self.blockchain.add_transaction(transaction.clone()) {
+            // A full mempool or a losing replacement is no fault of the peer.
+            if let Some(peer) = from
+                && transaction.fee < transaction.minimum_fee()
+            {
+                self.penalize(peer, Misbehavior::InvalidTransaction);
+            }
+            return;
+        }
+        self.broadcast(Message::NewTransaction(transaction), from);
+    }
+
+    /// Sends `message` to `peer`, dropping the peer if its connection has
+    /// gone away.
+    fn send(&mut self, peer: &str, message: Message) {
+        let Some(sender) = self.peers.get(peer) else {
+            return;
+        };
+        let envelope = Envelope {
+            from: self.id.clone(),
+            message,
+        };
+        if sender.send(envelope).is_err() {
+            debug!(node = %self.id, %peer, "peer disconnected");
+            self.remove_peer(peer);
+        }
+    }
+
+    fn broadcast(&mut self, message: Message, except: Option<&str>) {
+        let peers: Vec<String> = self.peers.keys().filter(|peer| Some(peer.as_str()) != except).cloned().collect();
+        for peer in peers {
+            self.send(&peer, message.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::ChainParams;
+    use crate::transaction::accounts::{account, payment, signed};
+
+    fn node_with_peer(peer: &str) -> (Node, mpsc::Receiver<Envelope>) {
+        let mut node = Node::with_blockchain("node".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        let (sender, receiver) = mpsc::channel();
+        node.add_peer(peer.to_owned(), sender);
+        (node, receiver)
+    }
+
+    fn deliver(node: &mut Node, from: &str, message: Message) {
+        let envelope = Envelope {
+            from: from.to_owned(),
+            message,
+        };
+        node.inbox_sender().send(envelope).unwrap();
+        node.process_messages();
+    }
+
+    #[cfg(feature = "system-clock")]
+    #[test]
+    fn test_invalid_blocks_get_a_peer_banned() {
+        let (mut node, _receiver) = node_with_peer("mallory");
+        let tip = node.blockchain().latest_block().header.clone();
+        let mut forged = Block::new(tip.index + 1, "Forged".to_owned(), tip.hash.clone());
+        forged.header.hash = "f".repeat(64);
+
+        deliver(&mut node, "mallory", Message::NewBlock(forged.clone()));
+        assert!(node.is_connected("mallory"));
+        assert!(!node.is_banned("mallory"));
+
+        deliver(&mut node, "mallory", Message::NewBlock(forged));
+        assert!(node.is_banned("mallory"));
+        assert!(!node.is_connected("mallory"));
+
+        let (sender, _receiver) = mpsc::channel();
+        node.add_peer("mallory".to_owned(), sender);
+        assert!(!node.is_connected("mallory"));
+    }
+
+    #[test]
+    fn test_orphans_connect_once_their_parents_arrive() {
+        let mut source = Blockchain::with_params(ChainParams::testing());
+        for i in 1..=3 {
+            assert!(source.add_block(format!("Block {} data", i)));
+        }
+        let blocks: Vec<Block> = source.iter().cloned().collect();
+
+        let (mut node, receiver) = node_with_peer("peer");
+        receiver.try_iter().for_each(drop);
+        deliver(&mut node, "peer", Message::NewBlock(blocks[3].clone()));
+        deliver(&mut node, "peer", Message::NewBlock(blocks[2].clone()));
+        assert_eq!(node.orphans().len(), 2);
+        assert_eq!(node.blockchain().latest_block().header.index, 0);
+        let requested: Vec<Message> = receiver.try_iter().map(|envelope| envelope.message).collect();
+        assert_eq!(
+            requested,
+            vec![
+                Message::GetBlock(blocks[2].header.hash.clone()),
+                Message::GetBlock(blocks[1].header.hash.clone()),
+            ]
+        );
+
+        deliver(&mut node, "peer", Message::NewBlock(blocks[1].clone()));
+        assert!(node.orphans().is_empty());
+        assert_eq!(node.blockchain().latest_block(), &blocks[3]);
+
+        let mut junk = blocks[3].clone();
+        junk.header.index = 5;
+        junk.header.prev_hash = "f".repeat(64);
+        junk.header.hash = "e".repeat(64);
+        deliver(&mut node, "peer", Message::NewBlock(junk));
+        assert!(node.orphans().is_empty());
+        assert_eq!(node.scores.get("peer"), Some(&Misbehavior::InvalidBlock.penalty()));
+    }
+
+    #[test]
+    fn test_bootstraps_from_a_peer_snapshot() {
+        let mut full = Node::with_blockchain("full".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        assert!(full.blockchain_mut().add_transaction(payment("alice", "bob", 5, 0)));
+        assert!(full.mine_pending_transactions("miner".to_owned()));
+        for i in 2..=5 {
+            assert!(full.mine_block(format!("Block {} data", i)));
+        }
+        let trusted = full.blockchain().get_block_by_index(3).unwrap().header.hash.clone();
+
+        let mut fresh = Node::with_blockchain("fresh".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        Node::connect(&mut full, &mut fresh);
+        fresh.request_snapshot("full", trusted.clone());
+        while full.process_messages() + fresh.process_messages() > 0 {}
+
+        assert_eq!(fresh.blockchain().base_height(), 3);
+        assert!(fresh.blockchain().is_pruned(2));
+        assert_eq!(fresh.blockchain().latest_block(), full.blockchain().latest_block());
+        assert_eq!(fresh.blockchain().snapshot(), full.blockchain().snapshot());
+
+        // A snapshot anchored anywhere but the trusted block is refused.
+        let (mut node, _receiver) = node_with_peer("peer");
+        node.request_snapshot("peer", trusted);
+        let other = full.blockchain().snapshot_at(&full.blockchain().latest_block().header.hash).unwrap();
+        deliver(&mut node, "peer", Message::Snapshot(Some(Box::new(other))));
+        assert_eq!(node.blockchain().latest_block().header.index, 0);
+        assert_eq!(node.scores.get("peer"), Some(&Misbehavior::InvalidBlock.penalty()));
+    }
+
+    #[test]
+    fn test_syncs_fetch_bodies_in_batches() {
+        let mut full = Node::with_blockchain("full".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        for i in 1..=MAX_BODIES_PER_MESSAGE + 4 {
+            assert!(full.mine_block(format!("Block {} data", i)));
+        }
+        let headers: Vec<BlockHeader> = full.blockchain().headers().cloned().collect();
+
+        let (mut node, receiver) = node_with_peer("peer");
+        deliver(&mut node, "peer", Message::Headers(headers));
+        let Some(Message::GetBodies(hashes)) = receiver.try_iter().map(|envelope| envelope.message).last() else {
+            panic!("expected a request for bodies");
+        };
+        assert_eq!(hashes.len(), MAX_BODIES_PER_MESSAGE);
+
+        let mut fresh = Node::with_blockchain("fresh".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        Node::connect(&mut full, &mut fresh);
+        fresh.send("full", Message::GetHeaders);
+        while full.process_messages() + fresh.process_messages() > 0 {}
+        assert_eq!(fresh.blockchain().latest_block(), full.blockchain().latest_block());
+    }
+
+    #[test]
+    fn test_nodes_on_different_networks_stay_apart() {
+        let mut mainnet = Node::with_blockchain("mainnet".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        let testnet_params = ChainParams {
+            network: crate::Network::Testnet,
+            ..ChainParams::testing()
+        };
+        let mut testnet = Node::with_blockchain("testnet".to_owned(), Blockchain::with_params(testnet_params));
+        assert_ne!(mainnet.blockchain().latest_block(), testnet.blockchain().latest_block());
+
+        Node::connect(&mut mainnet, &mut testnet);
+        assert!(!mainnet.is_connected("testnet"));
+        assert!(!testnet.is_connected("mainnet"));
+
+        let (mut node, _receiver) = node_with_peer("stranger");
+        assert!(testnet.mine_block("Testnet block".to_owned()));
+        let foreign = testnet.blockchain().latest_block().clone();
+        deliver(&mut node, "stranger", Message::NewBlock(foreign));
+        assert_eq!(node.blockchain().latest_block().header.index, 0);
+    }
+
+    #[test]
+    fn test_transaction_floods_and_malformed_messages_are_penalised() {
+        let (mut node, _receiver) = node_with_peer("spammer");
+        for amount in 0..MAX_TRANSACTIONS_PER_MINUTE as u64 {
+            let transaction = Transaction {
+                nonce: amount,
+                ..Transaction::new(String::new(), account("bob"), amount)
+            };
+            deliver(&mut node, "spammer", Message::NewTransaction(signed("alice", transaction)));
+        }
+        assert_eq!(node.blockchain().mempool().len(), MAX_TRANSACTIONS_PER_MINUTE as usize);
+        assert!(node.is_connected("spammer"));
+
+        for _ in 0..10 {
+            deliver(&mut node, "spammer", Message::Malformed);
+        }
+        assert!(node.is_banned("spammer"));
+
+        let (mut node, _receiver) = node_with_peer("flooder");
+        for amount in 0..MAX_TRANSACTIONS_PER_MINUTE as u64 + 20 {
+            let transaction = Transaction {
+                nonce: amount,
+                ..Transaction::new(String::new(), account("bob"), amount)
+            };
+            deliver(&mut node, "flooder", Message::NewTransaction(signed("alice", transaction)));
+        }
+        assert!(node.is_banned("flooder"));
+        assert_eq!(node.blockchain().mempool().len(), MAX_TRANSACTIONS_PER_MINUTE as usize);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Transaction, codec};
+
+/// The sequence number each account's next transaction must carry. An
+/// account starts at zero and moves up by one with every transaction it
+/// sends, so a transaction can only ever be included once.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountNonces {
+    next: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonceError {
+    pub account: String,
+    pub expected: u64,
+    pub found: u64,
+}
+
+impl fmt::Display for NonceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} sent nonce {} but its next nonce is {}",
+            self.account, self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for NonceError {}
+
+impl AccountNonces {
+    /// The nonce `account`'s next transaction must carry.
+    pub fn next(&self, account: &str) -> u64 {
+        self.next.get(account).copied().unwrap_or(0)
+    }
+
+    /// Accounts that have sent at least one transaction, with their next
+    /// nonce.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.next.iter().map(|(account, &nonce)| (account.as_str(), nonce))
+    }
+
+    /// Hex-encoded SHA-256 commitment to every account's next nonce.
+    pub fn root(&self) -> String {
+        codec::hash_hex(|writer| {
+            codec::write_len(writer, self.next.len())?;
+            for (account, nonce) in &self.next {
+                codec::write_str(writer, account)?;
+                writer.write_all(&nonce.to_le_bytes())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Advances the sender of every transaction in `transactions` but the
+    /// reward, in order. Leaves the nonces untouched if any transaction is
+    /// out of sequence.
+    pub(crate) fn apply_block(&mut self, transactions: &[Transaction]) -> Result<(), NonceError> {
+        let mut advanced: BTreeMap<&str, u64> = BTreeMap::new();
+        for transaction in transactions.iter().filter(|tx| !tx.is_reward()) {
+            let next = advanced
+                .entry(&transaction.sender)
+                .or_insert_with(|| self.next(&transaction.sender));
+            if transaction.nonce != *next {
+                return Err(NonceError {
+                    account: transaction.sender.clone(),
+                    expected: *next,
+                    found: transaction.nonce,
+                });
+            }
+            *next += 1;
+        }
+        for (account, next) in advanced {
+            self.next.insert(account.to_owned(), next);
+        }
+        Ok(())
+    }
+
+    /// Reverses `apply_block` for the same transactions.
+    pub(crate) fn rollback_block(&mut self, transactions: &[Transaction]) {
+        for transaction in transactions.iter().filter(|tx| !tx.is_reward()) {
+            let Some(next) = self.next.get_mut(&transaction.sender) else { continue };
+            *next -= 1;
+            if *next == 0 {
+                self.next.remove(&transaction.sender);
+            }
+        }
+    }
+}
+
+impl FromIterator<(String, u64)> for AccountNonces {
+    fn from_iter<I: IntoIterator<Item = (String, u64)>>(iter: I) -> Self {
+        AccountNonces {
+            next: iter.into_iter().filter(|&(_, nonce)| nonce > 0).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(sender: &str, nonce: u64) -> Transaction {
+        Transaction {
+            nonce,
+            ..Transaction::new(sender.to_owned(), "bob".to_owned(), 1)
+        }
+    }
+
+    #[test]
+    fn test_nonces_advance_in_sequence() {
+        let mut nonces = AccountNonces::default();
+        let block = [Transaction::reward("miner".to_owned(), 50), tx("alice", 0), tx("alice", 1), tx("carol", 0)];
+        assert_eq!(nonces.apply_block(&block), Ok(()));
+        assert_eq!((nonces.next("alice"), nonces.next("carol"), nonces.next("miner")), (2, 1, 0));
+
+        nonces.rollback_block(&block);
+        assert_eq!(nonces, AccountNonces::default());
+    }
+
+    #[test]
+    fn test_replays_and_gaps_are_rejected() {
+        let mut nonces = AccountNonces::default();
+        nonces.apply_block(&[tx("alice", 0)]).unwrap();
+
+        let replay = nonces.apply_block(&[tx("carol", 0), tx("alice", 0)]);
+        assert_eq!(
+            replay,
+            Err(NonceError {
+                account: "alice".to_owned(),
+                expected: 1,
+                found: 0
+            })
+        );
+        assert!(nonces.apply_block(&[tx("alice", 2)]).is_err());
+        assert_eq!((nonces.next("alice"), nonces.next("carol")), (1, 0));
+    }
+}
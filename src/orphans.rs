@@ -0,0 +1,129 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::Block;
+
+/// Orphans held at once by default. Each is already bounded by the block
+/// limits, so this caps the pool's memory.
+pub const MAX_ORPHANS: usize = 100;
+
+/// Blocks whose parent we have not seen yet, kept until the parent arrives.
+/// When full, the oldest orphan makes room for the newest.
+#[derive(Debug, Clone)]
+pub struct OrphanPool {
+    capacity: usize,
+    blocks: HashMap<String, Block>,
+    /// Hashes in arrival order, oldest first.
+    order: VecDeque<String>,
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        Self::with_capacity(MAX_ORPHANS)
+    }
+}
+
+impl OrphanPool {
+    pub fn with_capacity(capacity: usize) -> Self {
+        OrphanPool {
+            capacity,
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    /// Whether some orphan is waiting for the block with `hash`.
+    pub fn has_children(&self, hash: &str) -> bool {
+        self.blocks.values().any(|block| block.header.prev_hash == hash)
+    }
+
+    /// Holds `block`, evicting the oldest orphan if the pool is full.
+    /// Returns false if it is already held.
+    pub fn insert(&mut self, block: Block) -> bool {
+        if self.capacity == 0 || self.contains(&block.header.hash) {
+            return false;
+        }
+        while self.blocks.len() >= self.capacity {
+            let Some(oldest) = self.order.pop_front() else { break };
+            self.blocks.remove(&oldest);
+        }
+        self.order.push_back(block.header.hash.clone());
+        self.blocks.insert(block.header.hash.clone(), block);
+        true
+    }
+
+    pub fn remove(&mut self, hash: &str) -> Option<Block> {
+        let block = self.blocks.remove(hash)?;
+        self.order.retain(|held| held != hash);
+        Some(block)
+    }
+
+    /// Removes and returns the orphans whose parent is `hash`, oldest first.
+    pub fn take_children(&mut self, hash: &str) -> Vec<Block> {
+        let children: Vec<String> = self
+            .order
+            .iter()
+            .filter(|held| self.blocks[*held].header.prev_hash == hash)
+            .cloned()
+            .collect();
+        children.iter().filter_map(|child| self.remove(child)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockBody;
+
+    fn block(hash: &str, prev_hash: &str) -> Block {
+        let mut block = Block::unmined(1, 0, prev_hash.to_owned(), BlockBody::default());
+        block.header.hash = hash.to_owned();
+        block
+    }
+
+    #[test]
+    fn test_children_are_taken_in_arrival_order() {
+        let mut pool = OrphanPool::default();
+        assert!(pool.insert(block("b", "a")));
+        assert!(pool.insert(block("c", "b")));
+        assert!(pool.insert(block("b2", "a")));
+        assert!(!pool.insert(block("b", "a")));
+        assert!(pool.has_children("a"));
+
+        let children: Vec<String> = pool.take_children("a").into_iter().map(|block| block.header.hash).collect();
+        assert_eq!(children, vec!["b", "b2"]);
+        assert!(!pool.has_children("a"));
+        assert_eq!(pool.len(), 1);
+        assert!(pool.contains("c"));
+    }
+
+    #[test]
+    fn test_full_pool_evicts_the_oldest() {
+        let mut pool = OrphanPool::with_capacity(2);
+        pool.insert(block("b", "a"));
+        pool.insert(block("c", "b"));
+        pool.insert(block("d", "c"));
+
+        assert_eq!(pool.len(), 2);
+        assert!(!pool.contains("b"));
+        assert!(pool.contains("c") && pool.contains("d"));
+        assert!(pool.remove("c").is_some());
+        pool.insert(block("e", "d"));
+        assert!(pool.contains("d") && pool.contains("e"));
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+#[cfg(feature = "net")]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(feature = "net")]
+use std::net::{TcpListener, TcpStream};
+use std::ops::Range;
+#[cfg(feature = "net")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "net")]
+use std::sync::mpsc::{self, Sender, TryRecvError};
+#[cfg(feature = "net")]
+use std::thread;
+#[cfg(feature = "net")]
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+#[cfg(feature = "net")]
+use tracing::{debug, info, warn};
+
+#[cfg(feature = "net")]
+use crate::Node;
+use crate::{Block, BlockHeader, Blockchain, Target};
+
+/// Nonces handed to a worker per job.
+pub const DEFAULT_RANGE_SIZE: u64 = 1 << 24;
+
+/// Nonces a worker tries between checks for a newer job.
+#[cfg(feature = "net")]
+const WORKER_CHUNK: u64 = 10_000;
+
+#[cfg(feature = "net")]
+const WORKER_POLL: Duration = Duration::from_millis(100);
+
+/// A slice of the search space: `header` with every nonce in
+/// `nonce_start..nonce_end`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub header: BlockHeader,
+    pub nonce_start: u64,
+    pub nonce_end: u64,
+    /// Target a share must meet. Easier than the block's, so workers show
+    /// their effort long before one of them finds a block.
+    pub share_target: Target,
+}
+
+/// A nonce from a job whose hash meets the share target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    pub job_id: u64,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShareError {
+    /// The job was never handed out, or was voided by a new template.
+    UnknownJob,
+    OutOfRange,
+    Duplicate,
+    BelowTarget,
+    /// The share completed a block the chain no longer accepts.
+    Stale,
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareError::UnknownJob => write!(f, "unknown or expired job"),
+            ShareError::OutOfRange => write!(f, "nonce is outside the job's range"),
+            ShareError::Duplicate => write!(f, "share was already submitted"),
+            ShareError::BelowTarget => write!(f, "hash does not meet the share target"),
+            ShareError::Stale => write!(f, "block is no longer on the tip"),
+        }
+    }
+}
+
+impl std::error::Error for ShareError {}
+
+/// Messages between a coordinator and its workers, one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PoolMessage {
+    /// First message from a worker, naming who its shares are credited to.
+    Subscribe { worker: String },
+    /// Asks for more work once a job's range is used up.
+    GetJob,
+    /// Work for the worker. Replaces whatever job it had before.
+    Job(Job),
+    Share(Share),
+    /// Reply to a share. `block` is set if the share completed a block.
+    Accepted { block: bool },
+    Rejected(ShareError),
+}
+
+/// Splits the work on the next block into nonce-range jobs and checks the
+/// shares workers send back, assembling the block when one meets its
+/// target. Knows nothing of the transport.
+#[derive(Debug)]
+pub struct Coordinator {
+    miner: String,
+    share_target: Target,
+    range_size: u64,
+    /// Block being mined. Its header's nonce and extra nonce mark where
+    /// the next job starts.
+    template: Option<Block>,
+    /// Jobs handed out for the current template.
+    jobs: HashMap<u64, Job>,
+    last_job_id: u64,
+    submitted: HashSet<(u64, u64)>,
+    shares: HashMap<String, u64>,
+}
+
+impl Coordinator {
+    /// A coordinator paying block rewards to `miner`.
+    pub fn new(miner: String, share_target: Target) -> Self {
+        Self::with_range_size(miner, share_target, DEFAULT_RANGE_SIZE)
+    }
+
+    pub fn with_range_size(miner: String, share_target: Target, range_size: u64) -> Self {
+        Coordinator {
+            miner,
+            share_target,
+            range_size: range_size.max(1),
+            template: None,
+            jobs: HashMap::new(),
+            last_job_id: 0,
+            submitted: HashSet::new(),
+            shares: HashMap::new(),
+        }
+    }
+
+    /// Accepted shares per worker.
+    pub fn shares(&self) -> &HashMap<String, u64> {
+        &self.shares
+    }
+
+    /// Rebuilds the template if the tip or the pending transactions have
+    /// changed, voiding every job handed out so far. Returns whether it
+    /// did, in which case workers need new jobs.
+    pub fn refresh(&mut self, blockchain: &Blockchain) -> bool {
+        let candidate = blockchain.block_template(self.miner.clone());
+        if let Some(template) = &self.template
+            && template.header.prev_hash == candidate.header.prev_hash
+            && template.body == candidate.body
+        {
+            return false;
+        }
+        self.template = Some(candidate);
+        self.jobs.clear();
+        self.submitted.clear();
+        true
+    }
+
+    /// Hands out the next unclaimed nonce range of the template. Once the
+    /// nonces run out, moves on to the next extra nonce.
+    pub fn next_job(&mut self) -> Option<Job> {
+        let template = self.template.as_mut()?;
+        let header = template.header.clone();
+        let nonce_end = match header.nonce.checked_add(self.range_size) {
+            Some(end) => {
+                template.header.nonce = end;
+                end
+            }
+            None => {
+                template.header.extra_nonce = template.header.extra_nonce.wrapping_add(1);
+                template.header.nonce = 0;
+                u64::MAX
+            }
+        };
+        self.last_job_id += 1;
+        let job = Job {
+            id: self.last_job_id,
+            nonce_start: header.nonce,
+            nonce_end,
+            header,
+            share_target: self.share_target,
+        };
+        self.jobs.insert(job.id, job.clone());
+        Some(job)
+    }
+
+    /// Checks and credits a share from `worker`. Returns the finished block
+    /// if the share also meets the block's target.
+    pub fn submit(&mut self, worker: &str, share: Share) -> Result<Option<Block>, ShareError> {
+        let job = self.jobs.get(&share.job_id).ok_or(ShareError::UnknownJob)?;
+        if !(job.nonce_start..job.nonce_end).contains(&share.nonce) {
+            return Err(ShareError::OutOfRange);
+        }
+        if !self.submitted.insert((share.job_id, share.nonce)) {
+            return Err(ShareError::Duplicate);
+        }
+        let mut header = job.header.clone();
+        header.nonce = share.nonce;
+        let digest = digest(&header);
+        if !job.share_target.is_met_by(&digest) {
+            return Err(ShareError::BelowTarget);
+        }
+        *self.shares.entry(worker.to_owned()).or_default() += 1;
+
+        header.hash = hex::encode(digest);
+        if !header.meets_target() {
+            return Ok(None);
+        }
+        let body = self.template.as_ref().map(|template| template.body.clone()).unwrap_or_default();
+        Ok(Some(Block { header, body }))
+    }
+}
+
+/// Tries each nonce of `nonces` on `job`, passing every share found to
+/// `on_share`.
+pub fn search(job: &Job, nonces: Range<u64>, mut on_share: impl FnMut(Share)) {
+    let prefix = job.header.hasher_without_nonce();
+    for nonce in nonces {
+        let mut hasher = prefix.clone();
+        hasher.update(nonce.to_le_bytes());
+        if job.share_target.is_met_by(&hasher.finalize()) {
+            on_share(Share { job_id: job.id, nonce });
+        }
+    }
+}
+
+fn digest(header: &BlockHeader) -> Vec<u8> {
+    let mut hasher = header.hasher_without_nonce();
+    hasher.update(header.nonce.to_le_bytes());
+    hasher.finalize().to_vec()
+}
+
+/// What worker connections forward to the thread that owns the node.
+#[cfg(feature = "net")]
+#[derive(Debug)]
+pub(crate) enum WorkerEvent {
+    Connected { connection: u64, outbox: Sender<PoolMessage> },
+    Message { connection: u64, message: PoolMessage },
+    Disconnected { connection: u64 },
+}
+
+/// A coordinator and the workers connected to it, driven from the thread
+/// that owns the node.
+#[cfg(feature = "net")]
+#[derive(Debug)]
+pub(crate) struct PoolServer {
+    coordinator: Coordinator,
+    /// Outbox and subscribed name of every connected worker.
+    workers: HashMap<u64, (Sender<PoolMessage>, Option<String>)>,
+}
+
+#[cfg(feature = "net")]
+impl PoolServer {
+    pub(crate) fn new(coordinator: Coordinator) -> Self {
+        PoolServer {
+            coordinator,
+            workers: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn handle(&mut self, event: WorkerEvent, node: &mut Node) {
+        match event {
+            WorkerEvent::Connected { connection, outbox } => {
+                self.workers.insert(connection, (outbox, None));
+            }
+            WorkerEvent::Disconnected { connection } => {
+                self.workers.remove(&connection);
+            }
+            WorkerEvent::Message { connection, message } => self.handle_message(connection, message, node),
+        }
+    }
+
+    /// Sends every subscribed worker a new job if the template changed.
+    pub(crate) fn tick(&mut self, node: &Node) {
+        if !self.coordinator.refresh(node.blockchain()) {
+            return;
+        }
+        let connections: Vec<u64> = self
+            .workers
+            .iter()
+            .filter(|(_, (_, name))| name.is_some())
+            .map(|(&connection, _)| connection)
+            .collect();
+        for connection in connections {
+            self.send_job(connection);
+        }
+    }
+
+    fn handle_message(&mut self, connection: u64, message: PoolMessage, node: &mut Node) {
+        let Some((_, name)) = self.workers.get_mut(&connection) else {
+            return;
+        };
+        match message {
+            PoolMessage::Subscribe { worker } => {
+                info!(%worker, "pool worker subscribed");
+                *name = Some(worker);
+                self.tick(node);
+                self.send_job(connection);
+            }
+            PoolMessage::GetJob if name.is_some() => self.send_job(connection),
+            PoolMessage::Share(share) => {
+                let Some(worker) = name.clone() else { return };
+                let reply = match self.coordinator.submit(&worker, share) {
+                    Ok(None) => PoolMessage::Accepted { block: false },
+                    Ok(Some(block)) => {
+                        let index = block.header.index;
+                        if node.submit_block(block) {
+                            info!(%worker, index, "pool found a block");
+                            PoolMessage::Accepted { block: true }
+                        } else {
+                            PoolMessage::Rejected(ShareError::Stale)
+                        }
+                    }
+                    Err(err) => {
+                        debug!(%worker, error = %err, "share rejected");
+                        PoolMessage::Rejected(err)
+                    }
+                };
+                self.send(connection, reply);
+                self.tick(node);
+            }
+            other => debug!(connection, message = ?other, "ignoring pool message"),
+        }
+    }
+
+    fn send_job(&mut self, connection: u64) {
+        if let Some(job) = self.coordinator.next_job() {
+            self.send(connection, PoolMessage::Job(job));
+        }
+    }
+
+    fn send(&mut self, connection: u64, message: PoolMessage) {
+        let Some((outbox, _)) = self.workers.get(&connection) else {
+            return;
+        };
+        if outbox.send(message).is_err() {
+            self.workers.remove(&connection);
+        }
+    }
+}
+
+/// Accepts worker connections from `listener` and forwards what they send
+/// to `events`, one JSON-encoded `PoolMessage` per line each way.
+#[cfg(feature = "net")]
+pub(crate) fn serve(listener: TcpListener, events: Sender<WorkerEvent>) {
+    for (connection, stream) in (1..).zip(listener.incoming()) {
+        let result = stream.and_then(|stream| spawn_worker_connection(stream, connection, events.clone()));
+        if let Err(err) = result {
+            warn!(error = %err, "rejected pool worker");
+        }
+    }
+}
+
+#[cfg(feature = "net")]
+fn spawn_worker_connection(stream: TcpStream, connection: u64, events: Sender<WorkerEvent>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let (outbox, outgoing) = mpsc::channel::<PoolMessage>();
+    thread::spawn(move || {
+        for message in outgoing {
+            let Ok(line) = serde_json::to_string(&message) else { continue };
+            if writeln!(writer, "{}", line).is_err() {
+                break;
+            }
+        }
+    });
+    events
+        .send(WorkerEvent::Connected { connection, outbox })
+        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            match serde_json::from_str(&line) {
+                Ok(message) => {
+                    if events.send(WorkerEvent::Message { connection, message }).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => debug!(connection, error = %err, "dropping malformed pool message"),
+            }
+        }
+        let _ = events.send(WorkerEvent::Disconnected { connection });
+    });
+    Ok(())
+}
+
+/// Mines for the coordinator at `address` as `name` until `shutdown` is
+/// set or the connection drops, and returns how many shares were sent.
+#[cfg(feature = "net")]
+pub fn run_worker(address: &str, name: &str, shutdown: &AtomicBool) -> io::Result<u64> {
+    let stream = TcpStream::connect(address)?;
+    let mut writer = stream.try_clone()?;
+    let (inbox, messages) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            match serde_json::from_str::<PoolMessage>(&line) {
+                Ok(message) => {
+                    if inbox.send(message).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => debug!(error = %err, "dropping malformed pool message"),
+            }
+        }
+    });
+    let mut send = |message: &PoolMessage| -> io::Result<()> {
+        let line = serde_json::to_string(message).map_err(io::Error::other)?;
+        writeln!(writer, "{}", line)
+    };
+    send(&PoolMessage::Subscribe { worker: name.to_owned() })?;
+
+    let mut job: Option<Job> = None;
+    let mut cursor = 0;
+    let mut sent = 0;
+    while !shutdown.load(Ordering::SeqCst) {
+        let message = match &job {
+            Some(_) => messages.try_recv(),
+            None => messages.recv_timeout(WORKER_POLL).map_err(|err| match err {
+                mpsc::RecvTimeoutError::Timeout => TryRecvError::Empty,
+                mpsc::RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+            }),
+        };
+        match message {
+            Ok(PoolMessage::Job(next)) => {
+                debug!(job = next.id, index = next.header.index, "new pool job");
+                cursor = next.nonce_start;
+                job = Some(next);
+                continue;
+            }
+            Ok(PoolMessage::Accepted { block: true }) => info!("share completed a block"),
+            Ok(PoolMessage::Rejected(err)) => debug!(error = %err, "share rejected"),
+            Ok(_) | Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "coordinator went away"));
+            }
+        }
+
+        let Some(current) = &job else { continue };
+        if cursor >= current.nonce_end {
+            send(&PoolMessage::GetJob)?;
+            job = None;
+            continue;
+        }
+        let end = cursor.saturating_add(WORKER_CHUNK).min(current.nonce_end);
+        let mut shares = Vec::new();
+        search(current, cursor..end, |share| shares.push(share));
+        for share in shares {
+            send(&PoolMessage::Share(share))?;
+            sent += 1;
+        }
+        cursor = end;
+    }
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "net")]
+    use std::sync::Arc;
+    #[cfg(feature = "net")]
+    use std::time::Instant;
+
+    use super::*;
+    use crate::ChainParams;
+    use crate::transaction::accounts::payment;
+
+    fn params() -> ChainParams {
+        ChainParams {
+            target: Target::from_leading_zeros(2),
+            ..ChainParams::testing()
+        }
+    }
+
+    #[test]
+    fn test_jobs_split_the_nonces() {
+        let blockchain = Blockchain::with_params(params());
+        let mut coordinator = Coordinator::with_range_size("miner".to_owned(), Target::from_leading_zeros(1), 100);
+        assert!(coordinator.next_job().is_none());
+        assert!(coordinator.refresh(&blockchain));
+        assert!(!coordinator.refresh(&blockchain));
+
+        let first = coordinator.next_job().unwrap();
+        let second = coordinator.next_job().unwrap();
+        assert_eq!((first.nonce_start, first.nonce_end), (0, 100));
+        assert_eq!((second.nonce_start, second.nonce_end), (100, 200));
+        assert_eq!(first.header.prev_hash, blockchain.latest_block().header.hash);
+    }
+
+    #[test]
+    fn test_shares_are_checked_and_assemble_the_block() {
+        let mut blockchain = Blockchain::with_params(params());
+        assert!(blockchain.add_transaction(payment("alice", "bob", 5, 2)));
+        let mut coordinator = Coordinator::with_range_size("miner".to_owned(), Target::from_leading_zeros(1), 100_000);
+        coordinator.refresh(&blockchain);
+        let job = coordinator.next_job().unwrap();
+
+        let mut shares = Vec::new();
+        search(&job, job.nonce_start..job.nonce_end, |share| shares.push(share));
+        assert!(!shares.is_empty());
+
+        let miss = (job.nonce_start..job.nonce_end).find(|nonce| !shares.iter().any(|share| share.nonce == *nonce));
+        let miss = Share { job_id: job.id, nonce: miss.unwrap() };
+        assert_eq!(coordinator.submit("w", miss), Err(ShareError::BelowTarget));
+        let outside = Share { job_id: job.id, nonce: job.nonce_end };
+        assert_eq!(coordinator.submit("w", outside), Err(ShareError::OutOfRange));
+        let unknown = Share { job_id: job.id + 1, ..shares[0] };
+        assert_eq!(coordinator.submit("w", unknown), Err(ShareError::UnknownJob));
+
+        let mut block = None;
+        for share in &shares {
+            if let Some(found) = coordinator.submit("w", *share).unwrap() {
+                block = Some(found);
+                break;
+            }
+        }
+        assert_eq!(coordinator.submit("w", shares[0]), Err(ShareError::Duplicate));
+        let block = block.expect("some share meets the block target");
+        assert!(coordinator.shares()["w"] >= 1);
+        assert_eq!(block.body.transactions[0].recipient, "miner");
+
+        assert!(blockchain.accept_block(block));
+        assert!(blockchain.mempool().is_empty());
+        assert!(coordinator.refresh(&blockchain));
+        assert_eq!(coordinator.submit("w", shares[0]), Err(ShareError::UnknownJob));
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_workers_mine_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (events, incoming) = mpsc::channel();
+        thread::spawn(move || serve(listener, events));
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || run_worker(&address, "worker-1", &shutdown))
+        };
+
+        let mut node = Node::with_blockchain("pool".to_owned(), Blockchain::with_params(params()));
+        let mut server = PoolServer::new(Coordinator::new("miner".to_owned(), Target::from_leading_zeros(1)));
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while node.blockchain().latest_block().header.index < 2 {
+            assert!(Instant::now() < deadline, "pool found no blocks");
+            for event in incoming.try_iter() {
+                server.handle(event, &mut node);
+            }
+            server.tick(&node);
+            thread::sleep(Duration::from_millis(5));
+        }
+        shutdown.store(true, Ordering::SeqCst);
+
+        assert!(worker.join().unwrap().unwrap() >= 2);
+        assert!(server.coordinator.shares()["worker-1"] >= 2);
+        assert!(node.blockchain().is_valid_chain());
+    }
+}
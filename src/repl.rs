@@ -0,0 +1,207 @@
+use crate::{Block, Blockchain, ChainParams};
+
+const HELP: &str = "\
+commands:
+  mine <data>            mine a block carrying <data> on the active chain
+  tamper <index> <data>  overwrite a block's data without mining it again
+  validate               check the active chain
+  show <index>           print a block of the active chain
+  chain                  list the active chain's blocks
+  fork <height>          branch off the main chain after block <height>
+  reorg                  replace the main chain with the fork if it has more work
+  main                   go back to the main chain
+  help                   print this message
+  quit                   leave";
+
+/// An interactive session over a chain and, once `fork` is run, a branch
+/// of it. Commands act on whichever of the two is active.
+pub struct Repl {
+    params: ChainParams,
+    main: Blockchain,
+    fork: Option<Blockchain>,
+    on_fork: bool,
+}
+
+impl Repl {
+    pub fn new(params: ChainParams) -> Self {
+        Repl {
+            main: Blockchain::with_params(params.clone()),
+            params,
+            fork: None,
+            on_fork: false,
+        }
+    }
+
+    /// The prompt to show before reading the next line.
+    pub fn prompt(&self) -> &'static str {
+        if self.on_fork { "simplz (fork)> " } else { "simplz> " }
+    }
+
+    /// Runs one line of input and returns what to print, or `None` once the
+    /// session should end.
+    pub fn execute(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let output = match command {
+            "" => String::new(),
+            "mine" => self.mine(rest),
+            "tamper" => match rest.split_once(char::is_whitespace) {
+                Some((index, data)) => match index.parse() {
+                    Ok(index) => self.tamper(index, data.trim()),
+                    Err(_) => "usage: tamper <index> <data>".to_owned(),
+                },
+                None => "usage: tamper <index> <data>".to_owned(),
+            },
+            "validate" => self.validate(),
+            "show" => match rest.parse() {
+                Ok(index) => self.show(index),
+                Err(_) => "usage: show <index>".to_owned(),
+            },
+            "chain" => self.list(),
+            "fork" => match rest.parse() {
+                Ok(height) => self.branch(height),
+                Err(_) => "usage: fork <height>".to_owned(),
+            },
+            "reorg" => self.reorg(),
+            "main" => {
+                self.on_fork = false;
+                "on the main chain".to_owned()
+            }
+            "help" => HELP.to_owned(),
+            "quit" | "exit" => return None,
+            other => format!("unknown command '{}', try help", other),
+        };
+        Some(output)
+    }
+
+    fn active(&mut self) -> &mut Blockchain {
+        match &mut self.fork {
+            Some(fork) if self.on_fork => fork,
+            _ => &mut self.main,
+        }
+    }
+
+    fn mine(&mut self, data: &str) -> String {
+        let blockchain = self.active();
+        if !blockchain.add_block(data.to_owned()) {
+            return "block rejected".to_owned();
+        }
+        let header = &blockchain.latest_block().header;
+        format!("mined block {} {}", header.index, header.hash)
+    }
+
+    fn tamper(&mut self, index: u32, data: &str) -> String {
+        match self.active().block_mut(index) {
+            Some(block) => {
+                block.body.data = data.to_owned();
+                format!("block {} now reads '{}'; its hash was not updated", index, data)
+            }
+            None => format!("no block {}", index),
+        }
+    }
+
+    fn validate(&mut self) -> String {
+        let blockchain = self.active();
+        if blockchain.is_valid_chain() {
+            return format!("valid: {} blocks", blockchain.iter().count());
+        }
+        for block in blockchain.iter() {
+            if block.header.merkle_root != block.body.merkle_root() {
+                return format!("INVALID: block {} does not match its merkle root", block.header.index);
+            }
+            if block.header.hash != block.calculate_hash() {
+                return format!("INVALID: block {} does not match its hash", block.header.index);
+            }
+        }
+        "INVALID".to_owned()
+    }
+
+    fn show(&mut self, index: u32) -> String {
+        match self.active().get_block_by_index(index) {
+            Some(block) => serde_json::to_string_pretty(block).unwrap_or_default(),
+            None => format!("no block {}", index),
+        }
+    }
+
+    fn list(&mut self) -> String {
+        let lines: Vec<String> = self.active().iter().map(summary).collect();
+        lines.join("\n")
+    }
+
+    fn branch(&mut self, height: u32) -> String {
+        let shared = self.main.range(..=height).to_vec();
+        if shared.len() != height as usize + 1 {
+            return format!("no block {}", height);
+        }
+        let mut fork = Blockchain::with_params(self.params.clone());
+        if shared.len() > 1 && !fork.try_replace(shared) {
+            return "the main chain up to that block is invalid".to_owned();
+        }
+        self.fork = Some(fork);
+        self.on_fork = true;
+        format!("on a fork after block {}; mine past the main chain, then reorg", height)
+    }
+
+    fn reorg(&mut self) -> String {
+        let Some(fork) = &self.fork else {
+            return "no fork; start one with fork <height>".to_owned();
+        };
+        let fork_height = fork.latest_block().header.index;
+        let main_height = self.main.latest_block().header.index;
+        if !self.main.try_replace(fork.iter().cloned().collect()) {
+            return format!("the main chain kept its {} blocks over the fork's {}", main_height + 1, fork_height + 1);
+        }
+        self.fork = None;
+        self.on_fork = false;
+        format!("reorganised: the main chain now ends at block {}", fork_height)
+    }
+}
+
+fn summary(block: &Block) -> String {
+    format!("{:>4}  {}  {}", block.header.index, block.header.hash, block.body.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(repl: &mut Repl, line: &str) -> String {
+        repl.execute(line).unwrap()
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let mut repl = Repl::new(ChainParams::testing());
+        assert!(run(&mut repl, "mine pay alice 5").starts_with("mined block 1 "));
+        run(&mut repl, "mine pay bob 3");
+        assert_eq!(run(&mut repl, "validate"), "valid: 3 blocks");
+
+        assert!(run(&mut repl, "tamper 1 pay alice 500").starts_with("block 1 now reads"));
+        assert_eq!(run(&mut repl, "validate"), "INVALID: block 1 does not match its merkle root");
+        assert!(run(&mut repl, "show 1").contains("pay alice 500"));
+        assert_eq!(run(&mut repl, "tamper 9 x"), "no block 9");
+        assert_eq!(run(&mut repl, "tamper one x"), "usage: tamper <index> <data>");
+        assert!(repl.execute("quit").is_none());
+    }
+
+    #[test]
+    fn test_longer_fork_reorganises_the_chain() {
+        let mut repl = Repl::new(ChainParams::testing());
+        assert_eq!(run(&mut repl, "reorg"), "no fork; start one with fork <height>");
+        run(&mut repl, "mine a");
+        run(&mut repl, "mine b");
+        assert_eq!(run(&mut repl, "fork 5"), "no block 5");
+
+        run(&mut repl, "fork 1");
+        assert_eq!(repl.prompt(), "simplz (fork)> ");
+        run(&mut repl, "mine c");
+        assert_eq!(run(&mut repl, "reorg"), "the main chain kept its 3 blocks over the fork's 3");
+        run(&mut repl, "mine d");
+        assert_eq!(run(&mut repl, "reorg"), "reorganised: the main chain now ends at block 3");
+
+        assert_eq!(repl.prompt(), "simplz> ");
+        let data: Vec<String> = repl.main.iter().map(|block| block.body.data.clone()).collect();
+        assert_eq!(data, ["Genesis Block", "a", "c", "d"]);
+    }
+}
@@ -0,0 +1,1340 @@
+#[cfg(feature = "net")]
+use std::collections::HashMap;
+#[cfg(feature = "net")]
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(feature = "net")]
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream};
+#[cfg(feature = "net")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "net")]
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+#[cfg(feature = "net")]
+use std::sync::{Arc, Mutex, PoisonError};
+#[cfg(feature = "net")]
+use std::thread;
+#[cfg(feature = "net")]
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "net")]
+use subtle::ConstantTimeEq;
+#[cfg(feature = "net")]
+use tracing::debug;
+
+use crate::Node;
+use crate::address::Address;
+#[cfg(feature = "grpc")]
+use crate::Block;
+#[cfg(feature = "grpc")]
+use crate::grpc::BlockId;
+#[cfg(feature = "net")]
+use crate::websocket::{self, Frame};
+#[cfg(feature = "net")]
+use crate::{ChainEvent, TransactionEvent, TransactionFilter};
+use crate::faucet::Faucet;
+use crate::utxo::{OutPoint, UtxoTransaction};
+
+/// How long a rate limit window lasts.
+#[cfg(feature = "net")]
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Most clients tracked at once. Windows that have run out are forgotten
+/// to make room; while every tracked window is live, new clients wait.
+#[cfg(feature = "net")]
+const MAX_TRACKED_CLIENTS: usize = 4096;
+
+/// Threads answering RPC connections.
+#[cfg(feature = "net")]
+const WORKERS: usize = 8;
+
+/// Accepted connections waiting for a worker before new ones are turned
+/// away.
+#[cfg(feature = "net")]
+const MAX_QUEUED_CONNECTIONS: usize = 64;
+
+/// How long a client may take to send its request or read the reply.
+#[cfg(feature = "net")]
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest request line and headers taken together.
+#[cfg(feature = "net")]
+const MAX_HEAD_LEN: u64 = 16 * 1024;
+
+/// Largest request body. A transaction fits with room to spare.
+#[cfg(feature = "net")]
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+/// Most clients streaming events at once. Each holds two threads for as
+/// long as it stays connected, so further ones are turned away.
+#[cfg(feature = "net")]
+const MAX_SUBSCRIBERS: usize = 64;
+
+/// How long a WebSocket subscriber may go without hearing anything before
+/// it is pinged. A client that went away is noticed, and its slot freed,
+/// by the next event or ping.
+#[cfg(feature = "net")]
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// An HTTP request, reduced to what the routes need.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+    /// Sent as `X-Api-Key`, or as a bearer token in `Authorization`.
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::ok("application/json", body),
+            Err(err) => Response {
+                status: 500,
+                content_type: "text/plain",
+                body: err.to_string().into_bytes(),
+            },
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Response {
+            status: 404,
+            content_type: "text/plain",
+            body: b"not found".to_vec(),
+        }
+    }
+
+    pub fn bad_request(message: String) -> Self {
+        Response {
+            status: 400,
+            content_type: "text/plain",
+            body: message.into_bytes(),
+        }
+    }
+
+    /// For data this node once held but has pruned.
+    pub fn gone() -> Self {
+        Response {
+            status: 410,
+            content_type: "text/plain",
+            body: b"pruned".to_vec(),
+        }
+    }
+
+    /// For a privileged route called without a valid API key.
+    pub fn unauthorized() -> Self {
+        Response {
+            status: 401,
+            content_type: "text/plain",
+            body: b"a valid api key is required".to_vec(),
+        }
+    }
+
+    /// For a privileged route on a read-only endpoint.
+    pub fn forbidden() -> Self {
+        Response {
+            status: 403,
+            content_type: "text/plain",
+            body: b"read-only endpoint".to_vec(),
+        }
+    }
+
+    pub fn payload_too_large() -> Self {
+        Response {
+            status: 413,
+            content_type: "text/plain",
+            body: b"request body is too large".to_vec(),
+        }
+    }
+
+    pub fn too_many_requests() -> Self {
+        Response {
+            status: 429,
+            content_type: "text/plain",
+            body: b"rate limit exceeded".to_vec(),
+        }
+    }
+
+    /// For connections arriving while every worker is busy.
+    pub fn service_unavailable() -> Self {
+        Response {
+            status: 503,
+            content_type: "text/plain",
+            body: b"server is busy".to_vec(),
+        }
+    }
+
+    #[cfg(feature = "net")]
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            410 => "Gone",
+            413 => "Payload Too Large",
+            429 => "Too Many Requests",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Who may call what on an RPC endpoint exposed to the public. The default
+/// lets anyone call anything, as suits a private one.
+#[cfg(feature = "net")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RpcPolicy {
+    /// Refuses the privileged routes, which change the chain, outright.
+    pub read_only: bool,
+    /// Keys that unlock the privileged routes. Empty leaves them open.
+    pub api_keys: Vec<String>,
+    /// Requests a minute each client IP may make without a valid key. Zero
+    /// for no limit.
+    pub requests_per_minute: u32,
+    /// Requests a minute each valid key may make, wherever they come from.
+    /// Zero for no limit.
+    pub key_requests_per_minute: u32,
+}
+
+/// Applies an `RpcPolicy` to requests before they reach the node, counting
+/// each client's requests in fixed one-minute windows.
+#[cfg(feature = "net")]
+#[derive(Debug)]
+pub(crate) struct Gate {
+    policy: RpcPolicy,
+    /// Start of each client's current window and its requests so far.
+    windows: HashMap<String, (Instant, u32)>,
+}
+
+#[cfg(feature = "net")]
+impl Gate {
+    pub(crate) fn new(policy: RpcPolicy) -> Self {
+        Gate {
+            policy,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// The response turning away `request`, made by `client` at `now`, or
+    /// `None` to let it through.
+    pub(crate) fn check(&mut self, request: &Request, client: IpAddr, now: Instant) -> Option<Response> {
+        let key = request.api_key.as_deref().filter(|key| self.is_valid_key(key));
+        let (bucket, limit) = match key {
+            Some(key) => (format!("key {}", key), self.policy.key_requests_per_minute),
+            None => (format!("ip {}", client), self.policy.requests_per_minute),
+        };
+        if limit > 0 && !self.admit(bucket, limit, now) {
+            return Some(Response::too_many_requests());
+        }
+        if is_privileged(request) {
+            if self.policy.read_only {
+                return Some(Response::forbidden());
+            }
+            if !self.policy.api_keys.is_empty() && key.is_none() {
+                return Some(Response::unauthorized());
+            }
+        }
+        None
+    }
+
+    fn is_valid_key(&self, key: &str) -> bool {
+        self.policy
+            .api_keys
+            .iter()
+            .any(|valid| bool::from(valid.as_bytes().ct_eq(key.as_bytes())))
+    }
+
+    /// Counts a request against `bucket`, returning whether it is within
+    /// `limit` for the current window. A client not yet tracked is refused
+    /// if every tracked window is still live and there is no room for it.
+    fn admit(&mut self, bucket: String, limit: u32, now: Instant) -> bool {
+        if !self.windows.contains_key(&bucket) && self.windows.len() >= MAX_TRACKED_CLIENTS {
+            self.windows.retain(|_, (start, _)| now.duration_since(*start) < RATE_WINDOW);
+            if self.windows.len() >= MAX_TRACKED_CLIENTS {
+                return false;
+            }
+        }
+        let (start, count) = self.windows.entry(bucket).or_insert((now, 0));
+        if now.duration_since(*start) >= RATE_WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= limit
+    }
+}
+
+/// Whether `request` is for a route that changes the chain: submitting a
+/// transaction, which mines it, or asking the faucet for funds.
+#[cfg(feature = "net")]
+fn is_privileged(request: &Request) -> bool {
+    request.method == "POST" && matches!(request.path.trim_matches('/'), "transactions" | "faucet")
+}
+
+/// A connected peer, by the node id its handshake proved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peer {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    pub peer: String,
+    pub until: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub address: Address,
+    pub balance: u64,
+}
+
+/// The nonce an account's next transaction must carry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NextNonce {
+    pub account: String,
+    pub nonce: u64,
+}
+
+/// An unspent output a key address can sign for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unspent {
+    pub outpoint: OutPoint,
+    pub value: u64,
+}
+
+/// Test funds asked of a dev node's faucet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaucetRequest {
+    pub address: Address,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Submitted {
+    pub id: String,
+    pub block_hash: String,
+}
+
+/// Work for the thread that owns the node.
+#[cfg(feature = "net")]
+pub(crate) enum Call {
+    /// A request waiting for `route` to answer it.
+    Request(Request, Sender<Response>),
+    /// A WebSocket client on `/ws` with a filter, waiting for a channel of
+    /// the transaction events it selects.
+    Watch(TransactionFilter, Sender<Receiver<TransactionEvent>>),
+    /// A WebSocket client on `/ws` without one, or a gRPC `StreamBlocks`,
+    /// waiting for a channel of chain events.
+    Subscribe(Sender<Receiver<ChainEvent>>),
+    /// A gRPC `GetBlock`, waiting for the block it names.
+    #[cfg(feature = "grpc")]
+    Block(BlockId, Sender<Option<Block>>),
+    /// A gRPC `SubmitTransaction`, waiting for where the transaction was
+    /// mined or why it was refused.
+    #[cfg(feature = "grpc")]
+    Submit(UtxoTransaction, Sender<Result<Submitted, String>>),
+}
+
+/// Answers `call` from the node's state, on the thread that owns the node.
+/// `faucet` is as for `route`.
+#[cfg(feature = "net")]
+pub(crate) fn answer(node: &mut Node, faucet: Option<&Faucet>, call: Call) {
+    match call {
+        Call::Request(request, reply) => {
+            let _ = reply.send(route(node, faucet, &request));
+        }
+        Call::Watch(filter, reply) => {
+            let _ = reply.send(node.blockchain_mut().watch(filter));
+        }
+        Call::Subscribe(reply) => {
+            let _ = reply.send(node.blockchain_mut().subscribe());
+        }
+        #[cfg(feature = "grpc")]
+        Call::Block(id, reply) => {
+            let blockchain = node.blockchain();
+            let block = match id {
+                BlockId::Index(index) => blockchain.get_block_by_index(index),
+                BlockId::Hash(hash) => blockchain.get_block_by_hash(&hash),
+            };
+            let _ = reply.send(block.cloned());
+        }
+        #[cfg(feature = "grpc")]
+        Call::Submit(transaction, reply) => {
+            let _ = reply.send(submit(node, transaction));
+        }
+    }
+}
+
+/// Answers `request` from the node's state. Runs on the thread that owns
+/// the node, so routes may read and change it freely. `POST /faucet` is
+/// only served given a `faucet`, which dev mode sets up.
+pub fn route(node: &mut Node, faucet: Option<&Faucet>, request: &Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["transactions", id]) => match node.blockchain().receipt(id) {
+            Some(receipt) => Response::json(receipt),
+            None => Response::not_found(),
+        },
+        ("GET", ["addresses", address, "history"]) => Response::json(node.blockchain().history(address)),
+        ("GET", ["transactions", id, "payload"]) => match node.blockchain().get_transaction(id) {
+            Some(transaction) => Response::ok("application/octet-stream", transaction.payload.clone()),
+            None if is_pruned(node, id) => Response::gone(),
+            None => Response::not_found(),
+        },
+        ("GET", ["addresses", account, "nonce"]) => Response::json(&NextNonce {
+            account: (*account).to_owned(),
+            nonce: node.blockchain().next_nonce(account),
+        }),
+        ("GET", ["addresses", address, "balance"]) => match address.parse::<Address>() {
+            Ok(address) => Response::json(&Balance {
+                address,
+                balance: node.blockchain().utxo_set().balance(&address.to_string()),
+            }),
+            Err(err) => Response::bad_request(format!("invalid address: {}", err)),
+        },
+        ("GET", ["addresses", address, "outputs"]) => match address.parse::<Address>() {
+            Ok(address) => {
+                let owner = address.to_string();
+                let unspent: Vec<Unspent> = node
+                    .blockchain()
+                    .utxo_set()
+                    .outputs_owned_by(&owner)
+                    .filter(|(_, output)| output.lock.is_empty())
+                    .map(|(outpoint, output)| Unspent {
+                        outpoint: outpoint.clone(),
+                        value: output.value,
+                    })
+                    .collect();
+                Response::json(&unspent)
+            }
+            Err(err) => Response::bad_request(format!("invalid address: {}", err)),
+        },
+        ("POST", ["transactions"]) => submit_transaction(node, &request.body),
+        ("POST", ["faucet"]) => match faucet {
+            Some(faucet) => request_funds(node, faucet, &request.body),
+            None => Response::not_found(),
+        },
+        ("GET", ["peers"]) => {
+            let mut peers: Vec<Peer> = node.peer_ids().map(|id| Peer { id: id.to_owned() }).collect();
+            peers.sort_by(|a, b| a.id.cmp(&b.id));
+            Response::json(&peers)
+        }
+        ("GET", ["bans"]) => {
+            let bans: Vec<Ban> = node
+                .bans()
+                .iter()
+                .map(|(peer, until)| Ban {
+                    peer: peer.to_owned(),
+                    until,
+                })
+                .collect();
+            Response::json(&bans)
+        }
+        ("GET", ["stats"]) => Response::json(&node.blockchain().stats(..)),
+        ("GET", ["stats", from, to]) => match (from.parse::<u32>(), to.parse::<u32>()) {
+            (Ok(from), Ok(to)) => Response::json(&node.blockchain().stats(from..=to)),
+            _ => Response::bad_request("heights must be numbers".to_owned()),
+        },
+        _ => Response::not_found(),
+    }
+}
+
+/// Whether the transaction with `id` was mined in a block whose body has
+/// been pruned.
+fn is_pruned(node: &Node, id: &str) -> bool {
+    let blockchain = node.blockchain();
+    blockchain.receipt(id).is_some_and(|receipt| blockchain.is_pruned(receipt.height))
+}
+
+/// Pays the `FaucetRequest` in `body` from the faucet.
+fn request_funds(node: &mut Node, faucet: &Faucet, body: &[u8]) -> Response {
+    let request: FaucetRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(err) => return Response::bad_request(format!("invalid faucet request: {}", err)),
+    };
+    match faucet.pay(node, &request.address, request.amount) {
+        Ok(id) => Response::json(&Submitted {
+            id,
+            block_hash: node.blockchain().latest_block().header.hash.clone(),
+        }),
+        Err(err) => Response::bad_request(format!("faucet refused: {}", err)),
+    }
+}
+
+/// Mines a block carrying the signed UTXO transaction in `body`.
+fn submit_transaction(node: &mut Node, body: &[u8]) -> Response {
+    let transaction: UtxoTransaction = match serde_json::from_slice(body) {
+        Ok(transaction) => transaction,
+        Err(err) => return Response::bad_request(format!("invalid transaction: {}", err)),
+    };
+    match submit(node, transaction) {
+        Ok(submitted) => Response::json(&submitted),
+        Err(message) => Response::bad_request(message),
+    }
+}
+
+/// Mines a block carrying `transaction` if it spends outputs it may, for
+/// `POST /transactions` and gRPC `SubmitTransaction` alike.
+pub(crate) fn submit(node: &mut Node, transaction: UtxoTransaction) -> Result<Submitted, String> {
+    let blockchain = node.blockchain();
+    let height = blockchain.latest_block().header.index + 1;
+    if let Err(err) = blockchain.utxo_set().validate_transaction(&transaction, height, blockchain.params().network) {
+        return Err(format!("transaction rejected: {}", err));
+    }
+    let id = transaction.id();
+    if !node.mine_utxo_block(vec![transaction]) {
+        return Err("transaction rejected: block limits exceeded".to_owned());
+    }
+    Ok(Submitted {
+        id,
+        block_hash: node.blockchain().latest_block().header.hash.clone(),
+    })
+}
+
+/// Sends `request` to the RPC server at `address` and returns the status
+/// and body of the reply.
+#[cfg(feature = "net")]
+pub fn call(address: &str, request: &Request) -> io::Result<(u16, Vec<u8>)> {
+    let mut stream = TcpStream::connect(address)?;
+    write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\n", request.method, request.path, address)?;
+    if let Some(key) = &request.api_key {
+        write!(stream, "X-Api-Key: {}\r\n", key)?;
+    }
+    write!(stream, "Content-Length: {}\r\nConnection: close\r\n\r\n", request.body.len())?;
+    stream.write_all(&request.body)?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed status line"))?;
+    let headers = read_headers(&mut reader)?;
+    let mut body = vec![0; headers.content_length];
+    reader.read_exact(&mut body)?;
+    Ok((status, body))
+}
+
+/// Accepts HTTP connections from `listener` and has a pool of `WORKERS`
+/// threads hand each request that `policy` lets through to `calls`,
+/// writing back whatever reply comes through, until the listener fails.
+/// Connections arriving while the queue for the workers is full are
+/// turned away.
+#[cfg(feature = "net")]
+pub(crate) fn serve(listener: TcpListener, calls: Sender<Call>, policy: RpcPolicy) {
+    let gate = Arc::new(Mutex::new(Gate::new(policy)));
+    let queue = spawn_workers(calls, gate, Subscribers::default());
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            stream.set_read_timeout(Some(IO_TIMEOUT))?;
+            stream.set_write_timeout(Some(IO_TIMEOUT))?;
+            match queue.try_send(stream) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(stream)) => write_response(&stream, &Response::service_unavailable()),
+                Err(TrySendError::Disconnected(_)) => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+            }
+        });
+        if let Err(err) = result {
+            debug!(error = %err, "rpc request failed");
+        }
+    }
+}
+
+/// Starts the workers and returns the queue that feeds them connections.
+#[cfg(feature = "net")]
+fn spawn_workers(calls: Sender<Call>, gate: Arc<Mutex<Gate>>, subscribers: Subscribers) -> SyncSender<TcpStream> {
+    let (queue, connections) = mpsc::sync_channel::<TcpStream>(MAX_QUEUED_CONNECTIONS);
+    let connections = Arc::new(Mutex::new(connections));
+    for _ in 0..WORKERS {
+        let (calls, gate, connections) = (calls.clone(), Arc::clone(&gate), Arc::clone(&connections));
+        let subscribers = subscribers.clone();
+        thread::spawn(move || {
+            loop {
+                let next = connections.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                let Ok(stream) = next else {
+                    break;
+                };
+                if let Err(err) = respond(stream, &calls, &gate, &subscribers) {
+                    debug!(error = %err, "rpc request failed");
+                }
+            }
+        });
+    }
+    queue
+}
+
+/// Answers one connection. The gate sees the request line and headers
+/// before any of the body is read, so refused clients never get to send
+/// one.
+#[cfg(feature = "net")]
+fn respond(stream: TcpStream, calls: &Sender<Call>, gate: &Mutex<Gate>, subscribers: &Subscribers) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let (mut request, headers) = read_head(&mut reader)?;
+    let content_length = headers.content_length;
+    let client = stream.peer_addr()?.ip();
+    let refusal = gate.lock().unwrap_or_else(PoisonError::into_inner).check(&request, client, Instant::now());
+    if let Some(refusal) = refusal.or_else(|| (content_length > MAX_BODY_LEN).then(Response::payload_too_large)) {
+        write_response(&stream, &refusal)?;
+        stream.shutdown(Shutdown::Write)?;
+        // Closing with the body unread would reset the connection and could
+        // lose the reply, so discard what the client sends, up to the cap.
+        io::copy(&mut reader.take(content_length.min(MAX_BODY_LEN) as u64), &mut io::sink())?;
+        return Ok(());
+    }
+    request.body = vec![0; content_length];
+    reader.read_exact(&mut request.body)?;
+    drop(reader);
+
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    if (request.method.as_str(), path) == ("GET", "/ws") {
+        return subscribe(stream, headers.websocket_key.as_deref(), query, calls, subscribers);
+    }
+    let (reply, response) = mpsc::channel();
+    calls
+        .send(Call::Request(request, reply))
+        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+    let response = response
+        .recv()
+        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+    write_response(&stream, &response)
+}
+
+/// Reads `address`, `txid` and `depth` query parameters. `address` and
+/// `txid` may repeat, and at least one of them must be given.
+#[cfg(feature = "net")]
+fn parse_filter(query: &str) -> Result<TransactionFilter, String> {
+    let mut filter = TransactionFilter::default();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        match pair.split_once('=') {
+            Some(("address", address)) => {
+                filter.addresses.insert(address.to_owned());
+            }
+            Some(("txid", id)) => {
+                filter.transactions.insert(id.to_owned());
+            }
+            Some(("depth", depth)) => {
+                filter.depth = depth.parse().map_err(|_| format!("invalid depth: {}", depth))?;
+            }
+            _ => return Err(format!("unknown query parameter: {}", pair)),
+        }
+    }
+    if filter.addresses.is_empty() && filter.transactions.is_empty() {
+        return Err("subscribe to at least one address or txid".to_owned());
+    }
+    Ok(filter)
+}
+
+/// Upgrades `stream` to a WebSocket and pushes events to it as JSON text
+/// frames until the client goes away: every chain event given an empty
+/// `query`, or else the lifecycle events of the transactions the filter in
+/// `query` selects.
+#[cfg(feature = "net")]
+fn subscribe(
+    stream: TcpStream,
+    key: Option<&str>,
+    query: &str,
+    calls: &Sender<Call>,
+    subscribers: &Subscribers,
+) -> io::Result<()> {
+    let Some(key) = key else {
+        return write_response(&stream, &Response::bad_request("expected a WebSocket upgrade".to_owned()));
+    };
+    let filter = match query {
+        "" => None,
+        query => match parse_filter(query) {
+            Ok(filter) => Some(filter),
+            Err(message) => return write_response(&stream, &Response::bad_request(message)),
+        },
+    };
+    let Some(slot) = subscribers.enter() else {
+        return write_response(&stream, &Response::service_unavailable());
+    };
+    match filter {
+        None => {
+            let events = ask(calls, Call::Subscribe)?;
+            accept_upgrade(&stream, key)?;
+            forward(stream, events, ChainEvent::to_json, slot)
+        }
+        Some(filter) => {
+            let events = ask(calls, |reply| Call::Watch(filter, reply))?;
+            accept_upgrade(&stream, key)?;
+            forward(stream, events, |event| serde_json::to_string(event).unwrap_or_default(), slot)
+        }
+    }
+}
+
+/// Hands the node thread the call `make` builds around a reply channel and
+/// waits for its reply.
+#[cfg(feature = "net")]
+pub(crate) fn ask<T>(calls: &Sender<Call>, make: impl FnOnce(Sender<T>) -> Call) -> io::Result<T> {
+    let (reply, answer) = mpsc::channel();
+    calls.send(make(reply)).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+    answer.recv().map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+}
+
+#[cfg(feature = "net")]
+fn accept_upgrade(mut stream: &TcpStream, key: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket::accept_key(key)
+    )
+}
+
+/// Sends each of `events` over the WebSocket `stream` as a text frame on
+/// one thread, and answers the client's pings and close on another, until
+/// either side closes. `slot` is held until both threads are done with
+/// the connection.
+#[cfg(feature = "net")]
+fn forward<T: Send + 'static>(
+    stream: TcpStream,
+    events: Receiver<T>,
+    to_json: fn(&T) -> String,
+    slot: Slot,
+) -> io::Result<()> {
+    stream.set_read_timeout(None)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let writer = Arc::new(Mutex::new(stream));
+    let send = |writer: &Mutex<TcpStream>, frame: &Frame| {
+        let mut stream = writer.lock().unwrap_or_else(PoisonError::into_inner);
+        websocket::write_frame(&mut *stream, frame, None)
+    };
+
+    let replies = Arc::clone(&writer);
+    thread::spawn(move || {
+        loop {
+            let reply = match websocket::read_frame(&mut reader, websocket::MAX_CONTROL_LEN) {
+                Ok(Frame::Ping(payload)) => Frame::Pong(payload),
+                Ok(Frame::Close) | Err(_) => break,
+                Ok(_) => continue,
+            };
+            if send(&replies, &reply).is_err() {
+                break;
+            }
+        }
+        let _ = send(&replies, &Frame::Close);
+        let _ = replies.lock().unwrap_or_else(PoisonError::into_inner).shutdown(Shutdown::Both);
+    });
+
+    thread::spawn(move || {
+        let _slot = slot;
+        loop {
+            let frame = match events.recv_timeout(HEARTBEAT) {
+                Ok(event) => Frame::Text(to_json(&event)),
+                Err(RecvTimeoutError::Timeout) => Frame::Ping(Vec::new()),
+                Err(RecvTimeoutError::Disconnected) => Frame::Close,
+            };
+            if send(&writer, &frame).is_err() || frame == Frame::Close {
+                break;
+            }
+        }
+        debug!("event subscriber closed");
+        let _ = writer.lock().unwrap_or_else(PoisonError::into_inner).shutdown(Shutdown::Both);
+    });
+    Ok(())
+}
+
+/// Counts the WebSocket subscribers, up to `MAX_SUBSCRIBERS`.
+#[cfg(feature = "net")]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Subscribers(Arc<AtomicUsize>);
+
+#[cfg(feature = "net")]
+impl Subscribers {
+    /// Takes a slot, unless all of them are in use.
+    pub(crate) fn enter(&self) -> Option<Slot> {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| (count < MAX_SUBSCRIBERS).then_some(count + 1))
+            .ok()
+            .map(|_| Slot(Arc::clone(&self.0)))
+    }
+}
+
+/// One subscriber's place, given back when dropped.
+#[cfg(feature = "net")]
+#[derive(Debug)]
+pub(crate) struct Slot(Arc<AtomicUsize>);
+
+#[cfg(feature = "net")]
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reads the request line and headers, no more than `MAX_HEAD_LEN` bytes
+/// of them, and returns the request without its body along with the
+/// length the body claims.
+#[cfg(feature = "net")]
+fn read_head(reader: &mut impl BufRead) -> io::Result<(Request, Headers)> {
+    let mut head = reader.take(MAX_HEAD_LEN);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed request line"));
+    };
+    let headers = read_headers(&mut head)?;
+    let request = Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        body: Vec::new(),
+        api_key: headers.api_key.clone(),
+    };
+    Ok((request, headers))
+}
+
+/// The headers the server and client act on.
+#[cfg(feature = "net")]
+#[derive(Debug, Default)]
+struct Headers {
+    content_length: usize,
+    api_key: Option<String>,
+    /// `Sec-WebSocket-Key`, if the request asks to upgrade to a version 13
+    /// WebSocket.
+    websocket_key: Option<String>,
+}
+
+/// Reads headers up to the blank line.
+#[cfg(feature = "net")]
+fn read_headers(reader: &mut impl BufRead) -> io::Result<Headers> {
+    let mut headers = Headers::default();
+    let (mut upgrade, mut version) = (false, false);
+    let mut websocket_key = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "headers ended early or ran too long"));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            headers.websocket_key = websocket_key.filter(|_| upgrade && version);
+            return Ok(headers);
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            headers.content_length = value
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad content length"))?;
+        } else if name.eq_ignore_ascii_case("x-api-key") {
+            headers.api_key = Some(value.to_owned());
+        } else if name.eq_ignore_ascii_case("authorization")
+            && let Some(token) = value.strip_prefix("Bearer ")
+        {
+            headers.api_key = Some(token.trim().to_owned());
+        } else if name.eq_ignore_ascii_case("upgrade") {
+            upgrade = value.eq_ignore_ascii_case("websocket");
+        } else if name.eq_ignore_ascii_case("sec-websocket-version") {
+            version = value == "13";
+        } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+            websocket_key = Some(value.to_owned());
+        }
+    }
+}
+
+#[cfg(feature = "net")]
+fn write_response(mut stream: &TcpStream, response: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "net")]
+    use std::thread;
+
+    use super::*;
+    use crate::transaction::accounts::{account, payment, signed};
+    use crate::{Blockchain, ChainParams, ChainStats, Network, Receipt, Transaction, TransactionKind};
+
+    fn get(node: &mut Node, path: &str) -> Response {
+        let request = Request {
+            method: "GET".to_owned(),
+            path: path.to_owned(),
+            body: Vec::new(),
+            api_key: None,
+        };
+        route(node, None, &request)
+    }
+
+    #[test]
+    fn test_payload_route() {
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        let anchored = signed("alice", Transaction::with_payload(String::new(), "bob".to_owned(), 0, 8, b"document".to_vec()));
+        let id = anchored.id();
+        node.submit_transaction(anchored);
+        let underpaid = signed("alice", Transaction::with_payload(String::new(), "bob".to_owned(), 0, 7, b"underpaid".to_vec()));
+        node.submit_transaction(underpaid);
+        assert_eq!(node.blockchain().mempool().len(), 1);
+
+        assert_eq!(get(&mut node, &format!("/transactions/{}/payload", id)).status, 404);
+        assert!(node.mine_pending_transactions("miner".to_owned()));
+
+        let response = get(&mut node, &format!("/transactions/{}/payload", id));
+        assert_eq!(response, Response::ok("application/octet-stream", b"document".to_vec()));
+        assert_eq!(get(&mut node, "/transactions").status, 404);
+
+        node.blockchain_mut().add_block("Later block".to_owned());
+        node.blockchain_mut().prune(1);
+        assert_eq!(get(&mut node, &format!("/transactions/{}/payload", id)), Response::gone());
+    }
+
+    #[test]
+    fn test_receipt_and_history_routes() {
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        let payment = payment("alice", "bob", 5, 0);
+        node.submit_transaction(payment.clone());
+        assert_eq!(get(&mut node, &format!("/transactions/{}", payment.id())).status, 404);
+        assert!(node.mine_pending_transactions("miner".to_owned()));
+
+        let response = get(&mut node, &format!("/transactions/{}", payment.id()));
+        let receipt: Receipt = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(receipt.block_hash, node.blockchain().latest_block().header.hash);
+        assert_eq!((receipt.height, receipt.kind, receipt.position), (1, TransactionKind::Account, 1));
+
+        let response = get(&mut node, &format!("/addresses/{}/history", account("bob")));
+        let history: Vec<String> = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(history, vec![payment.id()]);
+        assert_eq!(get(&mut node, "/addresses/nobody/history").body, b"[]");
+    }
+
+    #[test]
+    fn test_stats_route() {
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        assert!(node.blockchain_mut().add_block("one".to_owned()));
+        assert!(node.blockchain_mut().add_block("two".to_owned()));
+
+        let stats: ChainStats = serde_json::from_slice(&get(&mut node, "/stats").body).unwrap();
+        assert_eq!(stats.blocks, 3);
+        let stats: ChainStats = serde_json::from_slice(&get(&mut node, "/stats/1/1").body).unwrap();
+        assert_eq!((stats.first_height, stats.last_height), (1, 1));
+        assert_eq!(get(&mut node, "/stats/1/tip").status, 400);
+    }
+
+    #[test]
+    fn test_nonce_route() {
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        assert!(node.blockchain_mut().add_transaction(payment("alice", "bob", 5, 0)));
+        assert!(node.blockchain_mut().mine_pending_transactions("miner".to_owned()));
+
+        let path = format!("/addresses/{}/nonce", account("alice"));
+        let next: NextNonce = serde_json::from_slice(&get(&mut node, &path).body).unwrap();
+        assert_eq!(next, NextNonce { account: account("alice"), nonce: 1 });
+        let path = format!("/addresses/{}/nonce", account("bob"));
+        let next: NextNonce = serde_json::from_slice(&get(&mut node, &path).body).unwrap();
+        assert_eq!(next.nonce, 0);
+    }
+
+    #[test]
+    fn test_bans_route() {
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        node.bans_mut().ban("mallory".to_owned(), 3_600);
+
+        let response = get(&mut node, "/bans");
+        assert_eq!(response.content_type, "application/json");
+        let bans: Vec<Ban> = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(bans, vec![Ban { peer: "mallory".to_owned(), until: 3_600 }]);
+    }
+
+    #[test]
+    fn test_peers_route() {
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        for id in ["b0b", "a11ce"] {
+            node.add_peer(id.to_owned(), std::sync::mpsc::channel().0);
+        }
+
+        let peers: Vec<Peer> = serde_json::from_slice(&get(&mut node, "/peers").body).unwrap();
+        let ids: Vec<&str> = peers.iter().map(|peer| peer.id.as_str()).collect();
+        assert_eq!(ids, ["a11ce", "b0b"]);
+    }
+
+    #[test]
+    fn test_balance_route() {
+        use ed25519_dalek::SigningKey;
+
+        use crate::utxo::{UtxoTransaction, address};
+
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        let miner = address(&SigningKey::from_bytes(&[7; 32]).verifying_key(), Network::Mainnet);
+        let coinbase = UtxoTransaction::coinbase(miner.clone(), crate::BLOCK_REWARD);
+        assert!(node.blockchain_mut().add_utxo_block(vec![coinbase]));
+
+        let response = get(&mut node, &format!("/addresses/{}/balance", miner));
+        let balance: Balance = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(balance.address.to_string(), miner);
+        assert_eq!(balance.balance, crate::BLOCK_REWARD);
+
+        let mut typo = miner.into_bytes();
+        typo[10] = if typo[10] == b'2' { b'3' } else { b'2' };
+        let typo = String::from_utf8(typo).unwrap();
+        assert_eq!(get(&mut node, &format!("/addresses/{}/balance", typo)).status, 400);
+    }
+
+    #[test]
+    fn test_spending_routes() {
+        use ed25519_dalek::SigningKey;
+
+        use crate::utxo::{TxOutput, address};
+
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(ChainParams::testing()));
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let miner = address(&key.verifying_key(), Network::Mainnet);
+        let coinbase = UtxoTransaction::coinbase(miner.clone(), crate::BLOCK_REWARD);
+        assert!(node.blockchain_mut().add_utxo_block(vec![coinbase.clone()]));
+
+        let response = get(&mut node, &format!("/addresses/{}/outputs", miner));
+        let unspent: Vec<Unspent> = serde_json::from_slice(&response.body).unwrap();
+        let outpoint = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        assert_eq!(unspent, vec![Unspent { outpoint: outpoint.clone(), value: crate::BLOCK_REWARD }]);
+
+        let mut payment = UtxoTransaction::new(vec![outpoint], vec![TxOutput::new(crate::BLOCK_REWARD, "bob".to_owned())]);
+        let post = |node: &mut Node, transaction: &UtxoTransaction| {
+            let request = Request {
+                method: "POST".to_owned(),
+                path: "/transactions".to_owned(),
+                body: serde_json::to_vec(transaction).unwrap(),
+                api_key: None,
+            };
+            route(node, None, &request)
+        };
+        assert_eq!(post(&mut node, &payment).status, 400);
+
+        payment.sign(&key);
+        let response = post(&mut node, &payment);
+        let submitted: Submitted = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(submitted.id, payment.id());
+        assert_eq!(submitted.block_hash, node.blockchain().latest_block().header.hash);
+        assert_eq!(node.blockchain().utxo_set().balance(&miner), 0);
+        assert_eq!(node.blockchain().utxo_set().balance("bob"), crate::BLOCK_REWARD);
+        assert_eq!(post(&mut node, &payment).status, 400);
+    }
+
+    #[test]
+    fn test_faucet_route() {
+        use ed25519_dalek::SigningKey;
+
+        use crate::Network;
+
+        let faucet = Faucet::dev(Network::Mainnet);
+        let params = ChainParams {
+            premine: vec![faucet.premine()],
+            ..ChainParams::testing()
+        };
+        let mut node = Node::with_blockchain("rpc".to_owned(), Blockchain::with_params(params));
+        let address = Address::from_key(&SigningKey::from_bytes(&[7; 32]).verifying_key(), Network::Mainnet);
+        let request = Request {
+            method: "POST".to_owned(),
+            path: "/faucet".to_owned(),
+            body: serde_json::to_vec(&FaucetRequest { address, amount: 40 }).unwrap(),
+            api_key: None,
+        };
+        assert_eq!(route(&mut node, None, &request).status, 404);
+
+        let response = route(&mut node, Some(&faucet), &request);
+        let submitted: Submitted = serde_json::from_slice(&response.body).unwrap();
+        assert!(node.blockchain().receipt(&submitted.id).is_some());
+        let balance: Balance = serde_json::from_slice(&get(&mut node, &format!("/addresses/{}/balance", address)).body).unwrap();
+        assert_eq!(balance.balance, 40);
+
+        let empty = Request {
+            body: b"{}".to_vec(),
+            ..request
+        };
+        assert_eq!(route(&mut node, Some(&faucet), &empty).status, 400);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_serve_forwards_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (calls, incoming) = mpsc::channel();
+        let policy = RpcPolicy {
+            api_keys: vec!["secret".to_owned()],
+            ..RpcPolicy::default()
+        };
+        thread::spawn(move || serve(listener, calls, policy));
+        thread::spawn(move || {
+            for call in incoming {
+                if let Call::Request(request, reply) = call {
+                    let body = format!("{} {} {}", request.method, request.path, request.body.len());
+                    let _ = reply.send(Response::ok("text/plain", body.into_bytes()));
+                }
+            }
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("POST /echo 3"));
+
+        let request = Request {
+            method: "GET".to_owned(),
+            path: "/echo".to_owned(),
+            body: b"hello".to_vec(),
+            api_key: None,
+        };
+        assert_eq!(call(&address.to_string(), &request).unwrap(), (200, b"GET /echo 5".to_vec()));
+
+        let faucet = Request {
+            method: "POST".to_owned(),
+            path: "/faucet".to_owned(),
+            ..request
+        };
+        assert_eq!(call(&address.to_string(), &faucet).unwrap().0, 401);
+        let keyed = Request {
+            api_key: Some("secret".to_owned()),
+            ..faucet
+        };
+        assert_eq!(call(&address.to_string(), &keyed).unwrap(), (200, b"POST /faucet 5".to_vec()));
+
+        // An idle connection holds one worker, not the whole server, and a
+        // body over the cap is refused before it is read.
+        let _idle = TcpStream::connect(address).unwrap();
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "POST /echo HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"));
+    }
+
+    #[cfg(feature = "net")]
+    fn request(method: &str, path: &str, api_key: Option<&str>) -> Request {
+        Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            body: Vec::new(),
+            api_key: api_key.map(str::to_owned),
+        }
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_gate_limits_each_client() {
+        use std::net::Ipv4Addr;
+
+        let mut gate = Gate::new(RpcPolicy {
+            api_keys: vec!["secret".to_owned()],
+            requests_per_minute: 2,
+            key_requests_per_minute: 3,
+            ..RpcPolicy::default()
+        });
+        let (alice, bob) = (IpAddr::from(Ipv4Addr::new(10, 0, 0, 1)), IpAddr::from(Ipv4Addr::new(10, 0, 0, 2)));
+        let start = Instant::now();
+        let stats = request("GET", "/stats", None);
+        assert_eq!(gate.check(&stats, alice, start), None);
+        assert_eq!(gate.check(&stats, alice, start), None);
+        assert_eq!(gate.check(&stats, alice, start), Some(Response::too_many_requests()));
+        assert_eq!(gate.check(&stats, bob, start), None);
+
+        // A valid key is counted on its own, however many addresses use it;
+        // an invalid one is counted against the address.
+        let keyed = request("GET", "/stats", Some("secret"));
+        for _ in 0..3 {
+            assert_eq!(gate.check(&keyed, alice, start), None);
+        }
+        assert_eq!(gate.check(&keyed, bob, start), Some(Response::too_many_requests()));
+        let wrong = request("GET", "/stats", Some("guess"));
+        assert_eq!(gate.check(&wrong, alice, start), Some(Response::too_many_requests()));
+
+        let later = start + RATE_WINDOW;
+        assert_eq!(gate.check(&stats, alice, later), None);
+        assert_eq!(gate.check(&keyed, bob, later), None);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_gate_tracks_a_bounded_number_of_clients() {
+        let mut gate = Gate::new(RpcPolicy {
+            requests_per_minute: 10,
+            ..RpcPolicy::default()
+        });
+        let start = Instant::now();
+        let stats = request("GET", "/stats", None);
+        let client = |i: u32| IpAddr::from(i.to_be_bytes());
+        for i in 0..MAX_TRACKED_CLIENTS as u32 {
+            assert_eq!(gate.check(&stats, client(i), start), None);
+        }
+        // Full of live windows: known clients carry on, new ones wait until
+        // a window runs out and makes room.
+        let newcomer = client(MAX_TRACKED_CLIENTS as u32);
+        assert_eq!(gate.check(&stats, newcomer, start), Some(Response::too_many_requests()));
+        assert_eq!(gate.check(&stats, client(0), start), None);
+        assert_eq!(gate.windows.len(), MAX_TRACKED_CLIENTS);
+        assert_eq!(gate.check(&stats, newcomer, start + RATE_WINDOW), None);
+        assert_eq!(gate.windows.len(), 1);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_gate_guards_privileged_routes() {
+        use std::net::Ipv4Addr;
+
+        let client = IpAddr::from(Ipv4Addr::LOCALHOST);
+        let now = Instant::now();
+        let mut open = Gate::new(RpcPolicy::default());
+        assert_eq!(open.check(&request("POST", "/transactions", None), client, now), None);
+
+        let mut keyed = Gate::new(RpcPolicy {
+            api_keys: vec!["secret".to_owned()],
+            ..RpcPolicy::default()
+        });
+        assert_eq!(keyed.check(&request("GET", "/transactions/abc", None), client, now), None);
+        assert_eq!(keyed.check(&request("POST", "/faucet", None), client, now), Some(Response::unauthorized()));
+        assert_eq!(
+            keyed.check(&request("POST", "/transactions", Some("guess")), client, now),
+            Some(Response::unauthorized())
+        );
+        assert_eq!(keyed.check(&request("POST", "/transactions", Some("secret")), client, now), None);
+
+        let mut public = Gate::new(RpcPolicy {
+            read_only: true,
+            api_keys: vec!["secret".to_owned()],
+            ..RpcPolicy::default()
+        });
+        assert_eq!(public.check(&request("GET", "/stats", None), client, now), None);
+        assert_eq!(
+            public.check(&request("POST", "/faucet", Some("secret")), client, now),
+            Some(Response::forbidden())
+        );
+    }
+
+    /// Connects to the WebSocket at `path` and returns the stream, to write
+    /// frames to, and a reader past the handshake.
+    #[cfg(feature = "net")]
+    fn open_websocket(address: std::net::SocketAddr, path: &str) -> (TcpStream, BufReader<TcpStream>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        (stream, reader)
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_websocket_streams_chain_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (calls, incoming) = mpsc::channel();
+        thread::spawn(move || serve(listener, calls, RpcPolicy::default()));
+        thread::spawn(move || {
+            let mut subscribers = Vec::new();
+            for call in incoming {
+                if let Call::Subscribe(reply) = call {
+                    let (sender, events) = mpsc::channel();
+                    sender.send(ChainEvent::NewBlock { index: 1, hash: "abc".to_owned() }).unwrap();
+                    sender.send(ChainEvent::DifficultyChange { height: 1, bits: 0x1f01_0000 }).unwrap();
+                    let _ = reply.send(events);
+                    subscribers.push(sender);
+                }
+            }
+        });
+
+        let (mut stream, mut reader) = open_websocket(address, "/ws");
+        let text = websocket::read_frame(&mut reader, MAX_BODY_LEN as u64).unwrap();
+        assert_eq!(text, Frame::Text(r#"{"event":"new_block","index":1,"hash":"abc"}"#.to_owned()));
+        let text = websocket::read_frame(&mut reader, MAX_BODY_LEN as u64).unwrap();
+        let target = format!("0001{}", "0".repeat(60));
+        let expected = format!(r#"{{"event":"difficulty_change","height":1,"target":"{}","difficulty":65536}}"#, target);
+        assert_eq!(text, Frame::Text(expected));
+        websocket::write_frame(&mut stream, &Frame::Ping(b"hi".to_vec()), Some([9, 8, 7, 6])).unwrap();
+        assert_eq!(websocket::read_frame(&mut reader, MAX_BODY_LEN as u64).unwrap(), Frame::Pong(b"hi".to_vec()));
+        websocket::write_frame(&mut stream, &Frame::Close, Some([9, 8, 7, 6])).unwrap();
+        assert_eq!(websocket::read_frame(&mut reader, MAX_BODY_LEN as u64).unwrap(), Frame::Close);
+
+        let plain = request("GET", "/ws", None);
+        assert_eq!(call(&address.to_string(), &plain).unwrap().0, 400);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_subscribers_are_bounded() {
+        let subscribers = Subscribers::default();
+        let mut slots: Vec<Slot> = (0..MAX_SUBSCRIBERS).map(|_| subscribers.enter().unwrap()).collect();
+        assert!(subscribers.enter().is_none());
+        slots.pop();
+        assert!(subscribers.enter().is_some());
+        drop(slots);
+        assert_eq!(subscribers.0.load(Ordering::SeqCst), 0);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_websocket_streams_events_for_a_filter() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (calls, incoming) = mpsc::channel();
+        thread::spawn(move || serve(listener, calls, RpcPolicy::default()));
+        thread::spawn(move || {
+            for call in incoming {
+                if let Call::Watch(filter, reply) = call {
+                    let (sender, events) = mpsc::channel();
+                    for id in filter.transactions {
+                        sender.send(TransactionEvent::Accepted { id }).unwrap();
+                    }
+                    let _ = reply.send(events);
+                }
+            }
+        });
+
+        let (_stream, mut reader) = open_websocket(address, "/ws?txid=abc&address=bob");
+        let text = websocket::read_frame(&mut reader, MAX_BODY_LEN as u64).unwrap();
+        assert_eq!(text, Frame::Text(r#"{"event":"accepted","id":"abc"}"#.to_owned()));
+        // The node dropped its sender, so the stream is over.
+        assert_eq!(websocket::read_frame(&mut reader, MAX_BODY_LEN as u64).unwrap(), Frame::Close);
+
+        let request = request("GET", "/ws?owner=bob", None);
+        assert_eq!(call(&address.to_string(), &request).unwrap().0, 400);
+        let filter = parse_filter("address=bob&address=carol&depth=3").unwrap();
+        assert_eq!((filter.addresses.len(), filter.depth), (2, 3));
+        assert!(parse_filter("txid=abc&depth=deep").is_err());
+        assert!(parse_filter("owner=bob").is_err());
+    }
+}
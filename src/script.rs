@@ -0,0 +1,554 @@
+use std::fmt;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::address::{Address, Network};
+
+/// Longest encoded script accepted.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Most items the stack may hold while a script runs.
+pub const MAX_STACK_DEPTH: usize = 1_000;
+/// Most keys a single `CheckMultiSig` may name.
+pub const MAX_MULTISIG_KEYS: usize = 20;
+
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_VERIFY: u8 = 0x69;
+const OP_DROP: u8 = 0x75;
+const OP_DUP: u8 = 0x76;
+const OP_SWAP: u8 = 0x7c;
+const OP_EQUAL: u8 = 0x87;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_SHA256: u8 = 0xa8;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKSIGVERIFY: u8 = 0xad;
+const OP_CHECKMULTISIG: u8 = 0xae;
+const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+
+/// A single instruction. Numbers on the stack are little-endian unsigned
+/// integers of up to eight bytes; an item is true if any byte is non-zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Push(Vec<u8>),
+    /// Pushes a number from 1 to 16. `Script::new` turns any other value
+    /// into the shortest push of it, which is how it is encoded.
+    Number(u8),
+    Dup,
+    Drop,
+    Swap,
+    Equal,
+    EqualVerify,
+    /// Fails unless the top item is true, which it pops.
+    Verify,
+    Sha256,
+    /// Pops a public key and then a signature, and pushes whether the
+    /// signature covers the spending transaction.
+    CheckSig,
+    CheckSigVerify,
+    /// Pops a key count, that many keys, a signature count and that many
+    /// signatures, and pushes whether each signature matches a different
+    /// key, in the order the keys were given.
+    CheckMultiSig,
+    /// Pops a block height and fails if the spending block is below it.
+    CheckLockTimeVerify,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    /// The encoding ends in the middle of an instruction.
+    Truncated,
+    UnknownOpcode(u8),
+    TooLarge,
+    StackUnderflow,
+    StackOverflow,
+    InvalidNumber,
+    TooManyKeys,
+    /// Unlocking scripts may only push data.
+    NotPushOnly,
+    VerifyFailed,
+    Locked { until: u64, height: u32 },
+    /// The script ran but left false on top of the stack.
+    Failed,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Truncated => write!(f, "script ends mid-instruction"),
+            ScriptError::UnknownOpcode(opcode) => write!(f, "unknown opcode {:#04x}", opcode),
+            ScriptError::TooLarge => write!(f, "script exceeds {} bytes", MAX_SCRIPT_SIZE),
+            ScriptError::StackUnderflow => write!(f, "stack underflow"),
+            ScriptError::StackOverflow => write!(f, "stack exceeds {} items", MAX_STACK_DEPTH),
+            ScriptError::InvalidNumber => write!(f, "stack item is not a valid number"),
+            ScriptError::TooManyKeys => write!(f, "multisig names more than {} keys", MAX_MULTISIG_KEYS),
+            ScriptError::NotPushOnly => write!(f, "unlocking script does more than push data"),
+            ScriptError::VerifyFailed => write!(f, "verify failed"),
+            ScriptError::Locked { until, height } => {
+                write!(f, "output is locked until height {} but spent at {}", until, height)
+            }
+            ScriptError::Failed => write!(f, "script evaluated to false"),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// What a script may inspect about the spend it guards.
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    /// The message signatures must cover: the spending transaction's id.
+    pub message: &'a [u8],
+    /// Height of the block the spend is included in.
+    pub height: u32,
+}
+
+/// A sequence of instructions, serialized as the hex of its encoding. The
+/// empty script is the default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    ops: Vec<Op>,
+}
+
+impl Script {
+    pub fn new(ops: Vec<Op>) -> Self {
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                Op::Number(n) => number(n as u64),
+                op => op,
+            })
+            .collect();
+        Script { ops }
+    }
+
+    /// Spendable with a signature by `key`.
+    pub fn pay_to_key(key: &VerifyingKey) -> Self {
+        Script::new(vec![Op::Push(key.as_bytes().to_vec()), Op::CheckSig])
+    }
+
+    /// Spendable with signatures from `required` of `keys`, given in the
+    /// same order as the keys.
+    pub fn multisig(required: u8, keys: &[VerifyingKey]) -> Self {
+        let mut ops = vec![number(required as u64)];
+        ops.extend(keys.iter().map(|key| Op::Push(key.as_bytes().to_vec())));
+        ops.push(number(keys.len() as u64));
+        ops.push(Op::CheckMultiSig);
+        Script::new(ops)
+    }
+
+    /// Like `pay_to_key`, but only from block `height` on.
+    pub fn timelocked(height: u32, key: &VerifyingKey) -> Self {
+        let mut ops = vec![number(height as u64), Op::CheckLockTimeVerify];
+        ops.extend(Script::pay_to_key(key).ops);
+        Script::new(ops)
+    }
+
+    /// Unlocks `pay_to_key` and `timelocked` scripts, or multisig scripts
+    /// when given one signature per required key.
+    pub fn unlock(signatures: &[Vec<u8>]) -> Self {
+        Script::new(signatures.iter().cloned().map(Op::Push).collect())
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        self.ops.iter().all(|op| matches!(op, Op::Push(_) | Op::Number(_)))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for op in &self.ops {
+            match op {
+                Op::Push(data) => {
+                    if data.len() < OP_PUSHDATA1 as usize {
+                        bytes.push(data.len() as u8);
+                    } else if let Ok(len) = u8::try_from(data.len()) {
+                        bytes.extend([OP_PUSHDATA1, len]);
+                    } else if let Ok(len) = u16::try_from(data.len()) {
+                        bytes.push(OP_PUSHDATA2);
+                        bytes.extend(len.to_le_bytes());
+                    } else {
+                        bytes.push(OP_PUSHDATA4);
+                        bytes.extend((data.len() as u32).to_le_bytes());
+                    }
+                    bytes.extend(data);
+                }
+                Op::Number(n @ 1..=16) => bytes.push(OP_1 + n - 1),
+                Op::Number(0) => bytes.push(0),
+                Op::Number(n) => bytes.extend([1, *n]),
+                Op::Dup => bytes.push(OP_DUP),
+                Op::Drop => bytes.push(OP_DROP),
+                Op::Swap => bytes.push(OP_SWAP),
+                Op::Equal => bytes.push(OP_EQUAL),
+                Op::EqualVerify => bytes.push(OP_EQUALVERIFY),
+                Op::Verify => bytes.push(OP_VERIFY),
+                Op::Sha256 => bytes.push(OP_SHA256),
+                Op::CheckSig => bytes.push(OP_CHECKSIG),
+                Op::CheckSigVerify => bytes.push(OP_CHECKSIGVERIFY),
+                Op::CheckMultiSig => bytes.push(OP_CHECKMULTISIG),
+                Op::CheckLockTimeVerify => bytes.push(OP_CHECKLOCKTIMEVERIFY),
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ScriptError> {
+        if bytes.len() > MAX_SCRIPT_SIZE {
+            return Err(ScriptError::TooLarge);
+        }
+        let mut ops = Vec::new();
+        let mut rest = bytes;
+        while let Some((&opcode, tail)) = rest.split_first() {
+            rest = tail;
+            let op = match opcode {
+                0..OP_PUSHDATA1 => Op::Push(take(&mut rest, opcode as usize)?.to_vec()),
+                OP_PUSHDATA1 => {
+                    let len = take(&mut rest, 1)?[0] as usize;
+                    Op::Push(take(&mut rest, len)?.to_vec())
+                }
+                OP_PUSHDATA2 => {
+                    let len = take(&mut rest, 2)?;
+                    let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+                    Op::Push(take(&mut rest, len)?.to_vec())
+                }
+                OP_PUSHDATA4 => {
+                    let len = take(&mut rest, 4)?;
+                    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+                    Op::Push(take(&mut rest, len)?.to_vec())
+                }
+                OP_1..=OP_16 => Op::Number(opcode - OP_1 + 1),
+                OP_DUP => Op::Dup,
+                OP_DROP => Op::Drop,
+                OP_SWAP => Op::Swap,
+                OP_EQUAL => Op::Equal,
+                OP_EQUALVERIFY => Op::EqualVerify,
+                OP_VERIFY => Op::Verify,
+                OP_SHA256 => Op::Sha256,
+                OP_CHECKSIG => Op::CheckSig,
+                OP_CHECKSIGVERIFY => Op::CheckSigVerify,
+                OP_CHECKMULTISIG => Op::CheckMultiSig,
+                OP_CHECKLOCKTIMEVERIFY => Op::CheckLockTimeVerify,
+                other => return Err(ScriptError::UnknownOpcode(other)),
+            };
+            ops.push(op);
+        }
+        Ok(Script { ops })
+    }
+
+    /// Script address on `network`: the owner recorded for outputs locked
+    /// by this script.
+    pub fn address(&self, network: Network) -> String {
+        Address::from_script(self, network).to_string()
+    }
+}
+
+impl Serialize for Script {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(self.to_bytes()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Script {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: Vec<u8> = hex::serde::deserialize(deserializer)?;
+        Script::from_bytes(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Runs `unlock` and then `lock` on one stack. The spend is allowed if
+/// neither fails and the top item left is true.
+pub fn verify(unlock: &Script, lock: &Script, context: &Context) -> Result<(), ScriptError> {
+    if !unlock.is_push_only() {
+        return Err(ScriptError::NotPushOnly);
+    }
+    let mut stack = Vec::new();
+    execute(unlock, &mut stack, context)?;
+    execute(lock, &mut stack, context)?;
+    match stack.last() {
+        Some(top) if is_true(top) => Ok(()),
+        _ => Err(ScriptError::Failed),
+    }
+}
+
+fn execute(script: &Script, stack: &mut Vec<Vec<u8>>, context: &Context) -> Result<(), ScriptError> {
+    for op in &script.ops {
+        match op {
+            Op::Push(data) => push(stack, data.clone())?,
+            Op::Number(n) => push(stack, vec![*n])?,
+            Op::Dup => {
+                let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+                push(stack, top)?;
+            }
+            Op::Drop => {
+                pop(stack)?;
+            }
+            Op::Swap => {
+                let (b, a) = (pop(stack)?, pop(stack)?);
+                stack.extend([b, a]);
+            }
+            Op::Equal | Op::EqualVerify => {
+                let equal = pop(stack)? == pop(stack)?;
+                if *op == Op::EqualVerify {
+                    check(equal)?;
+                } else {
+                    push(stack, boolean(equal))?;
+                }
+            }
+            Op::Verify => check(is_true(&pop(stack)?))?,
+            Op::Sha256 => {
+                let digest = Sha256::digest(pop(stack)?).to_vec();
+                push(stack, digest)?;
+            }
+            Op::CheckSig | Op::CheckSigVerify => {
+                let key = pop(stack)?;
+                let signature = pop(stack)?;
+                let valid = signature_matches(&key, &signature, context.message);
+                if *op == Op::CheckSigVerify {
+                    check(valid)?;
+                } else {
+                    push(stack, boolean(valid))?;
+                }
+            }
+            Op::CheckMultiSig => {
+                let key_count = pop_number(stack)? as usize;
+                if key_count > MAX_MULTISIG_KEYS {
+                    return Err(ScriptError::TooManyKeys);
+                }
+                let keys = pop_many(stack, key_count)?;
+                let signature_count = pop_number(stack)? as usize;
+                if signature_count > key_count {
+                    return Err(ScriptError::InvalidNumber);
+                }
+                let signatures = pop_many(stack, signature_count)?;
+                let mut keys = keys.iter();
+                let valid = signatures.iter().all(|signature| {
+                    keys.any(|key| signature_matches(key, signature, context.message))
+                });
+                push(stack, boolean(valid))?;
+            }
+            Op::CheckLockTimeVerify => {
+                let until = pop_number(stack)?;
+                if (context.height as u64) < until {
+                    return Err(ScriptError::Locked {
+                        until,
+                        height: context.height,
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The shortest instruction pushing `n`.
+fn number(n: u64) -> Op {
+    match n {
+        1..=16 => Op::Number(n as u8),
+        _ => {
+            let bytes = n.to_le_bytes();
+            let len = bytes.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1);
+            Op::Push(bytes[..len].to_vec())
+        }
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], ScriptError> {
+    if bytes.len() < len {
+        return Err(ScriptError::Truncated);
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn push(stack: &mut Vec<Vec<u8>>, item: Vec<u8>) -> Result<(), ScriptError> {
+    if stack.len() >= MAX_STACK_DEPTH {
+        return Err(ScriptError::StackOverflow);
+    }
+    stack.push(item);
+    Ok(())
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::StackUnderflow)
+}
+
+fn pop_number(stack: &mut Vec<Vec<u8>>) -> Result<u64, ScriptError> {
+    let item = pop(stack)?;
+    if item.len() > 8 {
+        return Err(ScriptError::InvalidNumber);
+    }
+    let mut bytes = [0; 8];
+    bytes[..item.len()].copy_from_slice(&item);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Pops `count` items, returning them in the order they were pushed.
+fn pop_many(stack: &mut Vec<Vec<u8>>, count: usize) -> Result<Vec<Vec<u8>>, ScriptError> {
+    let start = stack.len().checked_sub(count).ok_or(ScriptError::StackUnderflow)?;
+    Ok(stack.split_off(start))
+}
+
+fn check(condition: bool) -> Result<(), ScriptError> {
+    if condition { Ok(()) } else { Err(ScriptError::VerifyFailed) }
+}
+
+fn is_true(item: &[u8]) -> bool {
+    item.iter().any(|&byte| byte != 0)
+}
+
+fn boolean(value: bool) -> Vec<u8> {
+    if value { vec![1] } else { Vec::new() }
+}
+
+fn signature_matches(key: &[u8], signature: &[u8], message: &[u8]) -> bool {
+    let Ok(key) = <[u8; 32]>::try_from(key) else {
+        return false;
+    };
+    let Ok(key) = VerifyingKey::from_bytes(&key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify_strict(message, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    const MESSAGE: &[u8] = b"spending transaction id";
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn sign(key: &SigningKey) -> Vec<u8> {
+        key.sign(MESSAGE).to_bytes().to_vec()
+    }
+
+    fn run(unlock: &Script, lock: &Script, height: u32) -> Result<(), ScriptError> {
+        verify(unlock, lock, &Context { message: MESSAGE, height })
+    }
+
+    #[test]
+    fn test_encoding_round_trips() {
+        let keys = [key(1).verifying_key(), key(2).verifying_key()];
+        let scripts = [
+            Script::multisig(2, &keys),
+            Script::timelocked(70_000, &keys[0]),
+            Script::new(vec![Op::Push(vec![7; 300]), Op::Sha256, Op::Push(Vec::new()), Op::Drop]),
+        ];
+        for script in scripts {
+            assert_eq!(Script::from_bytes(&script.to_bytes()), Ok(script.clone()));
+            let json = serde_json::to_string(&script).unwrap();
+            assert_eq!(serde_json::from_str::<Script>(&json).unwrap(), script);
+        }
+
+        assert_eq!(Script::from_bytes(&[0x05, 1, 2]), Err(ScriptError::Truncated));
+        assert_eq!(Script::from_bytes(&[OP_PUSHDATA2, 0xff]), Err(ScriptError::Truncated));
+        assert_eq!(Script::from_bytes(&[0xff]), Err(ScriptError::UnknownOpcode(0xff)));
+        assert_eq!(Script::from_bytes(&[0; MAX_SCRIPT_SIZE + 1]), Err(ScriptError::TooLarge));
+    }
+
+    #[test]
+    fn test_numbers_and_pushes_encode_minimally() {
+        let numbers = Script::new(vec![Op::Number(0), Op::Number(1), Op::Number(16), Op::Number(17), Op::Number(255)]);
+        let expected = [Op::Push(Vec::new()), Op::Number(1), Op::Number(16), Op::Push(vec![17]), Op::Push(vec![255])];
+        assert_eq!(numbers.ops(), expected);
+        assert_eq!(numbers.to_bytes(), [0, OP_1, OP_16, 1, 17, 1, 255]);
+        assert_eq!(Script::from_bytes(&numbers.to_bytes()), Ok(numbers));
+
+        for len in [75, 76, 255, 256, 5_000] {
+            let script = Script::new(vec![Op::Push(vec![7; len])]);
+            let bytes = script.to_bytes();
+            let header = match len {
+                75 => vec![75],
+                76 | 255 => vec![OP_PUSHDATA1, len as u8],
+                _ => [[OP_PUSHDATA2].as_slice(), &(len as u16).to_le_bytes()].concat(),
+            };
+            assert_eq!(bytes[..header.len()], header);
+            assert_eq!(Script::from_bytes(&bytes), Ok(script));
+        }
+
+        // Pushes too long for two length bytes keep their full length, even
+        // though no script that size is accepted.
+        let long = Script::new(vec![Op::Push(vec![7; 70_000])]).to_bytes();
+        assert_eq!(long.len(), 5 + 70_000);
+        assert_eq!(long[..5], [OP_PUSHDATA4, 0x70, 0x11, 0x01, 0x00]);
+        assert_eq!(Script::from_bytes(&long), Err(ScriptError::TooLarge));
+        assert_eq!(Script::from_bytes(&[OP_PUSHDATA4, 2, 0, 0, 0, 9, 9]), Ok(Script::new(vec![Op::Push(vec![9, 9])])));
+        assert_eq!(Script::from_bytes(&[OP_PUSHDATA4, 2, 0]), Err(ScriptError::Truncated));
+    }
+
+    #[test]
+    fn test_pay_to_key() {
+        let alice = key(1);
+        let lock = Script::pay_to_key(&alice.verifying_key());
+
+        assert_eq!(run(&Script::unlock(&[sign(&alice)]), &lock, 0), Ok(()));
+        assert_eq!(run(&Script::unlock(&[sign(&key(2))]), &lock, 0), Err(ScriptError::Failed));
+        assert_eq!(run(&Script::default(), &lock, 0), Err(ScriptError::StackUnderflow));
+
+        let sneaky = Script::new(vec![Op::Number(1), Op::Dup]);
+        assert_eq!(run(&sneaky, &lock, 0), Err(ScriptError::NotPushOnly));
+    }
+
+    #[test]
+    fn test_multisig_requires_enough_ordered_signatures() {
+        let signers = [key(1), key(2), key(3)];
+        let keys: Vec<VerifyingKey> = signers.iter().map(SigningKey::verifying_key).collect();
+        let lock = Script::multisig(2, &keys);
+
+        let first_and_third = Script::unlock(&[sign(&signers[0]), sign(&signers[2])]);
+        assert_eq!(run(&first_and_third, &lock, 0), Ok(()));
+
+        let out_of_order = Script::unlock(&[sign(&signers[2]), sign(&signers[0])]);
+        assert_eq!(run(&out_of_order, &lock, 0), Err(ScriptError::Failed));
+
+        let repeated = Script::unlock(&[sign(&signers[0]), sign(&signers[0])]);
+        assert_eq!(run(&repeated, &lock, 0), Err(ScriptError::Failed));
+
+        let too_few = Script::unlock(&[sign(&signers[1])]);
+        assert_eq!(run(&too_few, &lock, 0), Err(ScriptError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_timelock() {
+        let alice = key(1);
+        let lock = Script::timelocked(300, &alice.verifying_key());
+        let unlock = Script::unlock(&[sign(&alice)]);
+
+        assert_eq!(run(&unlock, &lock, 299), Err(ScriptError::Locked { until: 300, height: 299 }));
+        assert_eq!(run(&unlock, &lock, 300), Ok(()));
+    }
+
+    #[test]
+    fn test_hash_lock_and_stack_limits() {
+        let secret = b"open sesame".to_vec();
+        let lock = Script::new(vec![
+            Op::Sha256,
+            Op::Push(Sha256::digest(&secret).to_vec()),
+            Op::Equal,
+        ]);
+        assert_eq!(run(&Script::unlock(&[secret]), &lock, 0), Ok(()));
+        assert_eq!(run(&Script::unlock(&[b"guess".to_vec()]), &lock, 0), Err(ScriptError::Failed));
+
+        let flood = Script::new(vec![Op::Number(1); MAX_STACK_DEPTH + 1]);
+        assert_eq!(run(&flood, &Script::default(), 0), Err(ScriptError::StackOverflow));
+    }
+}
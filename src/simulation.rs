@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+use crate::network::Envelope;
+use crate::{Blockchain, ChainParams, ManualClock, Node};
+
+/// Where the virtual clock starts, in seconds since the Unix epoch.
+const START_TIME: i64 = 1_700_000_000;
+
+/// How the virtual network treats every message it carries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConditions {
+    /// Least time a message spends in flight.
+    pub latency: Duration,
+    /// Extra delay, drawn uniformly up to this much, added to each message.
+    pub jitter: Duration,
+    /// Share of messages lost, from 0 to 1.
+    pub drop_rate: f64,
+}
+
+impl Default for LinkConditions {
+    /// A perfect network that delivers every message after 50ms.
+    fn default() -> Self {
+        LinkConditions {
+            latency: Duration::from_millis(50),
+            jitter: Duration::ZERO,
+            drop_rate: 0.0,
+        }
+    }
+}
+
+/// One direction of a connection. The sending node holds `sender`; the
+/// simulation drains `receiver` and decides when, and whether, each message
+/// arrives.
+#[derive(Debug)]
+struct Link {
+    sender: Sender<Envelope>,
+    receiver: Receiver<Envelope>,
+}
+
+/// Nodes run in process over a virtual network and a virtual clock. Nothing
+/// happens until the simulation is advanced, and the same seed and script
+/// always play out the same way, so scenarios such as "partition part of the
+/// network for a while, then heal it" can be scripted and their outcome
+/// asserted.
+#[derive(Debug)]
+pub struct Simulation {
+    nodes: Vec<Node>,
+    clock: Arc<ManualClock>,
+    /// Virtual time since the simulation started.
+    now: Duration,
+    conditions: LinkConditions,
+    /// Connections by sending and receiving node.
+    links: BTreeMap<(usize, usize), Link>,
+    /// Side of the partition each node is on. Messages only pass between
+    /// nodes on the same side.
+    sides: Vec<u32>,
+    /// Messages in flight by arrival time, then send order, with their
+    /// sending and receiving nodes.
+    in_flight: BTreeMap<(Duration, u64), (usize, usize, Envelope)>,
+    sent: u64,
+    rng: SplitMix64,
+}
+
+impl Simulation {
+    /// `count` unconnected nodes on testing parameters.
+    pub fn new(count: usize, seed: u64) -> Self {
+        Self::with_params(count, ChainParams::testing(), seed)
+    }
+
+    /// `count` unconnected nodes on `params`, all reading the simulation's
+    /// clock instead of the one `params` names.
+    pub fn with_params(count: usize, params: ChainParams, seed: u64) -> Self {
+        let clock = Arc::new(ManualClock::new(START_TIME));
+        let params = ChainParams {
+            clock: clock.clone(),
+            ..params
+        };
+        let nodes = (0..count)
+            .map(|i| Node::with_blockchain(format!("node-{}", i), Blockchain::with_params(params.clone())))
+            .collect();
+        Simulation {
+            nodes,
+            clock,
+            now: Duration::ZERO,
+            conditions: LinkConditions::default(),
+            links: BTreeMap::new(),
+            sides: vec![0; count],
+            in_flight: BTreeMap::new(),
+            sent: 0,
+            rng: SplitMix64(seed),
+        }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn node(&self, index: usize) -> &Node {
+        &self.nodes[index]
+    }
+
+    /// For mining or submitting transactions. Whatever the node sends goes
+    /// out over the virtual network on the next advance.
+    pub fn node_mut(&mut self, index: usize) -> &mut Node {
+        &mut self.nodes[index]
+    }
+
+    /// Virtual time since the simulation started.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn set_conditions(&mut self, conditions: LinkConditions) {
+        self.conditions = conditions;
+    }
+
+    /// Links `a` and `b` in both directions. Each announces its tip to the
+    /// other once the simulation advances.
+    pub fn connect(&mut self, a: usize, b: usize) {
+        if a == b || self.links.contains_key(&(a, b)) {
+            return;
+        }
+        for (from, to) in [(a, b), (b, a)] {
+            let (sender, receiver) = mpsc::channel();
+            self.links.insert((from, to), Link { sender, receiver });
+        }
+        if self.sides[a] == self.sides[b] {
+            self.open(a, b);
+        }
+    }
+
+    /// Links every node to every other.
+    pub fn connect_all(&mut self) {
+        for a in 0..self.nodes.len() {
+            for b in a + 1..self.nodes.len() {
+                self.connect(a, b);
+            }
+        }
+    }
+
+    /// Cuts `nodes` off from the rest of the network. Connections across
+    /// the cut drop, and messages already in flight across it are lost.
+    /// Nodes partitioned off together can still reach one another.
+    pub fn partition(&mut self, nodes: &[usize]) {
+        let side = self.sides.iter().max().map_or(0, |side| side + 1);
+        for &node in nodes {
+            self.sides[node] = side;
+        }
+        for (a, b) in self.connections() {
+            if self.sides[a] != self.sides[b] && self.nodes[a].is_connected(self.nodes[b].id()) {
+                let (first, second) = self.pair(a, b);
+                Node::disconnect(first, second);
+            }
+        }
+    }
+
+    /// Ends every partition. Connections cut by one come back, and both
+    /// ends announce their tips so the network can converge.
+    pub fn heal(&mut self) {
+        self.sides.iter_mut().for_each(|side| *side = 0);
+        for (a, b) in self.connections() {
+            if !self.nodes[a].is_connected(self.nodes[b].id()) {
+                self.open(a, b);
+            }
+        }
+    }
+
+    /// Mines a block on `node`, which announces it to its peers.
+    pub fn mine_block(&mut self, node: usize) -> bool {
+        let data = format!("{} at {}ms", self.nodes[node].id(), self.now.as_millis());
+        self.nodes[node].mine_block(data)
+    }
+
+    /// Moves virtual time forward by `duration`, delivering every message
+    /// due by then and letting each node handle its messages as they
+    /// arrive. Replies are sent, and arrive, within the same call if the
+    /// network is fast enough.
+    pub fn advance(&mut self, duration: Duration) {
+        let until = self.now + duration;
+        loop {
+            self.collect_sent();
+            let Some(entry) = self.in_flight.first_entry() else {
+                break;
+            };
+            if entry.key().0 > until {
+                break;
+            }
+            let ((arrival, _), (from, to, envelope)) = entry.remove_entry();
+            self.set_now(arrival);
+            if self.sides[from] == self.sides[to] {
+                let _ = self.nodes[to].inbox_sender().send(envelope);
+                self.nodes[to].process_messages();
+            }
+        }
+        self.set_now(until);
+    }
+
+    /// Advances until no message is left in flight, or `limit` has passed.
+    /// Returns whether the network went quiet.
+    pub fn run_until_quiet(&mut self, limit: Duration) -> bool {
+        let until = self.now + limit;
+        loop {
+            self.collect_sent();
+            let Some((&(arrival, _), _)) = self.in_flight.first_key_value() else {
+                return true;
+            };
+            if arrival > until {
+                self.set_now(until);
+                return false;
+            }
+            self.advance(arrival - self.now);
+        }
+    }
+
+    /// Whether every node has the same tip.
+    pub fn converged(&self) -> bool {
+        let tip = |node: &Node| node.blockchain().latest_block().header.hash.clone();
+        self.nodes.iter().all(|node| tip(node) == tip(&self.nodes[0]))
+    }
+
+    /// Pairs of connected nodes, each once.
+    fn connections(&self) -> Vec<(usize, usize)> {
+        self.links.keys().copied().filter(|(a, b)| a < b).collect()
+    }
+
+    fn open(&mut self, a: usize, b: usize) {
+        let (id_a, id_b) = (self.nodes[a].id().to_owned(), self.nodes[b].id().to_owned());
+        self.nodes[a].add_peer(id_b, self.links[&(a, b)].sender.clone());
+        self.nodes[b].add_peer(id_a, self.links[&(b, a)].sender.clone());
+    }
+
+    fn pair(&mut self, a: usize, b: usize) -> (&mut Node, &mut Node) {
+        let (low, high) = self.nodes.split_at_mut(a.max(b));
+        if a < b { (&mut low[a], &mut high[0]) } else { (&mut high[0], &mut low[b]) }
+    }
+
+    /// Moves messages the nodes have sent onto the wire, dropping some and
+    /// delaying the rest as the link conditions say.
+    fn collect_sent(&mut self) {
+        let conditions = self.conditions;
+        for (&(from, to), link) in &self.links {
+            for envelope in link.receiver.try_iter() {
+                self.sent += 1;
+                if self.rng.next_f64() < conditions.drop_rate {
+                    continue;
+                }
+                let jitter = conditions.jitter.mul_f64(self.rng.next_f64());
+                let arrival = self.now + conditions.latency + jitter;
+                self.in_flight.insert((arrival, self.sent), (from, to, envelope));
+            }
+        }
+    }
+
+    fn set_now(&mut self, now: Duration) {
+        self.now = now;
+        self.clock.set(START_TIME + now.as_secs() as i64);
+    }
+}
+
+/// A small, seedable random number generator, so runs repeat exactly.
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn height(simulation: &Simulation, node: usize) -> u32 {
+        simulation.node(node).blockchain().latest_block().header.index
+    }
+
+    #[test]
+    fn test_messages_arrive_after_the_latency() {
+        let mut simulation = Simulation::new(2, 1);
+        simulation.set_conditions(LinkConditions {
+            latency: Duration::from_secs(2),
+            ..LinkConditions::default()
+        });
+        simulation.connect(0, 1);
+        assert!(simulation.run_until_quiet(Duration::from_secs(60)));
+        assert!(simulation.mine_block(0));
+
+        simulation.advance(Duration::from_millis(1_999));
+        assert_eq!(height(&simulation, 1), 0);
+        simulation.advance(Duration::from_millis(1));
+        assert_eq!(height(&simulation, 1), 1);
+        assert_eq!(simulation.node(1).blockchain().params().clock.now(), START_TIME + simulation.now().as_secs() as i64);
+    }
+
+    #[test]
+    fn test_dropped_and_partitioned_messages_never_arrive() {
+        let mut simulation = Simulation::new(3, 1);
+        simulation.connect_all();
+        simulation.set_conditions(LinkConditions {
+            drop_rate: 1.0,
+            ..LinkConditions::default()
+        });
+        assert!(simulation.mine_block(0));
+        assert!(simulation.run_until_quiet(Duration::from_secs(60)));
+        assert_eq!((height(&simulation, 1), height(&simulation, 2)), (0, 0));
+
+        simulation.set_conditions(LinkConditions::default());
+        simulation.partition(&[2]);
+        assert!(!simulation.node(0).is_connected("node-2"));
+        assert!(simulation.mine_block(0));
+        assert!(simulation.run_until_quiet(Duration::from_secs(60)));
+        assert_eq!((height(&simulation, 1), height(&simulation, 2)), (2, 0));
+
+        simulation.heal();
+        assert!(simulation.run_until_quiet(Duration::from_secs(60)));
+        assert!(simulation.converged());
+    }
+
+    #[test]
+    fn test_same_seed_plays_out_the_same() {
+        let run = |seed| {
+            let mut simulation = Simulation::new(6, seed);
+            simulation.set_conditions(LinkConditions {
+                latency: Duration::from_millis(20),
+                jitter: Duration::from_millis(200),
+                drop_rate: 0.2,
+            });
+            simulation.connect_all();
+            for round in 0..5 {
+                assert!(simulation.mine_block(round % 6));
+                simulation.advance(Duration::from_millis(100));
+            }
+            simulation.run_until_quiet(Duration::from_secs(60));
+            simulation.nodes().iter().map(|node| node.blockchain().latest_block().header.hash.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(run(7), run(7));
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AccountNonces, Block, BlockHeader, state_root};
+use crate::utxo::{OutPoint, TxOutput, UtxoSet};
+
+/// Checkpoint of the chain state at a given block, used to bootstrap a node
+/// without replaying every block from genesis.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub block: Block,
+    pub state_root: String,
+    pub utxos: Vec<(OutPoint, TxOutput)>,
+    /// Each account's next nonce.
+    #[serde(default)]
+    pub nonces: AccountNonces,
+    /// Headers of the blocks just before `block`, oldest first: as many as
+    /// the median time past of the blocks after it takes.
+    #[serde(default)]
+    pub headers: Vec<BlockHeader>,
+}
+
+impl Snapshot {
+    pub fn height(&self) -> u32 {
+        self.block.header.index
+    }
+
+    pub fn block_hash(&self) -> &str {
+        &self.block.header.hash
+    }
+
+    /// Checks that the anchor block hashes to its recorded hash, that the
+    /// headers before it hash correctly and lead up to it, and that the UTXO
+    /// entries and nonces match the state root its header commits to.
+    pub fn verify(&self) -> Result<UtxoSet, SnapshotError> {
+        if self.block.header.hash != self.block.calculate_hash() {
+            return Err(SnapshotError::BlockHashMismatch);
+        }
+        let headers: Vec<&BlockHeader> = self.headers.iter().chain([&self.block.header]).collect();
+        let linked = headers.windows(2).all(|pair| {
+            pair[0].hash == pair[0].calculate_hash()
+                && pair[1].index == pair[0].index + 1
+                && pair[1].prev_hash == pair[0].hash
+        });
+        if !linked {
+            return Err(SnapshotError::UnlinkedHeaders);
+        }
+        let utxo: UtxoSet = self.utxos.iter().cloned().collect();
+        let root = state_root(&utxo, &self.nonces);
+        if utxo.len() != self.utxos.len() || root != self.state_root || root != self.block.header.state_root {
+            return Err(SnapshotError::StateRootMismatch);
+        }
+        Ok(utxo)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    BlockHashMismatch,
+    StateRootMismatch,
+    UnlinkedHeaders,
+    UntrustedBlock { expected: String, found: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BlockHashMismatch => write!(f, "snapshot block hash does not match its contents"),
+            SnapshotError::StateRootMismatch => write!(f, "snapshot state does not match its state root"),
+            SnapshotError::UnlinkedHeaders => write!(f, "snapshot headers do not lead up to its block"),
+            SnapshotError::UntrustedBlock { expected, found } => {
+                write!(f, "snapshot is anchored at {} but {} was trusted", found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
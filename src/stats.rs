@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Block, Target};
+
+/// Difficulty a block was mined at.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DifficultyPoint {
+    pub height: u32,
+    pub difficulty: f64,
+}
+
+/// How much room a block takes up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSize {
+    pub height: u32,
+    /// Encoded size; see `Block::size_bytes`.
+    pub bytes: usize,
+    /// See `Block::weight`.
+    pub weight: usize,
+}
+
+/// Fees paid by account transactions, by nearest-rank percentile.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeePercentiles {
+    pub p10: u64,
+    pub p50: u64,
+    pub p90: u64,
+}
+
+/// Metrics over a run of consecutive blocks. Zero where the run is too
+/// short to measure them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChainStats {
+    pub first_height: u32,
+    pub last_height: u32,
+    pub blocks: usize,
+    /// Mean seconds between consecutive blocks.
+    pub average_block_interval: f64,
+    pub difficulty: Vec<DifficultyPoint>,
+    /// Expected hashes it took to mine every block in the run.
+    pub total_work: f64,
+    /// Transactions per block, leaving out rewards and coinbases.
+    pub transactions_per_block: f64,
+    pub fees: FeePercentiles,
+    /// Hashes per second: the work of the blocks after the first over the
+    /// time they took.
+    pub hash_rate: f64,
+    pub sizes: Vec<BlockSize>,
+    /// Encoded size of the whole run.
+    pub total_bytes: usize,
+}
+
+impl ChainStats {
+    /// Computes the metrics over `blocks`, which must be consecutive.
+    pub fn compute(blocks: &[Block]) -> Self {
+        let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+            return ChainStats::default();
+        };
+        let difficulty: Vec<DifficultyPoint> = blocks
+            .iter()
+            .map(|block| DifficultyPoint {
+                height: block.header.index,
+                difficulty: Target::from_compact(block.header.bits).difficulty(),
+            })
+            .collect();
+        let total_work = difficulty.iter().map(|point| point.difficulty).sum();
+
+        let span = last.header.timestamp.saturating_sub(first.header.timestamp);
+        let (average_block_interval, hash_rate) = if span > 0 {
+            let work: f64 = difficulty[1..].iter().map(|point| point.difficulty).sum();
+            (span as f64 / (blocks.len() - 1) as f64, work / span as f64)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let mut fees = Vec::new();
+        let mut transactions = 0;
+        for block in blocks {
+            for transaction in block.body.transactions.iter().filter(|tx| !tx.is_reward()) {
+                fees.push(transaction.fee);
+                transactions += 1;
+            }
+            transactions += block.body.utxo_transactions.iter().filter(|tx| !tx.is_coinbase()).count();
+        }
+        fees.sort_unstable();
+
+        let sizes: Vec<BlockSize> = blocks
+            .iter()
+            .map(|block| BlockSize {
+                height: block.header.index,
+                bytes: block.size_bytes(),
+                weight: block.weight(),
+            })
+            .collect();
+        let total_bytes = sizes.iter().map(|size| size.bytes).sum();
+
+        ChainStats {
+            first_height: first.header.index,
+            last_height: last.header.index,
+            blocks: blocks.len(),
+            average_block_interval,
+            difficulty,
+            total_work,
+            transactions_per_block: transactions as f64 / blocks.len() as f64,
+            fees: FeePercentiles {
+                p10: percentile(&fees, 10),
+                p50: percentile(&fees, 50),
+                p90: percentile(&fees, 90),
+            },
+            hash_rate,
+            sizes,
+            total_bytes,
+        }
+    }
+}
+
+/// The nearest-rank `percent`th percentile of `sorted`, or zero if empty.
+fn percentile(sorted: &[u64], percent: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::accounts::{account, signed};
+    use crate::{Blockchain, ChainParams, ManualClock, Transaction};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_percentiles() {
+        let fees: Vec<u64> = (1..=10).collect();
+        assert_eq!(percentile(&fees, 10), 1);
+        assert_eq!(percentile(&fees, 50), 5);
+        assert_eq!(percentile(&fees, 90), 9);
+        assert_eq!(percentile(&[7], 10), 7);
+        assert_eq!(percentile(&[], 50), 0);
+    }
+
+    #[test]
+    fn test_stats_over_mined_blocks() {
+        let clock = Arc::new(ManualClock::default());
+        let mut blockchain = Blockchain::with_params(ChainParams {
+            clock: clock.clone(),
+            ..ChainParams::testing()
+        });
+        for (block, fees) in [[1, 3], [5, 7]].into_iter().enumerate() {
+            clock.set(10 * (block as i64 + 1));
+            for (i, fee) in fees.into_iter().enumerate() {
+                let transaction = Transaction {
+                    nonce: 2 * block as u64 + i as u64,
+                    ..Transaction::with_fee(String::new(), account("bob"), i as u64, fee)
+                };
+                assert!(blockchain.add_transaction(signed("alice", transaction)));
+            }
+            assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        }
+
+        let stats = blockchain.stats(..);
+        assert_eq!((stats.first_height, stats.last_height, stats.blocks), (0, 2, 3));
+        assert_eq!(stats.average_block_interval, 10.0);
+        assert_eq!(stats.difficulty.len(), 3);
+        assert_eq!(stats.difficulty[0].difficulty, 16.0);
+        assert_eq!(stats.total_work, 48.0);
+        assert_eq!(stats.hash_rate, 32.0 / 20.0);
+        assert_eq!(stats.transactions_per_block, 4.0 / 3.0);
+        assert_eq!(stats.fees, FeePercentiles { p10: 1, p50: 3, p90: 7 });
+        assert_eq!(stats.sizes.len(), 3);
+        assert_eq!(stats.sizes[2].bytes, blockchain.latest_block().size_bytes());
+        // Account transactions carry no witness data, so every byte counts
+        // four times.
+        assert_eq!(stats.sizes[2].weight, 4 * stats.sizes[2].bytes);
+        assert_eq!(stats.total_bytes, blockchain.size_bytes());
+
+        let tip = blockchain.stats(2..);
+        assert_eq!((tip.blocks, tip.average_block_interval, tip.hash_rate), (1, 0.0, 0.0));
+        assert_eq!(blockchain.stats(5..), ChainStats::default());
+    }
+}
@@ -0,0 +1,441 @@
+use std::fs;
+#[cfg(feature = "net")]
+use std::fs::OpenOptions;
+use std::io;
+#[cfg(feature = "net")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "net")]
+use ed25519_dalek::SigningKey;
+#[cfg(feature = "net")]
+use zeroize::Zeroizing;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+#[cfg(feature = "net")]
+use crate::NodeIdentity;
+use crate::tx_index::TxIndex;
+use crate::{AddressBook, BanList, Block, BlockHeader, Blockchain, ChainParams, Network, Snapshot, codec};
+
+const CHAIN_FILE: &str = "chain.dat";
+const PRUNED_FILE: &str = "pruned.json";
+const TX_INDEX_FILE: &str = "txindex.json";
+const ADDRESS_BOOK_FILE: &str = "peers.json";
+const BAN_LIST_FILE: &str = "bans.json";
+#[cfg(feature = "net")]
+const IDENTITY_FILE: &str = "identity.key";
+
+/// Directory under `data_dir` holding the state of `network`: the main
+/// network keeps `data_dir` itself, others a subdirectory named after them.
+pub fn network_dir(data_dir: &Path, network: Network) -> PathBuf {
+    match network {
+        Network::Mainnet => data_dir.to_owned(),
+        other => data_dir.join(other.name()),
+    }
+}
+
+/// What a pruned chain keeps of the blocks below those it holds.
+#[derive(Serialize, Deserialize)]
+struct PrunedBase {
+    headers: Vec<BlockHeader>,
+    /// The oldest block held, with the state as of it.
+    base: Snapshot,
+}
+
+/// The transaction index as of the block with hash `tip`.
+#[derive(Serialize, Deserialize)]
+struct StoredIndex {
+    tip: String,
+    index: TxIndex,
+}
+
+/// Writes the chain to `chain.dat` in `dir`, one record per block: its
+/// encoded length, the SHA-256 of the encoding and the `codec` encoding
+/// itself. The file is written under a temporary name and renamed into
+/// place so a crash never leaves it half written. A pruned chain also
+/// writes the headers and state it starts from to `pruned.json`, and only
+/// the blocks it holds to `chain.dat`. The transaction index is saved next
+/// to them in `txindex.json`, so lookups of pruned transactions survive a
+/// restart.
+pub fn save_chain(dir: &Path, blockchain: &Blockchain) -> io::Result<()> {
+    if blockchain.pruned_headers().is_empty() {
+        match fs::remove_file(dir.join(PRUNED_FILE)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    } else {
+        let pruned = PrunedBase {
+            headers: blockchain.pruned_headers().to_vec(),
+            base: blockchain.base_snapshot(),
+        };
+        write_json(dir, PRUNED_FILE, &pruned)?;
+    }
+    let mut records = Vec::new();
+    for block in blockchain {
+        write_record(&mut records, block)?;
+    }
+    write_file(dir, CHAIN_FILE, &records)?;
+    let index = StoredIndex {
+        tip: blockchain.latest_block().header.hash.clone(),
+        index: blockchain.tx_index().clone(),
+    };
+    write_json(dir, TX_INDEX_FILE, &index)?;
+    debug!(height = blockchain.latest_block().header.index, "chain saved");
+    Ok(())
+}
+
+/// Reads the chain stored in `dir` and validates it under `params`, or
+/// returns `None` if nothing has been saved there yet.
+///
+/// Damage is cut away rather than refused: reading stops at the first
+/// record that is truncated or fails its checksum, and at the first block
+/// that fails validation. What is left is saved back and what was lost is
+/// logged; the node fetches it again from its peers. A chain that starts
+/// from another genesis block is still an error, since that is the wrong
+/// network or configuration rather than damage.
+pub fn load_chain(dir: &Path, params: ChainParams) -> io::Result<Option<Blockchain>> {
+    let encoded = match fs::read(dir.join(CHAIN_FILE)) {
+        Ok(encoded) => encoded,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let (blocks, unreadable) = read_records(&encoded);
+
+    let pruned = match read_json::<Option<PrunedBase>>(dir, PRUNED_FILE) {
+        Ok(pruned) => pruned,
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            warn!(error = %err, "stored pruned headers are damaged: starting over");
+            return recovered(dir, Blockchain::with_params(params), blocks.len(), unreadable);
+        }
+        Err(err) => return Err(err),
+    };
+    let (mut blockchain, stored, skipped) = match pruned {
+        Some(pruned) => {
+            let Some(blockchain) = Blockchain::from_pruned(params.clone(), pruned.headers, pruned.base) else {
+                warn!("stored pruned headers are invalid: starting over");
+                return recovered(dir, Blockchain::with_params(params), blocks.len(), unreadable);
+            };
+            // The base may have moved past the first stored block if saving
+            // was interrupted between the two files.
+            let base = &blockchain.latest_block().header.hash;
+            match blocks.iter().position(|block| block.header.hash == *base) {
+                Some(i) => (blockchain, &blocks[i + 1..], 0),
+                None => (blockchain, &[][..], blocks.len()),
+            }
+        }
+        None => {
+            let blockchain = Blockchain::with_params(params);
+            let stored = match blocks.split_first() {
+                Some((genesis, _)) if genesis != blockchain.latest_block() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "stored chain starts from another genesis block",
+                    ));
+                }
+                Some((_, stored)) => stored,
+                None => &[][..],
+            };
+            (blockchain, stored, 0)
+        }
+    };
+
+    let accepted = stored
+        .iter()
+        .take_while(|block| blockchain.accept_block((*block).clone()))
+        .count();
+    let discarded = skipped + stored.len() - accepted;
+    if discarded == 0 && unreadable == 0 {
+        restore_tx_index(dir, &mut blockchain)?;
+        return Ok(Some(blockchain));
+    }
+    recovered(dir, blockchain, discarded, unreadable)
+}
+
+/// Swaps the index `blockchain` rebuilt from its blocks for the one saved
+/// with it, which also remembers pruned blocks. An index saved at another
+/// tip is left alone: the rebuilt one is what gets saved next.
+fn restore_tx_index(dir: &Path, blockchain: &mut Blockchain) -> io::Result<()> {
+    match read_json::<Option<StoredIndex>>(dir, TX_INDEX_FILE) {
+        Ok(Some(stored)) if stored.tip == blockchain.latest_block().header.hash => {
+            blockchain.restore_tx_index(stored.index);
+        }
+        Ok(_) => debug!("no transaction index saved at the tip: using the rebuilt one"),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            warn!(error = %err, "stored transaction index is damaged: using the rebuilt one");
+        }
+        Err(err) => return Err(err),
+    }
+    Ok(())
+}
+
+/// Saves `blockchain`, what was left of a damaged store, in its place.
+fn recovered(dir: &Path, blockchain: Blockchain, discarded: usize, unreadable: usize) -> io::Result<Option<Blockchain>> {
+    warn!(
+        height = blockchain.latest_block().header.index,
+        discarded_blocks = discarded,
+        unreadable_bytes = unreadable,
+        "stored chain was damaged: truncated to the last valid block"
+    );
+    save_chain(dir, &blockchain)?;
+    Ok(Some(blockchain))
+}
+
+/// Writes the address book to `peers.json` in `dir`, like `save_chain`.
+pub fn save_address_book(dir: &Path, book: &AddressBook) -> io::Result<()> {
+    write_json(dir, ADDRESS_BOOK_FILE, book)
+}
+
+/// Reads the address book saved in `dir`, or an empty one if there is none.
+pub fn load_address_book(dir: &Path) -> io::Result<AddressBook> {
+    read_json(dir, ADDRESS_BOOK_FILE)
+}
+
+/// Writes the ban list to `bans.json` in `dir`, like `save_chain`.
+pub fn save_ban_list(dir: &Path, bans: &BanList) -> io::Result<()> {
+    write_json(dir, BAN_LIST_FILE, bans)
+}
+
+/// Reads the ban list saved in `dir`, or an empty one if there is none.
+pub fn load_ban_list(dir: &Path) -> io::Result<BanList> {
+    read_json(dir, BAN_LIST_FILE)
+}
+
+/// Reads the node identity kept in `identity.key` in `dir`, generating and
+/// saving one on first run. The file holds the hex secret key and is
+/// readable only by its owner.
+#[cfg(feature = "net")]
+pub fn load_or_create_identity(dir: &Path) -> io::Result<NodeIdentity> {
+    let path = dir.join(IDENTITY_FILE);
+    match fs::read_to_string(&path) {
+        Ok(encoded) => {
+            let mut seed = Zeroizing::new([0; 32]);
+            hex::decode_to_slice(encoded.trim(), seed.as_mut_slice())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "stored node identity is corrupt"))?;
+            return Ok(NodeIdentity::from_key(SigningKey::from_bytes(&seed)));
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    let identity = NodeIdentity::generate()?;
+    let encoded = Zeroizing::new(hex::encode(identity.key().to_bytes()));
+    let temporary = dir.join(format!("{}.tmp", IDENTITY_FILE));
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&temporary)?.write_all(encoded.as_bytes())?;
+    fs::rename(&temporary, path)?;
+    Ok(identity)
+}
+
+fn read_json<T: DeserializeOwned + Default>(dir: &Path, file: &str) -> io::Result<T> {
+    match fs::read(dir.join(file)) {
+        Ok(encoded) => {
+            serde_json::from_slice(&encoded).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_json<T: Serialize + ?Sized>(dir: &Path, file: &str, value: &T) -> io::Result<()> {
+    let encoded = serde_json::to_vec(value).map_err(io::Error::other)?;
+    write_file(dir, file, &encoded)
+}
+
+fn write_file(dir: &Path, file: &str, contents: &[u8]) -> io::Result<()> {
+    let temporary = dir.join(format!("{}.tmp", file));
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, dir.join(file))
+}
+
+fn write_record(records: &mut Vec<u8>, block: &Block) -> io::Result<()> {
+    let mut encoded = Vec::new();
+    codec::encode_block(&mut encoded, block)?;
+    let len = u32::try_from(encoded.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "block too large"))?;
+    records.extend_from_slice(&len.to_le_bytes());
+    records.extend_from_slice(&Sha256::digest(&encoded));
+    records.extend_from_slice(&encoded);
+    Ok(())
+}
+
+/// The blocks of the records in `encoded` before the first damaged one,
+/// and how many bytes were left from there on.
+fn read_records(mut encoded: &[u8]) -> (Vec<Block>, usize) {
+    let mut blocks = Vec::new();
+    while let Some((block, rest)) = read_record(encoded) {
+        blocks.push(block);
+        encoded = rest;
+    }
+    (blocks, encoded.len())
+}
+
+fn read_record(encoded: &[u8]) -> Option<(Block, &[u8])> {
+    let (len, rest) = encoded.split_first_chunk::<4>()?;
+    let (checksum, rest) = rest.split_first_chunk::<32>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if len > rest.len() {
+        return None;
+    }
+    let (record, rest) = rest.split_at(len);
+    if Sha256::digest(record).as_slice() != checksum {
+        return None;
+    }
+    let block = codec::decode_block(&mut &record[..]).ok()?;
+    Some((block, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::accounts::{account, payment};
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_chain(dir.path(), ChainParams::default()).unwrap().is_none());
+
+        let mut blockchain = Blockchain::new();
+        blockchain.add_block("First block data".to_owned());
+        save_chain(dir.path(), &blockchain).unwrap();
+
+        let loaded = load_chain(dir.path(), ChainParams::default()).unwrap().unwrap();
+        assert_eq!(loaded.latest_block(), blockchain.latest_block());
+        let testnet = ChainParams::for_network(Network::Testnet);
+        assert_eq!(load_chain(dir.path(), testnet).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_damaged_chain_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut blockchain = Blockchain::with_params(ChainParams::testing());
+        for i in 1..=3 {
+            blockchain.add_block(format!("Block {}", i));
+        }
+        save_chain(dir.path(), &blockchain).unwrap();
+        let path = dir.path().join(CHAIN_FILE);
+        let intact = fs::read(&path).unwrap();
+
+        // A write cut short loses only the last block.
+        fs::write(&path, &intact[..intact.len() - 10]).unwrap();
+        let loaded = load_chain(dir.path(), ChainParams::testing()).unwrap().unwrap();
+        assert_eq!(loaded.latest_block(), blockchain.get_block_by_index(2).unwrap());
+        let (stored, unreadable) = read_records(&fs::read(&path).unwrap());
+        assert_eq!((stored.len(), unreadable), (3, 0));
+
+        // A flipped bit fails its record's checksum, and everything after it
+        // goes too.
+        let mut corrupt = intact.clone();
+        let second = read_records(&intact).0[0].size_bytes() + 36;
+        corrupt[second + 40] ^= 1;
+        fs::write(&path, &corrupt).unwrap();
+        let loaded = load_chain(dir.path(), ChainParams::testing()).unwrap().unwrap();
+        assert_eq!(loaded.latest_block().header.index, 0);
+
+        fs::write(&path, b"garbage").unwrap();
+        let loaded = load_chain(dir.path(), ChainParams::testing()).unwrap().unwrap();
+        assert_eq!(loaded.latest_block().header.index, 0);
+        assert!(loaded.is_valid_chain());
+    }
+
+    #[test]
+    fn test_pruned_chain_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = ChainParams {
+            keep_bodies: Some(2),
+            ..ChainParams::testing()
+        };
+        let mut blockchain = Blockchain::with_params(params.clone());
+        for i in 1..=4 {
+            blockchain.add_block(format!("Block {}", i));
+        }
+        save_chain(dir.path(), &blockchain).unwrap();
+
+        let (stored, _) = read_records(&fs::read(dir.path().join(CHAIN_FILE)).unwrap());
+        assert_eq!(stored.len(), 2);
+        let loaded = load_chain(dir.path(), params.clone()).unwrap().unwrap();
+        assert_eq!(loaded.latest_block(), blockchain.latest_block());
+        assert_eq!(loaded.base_height(), 3);
+        assert!(loaded.headers().eq(blockchain.headers()));
+
+        // A crash after the base moved on but before the blocks were saved.
+        let mut ahead = blockchain;
+        ahead.add_block("Block 5".to_owned());
+        let pruned = PrunedBase {
+            headers: ahead.pruned_headers().to_vec(),
+            base: ahead.base_snapshot(),
+        };
+        write_json(dir.path(), PRUNED_FILE, &pruned).unwrap();
+        assert_eq!(load_chain(dir.path(), params).unwrap().unwrap().base_height(), 4);
+    }
+
+    #[test]
+    fn test_transaction_index_survives_pruning_and_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = ChainParams {
+            keep_bodies: Some(2),
+            ..ChainParams::testing()
+        };
+        let mut blockchain = Blockchain::with_params(params.clone());
+        let payment = payment("alice", "bob", 5, 1);
+        assert!(blockchain.add_transaction(payment.clone()));
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+        for i in 2..=4 {
+            blockchain.add_block(format!("Block {}", i));
+        }
+        assert!(blockchain.is_pruned(1));
+        save_chain(dir.path(), &blockchain).unwrap();
+
+        let loaded = load_chain(dir.path(), params.clone()).unwrap().unwrap();
+        assert_eq!(loaded.tx_index(), blockchain.tx_index());
+        assert_eq!(loaded.receipt(&payment.id()).unwrap().height, 1);
+        assert_eq!(loaded.history(&account("bob")), [payment.id()]);
+
+        // An index saved at another tip is rebuilt from the blocks held.
+        let stale = StoredIndex {
+            tip: "0".repeat(64),
+            index: blockchain.tx_index().clone(),
+        };
+        write_json(dir.path(), TX_INDEX_FILE, &stale).unwrap();
+        let loaded = load_chain(dir.path(), params).unwrap().unwrap();
+        assert!(loaded.receipt(&payment.id()).is_none());
+    }
+
+    #[test]
+    fn test_peer_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_address_book(dir.path()).unwrap().is_empty());
+
+        let mut book = AddressBook::default();
+        book.mark_seen("127.0.0.1:7000", 1_700_000_000);
+        book.penalize("127.0.0.1:7001", 20);
+        save_address_book(dir.path(), &book).unwrap();
+        assert_eq!(load_address_book(dir.path()).unwrap(), book);
+
+        let mut bans = BanList::default();
+        bans.ban("mallory".to_owned(), 1_700_000_000);
+        save_ban_list(dir.path(), &bans).unwrap();
+        assert_eq!(load_ban_list(dir.path()).unwrap(), bans);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_identity_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = load_or_create_identity(dir.path()).unwrap();
+        assert_eq!(load_or_create_identity(dir.path()).unwrap().id(), identity.id());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.path().join(IDENTITY_FILE)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::write(dir.path().join(IDENTITY_FILE), "not hex").unwrap();
+        assert_eq!(load_or_create_identity(dir.path()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,348 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::Add;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Bytes in a block hash, and so in a target.
+const TARGET_LEN: usize = 32;
+
+/// Each retarget moves the difficulty by this many parts of the parent's.
+const ADJUSTMENT_PARTS: i64 = 2048;
+
+/// Most parts one slow block can ease the difficulty by.
+const MAX_EASING: i64 = 99;
+
+/// A proof-of-work target: a block is mined once its hash, read as a
+/// 256-bit big-endian number, is below the target. Headers carry it in
+/// the compact `bits` form: an exponent byte and a three-byte mantissa.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target([u8; TARGET_LEN]);
+
+impl Target {
+    /// The easiest target, met by every hash but the largest.
+    pub const MAX: Target = Target([0xff; TARGET_LEN]);
+
+    /// The target met by hashes with at least `zeros` leading zero hex
+    /// digits, the unit difficulty used to be counted in.
+    pub fn from_leading_zeros(zeros: usize) -> Self {
+        if zeros == 0 {
+            return Target::MAX;
+        }
+        let mut bytes = [0; TARGET_LEN];
+        let bits = TARGET_LEN * 8 - 4 * zeros.min(TARGET_LEN * 2);
+        // 2^bits, so exactly the hashes below it have the zeros required.
+        bytes[TARGET_LEN - 1 - bits / 8] = 1 << (bits % 8);
+        Target(bytes)
+    }
+
+    /// Decodes compact bits. The mantissa's sign bit is ignored, and
+    /// targets past the largest value saturate to `Target::MAX`.
+    pub fn from_compact(bits: u32) -> Self {
+        let exponent = (bits >> 24) as usize;
+        let mantissa = bits & 0x007f_ffff;
+        let mut bytes = [0; TARGET_LEN];
+        for (i, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+            // Byte `i` of the mantissa lands `exponent - 1 - i` bytes above
+            // the least significant one.
+            let Some(position) = exponent.checked_sub(1 + i) else { break };
+            if *byte == 0 {
+                continue;
+            }
+            if position >= TARGET_LEN {
+                return Target::MAX;
+            }
+            bytes[TARGET_LEN - 1 - position] = *byte;
+        }
+        Target(bytes)
+    }
+
+    /// Encodes the target as compact bits, rounding down to the three most
+    /// significant bytes.
+    pub fn to_compact(&self) -> u32 {
+        let Some(first) = self.0.iter().position(|&byte| byte != 0) else {
+            return 0;
+        };
+        let mut size = TARGET_LEN - first;
+        let mut mantissa = self.0[first..].iter().take(3).fold(0u32, |acc, &byte| acc << 8 | byte as u32);
+        if size < 3 {
+            mantissa <<= 8 * (3 - size);
+        }
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        (size as u32) << 24 | mantissa
+    }
+
+    /// Whether a hash with this digest meets the target.
+    pub fn is_met_by(&self, digest: &[u8]) -> bool {
+        digest.len() == TARGET_LEN && digest < &self.0[..]
+    }
+
+    /// The target multiplied by `numerator / denominator`, saturating at
+    /// `Target::MAX`. `Retarget` scales each block's target this way.
+    pub fn scaled(&self, numerator: u64, denominator: u64) -> Self {
+        // Room for the product before it is divided back down.
+        let mut wide = [0u8; TARGET_LEN + 8];
+        wide[8..].copy_from_slice(&self.0);
+        let mut carry: u128 = 0;
+        for byte in wide.iter_mut().rev() {
+            carry += *byte as u128 * numerator as u128;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        let denominator = denominator.max(1) as u128;
+        let mut remainder: u128 = 0;
+        for byte in wide.iter_mut() {
+            remainder = remainder << 8 | *byte as u128;
+            *byte = (remainder / denominator) as u8;
+            remainder %= denominator;
+        }
+        if wide[..8].iter().any(|&byte| byte != 0) {
+            return Target::MAX;
+        }
+        Target(wide[8..].try_into().expect("slice is 32 bytes"))
+    }
+
+    /// How many times harder this target is to meet than `Target::MAX`:
+    /// roughly the hashes it takes to find a block.
+    pub fn difficulty(&self) -> f64 {
+        as_f64(&Target::MAX.0) / as_f64(&self.0).max(1.0)
+    }
+
+    /// The expected number of hashes to meet the target, 2^256 divided by
+    /// one more than it, exactly as forks are weighed against each other.
+    pub fn work(&self) -> Work {
+        let one = Work::from(1);
+        if *self == Target::MAX {
+            return one;
+        }
+        // 2^256 / (t + 1) is (2^256 - t - 1) / (t + 1) + 1, which fits.
+        let divisor = add_be(&self.0, &one.0).expect("target is below the maximum");
+        let numerator = self.0.map(|byte| !byte);
+        let mut quotient = [0; TARGET_LEN];
+        let mut remainder = [0; TARGET_LEN];
+        for bit in 0..TARGET_LEN * 8 {
+            let carry = shift_left_be(&mut remainder, numerator[bit / 8] >> (7 - bit % 8) & 1);
+            if carry || remainder >= divisor {
+                sub_be(&mut remainder, &divisor);
+                quotient[bit / 8] |= 1 << (7 - bit % 8);
+            }
+        }
+        Work(quotient) + one
+    }
+}
+
+/// Proof of work done, summed over blocks to weigh one fork against
+/// another. Saturates rather than wrapping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Work([u8; TARGET_LEN]);
+
+impl From<u64> for Work {
+    fn from(value: u64) -> Self {
+        let mut bytes = [0; TARGET_LEN];
+        bytes[TARGET_LEN - 8..].copy_from_slice(&value.to_be_bytes());
+        Work(bytes)
+    }
+}
+
+impl Add for Work {
+    type Output = Work;
+
+    fn add(self, other: Work) -> Work {
+        add_be(&self.0, &other.0).map_or(Work([0xff; TARGET_LEN]), Work)
+    }
+}
+
+impl Sum for Work {
+    fn sum<I: Iterator<Item = Work>>(iter: I) -> Self {
+        iter.fold(Work::default(), Add::add)
+    }
+}
+
+impl fmt::Display for Work {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+/// Big-endian `a + b`, or `None` if it overflows.
+fn add_be(a: &[u8; TARGET_LEN], b: &[u8; TARGET_LEN]) -> Option<[u8; TARGET_LEN]> {
+    let mut sum = [0; TARGET_LEN];
+    let mut carry = 0u16;
+    for i in (0..TARGET_LEN).rev() {
+        carry += a[i] as u16 + b[i] as u16;
+        sum[i] = carry as u8;
+        carry >>= 8;
+    }
+    (carry == 0).then_some(sum)
+}
+
+/// Big-endian `a -= b`, wrapping.
+fn sub_be(a: &mut [u8; TARGET_LEN], b: &[u8; TARGET_LEN]) {
+    let mut borrow = 0i16;
+    for i in (0..TARGET_LEN).rev() {
+        let difference = a[i] as i16 - b[i] as i16 - borrow;
+        borrow = (difference < 0) as i16;
+        a[i] = difference.rem_euclid(256) as u8;
+    }
+}
+
+/// Shifts `a` left by one bit, bringing in `bit`. Returns the bit shifted
+/// out.
+fn shift_left_be(a: &mut [u8; TARGET_LEN], bit: u8) -> bool {
+    let mut carry = bit;
+    for byte in a.iter_mut().rev() {
+        let out = *byte >> 7;
+        *byte = *byte << 1 | carry;
+        carry = out;
+    }
+    carry == 1
+}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Lower targets are harder to meet.
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl Serialize for Target {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut bytes = [0; TARGET_LEN];
+        let encoded = String::deserialize(deserializer)?;
+        hex::decode_to_slice(encoded, &mut bytes).map_err(serde::de::Error::custom)?;
+        Ok(Target(bytes))
+    }
+}
+
+/// Per-block difficulty adjustment toward a block time, after Ethereum's
+/// Homestead rule: a block's target is its parent's, made 1/2048 harder if
+/// it was stamped within `block_time` of the parent, and 1/2048 easier for
+/// each further `block_time` it took, by at most 99/2048.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retarget {
+    /// Seconds a block should take.
+    pub block_time: i64,
+}
+
+impl Retarget {
+    /// The target for a block stamped `elapsed` seconds after a parent
+    /// mined at `parent`, never easier than `limit`. Rounded to what
+    /// compact bits can carry, so headers can be checked against it.
+    pub fn next(&self, parent: Target, elapsed: i64, limit: Target) -> Target {
+        let steps = (1 - elapsed / self.block_time.max(1)).max(-MAX_EASING);
+        let target = parent.scaled(ADJUSTMENT_PARTS as u64, (ADJUSTMENT_PARTS + steps) as u64);
+        Target::from_compact(target.min(limit).to_compact())
+    }
+}
+
+fn as_f64(bytes: &[u8]) -> f64 {
+    bytes.iter().fold(0.0, |acc, &byte| acc * 256.0 + byte as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leading_zeros_match_hex_prefixes() {
+        let target = Target::from_leading_zeros(3);
+        assert!(target.is_met_by(&hex::decode(format!("000fff{}", "ff".repeat(29))).unwrap()));
+        assert!(!target.is_met_by(&hex::decode(format!("0010{}01", "00".repeat(29))).unwrap()));
+        assert!(!target.is_met_by(&[0; 31]));
+        assert_eq!(Target::from_leading_zeros(0), Target::MAX);
+        assert!(Target::from_leading_zeros(64).is_met_by(&[0; 32]));
+        assert!(Target::from_leading_zeros(100).is_met_by(&[0; 32]));
+    }
+
+    #[test]
+    fn test_target_itself_is_not_met() {
+        // 2^244 has only two leading zero hex digits, so three-digit
+        // difficulty must turn it away.
+        let target = Target::from_leading_zeros(3);
+        let boundary = hex::decode(format!("0010{}", "00".repeat(30))).unwrap();
+        assert_eq!(target.to_string(), hex::encode(&boundary));
+        assert!(!target.is_met_by(&boundary));
+        assert!(Target::from_leading_zeros(1).is_met_by(&hex::decode(format!("0f{}", "ff".repeat(31))).unwrap()));
+        assert!(!Target([0; TARGET_LEN]).is_met_by(&[0; 32]));
+    }
+
+    #[test]
+    fn test_retarget_steps_toward_the_block_time() {
+        let retarget = Retarget { block_time: 10 };
+        let parent = Target::from_leading_zeros(4);
+        let limit = Target::from_leading_zeros(1);
+
+        let harder = retarget.next(parent, 9, limit);
+        assert!(harder < parent);
+        assert!((harder.difficulty() / parent.difficulty() - 2049.0 / 2048.0).abs() < 1e-4);
+        let easier = retarget.next(parent, 30, limit);
+        assert!((easier.difficulty() / parent.difficulty() - 2046.0 / 2048.0).abs() < 1e-4);
+        assert_eq!(retarget.next(parent, 10, limit), Target::from_compact(parent.to_compact()));
+        assert_eq!(retarget.next(parent, 100_000, limit), retarget.next(parent, 1_000, limit));
+        assert_eq!(retarget.next(limit, 1_000, limit), Target::from_compact(limit.to_compact()));
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        for zeros in 1..=60 {
+            let target = Target::from_leading_zeros(zeros);
+            assert_eq!(Target::from_compact(target.to_compact()), target, "{} zeros", zeros);
+        }
+        assert_eq!(Target::from_leading_zeros(4).to_compact(), 0x1f01_0000);
+        assert_eq!(Target::from_compact(0x1d00_ffff).to_compact(), 0x1d00_ffff);
+        assert_eq!(Target::from_compact(0x0312_3456).to_compact(), 0x0312_3456);
+        assert_eq!(Target::from_compact(0x2200_ffff), Target::MAX);
+        assert_eq!(Target::from_compact(0), Target([0; TARGET_LEN]));
+
+        // Beyond three significant bytes, compact form rounds down.
+        let rounded = Target::from_compact(Target::MAX.to_compact());
+        assert!(rounded < Target::MAX);
+        assert_eq!(Target::from_compact(rounded.to_compact()), rounded);
+    }
+
+    #[test]
+    fn test_work_counts_expected_hashes() {
+        assert_eq!(Target::MAX.work(), Work::from(1));
+        // Just over a sixteenth of hashes are below 2^252 + 1.
+        assert_eq!(Target::from_leading_zeros(1).work(), Work::from(15));
+        assert_eq!(Target::from_leading_zeros(4).work(), Work::from(0xffff));
+        assert_eq!(Target::from_leading_zeros(1).scaled(3, 2).work(), Work::from(10));
+        assert_eq!(Target([0; TARGET_LEN]).work(), Work([0xff; TARGET_LEN]));
+        assert_eq!(Work::from(3) + Work::from(4), Work::from(7));
+        assert_eq!([Work::from(1), Work::from(2)].into_iter().sum::<Work>(), Work::from(3));
+        assert!(Target::from_leading_zeros(2).work() > Target::from_leading_zeros(1).work());
+    }
+
+    #[test]
+    fn test_scaling_adjusts_smoothly() {
+        let target = Target::from_leading_zeros(4);
+        let easier = target.scaled(3, 2);
+        assert!(easier > target);
+        assert_eq!(easier.scaled(2, 3), target);
+        assert!((target.difficulty() / easier.difficulty() - 1.5).abs() < 1e-9);
+        assert_eq!(Target::MAX.scaled(2, 1), Target::MAX);
+        assert_eq!(target.scaled(1, 1), target);
+    }
+}
@@ -0,0 +1,155 @@
+#[cfg(feature = "system-clock")]
+use std::time::{Duration, Instant};
+
+use crate::mempool::NextBlock;
+use crate::{Block, BlockBody, Blockchain, block_overhead, state_root, with_reward};
+
+/// Assembles an unmined block on a chain's tip from its pending
+/// transactions, highest fee per byte first. Packing stops at the size
+/// budget and, if one is set, the assembly deadline, so a huge mempool
+/// cannot hold a miner up.
+#[derive(Debug, Clone)]
+pub struct BlockTemplateBuilder<'a> {
+    blockchain: &'a Blockchain,
+    miner: String,
+    max_size: usize,
+    #[cfg(feature = "system-clock")]
+    deadline: Option<Duration>,
+}
+
+impl<'a> BlockTemplateBuilder<'a> {
+    /// A template paying `miner`, filled up to the chain's block limits.
+    pub fn new(blockchain: &'a Blockchain, miner: String) -> Self {
+        BlockTemplateBuilder {
+            max_size: blockchain.params.limits.max_block_size,
+            blockchain,
+            miner,
+            #[cfg(feature = "system-clock")]
+            deadline: None,
+        }
+    }
+
+    /// Caps the serialized size of the whole block. Never raises it above
+    /// the chain's limit.
+    pub fn max_size(self, bytes: usize) -> Self {
+        BlockTemplateBuilder {
+            max_size: bytes.min(self.blockchain.params.limits.max_block_size),
+            ..self
+        }
+    }
+
+    /// Stops packing once `budget` has passed since `build` started. The
+    /// transactions picked by then still make a valid block.
+    #[cfg(feature = "system-clock")]
+    pub fn deadline(self, budget: Duration) -> Self {
+        BlockTemplateBuilder {
+            deadline: Some(budget),
+            ..self
+        }
+    }
+
+    /// The block, with every header field but the nonce filled in. The
+    /// mempool is left alone.
+    pub fn build(&self) -> Block {
+        let blockchain = self.blockchain;
+        let limits = &blockchain.params.limits;
+        let reserved = block_overhead(blockchain.params.authority.is_some(), &self.miner);
+        let tip = &blockchain.latest_block().header;
+        let timestamp = blockchain.next_timestamp();
+        let next = NextBlock {
+            nonces: &blockchain.nonces,
+            height: tip.index + 1,
+            median_time_past: blockchain.median_time_past(),
+        };
+
+        #[cfg(feature = "system-clock")]
+        let deadline = self.deadline.map(|budget| Instant::now() + budget);
+        #[cfg(feature = "system-clock")]
+        let mut out_of_time = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        #[cfg(not(feature = "system-clock"))]
+        let mut out_of_time = || false;
+
+        let included = blockchain.mempool.select(
+            limits.max_transactions.saturating_sub(1),
+            self.max_size.saturating_sub(reserved),
+            &next,
+            &mut out_of_time,
+        );
+        let body = BlockBody {
+            transactions: with_reward(self.miner.clone(), included),
+            ..BlockBody::default()
+        };
+        let mut nonces = blockchain.nonces.clone();
+        // Selected in nonce order, so this cannot fail.
+        let _ = nonces.apply_block(&body.transactions);
+        let params = &blockchain.params;
+        let mut block = Block::unmined(tip.index + 1, timestamp, tip.hash.clone(), body);
+        block.header.merkle_root = block.body.merkle_root();
+        block.header.state_root = state_root(&blockchain.utxo, &nonces);
+        block.header.bits = blockchain.next_target(timestamp).to_compact();
+        block.header.nonce = params.nonce_seed;
+        block.header.extra_nonce = params.extra_nonce;
+        block.header.network = params.network;
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::accounts::{self, signed};
+    use crate::{BlockHeader, BlockLimits, ChainParams, Transaction};
+
+    fn payment(sender: &str, fee: u64) -> Transaction {
+        accounts::payment(sender, "bob", 1, fee)
+    }
+
+    #[test]
+    fn test_packs_by_fee_density_within_the_size_budget() {
+        let mut blockchain = Blockchain::with_params(ChainParams::testing());
+        for (sender, fee) in [("alice", 1), ("carol", 9), ("dave", 5)] {
+            assert!(blockchain.add_transaction(payment(sender, fee)));
+        }
+        let full = BlockTemplateBuilder::new(&blockchain, "miner".to_owned()).build();
+        assert_eq!(full.body.transactions.len(), 4);
+        assert!(blockchain.params.limits.allows(&full));
+
+        // Room for the mined block with carol's payment but not the others.
+        let mined = BlockHeader::finished_size(false) + full.body.size_bytes();
+        let one = mined - payment("alice", 1).size_bytes() - payment("dave", 5).size_bytes();
+        let template = BlockTemplateBuilder::new(&blockchain, "miner".to_owned()).max_size(one).build();
+        assert_eq!(template.body.transactions[1..], [payment("carol", 9)]);
+        assert!(template.size_bytes() <= one);
+        assert_eq!(template.header.merkle_root, template.body.merkle_root());
+        assert_eq!(blockchain.pending_transactions().len(), 3);
+    }
+
+    #[test]
+    fn test_budget_never_exceeds_the_block_limit() {
+        let limits = BlockLimits {
+            max_block_size: 400,
+            ..BlockLimits::default()
+        };
+        let mut blockchain = Blockchain::with_params(ChainParams {
+            limits,
+            ..ChainParams::testing()
+        });
+        for nonce in 0..10 {
+            let transaction = Transaction { nonce, ..payment("alice", 1) };
+            assert!(blockchain.add_transaction(signed("alice", transaction)));
+        }
+        let template = BlockTemplateBuilder::new(&blockchain, "miner".to_owned()).max_size(usize::MAX).build();
+        assert!(limits.allows(&template));
+        assert!(template.body.transactions.len() < 11);
+    }
+
+    #[cfg(feature = "system-clock")]
+    #[test]
+    fn test_spent_deadline_leaves_only_the_reward() {
+        let mut blockchain = Blockchain::with_params(ChainParams::testing());
+        assert!(blockchain.add_transaction(payment("alice", 1)));
+        let template = BlockTemplateBuilder::new(&blockchain, "miner".to_owned()).deadline(Duration::ZERO).build();
+        assert_eq!(template.body.transactions.len(), 1);
+        assert!(template.body.transactions[0].is_reward());
+    }
+}
@@ -0,0 +1,237 @@
+use ed25519_dalek::{Signature, Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+use crate::{PAYLOAD_FEE_PER_BYTE, codec};
+
+/// Sender used for the transaction that pays the miner its reward and fees.
+pub const REWARD_SENDER: &str = "";
+
+/// `lock_until` values below this are block heights; the rest are Unix
+/// timestamps.
+pub const LOCK_TIME_THRESHOLD: u64 = 500_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub sender: String,
+    pub recipient: String,
+    pub amount: u64,
+    pub fee: u64,
+    /// The sender's sequence number: its first transaction carries zero,
+    /// and each one after the next number up.
+    #[serde(default)]
+    pub nonce: u64,
+    /// The earliest block height, or with `LOCK_TIME_THRESHOLD` and above
+    /// block timestamp, the transaction may be included at. Zero is
+    /// unlocked.
+    #[serde(default)]
+    pub lock_until: u64,
+    /// Opaque application data, such as a document hash to anchor.
+    #[serde(default, with = "hex::serde", skip_serializing_if = "Vec::is_empty")]
+    pub payload: Vec<u8>,
+    /// The sender's signature over `signing_hash`, hex-encoded. The sender
+    /// is the address of the key that makes it. Empty on the reward,
+    /// which nobody signs.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+}
+
+impl Transaction {
+    pub fn new(sender: String, recipient: String, amount: u64) -> Self {
+        Self::with_fee(sender, recipient, amount, 0)
+    }
+
+    pub fn with_fee(sender: String, recipient: String, amount: u64, fee: u64) -> Self {
+        Self::with_payload(sender, recipient, amount, fee, Vec::new())
+    }
+
+    pub fn with_payload(sender: String, recipient: String, amount: u64, fee: u64, payload: Vec<u8>) -> Self {
+        Transaction {
+            sender,
+            recipient,
+            amount,
+            fee,
+            nonce: 0,
+            lock_until: 0,
+            payload,
+            signature: String::new(),
+        }
+    }
+
+    /// Transaction crediting `miner` with the block reward and collected fees.
+    pub fn reward(miner: String, amount: u64) -> Self {
+        Self::new(REWARD_SENDER.to_owned(), miner, amount)
+    }
+
+    pub fn is_reward(&self) -> bool {
+        self.sender == REWARD_SENDER
+    }
+
+    /// Hex-encoded SHA-256 of the transaction's binary encoding, signature
+    /// included, used as its identifier and as its contribution to the
+    /// enclosing block's hash.
+    pub fn id(&self) -> String {
+        codec::hash_hex(|writer| codec::encode_transaction(writer, self))
+    }
+
+    /// Hex-encoded SHA-256 of every field but the signature: the message
+    /// the sender signs.
+    pub fn signing_hash(&self) -> String {
+        codec::hash_hex(|writer| codec::encode_unsigned_transaction(writer, self))
+    }
+
+    /// Signs the transaction with `key`, whose address must be the sender.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.signature = hex::encode(key.sign(self.signing_hash().as_bytes()).to_bytes());
+    }
+
+    /// Whether the sender is a key address and signed the transaction with
+    /// that key. False for the reward.
+    pub fn is_signed_by_sender(&self) -> bool {
+        let Some(key) = self.sender.parse::<Address>().ok().and_then(|address| address.verifying_key()) else {
+            return false;
+        };
+        let Some(signature) = hex::decode(&self.signature).ok().and_then(|bytes| Signature::from_slice(&bytes).ok()) else {
+            return false;
+        };
+        key.verify_strict(self.signing_hash().as_bytes(), &signature).is_ok()
+    }
+
+    /// Whether a block at `height` with `timestamp` may include the
+    /// transaction.
+    pub fn is_mature(&self, height: u32, timestamp: i64) -> bool {
+        if self.lock_until < LOCK_TIME_THRESHOLD {
+            height as u64 >= self.lock_until
+        } else {
+            timestamp >= 0 && timestamp as u64 >= self.lock_until
+        }
+    }
+
+    /// Smallest fee the transaction may pay: its payload is charged per byte.
+    pub fn minimum_fee(&self) -> u64 {
+        self.payload.len() as u64 * PAYLOAD_FEE_PER_BYTE
+    }
+
+    /// Exact size of the transaction in the binary encoding.
+    pub fn size_bytes(&self) -> usize {
+        codec::encoded_len(|writer| codec::encode_transaction(writer, self))
+    }
+}
+
+/// Accounts named in tests, each the address of a key derived from its
+/// name.
+#[cfg(test)]
+pub(crate) mod accounts {
+    use ed25519_dalek::SigningKey;
+    use sha2::{Digest, Sha256};
+
+    use super::Transaction;
+    use crate::Network;
+
+    pub(crate) fn key(name: &str) -> SigningKey {
+        SigningKey::from_bytes(&Sha256::digest(name.as_bytes()).into())
+    }
+
+    pub(crate) fn account(name: &str) -> String {
+        crate::utxo::address(&key(name).verifying_key(), Network::Mainnet)
+    }
+
+    /// `transaction` sent and signed by `name`.
+    pub(crate) fn signed(name: &str, transaction: Transaction) -> Transaction {
+        let mut transaction = Transaction {
+            sender: account(name),
+            ..transaction
+        };
+        transaction.sign(&key(name));
+        transaction
+    }
+
+    /// `sender` paying `amount` to `recipient`, with `fee`.
+    pub(crate) fn payment(sender: &str, recipient: &str, amount: u64, fee: u64) -> Transaction {
+        signed(sender, Transaction::with_fee(String::new(), account(recipient), amount, fee))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_id_depends_on_fields() {
+        let tx = Transaction::new("alice".to_owned(), "bob".to_owned(), 10);
+        let same = Transaction::new("alice".to_owned(), "bob".to_owned(), 10);
+        let different = Transaction::new("alice".to_owned(), "bob".to_owned(), 11);
+        let with_fee = Transaction::with_fee("alice".to_owned(), "bob".to_owned(), 10, 1);
+        let later = Transaction { nonce: 1, ..tx.clone() };
+        let locked = Transaction { lock_until: 1, ..tx.clone() };
+
+        assert_eq!(tx.id(), same.id());
+        assert_ne!(tx.id(), different.id());
+        assert_ne!(tx.id(), with_fee.id());
+        assert_ne!(tx.id(), later.id());
+        assert_ne!(tx.id(), locked.id());
+        assert_eq!(tx.size_bytes(), 4 + 5 + 4 + 3 + 4 * 8 + 4 + 4);
+    }
+
+    #[test]
+    fn test_signature_proves_the_sender() {
+        use accounts::{account, key, payment};
+
+        let payment = payment("alice", "bob", 5, 1);
+        assert!(payment.is_signed_by_sender());
+        let unsigned = Transaction {
+            signature: String::new(),
+            ..payment.clone()
+        };
+        assert_eq!(unsigned.signing_hash(), payment.signing_hash());
+        assert_ne!(unsigned.id(), payment.id());
+
+        let forged = Transaction {
+            sender: account("carol"),
+            ..payment.clone()
+        };
+        assert!(!forged.is_signed_by_sender());
+        let mut resigned = forged.clone();
+        resigned.sign(&key("alice"));
+        assert!(!resigned.is_signed_by_sender());
+
+        let raised = Transaction { amount: 500, ..payment.clone() };
+        assert!(!raised.is_signed_by_sender());
+        let named = Transaction {
+            sender: "alice".to_owned(),
+            ..payment.clone()
+        };
+        assert!(!named.is_signed_by_sender());
+        assert!(!Transaction::reward(account("miner"), 50).is_signed_by_sender());
+    }
+
+    #[test]
+    fn test_lock_until_height_or_time() {
+        let tx = Transaction::new("alice".to_owned(), "bob".to_owned(), 10);
+        assert!(tx.is_mature(0, 0));
+
+        let by_height = Transaction { lock_until: 5, ..tx.clone() };
+        assert!(!by_height.is_mature(4, i64::MAX));
+        assert!(by_height.is_mature(5, 0));
+
+        let by_time = Transaction { lock_until: 1_700_000_000, ..tx };
+        assert!(!by_time.is_mature(u32::MAX, 1_699_999_999));
+        assert!(by_time.is_mature(1, 1_700_000_000));
+    }
+
+    #[test]
+    fn test_payload_is_hashed_and_charged() {
+        let plain = Transaction::with_fee("alice".to_owned(), "bob".to_owned(), 0, 4);
+        let anchored = Transaction::with_payload("alice".to_owned(), "bob".to_owned(), 0, 4, b"doc!".to_vec());
+
+        assert_ne!(plain.id(), anchored.id());
+        assert_eq!(plain.minimum_fee(), 0);
+        assert_eq!(anchored.minimum_fee(), 4 * PAYLOAD_FEE_PER_BYTE);
+        assert_eq!(anchored.size_bytes(), plain.size_bytes() + 4);
+
+        let json = serde_json::to_string(&anchored).unwrap();
+        assert!(json.contains("\"payload\":\"646f6321\""));
+        assert_eq!(serde_json::from_str::<Transaction>(&json).unwrap(), anchored);
+        assert!(!serde_json::to_string(&plain).unwrap().contains("payload"));
+    }
+}
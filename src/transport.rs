@@ -0,0 +1,400 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use zeroize::Zeroizing;
+
+use crate::crypto::random;
+use crate::network::MAX_BODIES_PER_MESSAGE;
+use crate::{MAX_BLOCK_SIZE, Network};
+
+/// The Noise protocol the handshake follows.
+const NOISE_PROTOCOL: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// Mixed into the handshake and identity signatures so they cannot be
+/// replayed in any other protocol.
+const PROTOCOL: &[u8] = b"simplz-transport-v2";
+
+/// Largest handshake message. The longest, the responder's, is 192 bytes,
+/// so a peer that has not authenticated cannot make us allocate more.
+const MAX_HANDSHAKE_LEN: usize = 256;
+
+/// Largest frame an authenticated peer may send, so a hostile one cannot
+/// make us allocate without bound: a full batch of bodies, allowing for
+/// JSON encoding up to four times a block's size.
+const MAX_FRAME_LEN: usize = MAX_BODIES_PER_MESSAGE * 4 * MAX_BLOCK_SIZE;
+
+/// Bytes of the Poly1305 tag on every sealed message.
+const TAG_LEN: usize = 16;
+
+/// Largest plaintext of one Noise transport message. Frames are sealed in
+/// pieces of at most this size.
+const MAX_CHUNK_LEN: usize = 65535 - TAG_LEN;
+
+/// Which end of a connection this is. The side that dialled starts the
+/// handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    Initiator,
+    Responder,
+}
+
+/// A node's long-lived key pair. Peers know the node by its public key,
+/// and the handshake proves the node holds the matching secret key.
+#[derive(Clone)]
+pub struct NodeIdentity {
+    key: SigningKey,
+}
+
+impl NodeIdentity {
+    /// A fresh identity from the operating system's random number generator.
+    pub fn generate() -> io::Result<Self> {
+        let seed = Zeroizing::new(random::<32>()?);
+        Ok(NodeIdentity {
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    pub fn from_key(key: SigningKey) -> Self {
+        NodeIdentity { key }
+    }
+
+    /// The node id peers list and ban this node under: its hex public key.
+    pub fn id(&self) -> String {
+        node_id(&self.key.verifying_key())
+    }
+
+    pub(crate) fn key(&self) -> &SigningKey {
+        &self.key
+    }
+}
+
+impl fmt::Debug for NodeIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeIdentity").field("id", &self.id()).finish_non_exhaustive()
+    }
+}
+
+/// The node id of the peer holding `key`.
+pub fn node_id(key: &VerifyingKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+/// Encrypts and authenticates one direction of a connection: the Noise
+/// transport state and the next nonce to use with it. Each message is
+/// sealed with ChaCha20-Poly1305 under the next nonce, so frames cannot be
+/// altered, dropped, replayed or reordered unnoticed.
+pub(crate) struct FrameCipher {
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl FrameCipher {
+    fn new(transport: Arc<StatelessTransportState>) -> Self {
+        FrameCipher { transport, nonce: 0 }
+    }
+
+    /// Sends `plaintext` as one frame: its length, then the plaintext in
+    /// pieces of at most `MAX_CHUNK_LEN` bytes, each sealed on its own.
+    pub(crate) fn write_frame(&mut self, writer: &mut impl Write, plaintext: &[u8]) -> io::Result<()> {
+        if plaintext.len() > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame is too large"));
+        }
+        let mut frame = self.encrypt(&(plaintext.len() as u32).to_be_bytes())?;
+        for chunk in plaintext.chunks(MAX_CHUNK_LEN) {
+            frame.extend(self.encrypt(chunk)?);
+        }
+        writer.write_all(&frame)?;
+        writer.flush()
+    }
+
+    /// Reads a frame. Its length is authenticated before any room is made
+    /// for it.
+    pub(crate) fn read_frame(&mut self, reader: &mut impl Read) -> io::Result<Vec<u8>> {
+        let mut header = [0; 4 + TAG_LEN];
+        reader.read_exact(&mut header)?;
+        let length = self.decrypt(&header)?;
+        let len = u32::from_be_bytes(length.try_into().map_err(|_| invalid("malformed frame length"))?) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(invalid("peer sent an oversized frame"));
+        }
+        let mut plaintext = Vec::with_capacity(len);
+        let mut sealed = vec![0; len.min(MAX_CHUNK_LEN) + TAG_LEN];
+        while plaintext.len() < len {
+            let chunk = &mut sealed[..(len - plaintext.len()).min(MAX_CHUNK_LEN) + TAG_LEN];
+            reader.read_exact(chunk)?;
+            plaintext.extend(self.decrypt(chunk)?);
+        }
+        Ok(plaintext)
+    }
+
+    fn encrypt(&mut self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let mut sealed = vec![0; plaintext.len() + TAG_LEN];
+        let len = self.transport.write_message(nonce, plaintext, &mut sealed).map_err(noise_error)?;
+        sealed.truncate(len);
+        Ok(sealed)
+    }
+
+    fn decrypt(&mut self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let mut plaintext = vec![0; sealed.len()];
+        let len = self
+            .transport
+            .read_message(nonce, sealed, &mut plaintext)
+            .map_err(|_| invalid("message failed authentication"))?;
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+
+    /// The nonce for the next message. It reserves the largest count.
+    fn next_nonce(&mut self) -> io::Result<u64> {
+        if self.nonce == u64::MAX {
+            return Err(io::Error::other("connection ran out of nonces"));
+        }
+        self.nonce += 1;
+        Ok(self.nonce - 1)
+    }
+}
+
+impl fmt::Debug for FrameCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameCipher").field("nonce", &self.nonce).finish_non_exhaustive()
+    }
+}
+
+/// Runs the Noise XX handshake over a fresh connection and returns the
+/// peer's authenticated identity key, with the ciphers for reading from
+/// and writing to it.
+///
+/// The prologue binds the handshake to this protocol and `network`, so a
+/// peer on another network fails it. Both sides' static Noise keys are made
+/// for the connection; the handshake payloads carry each node's identity
+/// key and its signature over its static key, as in libp2p's Noise
+/// handshake, so a peer that cannot sign for the identity it claims is
+/// refused. Handshake messages are at most `MAX_HANDSHAKE_LEN` bytes.
+pub(crate) fn handshake(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    identity: &NodeIdentity,
+    network: Network,
+    role: Role,
+) -> io::Result<(VerifyingKey, FrameCipher, FrameCipher)> {
+    let builder = Builder::new(NOISE_PROTOCOL.parse().map_err(noise_error)?);
+    let static_key = builder.generate_keypair().map_err(noise_error)?;
+    let secret = Zeroizing::new(static_key.private);
+    let prologue = [PROTOCOL, &network.id().to_be_bytes()].concat();
+    let builder = builder.local_private_key(&secret).prologue(&prologue);
+    let mut noise = match role {
+        Role::Initiator => builder.build_initiator(),
+        Role::Responder => builder.build_responder(),
+    }
+    .map_err(noise_error)?;
+    let proof = identity_proof(identity, &static_key.public);
+
+    let remote_identity = match role {
+        Role::Initiator => {
+            // -> e
+            send(&mut noise, writer, &[])?;
+            // <- e, ee, s, es
+            let remote_identity = verify_identity(&receive(&mut noise, reader)?, &remote_static(&noise)?)?;
+            // -> s, se
+            send(&mut noise, writer, &proof)?;
+            remote_identity
+        }
+        Role::Responder => {
+            // -> e
+            receive(&mut noise, reader)?;
+            // <- e, ee, s, es
+            send(&mut noise, writer, &proof)?;
+            // -> s, se
+            verify_identity(&receive(&mut noise, reader)?, &remote_static(&noise)?)?
+        }
+    };
+
+    let transport = Arc::new(noise.into_stateless_transport_mode().map_err(noise_error)?);
+    Ok((remote_identity, FrameCipher::new(transport.clone()), FrameCipher::new(transport)))
+}
+
+/// Writes the next handshake message, carrying `payload`.
+fn send(noise: &mut HandshakeState, writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let mut message = [0; MAX_HANDSHAKE_LEN];
+    let len = noise.write_message(payload, &mut message).map_err(noise_error)?;
+    write_message(writer, &message[..len])
+}
+
+/// Reads the next handshake message and returns its payload.
+fn receive(noise: &mut HandshakeState, reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let message = read_message(reader)?;
+    let mut payload = vec![0; message.len()];
+    let len = noise
+        .read_message(&message, &mut payload)
+        .map_err(|_| invalid("handshake failed: the peer is on another network or is not a simplz node"))?;
+    payload.truncate(len);
+    Ok(payload)
+}
+
+fn remote_static(noise: &HandshakeState) -> io::Result<[u8; 32]> {
+    to_key(noise.get_remote_static().unwrap_or_default())
+}
+
+/// The node's identity key and its signature over the connection's static
+/// Noise key.
+fn identity_proof(identity: &NodeIdentity, static_key: &[u8]) -> Vec<u8> {
+    let signature = identity.key().sign(&[PROTOCOL, static_key].concat());
+    [identity.key().verifying_key().as_bytes().as_slice(), &signature.to_bytes()].concat()
+}
+
+fn verify_identity(proof: &[u8], static_key: &[u8; 32]) -> io::Result<VerifyingKey> {
+    let Some((key, signature)) = proof.split_first_chunk::<32>() else {
+        return Err(invalid("peer sent no identity"));
+    };
+    let key = VerifyingKey::from_bytes(key).map_err(|_| invalid("peer sent an invalid identity"))?;
+    let signature = Signature::from_slice(signature).map_err(|_| invalid("peer sent an invalid signature"))?;
+    key.verify(&[PROTOCOL, static_key].concat(), &signature)
+        .map_err(|_| invalid("peer could not prove its identity"))?;
+    Ok(key)
+}
+
+fn write_message(writer: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    writer.write_all(&(message.len() as u16).to_be_bytes())?;
+    writer.write_all(message)?;
+    writer.flush()
+}
+
+fn read_message(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0; 2];
+    reader.read_exact(&mut length)?;
+    let len = u16::from_be_bytes(length) as usize;
+    if len > MAX_HANDSHAKE_LEN {
+        return Err(invalid("peer sent an oversized handshake message"));
+    }
+    let mut message = vec![0; len];
+    reader.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn to_key(bytes: &[u8]) -> io::Result<[u8; 32]> {
+    bytes.try_into().map_err(|_| invalid("peer sent a malformed key"))
+}
+
+fn noise_error(err: snow::Error) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::*;
+
+    fn identity(seed: u8) -> NodeIdentity {
+        NodeIdentity::from_key(SigningKey::from_bytes(&[seed; 32]))
+    }
+
+    /// The two ends of an unauthenticated Noise session, run in memory.
+    fn session() -> (Arc<StatelessTransportState>, Arc<StatelessTransportState>) {
+        let params: snow::params::NoiseParams = "Noise_NN_25519_ChaChaPoly_SHA256".parse().unwrap();
+        let mut initiator = Builder::new(params.clone()).build_initiator().unwrap();
+        let mut responder = Builder::new(params).build_responder().unwrap();
+        let mut message = [0; MAX_HANDSHAKE_LEN];
+        let len = initiator.write_message(&[], &mut message).unwrap();
+        responder.read_message(&message[..len], &mut []).unwrap();
+        let len = responder.write_message(&[], &mut message).unwrap();
+        initiator.read_message(&message[..len], &mut []).unwrap();
+        (
+            Arc::new(initiator.into_stateless_transport_mode().unwrap()),
+            Arc::new(responder.into_stateless_transport_mode().unwrap()),
+        )
+    }
+
+    /// The responder's node id for the initiator, and the one frame it read.
+    type Server = thread::JoinHandle<io::Result<(String, Vec<u8>)>>;
+
+    /// Connects to a responder on `network` that reads one frame once the
+    /// handshake is done.
+    fn connect(network: Network) -> (TcpStream, Server) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let (remote, mut receiving, _) = handshake(&mut stream, &mut writer, &identity(1), network, Role::Responder)?;
+            Ok((node_id(&remote), receiving.read_frame(&mut stream)?))
+        });
+        (TcpStream::connect(address).unwrap(), server)
+    }
+
+    #[test]
+    fn test_frames_round_trip_and_detect_tampering() {
+        let (initiator, responder) = session();
+        let long = vec![7; 3 * MAX_CHUNK_LEN + 10];
+        let mut sender = FrameCipher::new(initiator);
+        let mut wire = Vec::new();
+        sender.write_frame(&mut wire, b"first").unwrap();
+        sender.write_frame(&mut wire, &long).unwrap();
+        assert!(!wire.windows(5).any(|window| window == b"first"));
+
+        let mut receiver = FrameCipher::new(responder.clone());
+        let mut reader = wire.as_slice();
+        assert_eq!(receiver.read_frame(&mut reader).unwrap(), b"first");
+        assert_eq!(receiver.read_frame(&mut reader).unwrap(), long);
+
+        // Replaying the first frame out of sequence fails, as does any
+        // flipped bit.
+        let mut replayed = wire.as_slice();
+        assert_eq!(receiver.read_frame(&mut replayed).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        wire[5] ^= 1;
+        let mut tampered = FrameCipher::new(responder);
+        assert_eq!(tampered.read_frame(&mut wire.as_slice()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_oversized_frames_are_refused_before_reading_them() {
+        let (initiator, responder) = session();
+        let mut sender = FrameCipher::new(initiator);
+        let header = sender.encrypt(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes()).unwrap();
+        let mut receiver = FrameCipher::new(responder);
+        assert_eq!(receiver.read_frame(&mut header.as_slice()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_handshake_authenticates_both_sides() {
+        let (mut stream, server) = connect(Network::Mainnet);
+        let mut writer = stream.try_clone().unwrap();
+        let (remote, _, mut sending) =
+            handshake(&mut stream, &mut writer, &identity(2), Network::Mainnet, Role::Initiator).unwrap();
+        assert_eq!(node_id(&remote), identity(1).id());
+        sending.write_frame(&mut writer, b"hello").unwrap();
+
+        let (remote, message) = server.join().unwrap().unwrap();
+        assert_eq!(remote, identity(2).id());
+        assert_eq!(message, b"hello");
+    }
+
+    #[test]
+    fn test_handshake_fails_across_networks() {
+        let (mut stream, server) = connect(Network::Testnet);
+        let mut writer = stream.try_clone().unwrap();
+        let error = handshake(&mut stream, &mut writer, &identity(2), Network::Mainnet, Role::Initiator).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        drop((stream, writer));
+        assert!(server.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_oversized_handshake_messages_are_refused() {
+        let mut message = ((MAX_HANDSHAKE_LEN + 1) as u16).to_be_bytes().to_vec();
+        message.resize(2 + MAX_HANDSHAKE_LEN + 1, 0);
+        let error = handshake(&mut message.as_slice(), &mut Vec::new(), &identity(1), Network::Mainnet, Role::Responder)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Block;
+use crate::utxo::BlockUndo;
+
+/// Which of a block's transaction lists a receipt points into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Account,
+    Utxo,
+}
+
+/// Where a mined transaction sits in the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub id: String,
+    pub block_hash: String,
+    pub height: u32,
+    pub kind: TransactionKind,
+    /// Index into the block's list of `kind` transactions.
+    pub position: usize,
+}
+
+/// Lookups from transaction id to where it was mined and from address to
+/// the transactions that touched it, kept in step with the chain as blocks
+/// are connected and disconnected.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxIndex {
+    /// Every place an id was mined, oldest first. Identical transactions,
+    /// such as equal rewards, share an id.
+    receipts: BTreeMap<String, Vec<Receipt>>,
+    /// Ids of the transactions that paid or spent from each address, in
+    /// chain order.
+    history: BTreeMap<String, Vec<String>>,
+}
+
+impl TxIndex {
+    /// The most recent place the transaction with `id` was mined.
+    pub fn receipt(&self, id: &str) -> Option<&Receipt> {
+        self.receipts.get(id)?.last()
+    }
+
+    /// Every place the transaction with `id` was mined, oldest first.
+    pub fn receipts(&self, id: &str) -> &[Receipt] {
+        self.receipts.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Ids of the transactions that paid or spent from `address`, oldest
+    /// first.
+    pub fn history(&self, address: &str) -> &[String] {
+        self.history.get(address).map_or(&[], Vec::as_slice)
+    }
+
+    /// Indexes `block`, appended to the chain with `undo` holding the
+    /// outputs it spent.
+    pub fn connect(&mut self, block: &Block, undo: &BlockUndo) {
+        for (id, receipt, addresses) in entries(block, undo) {
+            self.receipts.entry(id.clone()).or_default().push(receipt);
+            for address in addresses {
+                self.history.entry(address).or_default().push(id.clone());
+            }
+        }
+    }
+
+    /// Drops what `connect` indexed for `block`, which must be the latest
+    /// block still indexed.
+    pub fn disconnect(&mut self, block: &Block, undo: &BlockUndo) {
+        for (id, _, addresses) in entries(block, undo).into_iter().rev() {
+            if let Some(receipts) = self.receipts.get_mut(&id) {
+                receipts.pop();
+                if receipts.is_empty() {
+                    self.receipts.remove(&id);
+                }
+            }
+            for address in addresses {
+                let Some(ids) = self.history.get_mut(&address) else { continue };
+                if let Some(last) = ids.iter().rposition(|held| *held == id) {
+                    ids.remove(last);
+                }
+                if ids.is_empty() {
+                    self.history.remove(&address);
+                }
+            }
+        }
+    }
+}
+
+/// Each transaction in `block` with its receipt and the distinct addresses
+/// it touched, in block order.
+pub(crate) fn entries(block: &Block, undo: &BlockUndo) -> Vec<(String, Receipt, Vec<String>)> {
+    let receipt = |id: &str, kind, position| Receipt {
+        id: id.to_owned(),
+        block_hash: block.header.hash.clone(),
+        height: block.header.index,
+        kind,
+        position,
+    };
+    let mut entries = Vec::new();
+    for (position, transaction) in block.body.transactions.iter().enumerate() {
+        let id = transaction.id();
+        let mut addresses = Vec::new();
+        if !transaction.is_reward() {
+            addresses.push(transaction.sender.clone());
+        }
+        addresses.push(transaction.recipient.clone());
+        addresses.dedup();
+        entries.push((id.clone(), receipt(&id, TransactionKind::Account, position), addresses));
+    }
+    for (position, transaction) in block.body.utxo_transactions.iter().enumerate() {
+        let id = transaction.id();
+        let mut addresses: Vec<String> = Vec::new();
+        let spent = transaction.inputs.iter().filter_map(|input| {
+            undo.iter()
+                .find(|(outpoint, _)| *outpoint == input.outpoint)
+                .map(|(_, output)| &output.owner)
+        });
+        for owner in spent.chain(transaction.outputs.iter().map(|output| &output.owner)) {
+            if !addresses.contains(owner) {
+                addresses.push(owner.clone());
+            }
+        }
+        entries.push((id.clone(), receipt(&id, TransactionKind::Utxo, position), addresses));
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utxo::{OutPoint, TxOutput, UtxoTransaction};
+    use crate::{BlockBody, Transaction};
+
+    fn block(index: u32, body: BlockBody) -> Block {
+        let mut block = Block::unmined(index, 0, String::new(), body);
+        block.header.hash = format!("{:064}", index);
+        block
+    }
+
+    #[test]
+    fn test_connect_and_disconnect() {
+        let payment = Transaction::new("alice".to_owned(), "bob".to_owned(), 5);
+        let coinbase = UtxoTransaction::coinbase("carol".to_owned(), 50);
+        let first = block(
+            1,
+            BlockBody {
+                transactions: vec![Transaction::reward("miner".to_owned(), 50), payment.clone()],
+                utxo_transactions: vec![coinbase.clone()],
+                ..BlockBody::default()
+            },
+        );
+        let outpoint = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        let spend = UtxoTransaction::new(vec![outpoint.clone()], vec![TxOutput::new(50, "dave".to_owned())]);
+        let second = block(
+            2,
+            BlockBody {
+                transactions: vec![payment.clone()],
+                utxo_transactions: vec![UtxoTransaction::coinbase("dave".to_owned(), 50), spend.clone()],
+                ..BlockBody::default()
+            },
+        );
+        let second_undo = vec![(outpoint, TxOutput::new(50, "carol".to_owned()))];
+
+        let mut index = TxIndex::default();
+        index.connect(&first, &BlockUndo::new());
+        index.connect(&second, &second_undo);
+
+        let receipt = index.receipt(&spend.id()).unwrap();
+        assert_eq!((receipt.height, receipt.kind, receipt.position), (2, TransactionKind::Utxo, 1));
+        assert_eq!(index.receipt(&payment.id()).unwrap().height, 2);
+        assert_eq!(index.receipts(&payment.id()).iter().map(|receipt| receipt.height).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(index.history("carol"), [coinbase.id(), spend.id()]);
+        assert_eq!(index.history("bob"), [payment.id(), payment.id()]);
+        assert_eq!(index.history("dave").len(), 2);
+        assert!(index.history("").is_empty());
+
+        index.disconnect(&second, &second_undo);
+        assert!(index.receipt(&spend.id()).is_none());
+        assert_eq!(index.receipt(&payment.id()).unwrap().height, 1);
+        assert_eq!(index.history("carol"), [coinbase.id()]);
+        assert_eq!(index.history("bob"), [payment.id()]);
+        assert!(index.history("dave").is_empty());
+    }
+}
@@ -0,0 +1,668 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{BLOCK_REWARD, codec};
+use crate::address::{Address, Network};
+use crate::script::{self, Context, Script, ScriptError};
+
+/// Reference to a single output of an earlier transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub txid: String,
+    pub vout: u32,
+}
+
+/// An amount locked to the owner's key address or, if `lock` is not empty,
+/// to whoever satisfies that script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxOutput {
+    pub value: u64,
+    pub owner: String,
+    #[serde(default, skip_serializing_if = "Script::is_empty")]
+    pub lock: Script,
+}
+
+impl TxOutput {
+    pub fn new(value: u64, owner: String) -> Self {
+        TxOutput {
+            value,
+            owner,
+            lock: Script::default(),
+        }
+    }
+
+    /// An output spendable by satisfying `lock`, owned by its address on
+    /// `network`.
+    pub fn locked(value: u64, lock: Script, network: Network) -> Self {
+        TxOutput {
+            value,
+            owner: lock.address(network),
+            lock,
+        }
+    }
+}
+
+/// Spends an output: with `signature` for a key-owned output, or with the
+/// data `unlock` pushes for a script-locked one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxInput {
+    pub outpoint: OutPoint,
+    pub signature: String,
+    #[serde(default, skip_serializing_if = "Script::is_empty")]
+    pub unlock: Script,
+}
+
+/// A transaction that spends earlier outputs and creates new ones. A
+/// transaction without inputs is a coinbase.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoTransaction {
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+}
+
+impl UtxoTransaction {
+    pub fn new(outpoints: Vec<OutPoint>, outputs: Vec<TxOutput>) -> Self {
+        let inputs = outpoints
+            .into_iter()
+            .map(|outpoint| TxInput {
+                outpoint,
+                signature: String::new(),
+                unlock: Script::default(),
+            })
+            .collect();
+        UtxoTransaction { inputs, outputs }
+    }
+
+    pub fn coinbase(owner: String, value: u64) -> Self {
+        UtxoTransaction {
+            inputs: Vec::new(),
+            outputs: vec![TxOutput::new(value, owner)],
+        }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Hex-encoded SHA-256 of the spent outpoints and created outputs.
+    /// Signatures and unlocking scripts are excluded so the id is also the
+    /// message each input signs.
+    pub fn id(&self) -> String {
+        codec::hash_hex(|writer| codec::encode_unsigned_utxo_transaction(writer, self))
+    }
+
+    /// `key`'s signature over the transaction, for use in unlocking scripts.
+    pub fn signature(&self, key: &SigningKey) -> Vec<u8> {
+        key.sign(self.id().as_bytes()).to_bytes().to_vec()
+    }
+
+    /// Signs every input with `key`. All spent outputs must belong to it.
+    pub fn sign(&mut self, key: &SigningKey) {
+        let signature = hex::encode(self.signature(key));
+        for input in &mut self.inputs {
+            input.signature = signature.clone();
+        }
+    }
+
+    /// Exact size of the transaction in the binary encoding.
+    pub fn size_bytes(&self) -> usize {
+        codec::encoded_len(|writer| codec::encode_utxo_transaction(writer, self))
+    }
+
+    /// Bytes of the encoding that only prove the spends: each input's
+    /// signature and unlocking script.
+    pub fn witness_bytes(&self) -> usize {
+        self.inputs
+            .iter()
+            .map(|input| input.signature.len() + input.unlock.to_bytes().len())
+            .sum()
+    }
+}
+
+/// Address on `network` that outputs are locked to.
+pub fn address(key: &VerifyingKey, network: Network) -> String {
+    Address::from_key(key, network).to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UtxoError {
+    MissingOutput(OutPoint),
+    DoubleSpend(OutPoint),
+    InvalidSignature(OutPoint),
+    ScriptFailed(OutPoint, ScriptError),
+    InsufficientInputs { inputs: u64, outputs: u64 },
+    MisplacedCoinbase,
+    ExcessiveReward { paid: u64, allowed: u64 },
+    /// Values that add up to more than a `u64` holds.
+    ValueOverflow,
+    /// A transaction whose id still has unspent outputs, such as a repeated
+    /// coinbase, which would overwrite them.
+    DuplicateTransaction(String),
+    /// An output paying, or an input spending from, an address of another
+    /// network.
+    WrongNetwork(String),
+}
+
+impl fmt::Display for UtxoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UtxoError::MissingOutput(outpoint) => {
+                write!(f, "output {}:{} is not unspent", outpoint.txid, outpoint.vout)
+            }
+            UtxoError::DoubleSpend(outpoint) => {
+                write!(f, "output {}:{} is spent twice", outpoint.txid, outpoint.vout)
+            }
+            UtxoError::InvalidSignature(outpoint) => {
+                write!(f, "invalid signature spending {}:{}", outpoint.txid, outpoint.vout)
+            }
+            UtxoError::ScriptFailed(outpoint, err) => {
+                write!(f, "script spending {}:{} failed: {}", outpoint.txid, outpoint.vout, err)
+            }
+            UtxoError::InsufficientInputs { inputs, outputs } => {
+                write!(f, "outputs total {} but inputs only {}", outputs, inputs)
+            }
+            UtxoError::MisplacedCoinbase => write!(f, "coinbase must be the first transaction"),
+            UtxoError::ExcessiveReward { paid, allowed } => {
+                write!(f, "coinbase pays {} but at most {} is allowed", paid, allowed)
+            }
+            UtxoError::ValueOverflow => write!(f, "values overflow"),
+            UtxoError::DuplicateTransaction(txid) => write!(f, "transaction {} still has unspent outputs", txid),
+            UtxoError::WrongNetwork(address) => write!(f, "address {} belongs to another network", address),
+        }
+    }
+}
+
+impl std::error::Error for UtxoError {}
+
+/// Outputs spent by a block, kept so the block can be rolled back.
+pub type BlockUndo = Vec<(OutPoint, TxOutput)>;
+
+/// In-memory index of every unspent output on the chain.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet {
+    outputs: HashMap<OutPoint, TxOutput>,
+}
+
+impl UtxoSet {
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&TxOutput> {
+        self.outputs.get(outpoint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&OutPoint, &TxOutput)> {
+        self.outputs.iter()
+    }
+
+    /// Hex-encoded SHA-256 commitment to the whole set, independent of
+    /// insertion order.
+    pub fn root(&self) -> String {
+        let mut entries: Vec<_> = self.outputs.iter().collect();
+        entries.sort_by(|(a, _), (b, _)| (&a.txid, a.vout).cmp(&(&b.txid, b.vout)));
+        codec::hash_hex(|writer| {
+            codec::write_len(writer, entries.len())?;
+            for (outpoint, output) in entries {
+                codec::write_outpoint(writer, outpoint)?;
+                codec::write_output(writer, output)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Total value `owner` holds. Applied blocks never create more than a
+    /// `u64` of value, so this only saturates on a set built by hand.
+    pub fn balance(&self, owner: &str) -> u64 {
+        self.outputs
+            .values()
+            .filter(|output| output.owner == owner)
+            .fold(0, |total, output| total.saturating_add(output.value))
+    }
+
+    pub fn outputs_owned_by<'a>(
+        &'a self,
+        owner: &'a str,
+    ) -> impl Iterator<Item = (&'a OutPoint, &'a TxOutput)> + 'a {
+        self.outputs.iter().filter(move |(_, output)| output.owner == owner)
+    }
+
+    /// Checks that every input spends a distinct unspent output, either
+    /// signed by its owner or satisfying its lock script when included at
+    /// `height`, and that the inputs cover the outputs. Addresses of any
+    /// network but `network` are refused. Returns the fee: whatever the
+    /// inputs carry beyond the outputs.
+    pub fn validate_transaction(
+        &self,
+        transaction: &UtxoTransaction,
+        height: u32,
+        network: Network,
+    ) -> Result<u64, UtxoError> {
+        let message = transaction.id();
+        let mut input_total: u64 = 0;
+
+        for (i, input) in transaction.inputs.iter().enumerate() {
+            let outpoint = &input.outpoint;
+            if transaction.inputs[..i].iter().any(|other| &other.outpoint == outpoint) {
+                return Err(UtxoError::DoubleSpend(outpoint.clone()));
+            }
+            let spent = self
+                .outputs
+                .get(outpoint)
+                .ok_or_else(|| UtxoError::MissingOutput(outpoint.clone()))?;
+            if is_foreign(&spent.owner, network) {
+                return Err(UtxoError::WrongNetwork(spent.owner.clone()));
+            }
+            if !spent.lock.is_empty() {
+                let context = Context {
+                    message: message.as_bytes(),
+                    height,
+                };
+                script::verify(&input.unlock, &spent.lock, &context)
+                    .map_err(|err| UtxoError::ScriptFailed(outpoint.clone(), err))?;
+            } else if !verify_signature(&spent.owner, &message, &input.signature) {
+                return Err(UtxoError::InvalidSignature(outpoint.clone()));
+            }
+            input_total = input_total.checked_add(spent.value).ok_or(UtxoError::ValueOverflow)?;
+        }
+
+        check_network(&transaction.outputs, network)?;
+        let output_total = total_value(&transaction.outputs)?;
+        if output_total > input_total {
+            return Err(UtxoError::InsufficientInputs {
+                inputs: input_total,
+                outputs: output_total,
+            });
+        }
+        Ok(input_total - output_total)
+    }
+
+    /// Validates and applies the transactions of the block at `height` on
+    /// `network` in order. The coinbase may claim the block reward plus the
+    /// fees of the other transactions. On error the set is left exactly as
+    /// it was.
+    pub fn apply_block(
+        &mut self,
+        transactions: &[UtxoTransaction],
+        height: u32,
+        network: Network,
+    ) -> Result<BlockUndo, UtxoError> {
+        let mut undo = Vec::new();
+        let mut fees: u64 = 0;
+        for (i, transaction) in transactions.iter().enumerate() {
+            let txid = transaction.id();
+            let result = if transaction.is_coinbase() {
+                if i == 0 {
+                    check_network(&transaction.outputs, network)
+                        .and_then(|()| total_value(&transaction.outputs))
+                        .map(|_| 0)
+                } else {
+                    Err(UtxoError::MisplacedCoinbase)
+                }
+            } else {
+                self.validate_transaction(transaction, height, network)
+            };
+            let result = result.and_then(|fee| {
+                if self.has_outputs_of(&txid, transaction.outputs.len()) {
+                    return Err(UtxoError::DuplicateTransaction(txid.clone()));
+                }
+                fees.checked_add(fee).ok_or(UtxoError::ValueOverflow)
+            });
+            match result {
+                Ok(total) => fees = total,
+                Err(err) => {
+                    self.rollback_block(&transactions[..i], undo);
+                    return Err(err);
+                }
+            }
+            for input in &transaction.inputs {
+                let spent = self.outputs.remove(&input.outpoint).expect("validated input");
+                undo.push((input.outpoint.clone(), spent));
+            }
+            for (vout, output) in transaction.outputs.iter().enumerate() {
+                let outpoint = OutPoint {
+                    txid: txid.clone(),
+                    vout: vout as u32,
+                };
+                self.outputs.insert(outpoint, output.clone());
+            }
+        }
+
+        if let Some(coinbase) = transactions.first().filter(|tx| tx.is_coinbase()) {
+            let paid = total_value(&coinbase.outputs).expect("checked when applied");
+            let allowed = BLOCK_REWARD.saturating_add(fees);
+            if paid > allowed {
+                self.rollback_block(transactions, undo);
+                return Err(UtxoError::ExcessiveReward { paid, allowed });
+            }
+        }
+        Ok(undo)
+    }
+
+    /// Whether any of the first `count` outputs of `txid` is unspent.
+    fn has_outputs_of(&self, txid: &str, count: usize) -> bool {
+        (0..count).any(|vout| {
+            self.outputs.contains_key(&OutPoint {
+                txid: txid.to_owned(),
+                vout: vout as u32,
+            })
+        })
+    }
+
+    /// Reverses `apply_block`: drops the outputs the block created and
+    /// restores the ones it spent.
+    pub fn rollback_block(&mut self, transactions: &[UtxoTransaction], undo: BlockUndo) {
+        for transaction in transactions {
+            let txid = transaction.id();
+            for vout in 0..transaction.outputs.len() {
+                self.outputs.remove(&OutPoint {
+                    txid: txid.clone(),
+                    vout: vout as u32,
+                });
+            }
+        }
+        self.outputs.extend(undo);
+    }
+}
+
+impl FromIterator<(OutPoint, TxOutput)> for UtxoSet {
+    fn from_iter<I: IntoIterator<Item = (OutPoint, TxOutput)>>(iter: I) -> Self {
+        UtxoSet {
+            outputs: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Sum of the outputs' values, or an error if it overflows.
+fn total_value(outputs: &[TxOutput]) -> Result<u64, UtxoError> {
+    outputs
+        .iter()
+        .try_fold(0u64, |total, output| total.checked_add(output.value))
+        .ok_or(UtxoError::ValueOverflow)
+}
+
+/// Whether `owner` is an address, but of a network other than `network`.
+fn is_foreign(owner: &str, network: Network) -> bool {
+    owner.parse::<Address>().is_ok_and(|address| address.network() != network)
+}
+
+fn check_network(outputs: &[TxOutput], network: Network) -> Result<(), UtxoError> {
+    match outputs.iter().find(|output| is_foreign(&output.owner, network)) {
+        Some(output) => Err(UtxoError::WrongNetwork(output.owner.clone())),
+        None => Ok(()),
+    }
+}
+
+fn verify_signature(owner: &str, message: &str, signature: &str) -> bool {
+    let Some(key) = owner.parse::<Address>().ok().and_then(|address| address.verifying_key()) else {
+        return false;
+    };
+    let Ok(signature_bytes) = hex::decode(signature) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(&signature_bytes) else {
+        return false;
+    };
+    key.verify(message.as_bytes(), &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    #[test]
+    fn test_spend_and_rollback() {
+        let alice = key(1);
+        let bob = key(2);
+        let mut set = UtxoSet::default();
+
+        let coinbase = UtxoTransaction::coinbase(address(&alice.verifying_key(), Network::Mainnet), BLOCK_REWARD);
+        let funding = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        set.apply_block(std::slice::from_ref(&coinbase), 1, Network::Mainnet).unwrap();
+        assert_eq!(set.balance(&address(&alice.verifying_key(), Network::Mainnet)), BLOCK_REWARD);
+
+        let mut payment = UtxoTransaction::new(
+            vec![funding.clone()],
+            vec![
+                TxOutput::new(30, address(&bob.verifying_key(), Network::Mainnet)),
+                TxOutput::new(20, address(&alice.verifying_key(), Network::Mainnet)),
+            ],
+        );
+        payment.sign(&alice);
+        let block = vec![payment];
+        let undo = set.apply_block(&block, 1, Network::Mainnet).unwrap();
+        assert_eq!(set.balance(&address(&bob.verifying_key(), Network::Mainnet)), 30);
+        assert!(set.get(&funding).is_none());
+
+        set.rollback_block(&block, undo);
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.get(&funding).unwrap().value, BLOCK_REWARD);
+    }
+
+    #[test]
+    fn test_rejects_invalid_spends() {
+        let alice = key(1);
+        let mallory = key(3);
+        let mut set = UtxoSet::default();
+
+        let coinbase = UtxoTransaction::coinbase(address(&alice.verifying_key(), Network::Mainnet), BLOCK_REWARD);
+        let funding = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        set.apply_block(&[coinbase], 1, Network::Mainnet).unwrap();
+
+        let mut stolen = UtxoTransaction::new(
+            vec![funding.clone()],
+            vec![TxOutput::new(50, address(&mallory.verifying_key(), Network::Mainnet))],
+        );
+        stolen.sign(&mallory);
+        assert_eq!(
+            set.validate_transaction(&stolen, 1, Network::Mainnet),
+            Err(UtxoError::InvalidSignature(funding.clone()))
+        );
+
+        let mut inflated = UtxoTransaction::new(
+            vec![funding.clone()],
+            vec![TxOutput::new(51, address(&alice.verifying_key(), Network::Mainnet))],
+        );
+        inflated.sign(&alice);
+        assert!(matches!(
+            set.validate_transaction(&inflated, 1, Network::Mainnet),
+            Err(UtxoError::InsufficientInputs { .. })
+        ));
+
+        let mut first = UtxoTransaction::new(
+            vec![funding.clone()],
+            vec![TxOutput::new(10, address(&alice.verifying_key(), Network::Mainnet))],
+        );
+        first.sign(&alice);
+        let mut second = UtxoTransaction::new(
+            vec![funding.clone()],
+            vec![TxOutput::new(20, address(&alice.verifying_key(), Network::Mainnet))],
+        );
+        second.sign(&alice);
+        assert_eq!(
+            set.apply_block(&[first, second], 1, Network::Mainnet),
+            Err(UtxoError::MissingOutput(funding.clone()))
+        );
+        assert_eq!(set.get(&funding).unwrap().value, BLOCK_REWARD);
+    }
+
+    #[test]
+    fn test_script_locked_outputs() {
+        let alice = key(1);
+        let bob = key(2);
+        let lock = Script::timelocked(10, &bob.verifying_key());
+        let mut set = UtxoSet::default();
+
+        let funding = UtxoTransaction {
+            inputs: Vec::new(),
+            outputs: vec![TxOutput::locked(BLOCK_REWARD, lock.clone(), Network::Mainnet)],
+        };
+        let outpoint = OutPoint {
+            txid: funding.id(),
+            vout: 0,
+        };
+        set.apply_block(&[funding], 1, Network::Mainnet).unwrap();
+        assert_eq!(set.balance(&lock.address(Network::Mainnet)), BLOCK_REWARD);
+
+        let mut spend = UtxoTransaction::new(
+            vec![outpoint.clone()],
+            vec![TxOutput::new(BLOCK_REWARD, address(&bob.verifying_key(), Network::Mainnet))],
+        );
+        spend.inputs[0].unlock = Script::unlock(&[spend.signature(&alice)]);
+        assert!(matches!(
+            set.validate_transaction(&spend, 10, Network::Mainnet),
+            Err(UtxoError::ScriptFailed(_, ScriptError::Failed))
+        ));
+
+        spend.inputs[0].unlock = Script::unlock(&[spend.signature(&bob)]);
+        assert_eq!(
+            set.validate_transaction(&spend, 9, Network::Mainnet),
+            Err(UtxoError::ScriptFailed(outpoint, ScriptError::Locked { until: 10, height: 9 }))
+        );
+        assert_eq!(set.validate_transaction(&spend, 10, Network::Mainnet), Ok(0));
+
+        let json = serde_json::to_string(&spend).unwrap();
+        assert_eq!(serde_json::from_str::<UtxoTransaction>(&json).unwrap(), spend);
+    }
+
+    #[test]
+    fn test_coinbase_may_claim_fees() {
+        let alice = key(1);
+        let miner = address(&key(4).verifying_key(), Network::Mainnet);
+        let mut set = UtxoSet::default();
+
+        let coinbase = UtxoTransaction::coinbase(address(&alice.verifying_key(), Network::Mainnet), BLOCK_REWARD);
+        let funding = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        set.apply_block(&[coinbase], 1, Network::Mainnet).unwrap();
+
+        let mut payment = UtxoTransaction::new(
+            vec![funding.clone()],
+            vec![TxOutput::new(45, address(&alice.verifying_key(), Network::Mainnet))],
+        );
+        payment.sign(&alice);
+        assert_eq!(set.validate_transaction(&payment, 1, Network::Mainnet), Ok(5));
+
+        let greedy = UtxoTransaction::coinbase(miner.clone(), BLOCK_REWARD + 6);
+        assert_eq!(
+            set.apply_block(&[greedy, payment.clone()], 1, Network::Mainnet),
+            Err(UtxoError::ExcessiveReward { paid: 56, allowed: 55 })
+        );
+        assert_eq!(set.get(&funding).unwrap().value, BLOCK_REWARD);
+
+        let reward = UtxoTransaction::coinbase(miner.clone(), BLOCK_REWARD + 5);
+        set.apply_block(&[reward, payment], 1, Network::Mainnet).unwrap();
+        assert_eq!(set.balance(&miner), 55);
+    }
+
+    #[test]
+    fn test_overflowing_values_are_rejected() {
+        let alice = key(1);
+        let mut set = UtxoSet::default();
+        let overflowing = UtxoTransaction {
+            inputs: Vec::new(),
+            outputs: vec![TxOutput::new(u64::MAX, "alice".to_owned()), TxOutput::new(1, "alice".to_owned())],
+        };
+        assert_eq!(set.apply_block(&[overflowing], 1, Network::Mainnet), Err(UtxoError::ValueOverflow));
+        assert!(set.is_empty());
+
+        let coinbase = UtxoTransaction::coinbase(address(&alice.verifying_key(), Network::Mainnet), BLOCK_REWARD);
+        let funding = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        set.apply_block(&[coinbase], 1, Network::Mainnet).unwrap();
+        let mut payment = UtxoTransaction::new(
+            vec![funding],
+            vec![TxOutput::new(u64::MAX, "bob".to_owned()), TxOutput::new(2, "bob".to_owned())],
+        );
+        payment.sign(&alice);
+        assert_eq!(set.validate_transaction(&payment, 1, Network::Mainnet), Err(UtxoError::ValueOverflow));
+    }
+
+    #[test]
+    fn test_identical_coinbases_cannot_overwrite_outputs() {
+        let alice = key(1);
+        let mut set = UtxoSet::default();
+        let coinbase = UtxoTransaction::coinbase(address(&alice.verifying_key(), Network::Mainnet), BLOCK_REWARD);
+        let funding = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        let first = set.apply_block(std::slice::from_ref(&coinbase), 1, Network::Mainnet).unwrap();
+        assert_eq!(
+            set.apply_block(std::slice::from_ref(&coinbase), 2, Network::Mainnet),
+            Err(UtxoError::DuplicateTransaction(coinbase.id()))
+        );
+        assert_eq!((set.len(), set.balance(&address(&alice.verifying_key(), Network::Mainnet))), (1, BLOCK_REWARD));
+
+        // Once the first is spent, the same coinbase pays out again and
+        // both blocks roll back exactly.
+        let mut payment = UtxoTransaction::new(vec![funding.clone()], vec![TxOutput::new(50, "bob".to_owned())]);
+        payment.sign(&alice);
+        let spend = vec![payment];
+        let spend_undo = set.apply_block(&spend, 2, Network::Mainnet).unwrap();
+        let repeat = set.apply_block(std::slice::from_ref(&coinbase), 3, Network::Mainnet).unwrap();
+        assert_eq!(set.balance(&address(&alice.verifying_key(), Network::Mainnet)), BLOCK_REWARD);
+        assert_eq!(set.balance("bob"), 50);
+
+        set.rollback_block(std::slice::from_ref(&coinbase), repeat);
+        assert!(set.get(&funding).is_none());
+        set.rollback_block(&spend, spend_undo);
+        assert_eq!(set.get(&funding).unwrap().value, BLOCK_REWARD);
+        set.rollback_block(std::slice::from_ref(&coinbase), first);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn test_addresses_of_other_networks_are_rejected() {
+        let alice = key(1);
+        let mainnet = address(&alice.verifying_key(), Network::Mainnet);
+        let testnet = address(&alice.verifying_key(), Network::Testnet);
+        let mut set = UtxoSet::default();
+
+        let foreign = UtxoTransaction::coinbase(testnet.clone(), BLOCK_REWARD);
+        assert_eq!(
+            set.apply_block(&[foreign], 1, Network::Mainnet),
+            Err(UtxoError::WrongNetwork(testnet.clone()))
+        );
+
+        let coinbase = UtxoTransaction::coinbase(mainnet.clone(), BLOCK_REWARD);
+        let funding = OutPoint {
+            txid: coinbase.id(),
+            vout: 0,
+        };
+        set.apply_block(&[coinbase], 1, Network::Mainnet).unwrap();
+        let mut payment = UtxoTransaction::new(vec![funding.clone()], vec![TxOutput::new(50, testnet.clone())]);
+        payment.sign(&alice);
+        assert_eq!(
+            set.validate_transaction(&payment, 1, Network::Mainnet),
+            Err(UtxoError::WrongNetwork(testnet.clone()))
+        );
+
+        // The same key cannot unlock its outputs on the other network.
+        assert_eq!(
+            set.validate_transaction(&payment, 1, Network::Testnet),
+            Err(UtxoError::WrongNetwork(mainnet))
+        );
+    }
+}
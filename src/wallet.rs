@@ -0,0 +1,509 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::address::{Address, Network};
+use crate::crypto::{open, random, seal};
+use crate::rpc::Unspent;
+use crate::utxo::{TxOutput, UtxoTransaction};
+
+/// Words in a backup phrase: 24 words of 11 bits hold the 32-byte key seed
+/// and an 8-bit checksum.
+pub const MNEMONIC_WORDS: usize = 24;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Most memory scrypt may use for a wallet file's parameters, so a
+/// tampered file cannot exhaust it.
+const MAX_SCRYPT_MEMORY: u64 = 1 << 30;
+
+/// BIP39's English wordlist, one word per line in order.
+const WORDLIST: &str = include_str!("bip39_english.txt");
+
+#[derive(Debug)]
+pub enum WalletError {
+    Io(io::Error),
+    Corrupt(String),
+    WrongPassword,
+    UnknownKey(String),
+    DuplicateLabel(String),
+    InvalidMnemonic(String),
+    InsufficientFunds { available: u64, needed: u64 },
+    /// The amounts involved do not fit in a `u64`.
+    Overflow,
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::Io(err) => write!(f, "cannot access wallet: {}", err),
+            WalletError::Corrupt(reason) => write!(f, "wallet file is corrupt: {}", reason),
+            WalletError::WrongPassword => write!(f, "wrong password"),
+            WalletError::UnknownKey(label) => write!(f, "no key labelled {:?}", label),
+            WalletError::DuplicateLabel(label) => write!(f, "a key labelled {:?} already exists", label),
+            WalletError::InvalidMnemonic(reason) => write!(f, "invalid backup phrase: {}", reason),
+            WalletError::InsufficientFunds { available, needed } => {
+                write!(f, "needs {} but only {} is spendable", needed, available)
+            }
+            WalletError::Overflow => write!(f, "amounts overflow"),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+impl From<io::Error> for WalletError {
+    fn from(err: io::Error) -> Self {
+        WalletError::Io(err)
+    }
+}
+
+/// scrypt's cost parameters. Each wallet records its own, so raising the
+/// defaults never locks anyone out of an older file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScryptParams {
+    /// Base-2 logarithm of the CPU and memory cost, N.
+    pub log_n: u8,
+    /// Block size, r.
+    pub r: u32,
+    /// Parallelism, p.
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    /// N = 2^15, r = 8, p = 1: 32 MiB and well under a second per unlock.
+    fn default() -> Self {
+        ScryptParams { log_n: 15, r: 8, p: 1 }
+    }
+}
+
+impl ScryptParams {
+    fn check(&self) -> Result<(), WalletError> {
+        let blocks = (1u64 << self.log_n.min(32)) + self.p as u64;
+        let memory = 128u64.saturating_mul(self.r as u64).saturating_mul(blocks);
+        if self.log_n == 0 || self.log_n > 32 || self.r == 0 || self.p == 0 || memory > MAX_SCRYPT_MEMORY {
+            return Err(WalletError::Corrupt("scrypt parameters are out of range".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// A private key encrypted under the wallet password. The address is kept
+/// in the clear so keys can be listed without unlocking them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredKey {
+    pub label: String,
+    pub address: Address,
+    #[serde(with = "hex::serde")]
+    nonce: Vec<u8>,
+    /// The sealed key seed, ending with its Poly1305 tag.
+    #[serde(with = "hex::serde")]
+    ciphertext: Vec<u8>,
+}
+
+/// Keys for one network, encrypted at rest. The password is stretched with
+/// scrypt into a key, and each private key is sealed under it with
+/// ChaCha20-Poly1305, bound to its address as associated data. Plaintext
+/// keys only ever live in memory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wallet {
+    network: Network,
+    #[serde(with = "hex::serde")]
+    salt: Vec<u8>,
+    scrypt: ScryptParams,
+    keys: Vec<StoredKey>,
+}
+
+impl Wallet {
+    /// An empty wallet. Its password is set by the first key added.
+    pub fn new(network: Network) -> Result<Self, WalletError> {
+        Self::with_scrypt(network, ScryptParams::default())
+    }
+
+    pub fn with_scrypt(network: Network, scrypt: ScryptParams) -> Result<Self, WalletError> {
+        scrypt.check()?;
+        Ok(Wallet {
+            network,
+            salt: random::<SALT_LEN>()?.to_vec(),
+            scrypt,
+            keys: Vec::new(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, WalletError> {
+        let encoded = fs::read(path)?;
+        serde_json::from_slice(&encoded).map_err(|err| WalletError::Corrupt(err.to_string()))
+    }
+
+    /// Writes the wallet under a temporary name, readable only by its owner,
+    /// and renames it into place.
+    pub fn save(&self, path: &Path) -> Result<(), WalletError> {
+        let encoded = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        let temporary = path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&temporary)?.write_all(&encoded)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn keys(&self) -> &[StoredKey] {
+        &self.keys
+    }
+
+    pub fn address(&self, label: &str) -> Option<Address> {
+        self.key(label).map(|stored| stored.address)
+    }
+
+    /// Encrypts `key` into the wallet under `label`. Once the wallet holds
+    /// a key, `password` must match the one it was encrypted with.
+    pub fn add_key(&mut self, label: String, key: &SigningKey, password: &str) -> Result<Address, WalletError> {
+        if self.key(&label).is_some() {
+            return Err(WalletError::DuplicateLabel(label));
+        }
+        let secret = self.derive(password)?;
+        let address = Address::from_key(&key.verifying_key(), self.network);
+        let nonce = random::<NONCE_LEN>()?;
+        let seed = Zeroizing::new(key.to_bytes());
+        let ciphertext = seal(&secret, &nonce, address.to_string().as_bytes(), seed.as_slice());
+        self.keys.push(StoredKey {
+            label,
+            address,
+            nonce: nonce.to_vec(),
+            ciphertext,
+        });
+        Ok(address)
+    }
+
+    /// Decrypts the key labelled `label`.
+    pub fn unlock(&self, label: &str, password: &str) -> Result<SigningKey, WalletError> {
+        let stored = self.key(label).ok_or_else(|| WalletError::UnknownKey(label.to_owned()))?;
+        let secret = self.derive(password)?;
+        decrypt(&secret, stored)
+    }
+
+    fn key(&self, label: &str) -> Option<&StoredKey> {
+        self.keys.iter().find(|stored| stored.label == label)
+    }
+
+    /// Stretches `password` into the wallet's key, checking it against the
+    /// first stored key if there is one.
+    fn derive(&self, password: &str) -> Result<Zeroizing<[u8; 32]>, WalletError> {
+        self.scrypt.check()?;
+        let secret = scrypt(password.as_bytes(), &self.salt, &self.scrypt)?;
+        if let Some(first) = self.keys.first() {
+            decrypt(&secret, first)?;
+        }
+        Ok(secret)
+    }
+}
+
+/// A fresh key from the operating system's random number generator.
+pub fn generate_key() -> Result<SigningKey, WalletError> {
+    let seed = Zeroizing::new(random::<32>()?);
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// A BIP39 phrase from the English wordlist with `key`'s seed as its
+/// entropy: the seed and a SHA-256 checksum byte, 11 bits per word. Other
+/// BIP39 wallets accept the phrase, but derive their keys from it through
+/// BIP32 rather than using the entropy as the key.
+pub fn to_mnemonic(key: &SigningKey) -> String {
+    let mut bits = Zeroizing::new([0u8; 33]);
+    bits[..32].copy_from_slice(key.as_bytes());
+    bits[32] = Sha256::digest(key.as_bytes())[0];
+    let words: Vec<&str> = (0..MNEMONIC_WORDS).map(|i| mnemonic_word(read_bits(&bits[..], i * 11))).collect();
+    words.join(" ")
+}
+
+/// Restores the key a phrase from `to_mnemonic` was made from.
+pub fn from_mnemonic(phrase: &str) -> Result<SigningKey, WalletError> {
+    let words: Vec<&str> = phrase.split_whitespace().collect();
+    if words.len() != MNEMONIC_WORDS {
+        let reason = format!("expected {} words, got {}", MNEMONIC_WORDS, words.len());
+        return Err(WalletError::InvalidMnemonic(reason));
+    }
+    let mut bits = Zeroizing::new([0u8; 33]);
+    for (i, word) in words.iter().enumerate() {
+        let index = mnemonic_index(word)
+            .ok_or_else(|| WalletError::InvalidMnemonic(format!("unknown word {:?}", word)))?;
+        write_bits(&mut bits[..], i * 11, index);
+    }
+    let seed: [u8; 32] = bits[..32].try_into().expect("slice is 32 bytes");
+    let seed = Zeroizing::new(seed);
+    if Sha256::digest(seed.as_slice())[0] != bits[32] {
+        return Err(WalletError::InvalidMnemonic("checksum does not match".to_owned()));
+    }
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// A transaction paying `amount` to `to` out of `unspent`, which must be
+/// outputs owned by `key`. Spends outputs in order until they cover the
+/// amount and `fee`, returns the change to `key`'s address on `network`
+/// and signs every input.
+pub fn build_payment(
+    key: &SigningKey,
+    network: Network,
+    unspent: &[Unspent],
+    to: Address,
+    amount: u64,
+    fee: u64,
+) -> Result<UtxoTransaction, WalletError> {
+    let available = unspent.iter().fold(0, |total: u64, output| total.saturating_add(output.value));
+    let needed = amount.checked_add(fee).ok_or(WalletError::Overflow)?;
+    let mut outpoints = Vec::new();
+    let mut gathered: u64 = 0;
+    for output in unspent {
+        if gathered >= needed {
+            break;
+        }
+        outpoints.push(output.outpoint.clone());
+        gathered = gathered.checked_add(output.value).ok_or(WalletError::Overflow)?;
+    }
+    if gathered < needed {
+        return Err(WalletError::InsufficientFunds { available, needed });
+    }
+
+    let mut outputs = vec![TxOutput::new(amount, to.to_string())];
+    if gathered > needed {
+        let change = Address::from_key(&key.verifying_key(), network);
+        outputs.push(TxOutput::new(gathered - needed, change.to_string()));
+    }
+    let mut transaction = UtxoTransaction::new(outpoints, outputs);
+    transaction.sign(key);
+    Ok(transaction)
+}
+
+fn decrypt(secret: &[u8; 32], stored: &StoredKey) -> Result<SigningKey, WalletError> {
+    let nonce: &[u8; NONCE_LEN] = stored
+        .nonce
+        .as_slice()
+        .try_into()
+        .map_err(|_| WalletError::Corrupt(format!("key {:?} has the wrong nonce length", stored.label)))?;
+    let seed = open(secret, nonce, stored.address.to_string().as_bytes(), &stored.ciphertext)
+        .map(Zeroizing::new)
+        .ok_or(WalletError::WrongPassword)?;
+    let seed: &[u8; 32] = seed
+        .as_slice()
+        .try_into()
+        .map_err(|_| WalletError::Corrupt(format!("key {:?} has the wrong length", stored.label)))?;
+    let key = SigningKey::from_bytes(seed);
+    if Address::from_key(&key.verifying_key(), stored.address.network()) != stored.address {
+        return Err(WalletError::Corrupt(format!("key {:?} does not match its address", stored.label)));
+    }
+    Ok(key)
+}
+
+/// scrypt as RFC 7914 defines it, with a 32-byte output. `params` must
+/// have passed `ScryptParams::check`.
+fn scrypt(password: &[u8], salt: &[u8], params: &ScryptParams) -> Result<Zeroizing<[u8; 32]>, WalletError> {
+    let out_of_range = |_| WalletError::Corrupt("scrypt parameters are out of range".to_owned());
+    let params = scrypt::Params::new(params.log_n, params.r, params.p, 32).map_err(out_of_range)?;
+    let mut derived = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(password, salt, &params, derived.as_mut_slice()).expect("32 bytes is a valid output length");
+    Ok(derived)
+}
+
+/// The 11 bits of `bytes` starting at bit `offset`, most significant first.
+fn read_bits(bytes: &[u8], offset: usize) -> u16 {
+    (offset..offset + 11).fold(0, |acc, bit| acc << 1 | (bytes[bit / 8] >> (7 - bit % 8) & 1) as u16)
+}
+
+fn write_bits(bytes: &mut [u8], offset: usize, value: u16) {
+    for (i, bit) in (offset..offset + 11).enumerate() {
+        if value >> (10 - i) & 1 == 1 {
+            bytes[bit / 8] |= 1 << (7 - bit % 8);
+        }
+    }
+}
+
+fn mnemonic_word(index: u16) -> &'static str {
+    WORDLIST.lines().nth(index as usize).expect("the wordlist has 2048 words")
+}
+
+fn mnemonic_index(word: &str) -> Option<u16> {
+    WORDLIST.lines().position(|candidate| candidate == word).map(|index| index as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utxo::OutPoint;
+
+    /// Keeps the tests fast; the parameters are stored with the wallet.
+    const TEST_SCRYPT: ScryptParams = ScryptParams { log_n: 4, r: 1, p: 1 };
+
+    #[test]
+    fn test_kdf_matches_reference_vectors() {
+        // RFC 7914 section 12.
+        let params = ScryptParams { log_n: 4, r: 1, p: 1 };
+        assert_eq!(
+            hex::encode(scrypt(b"", b"", &params).unwrap().as_slice()),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442"
+        );
+        let params = ScryptParams { log_n: 10, r: 8, p: 16 };
+        assert_eq!(
+            hex::encode(scrypt(b"password", b"NaCl", &params).unwrap().as_slice()),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162"
+        );
+
+        let huge = ScryptParams { log_n: 24, r: 8, p: 1 };
+        assert!(matches!(Wallet::with_scrypt(Network::Mainnet, huge), Err(WalletError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_keys_are_encrypted_at_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wallet.json");
+        let mut wallet = Wallet::with_scrypt(Network::Testnet, TEST_SCRYPT).unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let address = wallet.add_key("savings".to_owned(), &key, "hunter2").unwrap();
+        assert_eq!(address.network(), Network::Testnet);
+        wallet.save(&path).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains(&hex::encode(key.to_bytes())));
+        assert!(contents.contains(&address.to_string()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let loaded = Wallet::load(&path).unwrap();
+        assert_eq!(loaded, wallet);
+        assert_eq!(loaded.address("savings"), Some(address));
+        assert_eq!(loaded.unlock("savings", "hunter2").unwrap().to_bytes(), key.to_bytes());
+        assert!(matches!(loaded.unlock("savings", "hunter3"), Err(WalletError::WrongPassword)));
+        assert!(matches!(loaded.unlock("spending", "hunter2"), Err(WalletError::UnknownKey(_))));
+    }
+
+    #[test]
+    fn test_added_keys_share_the_password() {
+        let mut wallet = Wallet::with_scrypt(Network::Mainnet, TEST_SCRYPT).unwrap();
+        wallet.add_key("first".to_owned(), &generate_key().unwrap(), "secret").unwrap();
+
+        let second = generate_key().unwrap();
+        assert!(matches!(
+            wallet.add_key("second".to_owned(), &second, "other"),
+            Err(WalletError::WrongPassword)
+        ));
+        assert!(matches!(
+            wallet.add_key("first".to_owned(), &second, "secret"),
+            Err(WalletError::DuplicateLabel(_))
+        ));
+        wallet.add_key("second".to_owned(), &second, "secret").unwrap();
+        assert_eq!(wallet.unlock("second", "secret").unwrap().to_bytes(), second.to_bytes());
+
+        // A key moved under another address fails authentication.
+        let mut swapped = wallet.clone();
+        swapped.keys[1].address = swapped.keys[0].address;
+        assert!(matches!(swapped.unlock("second", "secret"), Err(WalletError::WrongPassword)));
+    }
+
+    #[test]
+    fn test_mnemonic_round_trip() {
+        let key = SigningKey::from_bytes(&[42; 32]);
+        let phrase = to_mnemonic(&key);
+        assert_eq!(phrase.split(' ').count(), MNEMONIC_WORDS);
+        assert_eq!(from_mnemonic(&phrase).unwrap().to_bytes(), key.to_bytes());
+        assert_eq!(from_mnemonic(&format!("  {}\n", phrase.replace(' ', "  "))).unwrap().to_bytes(), key.to_bytes());
+
+        for index in 0..2048 {
+            assert_eq!(mnemonic_index(mnemonic_word(index)), Some(index));
+        }
+
+        let mut words: Vec<&str> = phrase.split(' ').collect();
+        words.swap(0, 1);
+        if words[0] != words[1] {
+            assert!(from_mnemonic(&words.join(" ")).is_err());
+        }
+        let (_, rest) = phrase.split_once(' ').unwrap();
+        assert!(from_mnemonic(&format!("xxxx {}", rest)).is_err());
+        assert!(from_mnemonic(rest).is_err());
+    }
+
+    #[test]
+    fn test_mnemonic_matches_bip39() {
+        // The published english.txt, byte for byte.
+        assert_eq!(
+            hex::encode(Sha256::digest(WORDLIST)),
+            "2f5eed53a4727b4bf8880d8f3f199efc90e58503646d9ff8eff3a2ed3b24dbda"
+        );
+        // Test vectors from the BIP39 reference implementation.
+        let zeros = SigningKey::from_bytes(&[0; 32]);
+        assert_eq!(to_mnemonic(&zeros), format!("{}art", "abandon ".repeat(23)));
+        let ones = SigningKey::from_bytes(&[0xff; 32]);
+        assert_eq!(to_mnemonic(&ones), format!("{}vote", "zoo ".repeat(23)));
+        let phrase = "legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth useful \
+                      legal winner thank year wave sausage worth title";
+        assert_eq!(from_mnemonic(phrase).unwrap().to_bytes(), [0x7f; 32]);
+    }
+
+    #[test]
+    fn test_payments_spend_enough_and_return_change() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let to = Address::from_key(&SigningKey::from_bytes(&[8; 32]).verifying_key(), Network::Mainnet);
+        let unspent: Vec<Unspent> = [30, 50, 20]
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| Unspent {
+                outpoint: OutPoint {
+                    txid: format!("{:064}", i),
+                    vout: 0,
+                },
+                value,
+            })
+            .collect();
+
+        let payment = build_payment(&key, Network::Mainnet, &unspent, to, 60, 5).unwrap();
+        assert_eq!(payment.inputs.len(), 2);
+        assert_eq!(payment.outputs[0], TxOutput::new(60, to.to_string()));
+        let change = Address::from_key(&key.verifying_key(), Network::Mainnet);
+        assert_eq!(payment.outputs[1], TxOutput::new(15, change.to_string()));
+        assert!(payment.inputs.iter().all(|input| !input.signature.is_empty()));
+
+        let exact = build_payment(&key, Network::Mainnet, &unspent, to, 75, 5).unwrap();
+        assert_eq!(exact.outputs.len(), 1);
+        assert!(matches!(
+            build_payment(&key, Network::Mainnet, &unspent, to, 100, 1),
+            Err(WalletError::InsufficientFunds { available: 100, needed: 101 })
+        ));
+        assert!(matches!(
+            build_payment(&key, Network::Mainnet, &unspent, to, u64::MAX, 1),
+            Err(WalletError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn test_payments_refuse_outputs_that_overflow() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let to = Address::from_key(&SigningKey::from_bytes(&[8; 32]).verifying_key(), Network::Mainnet);
+        let unspent: Vec<Unspent> = (0..2)
+            .map(|i| Unspent {
+                outpoint: OutPoint {
+                    txid: format!("{:064}", i),
+                    vout: 0,
+                },
+                value: u64::MAX - 1,
+            })
+            .collect();
+        assert!(matches!(
+            build_payment(&key, Network::Mainnet, &unspent, to, u64::MAX - 1, 1),
+            Err(WalletError::Overflow)
+        ));
+    }
+}
@@ -0,0 +1,109 @@
+//! Bindings for running a chain in the browser. Build for
+//! `wasm32-unknown-unknown` with `--no-default-features --features wasm`,
+//! for example through `wasm-pack build`.
+
+use std::sync::Arc;
+
+use ed25519_dalek::SigningKey;
+use wasm_bindgen::prelude::*;
+
+use crate::address::Address;
+use crate::export::{self, Format};
+use crate::{Blockchain, ChainParams, Clock, Target, Transaction};
+
+/// Reads the time from the browser's `Date.now()`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsClock;
+
+impl Clock for JsClock {
+    fn now(&self) -> i64 {
+        (js_sys::Date::now() / 1000.0) as i64
+    }
+}
+
+/// A `Blockchain` exported to JavaScript as `Blockchain`.
+#[wasm_bindgen(js_name = Blockchain)]
+pub struct WasmBlockchain {
+    inner: Blockchain,
+}
+
+#[wasm_bindgen(js_class = Blockchain)]
+impl WasmBlockchain {
+    /// A chain mined at `difficulty` leading zero hex digits. Keep it low:
+    /// every step above 4 makes mining sixteen times slower.
+    #[wasm_bindgen(constructor)]
+    pub fn new(difficulty: usize) -> WasmBlockchain {
+        let params = ChainParams {
+            target: Target::from_leading_zeros(difficulty),
+            clock: Arc::new(JsClock),
+            ..ChainParams::default()
+        };
+        WasmBlockchain {
+            inner: Blockchain::with_params(params),
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.inner.latest_block().header.index
+    }
+
+    #[wasm_bindgen(getter, js_name = latestHash)]
+    pub fn latest_hash(&self) -> String {
+        self.inner.latest_block().header.hash.clone()
+    }
+
+    /// Mines a block carrying `data`. `progress`, if given, is called with
+    /// the number of nonces tried so far while mining.
+    #[wasm_bindgen(js_name = addBlock)]
+    pub fn add_block(&mut self, data: String, progress: Option<js_sys::Function>) -> bool {
+        self.inner.add_block_with_progress(data, |attempts| {
+            if let Some(progress) = &progress {
+                let _ = progress.call1(&JsValue::NULL, &JsValue::from_f64(attempts as f64));
+            }
+        })
+    }
+
+    /// Queues a payment from the address of `secret_key`, a hex-encoded
+    /// Ed25519 secret key, signed with it and carrying the nonce after the
+    /// sender's last, pending ones included.
+    #[wasm_bindgen(js_name = addTransaction)]
+    pub fn add_transaction(&mut self, secret_key: String, recipient: String, amount: u64, fee: u64) -> Result<bool, JsError> {
+        let key = hex::decode(&secret_key)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .map(|bytes| SigningKey::from_bytes(&bytes))
+            .ok_or_else(|| JsError::new("secret key must be 32 hex-encoded bytes"))?;
+        let sender = Address::from_key(&key.verifying_key(), self.inner.params().network).to_string();
+        let pending = self.inner.pending_transactions().iter().filter(|tx| tx.sender == sender).count();
+        let mut transaction = Transaction {
+            nonce: self.inner.next_nonce(&sender) + pending as u64,
+            ..Transaction::with_fee(sender, recipient, amount, fee)
+        };
+        transaction.sign(&key);
+        Ok(self.inner.add_transaction(transaction))
+    }
+
+    #[wasm_bindgen(js_name = nextNonce)]
+    pub fn next_nonce(&self, account: String) -> u64 {
+        self.inner.next_nonce(&account)
+    }
+
+    #[wasm_bindgen(js_name = minePending)]
+    pub fn mine_pending(&mut self, miner: String) -> bool {
+        self.inner.mine_pending_transactions(miner)
+    }
+
+    #[wasm_bindgen(js_name = isValid)]
+    pub fn is_valid(&self) -> bool {
+        self.inner.validate_suffix(0)
+    }
+
+    /// The chain as JSON Lines, one block per line.
+    #[wasm_bindgen(js_name = toJson)]
+    pub fn to_json(&self) -> Result<String, JsError> {
+        let mut exported = Vec::new();
+        export::export(&self.inner, Format::Json, &mut exported)?;
+        Ok(String::from_utf8(exported)?)
+    }
+}
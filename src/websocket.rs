@@ -0,0 +1,208 @@
+use std::io::{self, Read, Write};
+
+use base64ct::{Base64, Encoding};
+
+/// Appended to a client's key before hashing it into the accept key.
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longest payload of a control frame, and of anything a subscriber sends
+/// the server, which only expects control frames from it.
+pub(crate) const MAX_CONTROL_LEN: u64 = 125;
+
+/// A whole, unfragmented WebSocket message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Close,
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+}
+
+impl Frame {
+    fn opcode(&self) -> u8 {
+        match self {
+            Frame::Text(_) => 0x1,
+            Frame::Binary(_) => 0x2,
+            Frame::Close => 0x8,
+            Frame::Ping(_) => 0x9,
+            Frame::Pong(_) => 0xa,
+        }
+    }
+
+    fn payload(&self) -> &[u8] {
+        match self {
+            Frame::Text(text) => text.as_bytes(),
+            Frame::Binary(payload) | Frame::Ping(payload) | Frame::Pong(payload) => payload,
+            Frame::Close => &[],
+        }
+    }
+}
+
+/// The `Sec-WebSocket-Accept` value answering a client's
+/// `Sec-WebSocket-Key`.
+pub(crate) fn accept_key(key: &str) -> String {
+    let digest = sha1(&[key.trim().as_bytes(), ACCEPT_GUID].concat());
+    Base64::encode_string(&digest)
+}
+
+/// Writes `frame` in one piece. Servers send frames as they are; clients
+/// must mask theirs with a `mask` of their choosing.
+pub(crate) fn write_frame(writer: &mut impl Write, frame: &Frame, mask: Option<[u8; 4]>) -> io::Result<()> {
+    let payload = frame.payload();
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut bytes = vec![0x80 | frame.opcode()];
+    match payload.len() {
+        len @ 0..=125 => bytes.push(mask_bit | len as u8),
+        len @ 126..=0xffff => {
+            bytes.push(mask_bit | 126);
+            bytes.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            bytes.push(mask_bit | 127);
+            bytes.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            bytes.extend_from_slice(&mask);
+            bytes.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        }
+        None => bytes.extend_from_slice(payload),
+    }
+    writer.write_all(&bytes)?;
+    writer.flush()
+}
+
+/// Reads one frame with a payload of at most `max_len` bytes, unmasking
+/// it if it is masked. Fragmented messages and unknown opcodes are
+/// refused.
+pub(crate) fn read_frame(reader: &mut impl Read, max_len: u64) -> io::Result<Frame> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    if head[0] & 0x80 == 0 {
+        return Err(invalid("fragmented frames are not supported"));
+    }
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > max_len {
+        return Err(invalid("frame too long"));
+    }
+    let mut mask = None;
+    if head[1] & 0x80 != 0 {
+        let mut key = [0; 4];
+        reader.read_exact(&mut key)?;
+        mask = Some(key);
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    if let Some(mask) = mask {
+        payload.iter_mut().enumerate().for_each(|(i, byte)| *byte ^= mask[i % 4]);
+    }
+    match head[0] & 0x0f {
+        0x1 => String::from_utf8(payload).map(Frame::Text).map_err(|_| invalid("text frame is not UTF-8")),
+        0x2 => Ok(Frame::Binary(payload)),
+        0x8 => Ok(Frame::Close),
+        0x9 => Ok(Frame::Ping(payload)),
+        0xa => Ok(Frame::Pong(payload)),
+        _ => Err(invalid("unknown opcode")),
+    }
+}
+
+/// SHA-1, which the handshake requires. Nothing else should use it.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (chunk, word) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key_matches_rfc_6455() {
+        assert_eq!(hex::encode(sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            hex::encode(sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_frames_round_trip() {
+        let frames = [
+            Frame::Text("hello".to_owned()),
+            Frame::Binary(vec![7; 300]),
+            Frame::Binary(vec![9; 70_000]),
+            Frame::Close,
+            Frame::Ping(b"ping".to_vec()),
+            Frame::Pong(Vec::new()),
+        ];
+        for frame in &frames {
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let mut bytes = Vec::new();
+                write_frame(&mut bytes, frame, mask).unwrap();
+                assert_eq!(read_frame(&mut bytes.as_slice(), 1 << 20).unwrap(), *frame);
+            }
+        }
+
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, &Frame::Text("hello".to_owned()), Some([1, 2, 3, 4])).unwrap();
+        assert_eq!(&bytes[..2], [0x81, 0x85]);
+        assert_eq!(read_frame(&mut bytes.as_slice(), 4).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        bytes[0] = 0x01;
+        assert!(read_frame(&mut bytes.as_slice(), MAX_CONTROL_LEN).is_err());
+    }
+}
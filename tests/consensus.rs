@@ -0,0 +1,115 @@
+use ed25519_dalek::SigningKey;
+use proptest::prelude::*;
+use simplz_blockchain::utxo::address;
+use simplz_blockchain::{Block, Blockchain, ChainParams, Network, Transaction, validate};
+
+/// One block to mine: its data and the (amount, fee) of each transaction
+/// it carries. Blocks with transactions are mined from the mempool.
+type BlockSpec = (String, Vec<(u64, u64)>);
+
+#[derive(Debug, Clone, Copy)]
+enum Tamper {
+    Data,
+    Link,
+    Nonce,
+    Reward,
+}
+
+fn block_specs() -> impl Strategy<Value = Vec<BlockSpec>> {
+    let transactions = prop::collection::vec((0..1_000u64, 0..10u64), 0..4);
+    prop::collection::vec(("[a-z ]{0,16}", transactions), 1..6)
+}
+
+fn mine_chain(specs: &[BlockSpec]) -> Vec<Block> {
+    let mut blockchain = Blockchain::with_params(ChainParams::testing());
+    let alice = SigningKey::from_bytes(&[7; 32]);
+    let mut nonce = 0;
+    for (data, transactions) in specs {
+        if transactions.is_empty() {
+            assert!(blockchain.add_block(data.clone()));
+            continue;
+        }
+        for &(amount, fee) in transactions {
+            let mut transaction = Transaction {
+                nonce,
+                ..Transaction::with_fee(address(&alice.verifying_key(), Network::Mainnet), "bob".to_owned(), amount, fee)
+            };
+            transaction.sign(&alice);
+            assert!(blockchain.add_transaction(transaction));
+            nonce += 1;
+        }
+        assert!(blockchain.mine_pending_transactions("miner".to_owned()));
+    }
+    blockchain.iter().cloned().collect()
+}
+
+/// Re-mines every block after `from` so each links to its predecessor.
+fn relink(blocks: &mut [Block], from: usize) {
+    let target = ChainParams::testing().target;
+    for i in from.max(1)..blocks.len() {
+        blocks[i].header.prev_hash = blocks[i - 1].header.hash.clone();
+        blocks[i].header.nonce = 0;
+        blocks[i].mine_block(target);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn mined_chains_are_valid(specs in block_specs()) {
+        let blocks = mine_chain(&specs);
+        prop_assert!(validate(&blocks, &ChainParams::testing()));
+    }
+
+    #[test]
+    fn tampered_chains_are_rejected(
+        specs in block_specs(),
+        tamper in prop_oneof![Just(Tamper::Data), Just(Tamper::Link), Just(Tamper::Nonce), Just(Tamper::Reward)],
+        at in any::<prop::sample::Index>(),
+    ) {
+        let mut blocks = mine_chain(&specs);
+        let i = 1 + at.index(blocks.len() - 1);
+        match tamper {
+            // The header no longer commits to the body.
+            Tamper::Data => blocks[i].body.data.push('!'),
+            // A validly mined block that builds on the wrong parent.
+            Tamper::Link => {
+                blocks[i].header.prev_hash = "f".repeat(64);
+                blocks[i].mine_block(ChainParams::testing().target);
+                relink(&mut blocks, i + 1);
+            }
+            // The recorded hash no longer matches the header.
+            Tamper::Nonce => blocks[i].header.nonce += 1,
+            // A reward beyond what the block may mint, properly re-mined.
+            Tamper::Reward => {
+                let reward = Transaction::reward("miner".to_owned(), u64::MAX / 2);
+                blocks[i].body.transactions.insert(0, reward);
+                relink(&mut blocks, i);
+            }
+        }
+        prop_assert!(!validate(&blocks, &ChainParams::testing()));
+    }
+
+    #[test]
+    fn timestamps_must_not_go_backwards(
+        specs in block_specs(),
+        timestamps in prop::collection::vec(0..100i64, 6),
+    ) {
+        let mut blocks = mine_chain(&specs);
+        for (block, &timestamp) in blocks[1..].iter_mut().zip(&timestamps) {
+            block.header.timestamp = timestamp;
+        }
+        relink(&mut blocks, 1);
+
+        // Never before the previous block, and after the median of the
+        // eleven before it.
+        let ordered = (1..blocks.len()).all(|i| {
+            let mut earlier: Vec<i64> = blocks[i.saturating_sub(11)..i].iter().map(|block| block.header.timestamp).collect();
+            earlier.sort_unstable();
+            let timestamp = blocks[i].header.timestamp;
+            timestamp >= blocks[i - 1].header.timestamp && timestamp > earlier[earlier.len() / 2]
+        });
+        prop_assert_eq!(validate(&blocks, &ChainParams::testing()), ordered);
+    }
+}
@@ -0,0 +1,133 @@
+use ed25519_dalek::SigningKey;
+use simplz_blockchain::utxo::address;
+use simplz_blockchain::{Blockchain, ChainParams, Network, Node, Transaction};
+
+fn nodes(count: usize) -> Vec<Node> {
+    (0..count)
+        .map(|i| Node::with_blockchain(format!("node-{}", i), Blockchain::with_params(ChainParams::testing())))
+        .collect()
+}
+
+fn connect(nodes: &mut [Node], a: usize, b: usize) {
+    let (left, right) = nodes.split_at_mut(b);
+    Node::connect(&mut left[a], &mut right[0]);
+}
+
+fn disconnect(nodes: &mut [Node], a: usize, b: usize) {
+    let (left, right) = nodes.split_at_mut(b);
+    Node::disconnect(&mut left[a], &mut right[0]);
+}
+
+/// Delivers messages until no node has anything left to process.
+fn settle(nodes: &mut [Node]) {
+    for _ in 0..1_000 {
+        let handled: usize = nodes.iter_mut().map(Node::process_messages).sum();
+        if handled == 0 {
+            return;
+        }
+    }
+    panic!("network did not settle");
+}
+
+fn tip_hashes(nodes: &[Node]) -> Vec<String> {
+    nodes
+        .iter()
+        .map(|node| node.blockchain().latest_block().header.hash.clone())
+        .collect()
+}
+
+fn assert_converged(nodes: &[Node]) {
+    let tips = tip_hashes(nodes);
+    assert!(tips.iter().all(|tip| *tip == tips[0]), "tips diverge: {:?}", tips);
+    assert!(nodes.iter().all(|node| node.blockchain().is_valid_chain()));
+}
+
+#[test]
+fn blocks_propagate_along_a_line_of_nodes() {
+    let mut nodes = nodes(3);
+    connect(&mut nodes, 0, 1);
+    connect(&mut nodes, 1, 2);
+    settle(&mut nodes);
+
+    assert!(nodes[0].mine_block("First block data".to_owned()));
+    assert!(nodes[0].mine_block("Second block data".to_owned()));
+    settle(&mut nodes);
+
+    assert_converged(&nodes);
+    assert_eq!(nodes[2].blockchain().latest_block().header.index, 2);
+}
+
+#[test]
+fn transactions_are_relayed_and_leave_every_mempool_once_mined() {
+    let mut nodes = nodes(3);
+    connect(&mut nodes, 0, 1);
+    connect(&mut nodes, 1, 2);
+    settle(&mut nodes);
+
+    let alice = SigningKey::from_bytes(&[7; 32]);
+    let mut payment = Transaction::with_fee(address(&alice.verifying_key(), Network::Mainnet), "bob".to_owned(), 5, 1);
+    payment.sign(&alice);
+    nodes[2].submit_transaction(payment);
+    settle(&mut nodes);
+    assert!(nodes.iter().all(|node| node.blockchain().mempool().len() == 1));
+
+    assert!(nodes[0].mine_pending_transactions("miner".to_owned()));
+    settle(&mut nodes);
+
+    assert_converged(&nodes);
+    assert!(nodes.iter().all(|node| node.blockchain().mempool().is_empty()));
+}
+
+#[test]
+fn late_joiner_catches_up() {
+    let mut nodes = nodes(2);
+    for i in 1..=3 {
+        assert!(nodes[0].mine_block(format!("Block {} data", i)));
+    }
+
+    connect(&mut nodes, 0, 1);
+    settle(&mut nodes);
+
+    assert_converged(&nodes);
+    assert_eq!(nodes[1].blockchain().latest_block().header.index, 3);
+}
+
+#[test]
+fn partitioned_nodes_reorg_onto_the_longest_fork() {
+    let mut nodes = nodes(3);
+    connect(&mut nodes, 0, 1);
+    connect(&mut nodes, 1, 2);
+    assert!(nodes[0].mine_block("Shared block".to_owned()));
+    settle(&mut nodes);
+
+    disconnect(&mut nodes, 1, 2);
+    assert!(nodes[0].mine_block("Short fork".to_owned()));
+    for i in 1..=3 {
+        assert!(nodes[2].mine_block(format!("Long fork {}", i)));
+    }
+    settle(&mut nodes);
+    assert_ne!(tip_hashes(&nodes)[0], tip_hashes(&nodes)[2]);
+
+    let events = nodes[0].blockchain_mut().subscribe();
+    connect(&mut nodes, 1, 2);
+    settle(&mut nodes);
+
+    assert_converged(&nodes);
+    assert_eq!(nodes[0].blockchain().latest_block().header.index, 4);
+    assert_eq!(nodes[0].blockchain().get_block_by_index(2).unwrap().body.data, "Long fork 1");
+    assert!(events.try_iter().any(|event| event.name() == "chain_reorg"));
+}
+
+#[test]
+fn addresses_spread_through_the_network() {
+    let mut nodes = nodes(3);
+    nodes[0].advertise("10.0.0.1:7000".to_owned());
+    connect(&mut nodes, 0, 1);
+    settle(&mut nodes);
+    assert!(nodes[1].address_book().get("10.0.0.1:7000").is_some());
+    assert!(nodes[0].address_book().is_empty());
+
+    connect(&mut nodes, 1, 2);
+    settle(&mut nodes);
+    assert!(nodes[2].address_book().get("10.0.0.1:7000").is_some());
+}
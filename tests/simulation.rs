@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use simplz_blockchain::simulation::{LinkConditions, Simulation};
+
+const BLOCK_INTERVAL: Duration = Duration::from_secs(10);
+
+fn height(simulation: &Simulation, node: usize) -> u32 {
+    simulation.node(node).blockchain().latest_block().header.index
+}
+
+#[test]
+fn network_converges_on_the_longer_side_of_a_healed_partition() {
+    let mut simulation = Simulation::new(30, 42);
+    simulation.set_conditions(LinkConditions {
+        latency: Duration::from_millis(40),
+        jitter: Duration::from_millis(160),
+        drop_rate: 0.0,
+    });
+    simulation.connect_all();
+    assert!(simulation.run_until_quiet(Duration::from_secs(60)));
+
+    // 30% of the nodes split off and mine 10 blocks while the rest mine 50.
+    let minority: Vec<usize> = (0..9).collect();
+    simulation.partition(&minority);
+    for round in 0..50 {
+        assert!(simulation.mine_block(9 + round % 21));
+        if round % 5 == 0 {
+            assert!(simulation.mine_block(round % 9));
+        }
+        simulation.advance(BLOCK_INTERVAL);
+    }
+    assert!(simulation.run_until_quiet(Duration::from_secs(60)));
+    assert!(!simulation.converged());
+    assert_eq!((height(&simulation, 0), height(&simulation, 29)), (10, 50));
+
+    simulation.heal();
+    assert!(simulation.run_until_quiet(Duration::from_secs(60)));
+    assert!(simulation.converged());
+    assert_eq!(height(&simulation, 0), 50);
+    assert!(simulation.nodes().iter().all(|node| node.blockchain().is_valid_chain()));
+}
+
+#[test]
+fn lossy_network_converges_once_blocks_keep_coming() {
+    let mut simulation = Simulation::new(20, 7);
+    simulation.set_conditions(LinkConditions {
+        latency: Duration::from_millis(100),
+        jitter: Duration::from_millis(400),
+        drop_rate: 0.1,
+    });
+    simulation.connect_all();
+    for round in 0..20 {
+        assert!(simulation.mine_block(round % 20));
+        simulation.advance(BLOCK_INTERVAL);
+    }
+
+    // A lost announcement leaves a node behind only until it hears of the
+    // next block.
+    let mut rounds = 0;
+    while !simulation.converged() {
+        rounds += 1;
+        assert!(rounds < 20, "network did not converge");
+        assert!(simulation.mine_block(0));
+        simulation.run_until_quiet(Duration::from_secs(60));
+    }
+}